@@ -1,3 +1,17 @@
 fn main() {
+    // mozjpeg bundles its own libjpeg, whose symbols clash with a statically linked
+    // libjpeg-turbo, so turbojpeg-sys has to link a shared libturbojpeg instead
+    println!("cargo:rerun-if-env-changed=TURBOJPEG_DYNAMIC");
+    println!("cargo:rerun-if-env-changed=TURBOJPEG_SHARED");
+    if std::env::var_os("CARGO_FEATURE_MOZJPEG").is_some() && !turbojpeg_linked_dynamically() {
+        panic!("The mozjpeg feature needs a shared libturbojpeg: set TURBOJPEG_DYNAMIC=1, with TURBOJPEG_SOURCE=pkg-config or TURBOJPEG_LIB_DIR pointing at it");
+    }
+
     tauri_build::build()
 }
+
+fn turbojpeg_linked_dynamically() -> bool {
+    ["TURBOJPEG_DYNAMIC", "TURBOJPEG_SHARED"].iter().any(|name| {
+        std::env::var(name).is_ok_and(|value| ["", "1", "yes", "true", "on"].contains(&value.to_ascii_lowercase().as_str()))
+    })
+}
@@ -0,0 +1,34 @@
+use crate::work_dir::WorkDir;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Directories the frontend may ask the backend to open or reveal things in: those
+/// conversions this session read from or wrote to, plus the working directory.
+/// Keeps reveal_path/open_path from launching arbitrary files by path.
+#[derive(Default)]
+pub struct PermittedDirs(Mutex<HashSet<PathBuf>>);
+
+impl PermittedDirs {
+    /// Permit the directory containing `file`
+    pub fn allow_parent_of(&self, file: &str) {
+        let Some(dir) = Path::new(file).parent() else {
+            return;
+        };
+        if let Ok(dir) = dir.canonicalize() {
+            self.0.lock().unwrap_or_else(|e| e.into_inner()).insert(dir);
+        }
+    }
+
+    /// Resolve `path` and return it when it lies inside a permitted directory
+    pub fn check(&self, path: &str) -> Result<Option<PathBuf>> {
+        let resolved = Path::new(path).canonicalize().context("Failed to resolve path")?;
+        let work_dir = WorkDir::path().canonicalize().ok();
+        let dirs = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let permitted = dirs.iter()
+            .chain(work_dir.as_ref())
+            .any(|dir| resolved.starts_with(dir));
+        Ok(permitted.then_some(resolved))
+    }
+}
@@ -0,0 +1,922 @@
+use crate::image_processor::ImageProcessor;
+use color_quant::NeuQuant;
+use image::{DynamicImage, GenericImage, GenericImageView, GrayImage, Rgb, RgbImage, Rgba};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Pixel rectangle in post-orientation source coordinates
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct CropRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How a redacted region is obscured
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum RedactionMode {
+    Blur,
+    Pixelate,
+    Solid {
+        #[serde(default)]
+        color: [u8; 3],
+    },
+}
+
+/// Region to obscure, in post-orientation source pixels
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+pub struct Redaction {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    #[serde(flatten)]
+    pub mode: RedactionMode,
+}
+
+/// How grayscale values are mapped to black and white
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum BilevelMode {
+    /// Fixed cutoff: values >= threshold become white
+    Threshold { value: u8 },
+    /// Automatic cutoff maximizing between-class variance (best for clean text scans)
+    Otsu,
+    /// Error diffusion, keeps the impression of gray in photos and mixed content
+    FloydSteinberg,
+}
+
+/// How transparency is composited onto the background for formats without alpha
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FlattenMode {
+    /// Blend in linear light; anti-aliased edges keep their true brightness
+    #[default]
+    Linear,
+    /// Blend the sRGB values directly, which darkens semi-transparent edges slightly
+    Fast,
+}
+
+/// Dithering used when reducing 16-bit or float images to 8 bits
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DitherMode {
+    /// 8x8 Bayer matrix; a fine regular pattern, and identical across runs and tiles
+    Ordered,
+    /// Error diffusion; the least visible noise on smooth gradients
+    FloydSteinberg,
+}
+
+/// User-supplied palette for indexed output
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct FixedPalette {
+    /// At most 256 RGB colors; index order is kept in the output
+    pub colors: Vec<[u8; 3]>,
+    /// Diffuse the mapping error (Floyd-Steinberg) instead of picking the nearest color per pixel
+    #[serde(default)]
+    pub dither: bool,
+}
+
+/// Edits applied to a live preview before the user commits to a conversion
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct PreviewTransform {
+    /// Clockwise rotation in degrees: 0, 90, 180 or 270
+    pub rotate: u16,
+    pub flip_horizontal: bool,
+    pub flip_vertical: bool,
+    /// Region to keep, in post-orientation source pixels
+    pub crop: Option<CropRect>,
+    /// Added to every channel, -255 to 255
+    pub brightness: i32,
+}
+
+/// Longest side of the copy histograms are counted on
+const HISTOGRAM_SAMPLE_SIZE: u32 = 1024;
+
+/// Smallest side trimming leaves, so a speck on a blank page doesn't become the image
+const MIN_TRIM_SIZE: u32 = 8;
+
+/// Sample counts for one channel, darkest bin first
+#[derive(Serialize, Clone, Debug)]
+pub struct ChannelHistogram {
+    /// "red", "green", "blue", "gray" or "alpha"
+    pub channel: &'static str,
+    pub counts: Vec<u32>,
+}
+
+/// Tone distribution of an image, counted on a downscaled copy
+#[derive(Serialize, Clone, Debug)]
+pub struct Histogram {
+    pub bins: u32,
+    /// The source's own channels: gray or red/green/blue, plus alpha when present
+    pub channels: Vec<ChannelHistogram>,
+    /// Rec. 709 luma of the color channels; equals the gray channel for grayscale
+    pub luminance: Vec<u32>,
+    /// Pixels counted in each channel
+    pub samples: u32,
+}
+
+impl Histogram {
+    /// Percentage of pixels whose luminance sits in the top and bottom bins, i.e.
+    /// blown highlights and crushed shadows at 256 bins. None for an empty image.
+    pub fn clipped_percent(&self) -> Option<(f32, f32)> {
+        let (&black, &white) = (self.luminance.first()?, self.luminance.last()?);
+        (self.samples > 0).then(|| {
+            let percent = |count: u32| count as f32 * 100.0 / self.samples as f32;
+            (percent(white), percent(black))
+        })
+    }
+}
+
+pub struct Adjustments;
+
+impl Adjustments {
+    /// Convert to a black and white image (Luma8 containing only 0 and 255)
+    pub fn to_bilevel(img: &DynamicImage, mode: BilevelMode) -> GrayImage {
+        let mut luma = img.to_luma8();
+
+        match mode {
+            BilevelMode::Threshold { value } => Self::apply_threshold(&mut luma, value),
+            BilevelMode::Otsu => {
+                let threshold = Self::otsu_threshold(&luma);
+                Self::apply_threshold(&mut luma, threshold);
+            }
+            BilevelMode::FloydSteinberg => Self::floyd_steinberg(&mut luma),
+        }
+
+        luma
+    }
+
+    fn apply_threshold(luma: &mut GrayImage, threshold: u8) {
+        for pixel in luma.pixels_mut() {
+            pixel.0[0] = if pixel.0[0] >= threshold { 255 } else { 0 };
+        }
+    }
+
+    /// Mask with 1 for ink and 0 for paper, split at Otsu's threshold, plus the share
+    /// of ink pixels. The minority side is taken as ink so light-on-dark text works too.
+    pub fn ink_mask(luma: &GrayImage) -> (GrayImage, f32) {
+        let threshold = Self::otsu_threshold(luma);
+        let dark = luma.pixels().filter(|pixel| pixel.0[0] < threshold).count() as f32 / luma.len().max(1) as f32;
+        let dark_is_ink = dark <= 0.5;
+
+        let mut mask = luma.clone();
+        for pixel in mask.pixels_mut() {
+            pixel.0[0] = ((pixel.0[0] < threshold) == dark_is_ink) as u8;
+        }
+        (mask, if dark_is_ink { dark } else { 1.0 - dark })
+    }
+
+    /// Level separating the darker and lighter class: pixels below it are dark
+    fn otsu_threshold(luma: &GrayImage) -> u8 {
+        let mut histogram = [0u64; 256];
+        for pixel in luma.pixels() {
+            histogram[pixel.0[0] as usize] += 1;
+        }
+
+        let total = luma.width() as f64 * luma.height() as f64;
+        let sum_all: f64 = histogram.iter().enumerate().map(|(i, &c)| i as f64 * c as f64).sum();
+
+        let mut sum_background = 0.0;
+        let mut weight_background = 0.0;
+        let mut best_threshold = 128u8;
+        let mut best_variance = 0.0;
+
+        for (value, &count) in histogram.iter().enumerate() {
+            weight_background += count as f64;
+            if weight_background == 0.0 {
+                continue;
+            }
+            let weight_foreground = total - weight_background;
+            if weight_foreground == 0.0 {
+                break;
+            }
+
+            sum_background += value as f64 * count as f64;
+            let mean_background = sum_background / weight_background;
+            let mean_foreground = (sum_all - sum_background) / weight_foreground;
+
+            let variance = weight_background * weight_foreground
+                * (mean_background - mean_foreground).powi(2);
+            if variance > best_variance {
+                best_variance = variance;
+                // Pixels at or below `value` are background, so the cutoff is the next level
+                best_threshold = (value as u8).saturating_add(1);
+            }
+        }
+
+        best_threshold
+    }
+
+    fn floyd_steinberg(luma: &mut GrayImage) {
+        let (width, height) = (luma.width() as usize, luma.height() as usize);
+        let mut values: Vec<f32> = luma.as_raw().iter().map(|&v| v as f32).collect();
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let old = values[index];
+                let new = if old >= 128.0 { 255.0 } else { 0.0 };
+                values[index] = new;
+                let error = old - new;
+
+                if x + 1 < width {
+                    values[index + 1] += error * 7.0 / 16.0;
+                }
+                if y + 1 < height {
+                    if x > 0 {
+                        values[index + width - 1] += error * 3.0 / 16.0;
+                    }
+                    values[index + width] += error * 5.0 / 16.0;
+                    if x + 1 < width {
+                        values[index + width + 1] += error * 1.0 / 16.0;
+                    }
+                }
+            }
+        }
+
+        for (pixel, value) in luma.pixels_mut().zip(values) {
+            pixel.0[0] = if value >= 128.0 { 255 } else { 0 };
+        }
+    }
+
+    /// Apply the rotation, flips and brightness of a preview transform (crop is
+    /// applied by the caller in source coordinates)
+    pub fn apply_preview_transform(img: DynamicImage, transform: &PreviewTransform) -> DynamicImage {
+        let mut img = match transform.rotate % 360 {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            270 => img.rotate270(),
+            _ => img,
+        };
+        if transform.flip_horizontal {
+            img = img.fliph();
+        }
+        if transform.flip_vertical {
+            img = img.flipv();
+        }
+        if transform.brightness != 0 {
+            img = img.brighten(transform.brightness.clamp(-255, 255));
+        }
+        img
+    }
+
+    /// Stretch the histogram so the darkest samples become black and the brightest white.
+    /// `clip_percent` of the color samples at each end are ignored as outliers and
+    /// saturate. All color channels share one mapping so hues don't shift; alpha is
+    /// left alone. None when the image already spans the full range or isn't 8/16-bit.
+    pub fn auto_levels(img: &DynamicImage, clip_percent: f32) -> Option<DynamicImage> {
+        let clip = clip_percent as f64 / 100.0;
+        let mut out = img.clone();
+        let changed = match &mut out {
+            DynamicImage::ImageLuma8(buf) => stretch_levels(buf, 1, false, u8::MAX as u32, clip),
+            DynamicImage::ImageLumaA8(buf) => stretch_levels(buf, 2, true, u8::MAX as u32, clip),
+            DynamicImage::ImageRgb8(buf) => stretch_levels(buf, 3, false, u8::MAX as u32, clip),
+            DynamicImage::ImageRgba8(buf) => stretch_levels(buf, 4, true, u8::MAX as u32, clip),
+            DynamicImage::ImageLuma16(buf) => stretch_levels(buf, 1, false, u16::MAX as u32, clip),
+            DynamicImage::ImageLumaA16(buf) => stretch_levels(buf, 2, true, u16::MAX as u32, clip),
+            DynamicImage::ImageRgb16(buf) => stretch_levels(buf, 3, false, u16::MAX as u32, clip),
+            DynamicImage::ImageRgba16(buf) => stretch_levels(buf, 4, true, u16::MAX as u32, clip),
+            _ => false,
+        };
+        changed.then_some(out)
+    }
+
+    /// Count each channel into `bins` equal-width bins spanning the full sample range.
+    /// Large images are downscaled (nearest neighbour, so no new tones appear) first.
+    pub fn histogram(img: &DynamicImage, bins: u32) -> Histogram {
+        let sampled = (img.width().max(img.height()) > HISTOGRAM_SAMPLE_SIZE)
+            .then(|| img.resize(HISTOGRAM_SAMPLE_SIZE, HISTOGRAM_SAMPLE_SIZE, image::imageops::FilterType::Nearest));
+        let img = sampled.as_ref().unwrap_or(img);
+
+        let color = img.color();
+        let names: &[&'static str] = match (color.has_color(), color.has_alpha()) {
+            (true, true) => &["red", "green", "blue", "alpha"],
+            (true, false) => &["red", "green", "blue"],
+            (false, true) => &["gray", "alpha"],
+            (false, false) => &["gray"],
+        };
+
+        // 16-bit covers 8-bit sources exactly (v * 257) and keeps deep sources' precision
+        let pixels = img.to_rgba16();
+        let bin = |value: u16| (value as u64 * bins as u64 / (u16::MAX as u64 + 1)) as usize;
+        let mut counts = vec![vec![0u32; bins as usize]; names.len()];
+        let mut luminance = vec![0u32; bins as usize];
+
+        for pixel in pixels.pixels() {
+            let [r, g, b, a] = pixel.0;
+            let values: &[u16] = match (color.has_color(), color.has_alpha()) {
+                (true, true) => &[r, g, b, a],
+                (true, false) => &[r, g, b],
+                (false, true) => &[r, a],
+                (false, false) => &[r],
+            };
+            for (channel, &value) in counts.iter_mut().zip(values) {
+                channel[bin(value)] += 1;
+            }
+            let luma = 0.2126 * r as f32 + 0.7152 * g as f32 + 0.0722 * b as f32;
+            luminance[bin(luma.round().min(u16::MAX as f32) as u16)] += 1;
+        }
+
+        Histogram {
+            bins,
+            channels: names.iter()
+                .zip(counts)
+                .map(|(&channel, counts)| ChannelHistogram { channel, counts })
+                .collect(),
+            luminance,
+            samples: img.width() * img.height(),
+        }
+    }
+
+    /// Reduce a 16-bit or float image to the 8-bit layout with the same channels,
+    /// dithering the color channels to hide banding; alpha is rounded. None when the
+    /// image is already 8-bit.
+    pub fn reduce_to_8bit(img: &DynamicImage, mode: DitherMode) -> Option<DynamicImage> {
+        let (width, height) = img.dimensions();
+        let (channels, alpha) = match img {
+            DynamicImage::ImageLuma16(_) => (1, false),
+            DynamicImage::ImageLumaA16(_) => (2, true),
+            DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgb32F(_) => (3, false),
+            DynamicImage::ImageRgba16(_) | DynamicImage::ImageRgba32F(_) => (4, true),
+            _ => return None,
+        };
+
+        // Samples on the 0-255 scale, still fractional
+        let mut samples: Vec<f32> = match img {
+            DynamicImage::ImageRgb32F(buf) => buf.as_raw().iter().map(|&v| v * 255.0).collect(),
+            DynamicImage::ImageRgba32F(buf) => buf.as_raw().iter().map(|&v| v * 255.0).collect(),
+            DynamicImage::ImageLuma16(buf) => buf.as_raw().iter().map(|&v| v as f32 / 257.0).collect(),
+            DynamicImage::ImageLumaA16(buf) => buf.as_raw().iter().map(|&v| v as f32 / 257.0).collect(),
+            DynamicImage::ImageRgb16(buf) => buf.as_raw().iter().map(|&v| v as f32 / 257.0).collect(),
+            DynamicImage::ImageRgba16(buf) => buf.as_raw().iter().map(|&v| v as f32 / 257.0).collect(),
+            _ => return None,
+        };
+        let color = if alpha { channels - 1 } else { channels };
+        let (width_px, height_px) = (width as usize, height as usize);
+        let mut out = vec![0u8; samples.len()];
+
+        match mode {
+            DitherMode::Ordered => {
+                for (index, (sample, target)) in samples.iter().zip(out.iter_mut()).enumerate() {
+                    let pixel = index / channels;
+                    let offset = if index % channels < color {
+                        let (x, y) = (pixel % width_px, pixel / width_px);
+                        (BAYER_8X8[y % 8][x % 8] as f32 + 0.5) / 64.0 - 0.5
+                    } else {
+                        0.0
+                    };
+                    *target = (sample + offset).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+            DitherMode::FloydSteinberg => {
+                let stride = width_px * channels;
+                for y in 0..height_px {
+                    for x in 0..width_px {
+                        for channel in 0..channels {
+                            let index = y * stride + x * channels + channel;
+                            let quantized = samples[index].round().clamp(0.0, 255.0);
+                            out[index] = quantized as u8;
+                            if channel >= color {
+                                continue;
+                            }
+
+                            let error = samples[index] - quantized;
+                            if x + 1 < width_px {
+                                samples[index + channels] += error * 7.0 / 16.0;
+                            }
+                            if y + 1 < height_px {
+                                if x > 0 {
+                                    samples[index + stride - channels] += error * 3.0 / 16.0;
+                                }
+                                samples[index + stride] += error * 5.0 / 16.0;
+                                if x + 1 < width_px {
+                                    samples[index + stride + channels] += error * 1.0 / 16.0;
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        Some(match channels {
+            1 => DynamicImage::ImageLuma8(image::ImageBuffer::from_raw(width, height, out)?),
+            2 => DynamicImage::ImageLumaA8(image::ImageBuffer::from_raw(width, height, out)?),
+            3 => DynamicImage::ImageRgb8(image::ImageBuffer::from_raw(width, height, out)?),
+            _ => DynamicImage::ImageRgba8(image::ImageBuffer::from_raw(width, height, out)?),
+        })
+    }
+
+    /// Rotate clockwise by an arbitrary angle, cropping to the largest rectangle of the
+    /// original aspect ratio that the rotated image fully covers, so no empty corners
+    /// appear. Bilinear sampling; the pixel layout of the input is kept.
+    pub fn rotate_and_crop(img: &DynamicImage, degrees: f32) -> DynamicImage {
+        let (width, height) = (img.width() as f32, img.height() as f32);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let (sin_abs, cos_abs) = (sin.abs(), cos.abs());
+        let scale = (width / (width * cos_abs + height * sin_abs))
+            .min(height / (width * sin_abs + height * cos_abs));
+        let out_width = ((width * scale).floor() as u32).max(1);
+        let out_height = ((height * scale).floor() as u32).max(1);
+
+        Self::rotate_resampled(img, degrees, (out_width, out_height), None)
+    }
+
+    /// Rotate clockwise by an arbitrary angle on a canvas of the original size. Corners
+    /// the rotated image doesn't cover get `fill` (RGBA, 0 to 1). Bilinear sampling;
+    /// the pixel layout of the input is kept.
+    pub fn rotate_with_fill(img: &DynamicImage, degrees: f32, fill: [f32; 4]) -> DynamicImage {
+        Self::rotate_resampled(img, degrees, img.dimensions(), Some(fill))
+    }
+
+    /// Rotate about the center into an `out_width`×`out_height` canvas centered on the
+    /// source. Output pixels mapping outside the source get `fill`, or the nearest
+    /// edge pixel without one.
+    fn rotate_resampled(img: &DynamicImage, degrees: f32, (out_width, out_height): (u32, u32), fill: Option<[f32; 4]>) -> DynamicImage {
+        let (width, height) = (img.width() as f32, img.height() as f32);
+        let (sin, cos) = degrees.to_radians().sin_cos();
+
+        let source = img.to_rgba32f();
+        let sample = |x: f32, y: f32| -> [f32; 4] {
+            let outside = x < -0.5 || y < -0.5 || x > width - 0.5 || y > height - 0.5;
+            if let Some(fill) = fill.filter(|_| outside) {
+                return fill;
+            }
+            let x = x.clamp(0.0, width - 1.0);
+            let y = y.clamp(0.0, height - 1.0);
+            let (x0, y0) = (x.floor() as u32, y.floor() as u32);
+            let (x1, y1) = ((x0 + 1).min(img.width() - 1), (y0 + 1).min(img.height() - 1));
+            let (fx, fy) = (x - x0 as f32, y - y0 as f32);
+            let [a, b, c, d] = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)].map(|(x, y)| source.get_pixel(x, y).0);
+            std::array::from_fn(|i| {
+                let top = a[i] + (b[i] - a[i]) * fx;
+                let bottom = c[i] + (d[i] - c[i]) * fx;
+                top + (bottom - top) * fy
+            })
+        };
+
+        // Map each output pixel back into the source: inverse (counter-clockwise) rotation about the center
+        let (center_x, center_y) = ((width - 1.0) / 2.0, (height - 1.0) / 2.0);
+        let (out_center_x, out_center_y) = ((out_width - 1) as f32 / 2.0, (out_height - 1) as f32 / 2.0);
+        let rotated = image::Rgba32FImage::from_fn(out_width, out_height, |x, y| {
+            let (dx, dy) = (x as f32 - out_center_x, y as f32 - out_center_y);
+            Rgba(sample(center_x + dx * cos + dy * sin, center_y - dx * sin + dy * cos))
+        });
+
+        let rotated = DynamicImage::ImageRgba32F(rotated);
+        match img {
+            DynamicImage::ImageLuma8(_) => DynamicImage::ImageLuma8(rotated.to_luma8()),
+            DynamicImage::ImageLumaA8(_) => DynamicImage::ImageLumaA8(rotated.to_luma_alpha8()),
+            DynamicImage::ImageRgb8(_) => DynamicImage::ImageRgb8(rotated.to_rgb8()),
+            DynamicImage::ImageRgba8(_) => DynamicImage::ImageRgba8(rotated.to_rgba8()),
+            DynamicImage::ImageLuma16(_) => DynamicImage::ImageLuma16(rotated.to_luma16()),
+            DynamicImage::ImageLumaA16(_) => DynamicImage::ImageLumaA16(rotated.to_luma_alpha16()),
+            DynamicImage::ImageRgb16(_) => DynamicImage::ImageRgb16(rotated.to_rgb16()),
+            DynamicImage::ImageRgb32F(_) => DynamicImage::ImageRgb32F(rotated.to_rgb32f()),
+            _ => rotated,
+        }
+    }
+
+    /// Composite the image over a solid background, dropping alpha
+    pub fn flatten(img: &DynamicImage, background: [u8; 3], mode: FlattenMode) -> RgbImage {
+        const LINEAR_STEPS: usize = 1 << 16;
+
+        let rgba = ImageProcessor::as_rgba8(img);
+        let (to_linear, to_srgb) = match mode {
+            FlattenMode::Linear => {
+                let to_linear: Vec<f32> = (0..=255u8).map(|v| srgb_to_linear(v as f32 / 255.0)).collect();
+                let to_srgb: Vec<u8> = (0..LINEAR_STEPS)
+                    .map(|i| (linear_to_srgb(i as f32 / (LINEAR_STEPS - 1) as f32) * 255.0).round() as u8)
+                    .collect();
+                (to_linear, to_srgb)
+            }
+            FlattenMode::Fast => (Vec::new(), Vec::new()),
+        };
+
+        let mut flat = RgbImage::new(rgba.width(), rgba.height());
+        for (out, pixel) in flat.pixels_mut().zip(rgba.pixels()) {
+            let [r, g, b, a] = pixel.0;
+            *out = match a {
+                255 => Rgb([r, g, b]),
+                0 => Rgb(background),
+                _ if mode == FlattenMode::Fast => {
+                    let blend = |c: u8, bg: u8| ((c as u32 * a as u32 + bg as u32 * (255 - a as u32) + 127) / 255) as u8;
+                    Rgb([blend(r, background[0]), blend(g, background[1]), blend(b, background[2])])
+                }
+                _ => {
+                    let alpha = a as f32 / 255.0;
+                    let blend = |c: u8, bg: u8| {
+                        let linear = to_linear[c as usize] * alpha + to_linear[bg as usize] * (1.0 - alpha);
+                        to_srgb[(linear * (LINEAR_STEPS - 1) as f32).round() as usize]
+                    };
+                    Rgb([blend(r, background[0]), blend(g, background[1]), blend(b, background[2])])
+                }
+            };
+        }
+
+        flat
+    }
+
+    /// Reduce to at most `max_colors` RGB colors, returning (palette, per-pixel indices).
+    /// Images that already fit get an exact palette; others are quantized with NeuQuant.
+    pub fn quantize(img: &DynamicImage, max_colors: usize) -> (Vec<[u8; 3]>, Vec<u8>) {
+        let rgb = ImageProcessor::as_rgb8(img);
+        let max_colors = max_colors.clamp(2, 256);
+
+        let mut lookup: HashMap<[u8; 3], u8> = HashMap::new();
+        let mut palette = Vec::new();
+        let mut indices = Vec::with_capacity(rgb.width() as usize * rgb.height() as usize);
+        let mut exact = true;
+
+        for pixel in rgb.pixels() {
+            let index = match lookup.get(&pixel.0) {
+                Some(&index) => index,
+                None if palette.len() < max_colors => {
+                    let index = palette.len() as u8;
+                    palette.push(pixel.0);
+                    lookup.insert(pixel.0, index);
+                    index
+                }
+                None => {
+                    exact = false;
+                    break;
+                }
+            };
+            indices.push(index);
+        }
+
+        if exact {
+            return (palette, indices);
+        }
+
+        let rgba = ImageProcessor::as_rgba8(img);
+        let quantizer = NeuQuant::new(10, max_colors, rgba.as_raw());
+        let palette = quantizer
+            .color_map_rgb()
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+        let indices = rgba
+            .as_raw()
+            .chunks_exact(4)
+            .map(|pixel| quantizer.index_of(pixel) as u8)
+            .collect();
+
+        (palette, indices)
+    }
+
+    /// Map every pixel to the nearest palette entry (squared RGB distance), returning
+    /// per-pixel indices. Alpha is ignored; flatten first if it matters.
+    pub fn map_to_palette(img: &DynamicImage, palette: &FixedPalette) -> Vec<u8> {
+        let rgb = ImageProcessor::as_rgb8(img);
+        let nearest = |color: [f32; 3]| -> usize {
+            let distance = |entry: &[u8; 3]| -> f32 {
+                (0..3).map(|c| (entry[c] as f32 - color[c]).powi(2)).sum()
+            };
+            (0..palette.colors.len())
+                .min_by(|&a, &b| distance(&palette.colors[a]).total_cmp(&distance(&palette.colors[b])))
+                .unwrap_or(0)
+        };
+
+        if !palette.dither {
+            let mut lookup: HashMap<[u8; 3], u8> = HashMap::new();
+            return rgb.pixels()
+                .map(|pixel| *lookup.entry(pixel.0).or_insert_with(|| nearest(pixel.0.map(|c| c as f32)) as u8))
+                .collect();
+        }
+
+        let (width, height) = (rgb.width() as usize, rgb.height() as usize);
+        let mut values: Vec<[f32; 3]> = rgb.pixels().map(|pixel| pixel.0.map(|c| c as f32)).collect();
+        let mut indices = Vec::with_capacity(values.len());
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = y * width + x;
+                let old = values[index].map(|c| c.clamp(0.0, 255.0));
+                let chosen = nearest(old);
+                indices.push(chosen as u8);
+
+                let new = palette.colors[chosen];
+                let error = [old[0] - new[0] as f32, old[1] - new[1] as f32, old[2] - new[2] as f32];
+                let mut spread = |target: usize, weight: f32| {
+                    for (value, error) in values[target].iter_mut().zip(error) {
+                        *value += error * weight;
+                    }
+                };
+
+                if x + 1 < width {
+                    spread(index + 1, 7.0 / 16.0);
+                }
+                if y + 1 < height {
+                    if x > 0 {
+                        spread(index + width - 1, 3.0 / 16.0);
+                    }
+                    spread(index + width, 5.0 / 16.0);
+                    if x + 1 < width {
+                        spread(index + width + 1, 1.0 / 16.0);
+                    }
+                }
+            }
+        }
+
+        indices
+    }
+
+    /// Find the rectangle left after removing uniform borders matching the top-left
+    /// corner within `tolerance`. Fully transparent corners trim all fully transparent
+    /// margins regardless of color. The rectangle is widened around the content to at
+    /// least MIN_TRIM_SIZE on each side. Returns None when nothing would be trimmed or
+    /// the whole image is uniform.
+    pub fn find_trim_rect(img: &DynamicImage, tolerance: u8) -> Option<CropRect> {
+        let rgba = ImageProcessor::as_rgba8(img);
+        let (width, height) = rgba.dimensions();
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        let reference = *rgba.get_pixel(0, 0);
+        let is_border = |pixel: &Rgba<u8>| -> bool {
+            if reference.0[3] == 0 {
+                return pixel.0[3] == 0;
+            }
+            pixel.0.iter()
+                .zip(reference.0.iter())
+                .all(|(&a, &b)| a.abs_diff(b) <= tolerance)
+        };
+        let row_is_border = |y: u32, x0: u32, x1: u32| (x0..x1).all(|x| is_border(rgba.get_pixel(x, y)));
+        let column_is_border = |x: u32, y0: u32, y1: u32| (y0..y1).all(|y| is_border(rgba.get_pixel(x, y)));
+
+        let top = (0..height).find(|&y| !row_is_border(y, 0, width))?;
+        let bottom = (top..height).rev().find(|&y| !row_is_border(y, 0, width))?;
+        let left = (0..width).find(|&x| !column_is_border(x, top, bottom + 1))?;
+        let right = (left..width).rev().find(|&x| !column_is_border(x, top, bottom + 1))?;
+
+        let (x, rect_width) = Self::widen_span(left, right - left + 1, width);
+        let (y, rect_height) = Self::widen_span(top, bottom - top + 1, height);
+        let rect = CropRect { x, y, width: rect_width, height: rect_height };
+
+        (rect.width != width || rect.height != height).then_some(rect)
+    }
+
+    /// Grow a span to MIN_TRIM_SIZE (or the full length) about its center
+    fn widen_span(start: u32, length: u32, total: u32) -> (u32, u32) {
+        let widened = length.max(MIN_TRIM_SIZE.min(total));
+        let start = start.saturating_sub((widened - length) / 2).min(total - widened);
+        (start, widened)
+    }
+
+    /// Obscure the given regions in place. Regions are clamped to the image bounds and
+    /// ones that fall entirely outside are dropped; the regions actually applied are returned.
+    pub fn apply_redactions(img: &mut DynamicImage, redactions: &[Redaction]) -> Vec<Redaction> {
+        let (width, height) = img.dimensions();
+        let mut applied = Vec::new();
+
+        for redaction in redactions {
+            let x = redaction.x.min(width);
+            let y = redaction.y.min(height);
+            let w = redaction.width.min(width - x);
+            let h = redaction.height.min(height - y);
+            if w == 0 || h == 0 {
+                continue;
+            }
+
+            match redaction.mode {
+                RedactionMode::Blur => {
+                    // Strong enough that text inside the region can't be recovered
+                    let sigma = (w.max(h) as f32 / 8.0).max(8.0);
+                    let blurred = img.crop_imm(x, y, w, h).blur(sigma);
+                    image::imageops::replace(img, &blurred, x as i64, y as i64);
+                }
+                RedactionMode::Pixelate => {
+                    // Block size grows with the region so small regions don't stay legible
+                    let block = (w.max(h) / 8).max(4);
+                    for block_y in (y..y + h).step_by(block as usize) {
+                        for block_x in (x..x + w).step_by(block as usize) {
+                            let block_w = block.min(x + w - block_x);
+                            let block_h = block.min(y + h - block_y);
+                            let mut sum = [0u64; 4];
+                            for py in block_y..block_y + block_h {
+                                for px in block_x..block_x + block_w {
+                                    let pixel = img.get_pixel(px, py);
+                                    for (total, channel) in sum.iter_mut().zip(pixel.0) {
+                                        *total += channel as u64;
+                                    }
+                                }
+                            }
+                            let count = (block_w * block_h) as u64;
+                            let average = Rgba(sum.map(|total| (total / count) as u8));
+                            for py in block_y..block_y + block_h {
+                                for px in block_x..block_x + block_w {
+                                    img.put_pixel(px, py, average);
+                                }
+                            }
+                        }
+                    }
+                }
+                RedactionMode::Solid { color } => {
+                    let fill = Rgba([color[0], color[1], color[2], 255]);
+                    for py in y..y + h {
+                        for px in x..x + w {
+                            img.put_pixel(px, py, fill);
+                        }
+                    }
+                }
+            }
+
+            applied.push(Redaction { x, y, width: w, height: h, mode: redaction.mode });
+        }
+
+        applied
+    }
+}
+
+/// Remap the color samples of interleaved pixels in place through a black/white point
+/// stretch. Returns false, leaving the samples untouched, when there's nothing to stretch.
+fn stretch_levels<T>(samples: &mut [T], channels: usize, alpha: bool, max: u32, clip: f64) -> bool
+where
+    T: Copy + Into<u32> + TryFrom<u32>,
+{
+    let color = if alpha { channels - 1 } else { channels };
+    let mut histogram = vec![0u64; max as usize + 1];
+    for pixel in samples.chunks_exact(channels) {
+        for &value in &pixel[..color] {
+            histogram[value.into() as usize] += 1;
+        }
+    }
+
+    let total: u64 = histogram.iter().sum();
+    let clip_count = (total as f64 * clip) as u64;
+    let (Some(black), Some(white)) = (
+        clip_point(histogram.iter().enumerate(), clip_count),
+        clip_point(histogram.iter().enumerate().rev(), clip_count),
+    ) else {
+        return false;
+    };
+    if white <= black || (black == 0 && white == max as usize) {
+        return false;
+    }
+
+    let range = (white - black) as f64;
+    let lut: Vec<u32> = (0..=max)
+        .map(|value| {
+            let scaled = ((value as f64 - black as f64) / range).clamp(0.0, 1.0);
+            (scaled * max as f64).round() as u32
+        })
+        .collect();
+
+    for pixel in samples.chunks_exact_mut(channels) {
+        for value in &mut pixel[..color] {
+            if let Ok(mapped) = T::try_from(lut[(*value).into() as usize]) {
+                *value = mapped;
+            }
+        }
+    }
+
+    true
+}
+
+/// First histogram value, walking from one end, past which more than `clip_count` samples lie
+fn clip_point<'a>(mut counts: impl Iterator<Item = (usize, &'a u64)>, clip_count: u64) -> Option<usize> {
+    let mut seen = 0;
+    counts
+        .find(|(_, &count)| {
+            seen += count;
+            seen > clip_count
+        })
+        .map(|(value, _)| value)
+}
+
+/// Bayer threshold matrix for ordered dithering, values 0-63
+const BAYER_8X8: [[u8; 8]; 8] = [
+    [0, 32, 8, 40, 2, 34, 10, 42],
+    [48, 16, 56, 24, 50, 18, 58, 26],
+    [12, 44, 4, 36, 14, 46, 6, 38],
+    [60, 28, 52, 20, 62, 30, 54, 22],
+    [3, 35, 11, 43, 1, 33, 9, 41],
+    [51, 19, 59, 27, 49, 17, 57, 25],
+    [15, 47, 7, 39, 13, 45, 5, 37],
+    [63, 31, 55, 23, 61, 29, 53, 21],
+];
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::text_page;
+    use image::{Luma, RgbaImage};
+
+    #[test]
+    fn threshold_whitens_values_at_or_above_the_cutoff() {
+        let ramp = DynamicImage::ImageLuma8(GrayImage::from_fn(256, 1, |x, _| Luma([x as u8])));
+        let bilevel = Adjustments::to_bilevel(&ramp, BilevelMode::Threshold { value: 100 });
+        for (x, _, pixel) in bilevel.enumerate_pixels() {
+            assert_eq!(pixel.0[0], if x >= 100 { 255 } else { 0 }, "value {x}");
+        }
+    }
+
+    #[test]
+    fn otsu_separates_gray_ink_from_gray_paper() {
+        // Ink at 70 and paper at 190, both with a little noise
+        let page = text_page(400, 300);
+        let scan = GrayImage::from_fn(400, 300, |x, y| {
+            let noise = ((x * 7 + y * 13) % 21) as u8;
+            Luma([if page.get_pixel(x, y).0[0] == 0 { 60 + noise } else { 180 + noise }])
+        });
+        let bilevel = Adjustments::to_bilevel(&DynamicImage::ImageLuma8(scan), BilevelMode::Otsu);
+        assert_eq!(bilevel, page);
+    }
+
+    #[test]
+    fn floyd_steinberg_keeps_the_average_gray() {
+        let gray = DynamicImage::ImageLuma8(GrayImage::from_pixel(128, 128, Luma([128])));
+        let bilevel = Adjustments::to_bilevel(&gray, BilevelMode::FloydSteinberg);
+        assert!(bilevel.pixels().all(|pixel| pixel.0[0] == 0 || pixel.0[0] == 255));
+        let white = bilevel.pixels().filter(|pixel| pixel.0[0] == 255).count() as f32 / bilevel.len() as f32;
+        assert!((white - 0.5).abs() < 0.02, "white share {white}");
+    }
+
+    /// `content` pasted at (x, y) onto a `width` x `height` canvas of `border`
+    fn framed(content: &RgbaImage, border: Rgba<u8>, width: u32, height: u32, x: u32, y: u32) -> DynamicImage {
+        let mut canvas = RgbaImage::from_pixel(width, height, border);
+        image::imageops::replace(&mut canvas, content, x as i64, y as i64);
+        DynamicImage::ImageRgba8(canvas)
+    }
+
+    #[test]
+    fn trim_finds_the_content_inside_a_uniform_border() {
+        let content = crate::test_util::gradient(30, 20).to_rgba8();
+        let img = framed(&content, Rgba([255, 255, 255, 255]), 64, 48, 11, 7);
+        assert_eq!(
+            Adjustments::find_trim_rect(&img, 0),
+            Some(CropRect { x: 11, y: 7, width: 30, height: 20 })
+        );
+    }
+
+    #[test]
+    fn trim_tolerance_covers_border_noise() {
+        let content = RgbaImage::from_pixel(20, 20, Rgba([0, 0, 0, 255]));
+        let mut img = framed(&content, Rgba([250, 250, 250, 255]), 40, 40, 10, 10).to_rgba8();
+        for (x, y, pixel) in img.enumerate_pixels_mut() {
+            if pixel.0[0] != 0 {
+                let value = 250 + ((x * 3 + y) % 6) as u8;
+                *pixel = Rgba([value, value, value, 255]);
+            }
+        }
+        let img = DynamicImage::ImageRgba8(img);
+
+        let expected = CropRect { x: 10, y: 10, width: 20, height: 20 };
+        assert_eq!(Adjustments::find_trim_rect(&img, 5), Some(expected));
+        assert_ne!(Adjustments::find_trim_rect(&img, 2), Some(expected));
+    }
+
+    #[test]
+    fn uniform_images_are_not_trimmed() {
+        let img = DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 32, Rgba([0, 0, 0, 255])));
+        assert_eq!(Adjustments::find_trim_rect(&img, 0), None);
+        let noisy = DynamicImage::ImageLuma8(GrayImage::from_fn(32, 32, |x, y| Luma([((x + y) % 4) as u8])));
+        assert_eq!(Adjustments::find_trim_rect(&noisy, 3), None);
+    }
+
+    #[test]
+    fn trim_keeps_a_minimum_size_around_tiny_content() {
+        let speck = RgbaImage::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let rect = Adjustments::find_trim_rect(&framed(&speck, Rgba([255, 255, 255, 255]), 100, 50, 60, 1), 0).unwrap();
+        assert_eq!((rect.width, rect.height), (MIN_TRIM_SIZE, MIN_TRIM_SIZE));
+        assert!(rect.x <= 60 && rect.x + rect.width >= 62);
+        assert_eq!(rect.y, 0);
+
+        // Images narrower than the minimum keep their full width
+        let narrow = framed(&speck, Rgba([255, 255, 255, 255]), 4, 50, 1, 20);
+        let rect = Adjustments::find_trim_rect(&narrow, 0).unwrap();
+        assert_eq!((rect.x, rect.width), (0, 4));
+        assert_eq!(rect.height, MIN_TRIM_SIZE);
+    }
+
+    #[test]
+    fn trim_removes_transparent_margins_whatever_their_color() {
+        let content = RgbaImage::from_pixel(10, 12, Rgba([200, 30, 30, 128]));
+        let mut canvas = RgbaImage::from_fn(40, 30, |x, y| Rgba([(x * 6) as u8, (y * 8) as u8, 77, 0]));
+        image::imageops::replace(&mut canvas, &content, 5, 16);
+        let rect = Adjustments::find_trim_rect(&DynamicImage::ImageRgba8(canvas), 0);
+        assert_eq!(rect, Some(CropRect { x: 5, y: 16, width: 10, height: 12 }));
+    }
+}
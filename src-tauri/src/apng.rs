@@ -0,0 +1,317 @@
+use crate::file_ops::FileOps;
+use crate::image_processor::PngCompression;
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use serde::Serialize;
+use std::path::Path;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ApngSummary {
+    pub output_path: String,
+    pub output_bytes: u64,
+    /// Frames in the file, after unchanged frames were merged into the one before
+    pub frames: u32,
+    /// Frames stored as only the region that changed since the previous frame
+    pub delta_frames: u32,
+    /// Input frames identical to the previous one, whose delay was added to it instead
+    pub merged_frames: u32,
+}
+
+/// One fcTL/fdAT pair: a region of the canvas and how it combines with what's there
+struct Frame {
+    x: u32,
+    y: u32,
+    pixels: RgbaImage,
+    blend: png::BlendOp,
+    delay_ms: u32,
+}
+
+/// Animated PNG from a sequence of equally sized frames
+pub struct ApngEncoder;
+
+impl ApngEncoder {
+    /// Write `frames` to `output_path` as an APNG showing each for `delay_ms` and
+    /// playing `loops` times (0 repeats forever). With `delta`, each frame after the
+    /// first stores only the bounding box of the pixels that changed, drawn over the
+    /// previous frame.
+    /// When every changed pixel is opaque, unchanged pixels inside the box may become
+    /// transparent and blend over the canvas; otherwise the box replaces the canvas
+    /// region. Whichever of those and the whole frame compresses smallest is stored,
+    /// and frames that change nothing extend the previous frame's delay.
+    pub fn write(
+        frames: &[RgbaImage],
+        output_path: &Path,
+        delay_ms: u32,
+        loops: u32,
+        delta: bool,
+        compression: PngCompression,
+    ) -> Result<ApngSummary> {
+        let first = frames.first().context("An animation needs at least one frame")?;
+        let (width, height) = first.dimensions();
+        if let Some(frame) = frames.iter().find(|frame| frame.dimensions() != (width, height)) {
+            anyhow::bail!(
+                "Every frame must be {}x{} like the first; found {}x{}",
+                width, height, frame.width(), frame.height()
+            );
+        }
+
+        let mut planned = vec![Frame { x: 0, y: 0, pixels: first.clone(), blend: png::BlendOp::Source, delay_ms }];
+        let (mut delta_frames, mut merged_frames) = (0, 0);
+        for (previous, current) in frames.iter().zip(&frames[1..]) {
+            if !delta {
+                planned.push(Frame { x: 0, y: 0, pixels: current.clone(), blend: png::BlendOp::Source, delay_ms });
+                continue;
+            }
+            match Self::delta(previous, current, compression)? {
+                None => {
+                    if let Some(last) = planned.last_mut() {
+                        last.delay_ms = last.delay_ms.saturating_add(delay_ms);
+                    }
+                    merged_frames += 1;
+                }
+                Some(frame) => {
+                    if frame.pixels.dimensions() != (width, height) || frame.blend == png::BlendOp::Over {
+                        delta_frames += 1;
+                    }
+                    planned.push(Frame { delay_ms, ..frame });
+                }
+            }
+        }
+
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        compression.configure(&mut encoder);
+        encoder.set_animated(planned.len() as u32, loops).context("Failed to set up APNG")?;
+
+        let mut writer = encoder.write_header().context("Failed to write PNG header")?;
+        for frame in &planned {
+            // Position back to the origin first so the new size is always in bounds
+            writer.reset_frame_position().context("Failed to place APNG frame")?;
+            writer.set_frame_dimension(frame.pixels.width(), frame.pixels.height())
+                .context("Failed to size APNG frame")?;
+            writer.set_frame_position(frame.x, frame.y).context("Failed to place APNG frame")?;
+            let (numerator, denominator) = Self::delay_fraction(frame.delay_ms);
+            writer.set_frame_delay(numerator, denominator).context("Failed to set APNG frame delay")?;
+            writer.set_blend_op(frame.blend).context("Failed to set APNG blend op")?;
+            // The canvas always keeps the frame, so the next delta applies on top of it
+            writer.set_dispose_op(png::DisposeOp::None).context("Failed to set APNG dispose op")?;
+            writer.write_image_data(frame.pixels.as_raw()).context("Failed to encode APNG frame")?;
+        }
+        writer.finish().context("Failed to finish APNG")?;
+        FileOps::write_atomic(output_path, &data).context("Failed to write APNG")?;
+
+        Ok(ApngSummary {
+            output_path: output_path.to_string_lossy().to_string(),
+            output_bytes: data.len() as u64,
+            frames: planned.len() as u32,
+            delta_frames,
+            merged_frames,
+        })
+    }
+
+    /// The smallest frame that turns `previous` into `current` on the canvas, or None
+    /// when they're identical. Delay is left at zero for the caller to fill in.
+    fn delta(previous: &RgbaImage, current: &RgbaImage, compression: PngCompression) -> Result<Option<Frame>> {
+        let (width, height) = current.dimensions();
+        let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+        let mut changed = 0u64;
+        let mut opaque = true;
+        for ((x, y, before), after) in previous.enumerate_pixels().zip(current.pixels()) {
+            if before != after {
+                left = left.min(x);
+                top = top.min(y);
+                right = right.max(x);
+                bottom = bottom.max(y);
+                changed += 1;
+                opaque &= after.0[3] == 255;
+            }
+        }
+        if changed == 0 {
+            return Ok(None);
+        }
+
+        let mut candidates = vec![Frame { x: 0, y: 0, pixels: current.clone(), blend: png::BlendOp::Source, delay_ms: 0 }];
+        let (box_width, box_height) = (right - left + 1, bottom - top + 1);
+        let cropped = image::imageops::crop_imm(current, left, top, box_width, box_height).to_image();
+        if opaque {
+            // Transparent pixels leave the canvas, and so the previous frame, showing through
+            let mut pixels = cropped.clone();
+            for (x, y, pixel) in pixels.enumerate_pixels_mut() {
+                if previous.get_pixel(left + x, top + y) == current.get_pixel(left + x, top + y) {
+                    pixel.0 = [0; 4];
+                }
+            }
+            candidates.push(Frame { x: left, y: top, pixels, blend: png::BlendOp::Over, delay_ms: 0 });
+        }
+        if (box_width, box_height) != (width, height) {
+            candidates.push(Frame { x: left, y: top, pixels: cropped, blend: png::BlendOp::Source, delay_ms: 0 });
+        }
+
+        let mut smallest = None;
+        for frame in candidates {
+            let size = Self::compressed_size(&frame.pixels, compression)?;
+            if smallest.as_ref().is_none_or(|&(smallest_size, _)| size < smallest_size) {
+                smallest = Some((size, frame));
+            }
+        }
+        Ok(smallest.map(|(_, frame)| frame))
+    }
+
+    /// Bytes `pixels` take as a PNG at `compression`; the APNG stores the same
+    /// compressed stream, less a constant header
+    fn compressed_size(pixels: &RgbaImage, compression: PngCompression) -> Result<usize> {
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, pixels.width(), pixels.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        compression.configure(&mut encoder);
+        encoder.write_header()
+            .and_then(|mut writer| writer.write_image_data(pixels.as_raw()))
+            .context("Failed to encode APNG frame")?;
+        Ok(data.len())
+    }
+
+    /// fcTL delay as a fraction of a second; hundredths once milliseconds overflow u16
+    fn delay_fraction(delay_ms: u32) -> (u16, u16) {
+        match u16::try_from(delay_ms) {
+            Ok(ms) => (ms, 1000),
+            Err(_) => ((delay_ms / 10).min(u16::MAX as u32) as u16, 100),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{gradient, photo, TempDir};
+    use image::{AnimationDecoder, Rgba};
+
+    fn base() -> RgbaImage {
+        gradient(64, 48).to_rgba8()
+    }
+
+    /// `img` with an 8x8 square of `color` at (x, y)
+    fn with_square(img: &RgbaImage, (x, y): (u32, u32), color: [u8; 4]) -> RgbaImage {
+        let mut img = img.clone();
+        for dy in 0..8 {
+            for dx in 0..8 {
+                img.put_pixel(x + dx, y + dy, Rgba(color));
+            }
+        }
+        img
+    }
+
+    /// Composited frames and their delays in milliseconds, as a viewer shows them
+    fn decode(path: &Path) -> Vec<(RgbaImage, u32)> {
+        let file = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        image::codecs::png::PngDecoder::new(file).unwrap()
+            .apng()
+            .into_frames()
+            .map(|frame| {
+                let frame = frame.unwrap();
+                let (numerator, denominator) = frame.delay().numer_denom_ms();
+                (frame.into_buffer(), numerator / denominator)
+            })
+            .collect()
+    }
+
+    /// Size, position and blend op of each stored frame
+    fn stored_frames(path: &Path) -> Vec<(u32, u32, u32, u32, png::BlendOp)> {
+        let mut reader = png::Decoder::new(std::fs::File::open(path).unwrap()).read_info().unwrap();
+        let count = reader.info().animation_control.unwrap().num_frames;
+        let mut buffer = vec![0; reader.output_buffer_size()];
+        (0..count)
+            .map(|_| {
+                reader.next_frame(&mut buffer).unwrap();
+                let control = reader.info().frame_control.unwrap();
+                (control.width, control.height, control.x_offset, control.y_offset, control.blend_op)
+            })
+            .collect()
+    }
+
+    fn write(dir: &TempDir, name: &str, frames: &[RgbaImage], delta: bool) -> (ApngSummary, std::path::PathBuf) {
+        let path = std::path::PathBuf::from(dir.file(name));
+        let summary = ApngEncoder::write(frames, &path, 100, 0, delta, PngCompression::Fast).unwrap();
+        (summary, path)
+    }
+
+    #[test]
+    fn every_composited_frame_matches_its_input() {
+        let dir = TempDir::new();
+        let opaque = with_square(&base(), (10, 10), [255, 0, 0, 255]);
+        let translucent = with_square(&opaque, (40, 30), [0, 0, 255, 128]);
+        let frames = [base(), base(), opaque.clone(), opaque.clone(), opaque, translucent.clone(), photo(64, 48).to_rgba8()];
+
+        let (summary, path) = write(&dir, "all.png", &frames, true);
+        assert_eq!((summary.frames, summary.merged_frames), (4, 3));
+
+        let decoded = decode(&path);
+        let expected = [(&frames[0], 200), (&frames[2], 300), (&translucent, 100), (&frames[6], 100)];
+        assert_eq!(decoded.len(), expected.len());
+        for (index, ((img, delay), (expected_img, expected_delay))) in decoded.iter().zip(expected).enumerate() {
+            assert!(img == expected_img, "frame {} differs from its input", index);
+            assert_eq!(*delay, expected_delay, "frame {}", index);
+        }
+    }
+
+    #[test]
+    fn opaque_changes_blend_over_the_previous_frame() {
+        let dir = TempDir::new();
+        let frames = [base(), with_square(&base(), (10, 12), [255, 0, 0, 255])];
+
+        let (summary, path) = write(&dir, "opaque.png", &frames, true);
+        assert_eq!(summary.delta_frames, 1);
+        assert_eq!(stored_frames(&path)[1], (8, 8, 10, 12, png::BlendOp::Over));
+        assert!(decode(&path)[1].0 == frames[1]);
+    }
+
+    #[test]
+    fn translucent_changes_replace_their_region() {
+        let dir = TempDir::new();
+        let frames = [base(), with_square(&base(), (20, 4), [0, 255, 0, 90])];
+
+        let (summary, path) = write(&dir, "translucent.png", &frames, true);
+        assert_eq!(summary.delta_frames, 1);
+        // Blending over would mix the old pixels into the translucent ones
+        assert_eq!(stored_frames(&path)[1], (8, 8, 20, 4, png::BlendOp::Source));
+        assert!(decode(&path)[1].0 == frames[1]);
+    }
+
+    #[test]
+    fn deltas_are_never_larger_than_full_frames() {
+        let dir = TempDir::new();
+        // A scatter of translucent pixels whose bounding box is most of the canvas,
+        // and a frame unrelated to the one before
+        let mut scattered = base();
+        for i in 0..40 {
+            scattered.put_pixel(i * 7 % 60 + 2, i * 5 % 44 + 2, Rgba([255, 255, 255, 100]));
+        }
+        let frames = [base(), scattered, photo(64, 48).to_rgba8(), base()];
+
+        let (delta, delta_path) = write(&dir, "delta.png", &frames, true);
+        let (full, _) = write(&dir, "full.png", &frames, false);
+        assert!(delta.output_bytes <= full.output_bytes, "{} > {}", delta.output_bytes, full.output_bytes);
+        for (index, ((img, _), input)) in decode(&delta_path).iter().zip(&frames).enumerate() {
+            assert!(img == input, "frame {} differs from its input", index);
+        }
+    }
+
+    #[test]
+    fn without_delta_every_frame_is_stored_whole() {
+        let dir = TempDir::new();
+        let frames = [base(), base(), with_square(&base(), (0, 0), [0, 0, 0, 255])];
+
+        let (summary, path) = write(&dir, "whole.png", &frames, false);
+        assert_eq!((summary.frames, summary.delta_frames, summary.merged_frames), (3, 0, 0));
+        assert!(stored_frames(&path).iter().all(|&frame| frame == (64, 48, 0, 0, png::BlendOp::Source)));
+    }
+
+    #[test]
+    fn long_delays_fall_back_to_hundredths() {
+        assert_eq!(ApngEncoder::delay_fraction(250), (250, 1000));
+        assert_eq!(ApngEncoder::delay_fraction(70_000), (7000, 100));
+        assert_eq!(ApngEncoder::delay_fraction(u32::MAX), (u16::MAX, 100));
+    }
+}
@@ -0,0 +1,146 @@
+use crate::image_processor::{ImageProcessor, JpegEncodeOptions};
+use anyhow::{Context, Result};
+use image::{DynamicImage, GrayImage, ImageFormat};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Side of the square SSIM window, and the step between windows
+const WINDOW: u32 = 8;
+const STRIDE: u32 = 4;
+/// Stabilizing constants for 8-bit samples: (0.01 * 255)² and (0.03 * 255)²
+const C1: f64 = 6.5025;
+const C2: f64 = 58.5225;
+/// Probes the search may spend; enough to narrow 0-100 to a single step
+const MAX_ATTEMPTS: usize = 7;
+
+/// How close to the processed image an auto_quality output must stay
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Fidelity {
+    /// Visible softening on close inspection; smallest files
+    Low,
+    /// Hard to tell apart at normal viewing size
+    Medium,
+    /// Indistinguishable from the source to most viewers
+    High,
+}
+
+impl Fidelity {
+    /// Lowest luma SSIM against the processed image that counts as good enough
+    pub fn ssim_threshold(self) -> f64 {
+        match self {
+            Fidelity::Low => 0.95,
+            Fidelity::Medium => 0.98,
+            Fidelity::High => 0.99,
+        }
+    }
+}
+
+/// Picks the lowest encoder quality that still looks good, per image
+pub struct AutoQuality;
+
+impl AutoQuality {
+    /// Encode at the lowest quality whose output, decoded again, reaches the SSIM
+    /// threshold of `fidelity`. Bisects the quality range assuming SSIM rises with
+    /// quality; when even the top of the range misses the threshold, that attempt is
+    /// returned. Returns the data, the quality used and the SSIM it achieved.
+    pub fn encode(
+        img: &DynamicImage,
+        format: ImageFormat,
+        webp_method: u8,
+        jpeg: JpegEncodeOptions,
+        fidelity: Fidelity,
+    ) -> Result<(Vec<u8>, u8, f64)> {
+        // Same usable ranges as encode_to_size
+        let min_quality = match format {
+            ImageFormat::Jpeg => 10u8,
+            ImageFormat::WebP => 0u8,
+            _ => anyhow::bail!("Auto quality is only supported for JPEG and WebP output"),
+        };
+        let reference = img.to_luma8();
+        let threshold = fidelity.ssim_threshold();
+
+        let attempt = |quality: u8| -> Result<(Vec<u8>, u8, f64)> {
+            let data = match format {
+                ImageFormat::WebP => ImageProcessor::encode_webp(img, quality, webp_method)?,
+                _ => ImageProcessor::encode_jpeg(img, quality, jpeg)?,
+            };
+            let decoded = image::load_from_memory_with_format(&data, format)
+                .context("Failed to decode output for comparison")?;
+            let ssim = Self::ssim(&reference, &decoded.to_luma8());
+            Ok((data, quality, ssim))
+        };
+
+        let mut best = attempt(100)?;
+        if best.2 < threshold {
+            return Ok(best);
+        }
+        // `high` always reaches the threshold and `best` holds its attempt
+        let (mut low, mut high) = (min_quality, 100u8);
+        for _ in 0..MAX_ATTEMPTS {
+            if low >= high {
+                break;
+            }
+            let quality = low + (high - low) / 2;
+            let result = attempt(quality)?;
+            if result.2 >= threshold {
+                high = quality;
+                best = result;
+            } else {
+                low = quality + 1;
+            }
+        }
+
+        Ok(best)
+    }
+
+    /// Mean SSIM of two equally sized grayscale images over 8x8 windows spaced 4
+    /// pixels apart. Images smaller than a window are compared as a single window.
+    pub fn ssim(a: &GrayImage, b: &GrayImage) -> f64 {
+        let (width, height) = a.dimensions();
+        let (window_w, window_h) = (WINDOW.min(width), WINDOW.min(height));
+        if window_w == 0 || window_h == 0 || b.dimensions() != (width, height) {
+            return 0.0;
+        }
+
+        let ys: Vec<u32> = (0..=height - window_h).step_by(STRIDE as usize).collect();
+        let (sum, count) = ys
+            .par_iter()
+            .map(|&y| {
+                let mut sum = 0.0;
+                let mut count = 0u64;
+                for x in (0..=width - window_w).step_by(STRIDE as usize) {
+                    sum += Self::window_ssim(a, b, x, y, window_w, window_h);
+                    count += 1;
+                }
+                (sum, count)
+            })
+            .reduce(|| (0.0, 0), |l, r| (l.0 + r.0, l.1 + r.1));
+
+        sum / count.max(1) as f64
+    }
+
+    fn window_ssim(a: &GrayImage, b: &GrayImage, x: u32, y: u32, width: u32, height: u32) -> f64 {
+        let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for dy in 0..height {
+            for dx in 0..width {
+                let va = a.get_pixel(x + dx, y + dy).0[0] as f64;
+                let vb = b.get_pixel(x + dx, y + dy).0[0] as f64;
+                sum_a += va;
+                sum_b += vb;
+                sum_aa += va * va;
+                sum_bb += vb * vb;
+                sum_ab += va * vb;
+            }
+        }
+
+        let n = (width * height) as f64;
+        let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+        let var_a = sum_aa / n - mean_a * mean_a;
+        let var_b = sum_bb / n - mean_b * mean_b;
+        let covariance = sum_ab / n - mean_a * mean_b;
+
+        ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+            / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2))
+    }
+}
@@ -0,0 +1,453 @@
+use anyhow::{Context, Result};
+use image::{DynamicImage, RgbImage, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+/// Largest image the fallback parser will allocate for
+const MAX_PIXELS: u64 = 1 << 28;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_RLE4: u32 = 2;
+const BI_BITFIELDS: u32 = 3;
+const BI_ALPHABITFIELDS: u32 = 6;
+
+/// Pixel storage of a BMP file
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BmpCompression {
+    Rgb,
+    Rle8,
+    Rle4,
+    Bitfields,
+}
+
+/// BMP sub-format as reported by analyze_image
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BmpInfo {
+    /// "core" (OS/2 12-byte), "info", "v2", "v3", "v4" or "v5"
+    pub header: String,
+    pub bits_per_pixel: u16,
+    pub compression: BmpCompression,
+    /// Rows stored first to last (negative height) rather than bottom-up
+    pub top_down: bool,
+    /// Channel layout of 16-bit data, e.g. "565" or "555"
+    pub channel_layout: Option<String>,
+}
+
+/// Parsed BITMAPFILEHEADER and DIB header
+struct Header {
+    width: u32,
+    height: u32,
+    top_down: bool,
+    header_size: u32,
+    bits_per_pixel: u16,
+    compression: u32,
+    /// Red, green, blue and alpha masks for 16/32-bit data
+    masks: [u32; 4],
+    palette: Vec<[u8; 3]>,
+    data_offset: usize,
+}
+
+pub struct BmpProcessor;
+
+impl BmpProcessor {
+    /// Describe a BMP file's header
+    pub fn info(path: &str) -> Result<BmpInfo> {
+        let data = std::fs::read(path).context("Failed to read BMP file")?;
+        let header = Header::parse(&data)?;
+
+        let compression = match header.compression {
+            BI_RLE8 => BmpCompression::Rle8,
+            BI_RLE4 => BmpCompression::Rle4,
+            BI_BITFIELDS | BI_ALPHABITFIELDS => BmpCompression::Bitfields,
+            _ => BmpCompression::Rgb,
+        };
+        let channel_layout = (header.bits_per_pixel == 16).then(|| {
+            let bits: Vec<String> = header.masks.iter()
+                .filter(|mask| **mask != 0)
+                .map(|mask| mask.count_ones().to_string())
+                .collect();
+            bits.concat()
+        });
+
+        Ok(BmpInfo {
+            header: match header.header_size {
+                12 => "core",
+                40 => "info",
+                52 => "v2",
+                56 => "v3",
+                108 => "v4",
+                124 => "v5",
+                _ => "unknown",
+            }
+            .to_string(),
+            bits_per_pixel: header.bits_per_pixel,
+            compression,
+            top_down: header.top_down,
+            channel_layout,
+        })
+    }
+
+    /// Read width and height from the header
+    pub fn dimensions(path: &str) -> Result<(u32, u32)> {
+        let data = std::fs::read(path).context("Failed to read BMP file")?;
+        let header = Header::parse(&data)?;
+        Ok((header.width, header.height))
+    }
+
+    /// Whether a file uses a layout the image crate mishandles: RLE data,
+    /// 16-bit pixels, or top-down rows. Legacy writers produce these in
+    /// combinations the spec doesn't allow (e.g. top-down RLE), which the
+    /// image crate either rejects or decodes upside down.
+    pub fn needs_fallback(path: &str) -> Result<bool> {
+        let data = std::fs::read(path).context("Failed to read BMP file")?;
+        let header = Header::parse(&data)?;
+        Ok(header.top_down
+            || header.bits_per_pixel == 16
+            || matches!(header.compression, BI_RLE8 | BI_RLE4))
+    }
+
+    /// Decode a BMP with the built-in parser
+    pub fn load(path: &str) -> Result<DynamicImage> {
+        let data = std::fs::read(path).context("Failed to read BMP file")?;
+        Self::decode(&data)
+    }
+
+    pub fn decode(data: &[u8]) -> Result<DynamicImage> {
+        let header = Header::parse(data)?;
+        let pixels = data.get(header.data_offset..).context("BMP pixel data offset is past the end of the file")?;
+
+        match (header.compression, header.bits_per_pixel) {
+            (BI_RLE8, 8) | (BI_RLE4, 4) => {
+                let indices = decode_rle(pixels, &header);
+                Ok(DynamicImage::ImageRgb8(header.expand_palette(&indices)))
+            }
+            (BI_RGB, 1 | 4 | 8) => {
+                let indices = unpack_indices(pixels, &header)?;
+                Ok(DynamicImage::ImageRgb8(header.expand_palette(&indices)))
+            }
+            (BI_RGB | BI_BITFIELDS | BI_ALPHABITFIELDS, 16 | 32) | (BI_RGB, 24) => decode_direct(pixels, &header),
+            (compression, bits) => {
+                anyhow::bail!("Unsupported BMP variant (compression {}, {} bits per pixel)", compression, bits)
+            }
+        }
+    }
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Header> {
+        if data.get(0..2) != Some(b"BM".as_slice()) {
+            anyhow::bail!("Not a BMP file");
+        }
+        let u16_at = |pos: usize| data.get(pos..pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let u32_at = |pos: usize| data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+        let truncated = || anyhow::anyhow!("BMP header is truncated");
+
+        let data_offset = u32_at(10).ok_or_else(truncated)? as usize;
+        let header_size = u32_at(14).ok_or_else(truncated)?;
+
+        let (width, height, top_down, bits_per_pixel, compression, colors_used, entry_size) = if header_size == 12 {
+            let width = u16_at(18).ok_or_else(truncated)? as u32;
+            let height = u16_at(20).ok_or_else(truncated)? as u32;
+            (width, height, false, u16_at(24).ok_or_else(truncated)?, BI_RGB, 0, 3)
+        } else if header_size >= 40 {
+            let width = u32_at(18).ok_or_else(truncated)? as i32;
+            let height = u32_at(22).ok_or_else(truncated)? as i32;
+            if width <= 0 || height == 0 || height == i32::MIN {
+                anyhow::bail!("BMP has invalid dimensions {}x{}", width, height);
+            }
+            (
+                width as u32,
+                height.unsigned_abs(),
+                height < 0,
+                u16_at(28).ok_or_else(truncated)?,
+                u32_at(30).ok_or_else(truncated)?,
+                u32_at(46).ok_or_else(truncated)?,
+                4,
+            )
+        } else {
+            anyhow::bail!("Unsupported BMP header size {}", header_size);
+        };
+
+        if width as u64 * height as u64 > MAX_PIXELS {
+            anyhow::bail!("BMP is too large ({}x{})", width, height);
+        }
+
+        // Masks live inside V2+ headers, or directly after a 40-byte header
+        let mut palette_start = 14 + header_size as usize;
+        let masks = match compression {
+            BI_BITFIELDS | BI_ALPHABITFIELDS => {
+                let (start, count) = if header_size >= 52 {
+                    (54, if header_size >= 56 { 4 } else { 3 })
+                } else {
+                    let count = if compression == BI_ALPHABITFIELDS { 4 } else { 3 };
+                    palette_start += count * 4;
+                    (palette_start - count * 4, count)
+                };
+                let mut masks = [0u32; 4];
+                for (i, mask) in masks.iter_mut().enumerate().take(count) {
+                    *mask = u32_at(start + i * 4).ok_or_else(truncated)?;
+                }
+                masks
+            }
+            _ if bits_per_pixel == 16 => [0x7c00, 0x03e0, 0x001f, 0],
+            _ => [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0],
+        };
+
+        let palette = if bits_per_pixel <= 8 {
+            let max = 1usize << bits_per_pixel;
+            let count = match colors_used as usize {
+                0 => max,
+                n => n.min(max),
+            };
+            (0..count)
+                .map_while(|i| data.get(palette_start + i * entry_size..palette_start + i * entry_size + 3))
+                .map(|bgr| [bgr[2], bgr[1], bgr[0]])
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        Ok(Header {
+            width,
+            height,
+            top_down,
+            header_size,
+            bits_per_pixel,
+            compression,
+            masks,
+            palette,
+            data_offset,
+        })
+    }
+
+    /// Image row for the `row`th row stored in the file
+    fn image_row(&self, row: u32) -> u32 {
+        if self.top_down { row } else { self.height - 1 - row }
+    }
+
+    /// Stored row length, padded to 4 bytes
+    fn stride(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel as usize).div_ceil(32) * 4
+    }
+
+    /// Expand palette indices (in image row order) to RGB. Out-of-range indices are black.
+    fn expand_palette(&self, indices: &[u8]) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let index = indices[(y * self.width + x) as usize] as usize;
+            image::Rgb(self.palette.get(index).copied().unwrap_or([0, 0, 0]))
+        })
+    }
+}
+
+/// Unpack 1/4/8-bit rows into one index per pixel
+fn unpack_indices(pixels: &[u8], header: &Header) -> Result<Vec<u8>> {
+    let (width, bits) = (header.width as usize, header.bits_per_pixel as usize);
+    let stride = header.stride();
+    let mut indices = vec![0u8; width * header.height as usize];
+
+    for row in 0..header.height {
+        let line = pixels.get(row as usize * stride..(row as usize + 1) * stride)
+            .context("BMP pixel data is truncated")?;
+        let y = header.image_row(row) as usize;
+        for x in 0..width {
+            let bit = x * bits;
+            let byte = line[bit / 8];
+            let shift = 8 - bits - bit % 8;
+            indices[y * width + x] = (byte >> shift) & ((1u16 << bits) - 1) as u8;
+        }
+    }
+
+    Ok(indices)
+}
+
+/// Decode RLE8/RLE4 data into one index per pixel. Pixels skipped by delta or
+/// end-of-line codes keep index 0. Truncated data ends the image early.
+fn decode_rle(pixels: &[u8], header: &Header) -> Vec<u8> {
+    let (width, height) = (header.width as usize, header.height as usize);
+    let rle4 = header.compression == BI_RLE4;
+    let mut indices = vec![0u8; width * height];
+    let (mut x, mut row) = (0usize, 0usize);
+
+    let mut put = |x: usize, row: usize, value: u8| {
+        if x < width && row < height {
+            let y = header.image_row(row as u32) as usize;
+            indices[y * width + x] = value;
+        }
+    };
+
+    let mut pos = 0;
+    while pos + 1 < pixels.len() && row < height {
+        let (count, value) = (pixels[pos] as usize, pixels[pos + 1]);
+        pos += 2;
+
+        if count > 0 {
+            // Encoded run; RLE4 alternates the two nibbles
+            for i in 0..count {
+                put(x, row, if rle4 { nibble(value, i) } else { value });
+                x += 1;
+            }
+            continue;
+        }
+
+        match value {
+            0 => {
+                x = 0;
+                row += 1;
+            }
+            1 => break,
+            2 => {
+                let (Some(&dx), Some(&dy)) = (pixels.get(pos), pixels.get(pos + 1)) else {
+                    break;
+                };
+                pos += 2;
+                x += dx as usize;
+                row += dy as usize;
+            }
+            literal => {
+                // Absolute run, padded to a 16-bit boundary
+                let literal = literal as usize;
+                let bytes = if rle4 { literal.div_ceil(2) } else { literal };
+                let Some(run) = pixels.get(pos..pos + bytes) else {
+                    break;
+                };
+                for i in 0..literal {
+                    put(x, row, if rle4 { nibble(run[i / 2], i) } else { run[i] });
+                    x += 1;
+                }
+                pos += bytes + (bytes & 1);
+            }
+        }
+    }
+
+    indices
+}
+
+/// High nibble for even pixel positions, low nibble for odd ones
+fn nibble(byte: u8, i: usize) -> u8 {
+    if i.is_multiple_of(2) { byte >> 4 } else { byte & 0x0f }
+}
+
+/// Decode 16/24/32-bit pixels through the channel masks
+fn decode_direct(pixels: &[u8], header: &Header) -> Result<DynamicImage> {
+    let (width, bytes) = (header.width as usize, header.bits_per_pixel as usize / 8);
+    let stride = header.stride();
+    // 24-bit data has no masks, whatever the header says
+    let masks = if bytes == 3 { [0x00ff_0000, 0x0000_ff00, 0x0000_00ff, 0] } else { header.masks };
+    let has_alpha = masks[3] != 0;
+    let mut rgba = RgbaImage::new(header.width, header.height);
+    let mut any_alpha = false;
+
+    for row in 0..header.height {
+        let line = pixels.get(row as usize * stride..(row as usize + 1) * stride)
+            .context("BMP pixel data is truncated")?;
+        let y = header.image_row(row);
+        for x in 0..width {
+            let px = &line[x * bytes..(x + 1) * bytes];
+            let value = match bytes {
+                2 => u16::from_le_bytes([px[0], px[1]]) as u32,
+                3 => u32::from_le_bytes([px[0], px[1], px[2], 0]),
+                _ => u32::from_le_bytes([px[0], px[1], px[2], px[3]]),
+            };
+            let alpha = if has_alpha { channel(value, masks[3]) } else { 255 };
+            any_alpha |= alpha != 0;
+            rgba.put_pixel(x as u32, y, image::Rgba([
+                channel(value, masks[0]),
+                channel(value, masks[1]),
+                channel(value, masks[2]),
+                alpha,
+            ]));
+        }
+    }
+
+    // Writers that declare an alpha mask but leave it all zero mean opaque
+    if has_alpha && any_alpha {
+        Ok(DynamicImage::ImageRgba8(rgba))
+    } else {
+        Ok(DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8()))
+    }
+}
+
+/// Extract a masked channel and scale it to 8 bits
+fn channel(value: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    let bits = mask.count_ones().min(32);
+    let max = if bits >= 32 { u32::MAX as u64 } else { (1u64 << bits) - 1 };
+    let raw = ((value & mask) >> mask.trailing_zeros()) as u64;
+    ((raw * 255 + max / 2) / max) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processor::ImageProcessor;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/bmp");
+
+    /// A fixture with its expected header description: header, bits per pixel,
+    /// compression, top-down, 16-bit channel layout
+    type Fixture = (&'static str, &'static str, u16, BmpCompression, bool, Option<&'static str>);
+
+    const CORPUS: [Fixture; 8] = [
+        ("rle8", "info", 8, BmpCompression::Rle8, false, None),
+        ("rle8_top_down", "info", 8, BmpCompression::Rle8, true, None),
+        ("rle4", "info", 4, BmpCompression::Rle4, false, None),
+        ("rgb565", "info", 16, BmpCompression::Bitfields, false, Some("565")),
+        ("rgb555", "info", 16, BmpCompression::Rgb, false, Some("555")),
+        ("rgb444_v4_top_down", "v4", 16, BmpCompression::Bitfields, true, Some("444")),
+        ("rgb24_top_down", "info", 24, BmpCompression::Rgb, true, None),
+        ("os2_core_8", "core", 8, BmpCompression::Rgb, false, None),
+    ];
+
+    fn fixture(name: &str, extension: &str) -> String {
+        format!("{}/{}.{}", FIXTURES, name, extension)
+    }
+
+    #[test]
+    fn fixtures_decode_to_their_reference_pngs() {
+        for (name, ..) in CORPUS {
+            let reference = image::open(fixture(name, "png")).unwrap().to_rgb8();
+            let path = fixture(name, "bmp");
+
+            let decoded = BmpProcessor::load(&path).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(decoded.to_rgb8(), reference, "{} through the built-in parser", name);
+            // The input path picks the built-in parser or the image crate per file
+            let loaded = ImageProcessor::load_image(&path).unwrap_or_else(|e| panic!("{}: {}", name, e));
+            assert_eq!(loaded.to_rgb8(), reference, "{} through load_image", name);
+        }
+    }
+
+    #[test]
+    fn fixtures_report_their_sub_format() {
+        for (name, header, bits_per_pixel, compression, top_down, channel_layout) in CORPUS {
+            let info = BmpProcessor::info(&fixture(name, "bmp")).unwrap();
+            assert_eq!(info.header, header, "{}", name);
+            assert_eq!(info.bits_per_pixel, bits_per_pixel, "{}", name);
+            assert_eq!(info.compression, compression, "{}", name);
+            assert_eq!(info.top_down, top_down, "{}", name);
+            assert_eq!(info.channel_layout.as_deref(), channel_layout, "{}", name);
+        }
+    }
+
+    #[test]
+    fn legacy_layouts_take_the_built_in_parser() {
+        for (name, _, bits_per_pixel, compression, top_down, _) in CORPUS {
+            let expected = top_down || bits_per_pixel == 16 || matches!(compression, BmpCompression::Rle8 | BmpCompression::Rle4);
+            assert_eq!(BmpProcessor::needs_fallback(&fixture(name, "bmp")).unwrap(), expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn truncated_rle_ends_the_image_early() {
+        let data = std::fs::read(fixture("rle8", "bmp")).unwrap();
+        let header = Header::parse(&data).unwrap();
+        // Cut after the first (bottom) row's codes
+        let img = BmpProcessor::decode(&data[..header.data_offset + 8]).unwrap().to_rgb8();
+        let reference = image::open(fixture("rle8", "png")).unwrap().to_rgb8();
+
+        assert_eq!(img.get_pixel(0, 4), reference.get_pixel(0, 4));
+        assert_eq!(img.get_pixel(0, 0).0, [0, 255, 0]);
+    }
+}
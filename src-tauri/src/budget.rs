@@ -0,0 +1,129 @@
+use rayon::prelude::*;
+use std::sync::{Condvar, Mutex};
+
+/// Limits how many bytes of decoded image data may be in flight at once.
+/// Items are admitted in `map_admitted` until enough of the budget is free.
+pub struct MemoryBudget {
+    limit: u64,
+    used: Mutex<u64>,
+    released: Condvar,
+}
+
+/// Share of the budget held by one in-flight item, returned on drop
+pub struct MemoryPermit<'a> {
+    budget: &'a MemoryBudget,
+    bytes: u64,
+}
+
+impl MemoryBudget {
+    pub fn new(limit: u64) -> Self {
+        MemoryBudget {
+            limit: limit.max(1),
+            used: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Reserve `bytes` of the budget. Requests larger than the whole budget are
+    /// clamped to it, so such an item simply runs alone.
+    fn acquire(&self, bytes: u64) -> MemoryPermit<'_> {
+        let bytes = bytes.clamp(1, self.limit);
+
+        let mut used = self.used.lock().unwrap_or_else(|e| e.into_inner());
+        while *used + bytes > self.limit {
+            used = self.released.wait(used).unwrap_or_else(|e| e.into_inner());
+        }
+        *used += bytes;
+
+        MemoryPermit { budget: self, bytes }
+    }
+
+    /// Run `work` for every item on the rayon pool, returning the results in input
+    /// order. With a budget, an item only starts once `bytes(item)` of it is free.
+    ///
+    /// Items wait for admission on the calling thread, never on a pool worker. A
+    /// worker holding a permit can steal another item while it waits in a join; had
+    /// that item blocked for budget, the worker would wait on itself. Must be called
+    /// from outside the pool for the same reason.
+    pub fn map_admitted<T: Sync, R: Send>(
+        budget: Option<&Self>,
+        items: &[T],
+        bytes: impl Fn(&T) -> u64,
+        work: impl Fn(&T) -> R + Sync,
+    ) -> Vec<R> {
+        let Some(budget) = budget else {
+            return items.par_iter().map(&work).collect();
+        };
+        debug_assert!(rayon::current_thread_index().is_none(), "map_admitted called from a rayon worker");
+
+        let results: Vec<Mutex<Option<R>>> = items.iter().map(|_| Mutex::new(None)).collect();
+        let work = &work;
+        rayon::in_place_scope(|scope| {
+            for (item, slot) in items.iter().zip(&results) {
+                let permit = budget.acquire(bytes(item));
+                scope.spawn(move |_| {
+                    let result = work(item);
+                    drop(permit);
+                    *slot.lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+                });
+            }
+        });
+        results.into_iter()
+            .map(|slot| slot.into_inner().unwrap_or_else(|e| e.into_inner()).expect("every admitted item ran"))
+            .collect()
+    }
+}
+
+impl Drop for MemoryPermit<'_> {
+    fn drop(&mut self) {
+        let mut used = self.budget.used.lock().unwrap_or_else(|e| e.into_inner());
+        *used -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Duration;
+
+    #[test]
+    fn mixed_sizes_under_a_small_budget_finish_within_it() {
+        const LIMIT: u64 = 100;
+        // Includes items larger than the whole budget, which are clamped to it
+        let sizes: Vec<u64> = (0..64).map(|i| [5, 300, 40, 70, 10, 100, 1, 55][i % 8]).collect();
+        let expected: Vec<u64> = sizes.iter().map(|size| size + 499_500).collect();
+
+        // On its own thread so a deadlock fails the test instead of hanging it
+        let (done, finished) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let budget = MemoryBudget::new(LIMIT);
+            let (in_flight, peak) = (AtomicU64::new(0), AtomicU64::new(0));
+            let results = MemoryBudget::map_admitted(Some(&budget), &sizes, |&size| size, |&size| {
+                let held = size.clamp(1, LIMIT);
+                peak.fetch_max(in_flight.fetch_add(held, Ordering::SeqCst) + held, Ordering::SeqCst);
+                // Conversions fork for checksums and inside image operations; each
+                // join is a point where this worker may steal another item
+                let (sum, _) = rayon::join(
+                    || (0..1000u64).into_par_iter().sum::<u64>(),
+                    || std::thread::sleep(Duration::from_millis(1)),
+                );
+                in_flight.fetch_sub(held, Ordering::SeqCst);
+                size + sum
+            });
+            done.send((results, peak.into_inner())).ok();
+        });
+
+        let (results, peak) = finished.recv_timeout(Duration::from_secs(60)).expect("batch deadlocked");
+        assert_eq!(results, expected);
+        assert!(peak <= LIMIT, "{} bytes in flight", peak);
+    }
+
+    #[test]
+    fn without_a_budget_every_item_runs() {
+        let items: Vec<u32> = (0..100).collect();
+        let results = MemoryBudget::map_admitted(None, &items, |_| u64::MAX, |&item| item * 2);
+        assert_eq!(results, items.iter().map(|item| item * 2).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,231 @@
+use image::GrayImage;
+
+/// Terminating codes for white runs of 0-63 pixels (ITU-T T.4, table 2)
+const WHITE_TERMINATING: [&str; 64] = [
+    "00110101", "000111", "0111", "1000", "1011", "1100", "1110", "1111",
+    "10011", "10100", "00111", "01000", "001000", "000011", "110100", "110101",
+    "101010", "101011", "0100111", "0001100", "0001000", "0010111", "0000011", "0000100",
+    "0101000", "0101011", "0010011", "0100100", "0011000", "00000010", "00000011", "00011010",
+    "00011011", "00010010", "00010011", "00010100", "00010101", "00010110", "00010111", "00101000",
+    "00101001", "00101010", "00101011", "00101100", "00101101", "00000100", "00000101", "00001010",
+    "00001011", "01010010", "01010011", "01010100", "01010101", "00100100", "00100101", "01011000",
+    "01011001", "01011010", "01011011", "01001010", "01001011", "00110010", "00110011", "00110100",
+];
+
+/// Terminating codes for black runs of 0-63 pixels (ITU-T T.4, table 2)
+const BLACK_TERMINATING: [&str; 64] = [
+    "0000110111", "010", "11", "10", "011", "0011", "0010", "00011",
+    "000101", "000100", "0000100", "0000101", "0000111", "00000100", "00000111", "000011000",
+    "0000010111", "0000011000", "0000001000", "00001100111", "00001101000", "00001101100", "00000110111", "00000101000",
+    "00000010111", "00000011000", "000011001010", "000011001011", "000011001100", "000011001101", "000001101000", "000001101001",
+    "000001101010", "000001101011", "000011010010", "000011010011", "000011010100", "000011010101", "000011010110", "000011010111",
+    "000001101100", "000001101101", "000011011010", "000011011011", "000001010100", "000001010101", "000001010110", "000001010111",
+    "000001100100", "000001100101", "000001010010", "000001010011", "000000100100", "000000110111", "000000111000", "000000100111",
+    "000000101000", "000001011000", "000001011001", "000000101011", "000000101100", "000001011010", "000001100110", "000001100111",
+];
+
+/// Make-up codes for white runs of 64-1728 pixels in steps of 64 (T.4, table 3)
+const WHITE_MAKEUP: [&str; 27] = [
+    "11011", "10010", "010111", "0110111", "00110110", "00110111", "01100100", "01100101",
+    "01101000", "01100111", "011001100", "011001101", "011010010", "011010011", "011010100", "011010101",
+    "011010110", "011010111", "011011000", "011011001", "011011010", "011011011", "010011000", "010011001",
+    "010011010", "011000", "010011011",
+];
+
+/// Make-up codes for black runs of 64-1728 pixels in steps of 64 (T.4, table 3)
+const BLACK_MAKEUP: [&str; 27] = [
+    "0000001111", "000011001000", "000011001001", "000001011011", "000000110011", "000000110100", "000000110101", "0000001101100",
+    "0000001101101", "0000001001010", "0000001001011", "0000001001100", "0000001001101", "0000001110010", "0000001110011", "0000001110100",
+    "0000001110101", "0000001110110", "0000001110111", "0000001010010", "0000001010011", "0000001010100", "0000001010101", "0000001011010",
+    "0000001011011", "0000001100100", "0000001100101",
+];
+
+/// Make-up codes shared by both colors for runs of 1792-2560 pixels (T.4, table 3)
+const EXTENDED_MAKEUP: [&str; 13] = [
+    "00000001000", "00000001100", "00000001101", "000000010010", "000000010011", "000000010100", "000000010101",
+    "000000010110", "000000010111", "000000011100", "000000011101", "000000011110", "000000011111",
+];
+
+/// Vertical mode codes by b1 - a1 + 3, i.e. VR3 down to VL3
+const VERTICAL: [&str; 7] = ["0000011", "000011", "011", "1", "010", "000010", "0000010"];
+const PASS: &str = "0001";
+const HORIZONTAL: &str = "001";
+const EOL: &str = "000000000001";
+
+/// MSB-first bit packing, as FillOrder 1 expects
+#[derive(Default)]
+struct BitWriter {
+    data: Vec<u8>,
+    current: u8,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn put(&mut self, code: &str) {
+        for bit in code.bytes() {
+            self.current = (self.current << 1) | (bit == b'1') as u8;
+            self.bits += 1;
+            if self.bits == 8 {
+                self.data.push(self.current);
+                (self.current, self.bits) = (0, 0);
+            }
+        }
+    }
+
+    /// A run of `length` pixels: 2560-pixel make-ups while they're needed, one
+    /// make-up for the remaining multiple of 64, then the terminating code
+    fn run(&mut self, mut length: usize, black: bool) {
+        let (terminating, makeup) = if black {
+            (&BLACK_TERMINATING, &BLACK_MAKEUP)
+        } else {
+            (&WHITE_TERMINATING, &WHITE_MAKEUP)
+        };
+        while length >= 2560 + 64 {
+            self.put(EXTENDED_MAKEUP[EXTENDED_MAKEUP.len() - 1]);
+            length -= 2560;
+        }
+        if length >= 64 {
+            let index = length / 64 - 1;
+            self.put(makeup.get(index).unwrap_or_else(|| &EXTENDED_MAKEUP[index - makeup.len()]));
+            length %= 64;
+        }
+        self.put(terminating[length]);
+    }
+
+    /// Pad the last byte with zeros
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.data.push(self.current << (8 - self.bits));
+        }
+        self.data
+    }
+}
+
+/// CCITT Group 4 (ITU-T T.6) coding of bilevel images, as TIFF compression 4
+pub struct CcittG4;
+
+impl CcittG4 {
+    /// Encode `img` as a single strip, with pixels below 128 black. Each row is coded
+    /// against the one above it (all white for the first row); the strip ends with an
+    /// EOFB and is padded to a whole byte. Decoders expect PhotometricInterpretation
+    /// WhiteIsZero.
+    pub fn encode(img: &GrayImage) -> Vec<u8> {
+        let width = img.width() as usize;
+        let mut writer = BitWriter::default();
+        let mut reference = vec![false; width];
+        let mut line = vec![false; width];
+
+        for row in img.rows() {
+            for (pixel, black) in row.zip(line.iter_mut()) {
+                *black = pixel.0[0] < 128;
+            }
+            Self::encode_line(&mut writer, &line, &reference);
+            std::mem::swap(&mut line, &mut reference);
+        }
+
+        writer.put(EOL);
+        writer.put(EOL);
+        writer.finish()
+    }
+
+    /// One coding line in the two-dimensional mode. Positions are pixel indices,
+    /// with `width` standing for the changing element past the end of a line.
+    fn encode_line(writer: &mut BitWriter, line: &[bool], reference: &[bool]) {
+        let width = line.len();
+        // Coding starts at an imaginary white pixel before the line
+        let mut a0 = 0;
+        let mut a1 = if line.first() == Some(&true) { 0 } else { Self::next_change(line, 0, false) };
+        let mut b1 = if reference.first() == Some(&true) { 0 } else { Self::next_change(reference, 0, false) };
+
+        loop {
+            let b2 = if b1 < width { Self::next_change(reference, b1, reference[b1]) } else { width };
+            if b2 < a1 {
+                writer.put(PASS);
+                a0 = b2;
+            } else if b1.abs_diff(a1) <= 3 {
+                writer.put(VERTICAL[(b1 as isize - a1 as isize + 3) as usize]);
+                a0 = a1;
+            } else {
+                let a2 = if a1 < width { Self::next_change(line, a1, line[a1]) } else { width };
+                // Only the very first run of a line can be an empty white one
+                let black = a0 + a1 != 0 && line[a0];
+                writer.put(HORIZONTAL);
+                writer.run(a1 - a0, black);
+                writer.run(a2 - a1, !black);
+                a0 = a2;
+            }
+
+            if a0 >= width {
+                break;
+            }
+            let color = line[a0];
+            a1 = Self::next_change(line, a0, color);
+            b1 = Self::next_change(reference, a0, !color);
+            b1 = Self::next_change(reference, b1, color);
+        }
+    }
+
+    /// First position at or after `start` whose color isn't `color`, or the line end
+    fn next_change(line: &[bool], start: usize, color: bool) -> usize {
+        line.get(start..)
+            .and_then(|rest| rest.iter().position(|&pixel| pixel != color))
+            .map_or(line.len(), |offset| start + offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::text_page;
+    use image::Luma;
+
+    /// Decode with an independent G4 decoder, black pixels as 0
+    fn decode(data: &[u8], width: u32) -> GrayImage {
+        let mut pixels = Vec::new();
+        let mut rows = 0;
+        fax::decoder::decode_g4(data.iter().copied(), width as u16, None, |transitions| {
+            pixels.extend(fax::decoder::pels(transitions, width as u16).map(|color| match color {
+                fax::Color::Black => 0u8,
+                fax::Color::White => 255,
+            }));
+            rows += 1;
+        })
+        .expect("valid G4 data");
+        GrayImage::from_raw(width, rows, pixels).unwrap()
+    }
+
+    #[test]
+    fn white_row_is_a_single_vertical_code() {
+        // V0 against the imaginary all-white reference, then EOFB
+        let img = GrayImage::from_pixel(8, 1, Luma([255]));
+        assert_eq!(CcittG4::encode(&img), [0x80, 0x08, 0x00, 0x80]);
+    }
+
+    #[test]
+    fn text_page_round_trips() {
+        let page = text_page(600, 400);
+        assert_eq!(decode(&CcittG4::encode(&page), 600), page);
+    }
+
+    #[test]
+    fn long_runs_and_black_line_starts_round_trip() {
+        // Runs past the 2560-pixel make-ups, rows starting black, and rows that
+        // differ from the one above by more than the vertical modes cover
+        let img = GrayImage::from_fn(6000, 6, |x, y| match y {
+            0 => Luma([255]),
+            1 => Luma([if x < 5000 { 0 } else { 255 }]),
+            2 => Luma([if x % 7 == 0 || x > 5990 { 0 } else { 255 }]),
+            3 => Luma([0]),
+            4 => Luma([if (100..2800).contains(&x) { 255 } else { 0 }]),
+            _ => Luma([if x == 0 || x == 5999 { 0 } else { 255 }]),
+        });
+        assert_eq!(decode(&CcittG4::encode(&img), 6000), img);
+    }
+
+    #[test]
+    fn gray_splits_at_128() {
+        let img = GrayImage::from_fn(4, 1, |x, _| Luma([[0, 127, 128, 255][x as usize]]));
+        let decoded = decode(&CcittG4::encode(&img), 4);
+        assert_eq!(decoded.as_raw(), &[0, 0, 255, 255]);
+    }
+}
@@ -0,0 +1,138 @@
+use crate::errors::{AppError, ErrorCode};
+use crate::image_processor::ImageProcessor;
+use crate::{
+    encode_output, load_options, output_orientation, process_image, resolve_target_format, validate_settings,
+    write_metadata, ConversionSettings, Encoded,
+};
+use serde_json::{json, Value};
+use std::io::{IsTerminal, Read, Write};
+
+const USAGE: &str = "\
+Usage: image-converter --to FORMAT [OPTIONS] < INPUT > OUTPUT
+
+Reads an image from stdin, converts it and writes the result to stdout.
+
+Options:
+  --to FORMAT         Output format (jpeg, png, webp, ...)
+  --quality N         Encoder quality, 1-100 (default 85)
+  --max-width N       Scale down to at most N pixels wide
+  --max-height N      Scale down to at most N pixels high
+  --settings JSON     Any conversion settings, as the app's JSON object
+  --help              Show this message
+";
+
+/// Convert an image piped in on stdin and write it to stdout, e.g.
+/// `cat in.heic | image-converter --to jpeg > out.jpg`. Returns the process exit code.
+///
+/// Runs the same pipeline as a conversion in the app, entirely in memory. Settings
+/// that need a source or output file (metadata copied from the source, sidecars,
+/// keep_smaller, verification) have nothing to work on and are ignored.
+pub fn run(args: &[String]) -> i32 {
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        print!("{}", USAGE);
+        return 0;
+    }
+
+    match convert(args) {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("error [{}]: {}", e.code.as_str(), e.message);
+            if e.code == ErrorCode::InvalidSettings {
+                eprint!("\n{}", USAGE);
+            }
+            1
+        }
+    }
+}
+
+fn convert(args: &[String]) -> Result<(), AppError> {
+    let mut settings = parse_args(args)?;
+    let format = resolve_target_format(&mut settings, None)?;
+    validate_settings(&settings)?;
+
+    let stdin = std::io::stdin();
+    if stdin.is_terminal() {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "Pipe an image into stdin")
+            .with_param("setting", "stdin"));
+    }
+    let mut data = Vec::new();
+    stdin.lock().read_to_end(&mut data)?;
+    if data.is_empty() {
+        return Err(AppError::new(ErrorCode::DecodeCorrupt, "No image data on stdin"));
+    }
+
+    let (img, _) = ImageProcessor::load_from_memory(&data, &load_options(&settings))
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt))?;
+    drop(data);
+
+    let processed = process_image(&img, None, format, &settings)?;
+    let Encoded { data, .. } = encode_output(&processed.image, format, &settings, output_orientation(None, &settings)?)?;
+    let data = write_metadata(data, None, format, &settings)?;
+
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(&data)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+/// Settings from the command line: the --settings object, overridden by the
+/// individual flags. Quality and metadata default as they would for a pasted image.
+fn parse_args(args: &[String]) -> Result<ConversionSettings, AppError> {
+    let mut object = serde_json::Map::new();
+    object.insert("quality".into(), json!(85));
+    object.insert("preserve_metadata".into(), json!(false));
+    let mut flags = serde_json::Map::new();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |setting: &'static str| {
+            args.next().ok_or_else(|| {
+                AppError::new(ErrorCode::InvalidSettings, format!("{} needs a value", arg))
+                    .with_param("setting", setting)
+            })
+        };
+        match arg.as_str() {
+            "--to" => {
+                flags.insert("target_format".into(), json!(value("target_format")?.to_lowercase()));
+            }
+            "--quality" => {
+                flags.insert("quality".into(), json!(parse_number(value("quality")?, "quality")?));
+            }
+            "--max-width" => {
+                flags.insert("max_width".into(), json!(parse_number(value("max_width")?, "max_width")?));
+            }
+            "--max-height" => {
+                flags.insert("max_height".into(), json!(parse_number(value("max_height")?, "max_height")?));
+            }
+            "--settings" => {
+                let text = value("settings")?;
+                let Value::Object(given) = serde_json::from_str(text).map_err(|e| {
+                    AppError::new(ErrorCode::InvalidSettings, format!("--settings is not valid JSON: {}", e))
+                        .with_param("setting", "settings")
+                })?
+                else {
+                    return Err(AppError::new(ErrorCode::InvalidSettings, "--settings must be a JSON object")
+                        .with_param("setting", "settings"));
+                };
+                object.extend(given);
+            }
+            _ => {
+                return Err(AppError::new(ErrorCode::InvalidSettings, format!("Unknown argument {}", arg))
+                    .with_param("setting", arg.clone()));
+            }
+        }
+    }
+
+    object.extend(flags);
+    serde_json::from_value(Value::Object(object)).map_err(|e| {
+        AppError::new(ErrorCode::InvalidSettings, format!("Invalid settings: {}", e))
+            .with_param("setting", "settings")
+    })
+}
+
+fn parse_number(text: &str, setting: &'static str) -> Result<u32, AppError> {
+    text.parse().map_err(|_| {
+        AppError::new(ErrorCode::InvalidSettings, format!("{} must be a whole number, got {}", setting, text))
+            .with_param("setting", setting)
+    })
+}
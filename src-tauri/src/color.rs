@@ -0,0 +1,288 @@
+use crate::heif_limits::HeifLimits;
+use crate::image_processor::ImageProcessor;
+use crate::metadata::{MetadataProcessor, PngColorChunks};
+use anyhow::{Context, Result};
+
+/// D50 colorants (chromatically adapted primaries) of the builtin RGB spaces
+const SRGB_COLORANTS: [[f64; 3]; 3] = [
+    [0.4361, 0.2225, 0.0139],
+    [0.3851, 0.7169, 0.0971],
+    [0.1431, 0.0606, 0.7141],
+];
+const ADOBE_RGB_COLORANTS: [[f64; 3]; 3] = [
+    [0.6097, 0.3111, 0.0195],
+    [0.2053, 0.6257, 0.0609],
+    [0.1492, 0.0632, 0.7446],
+];
+const DISPLAY_P3_COLORANTS: [[f64; 3]; 3] = [
+    [0.5151, 0.2412, -0.0011],
+    [0.2919, 0.6922, 0.0419],
+    [0.1572, 0.0666, 0.7841],
+];
+const D50_WHITE: [f64; 3] = [0.9642, 1.0, 0.8249];
+/// Cone response matrix of the Bradford chromatic adaptation transform
+const BRADFORD: [[f64; 3]; 3] = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+/// Tone response curve of a builtin profile
+enum Trc {
+    Srgb,
+    Gamma(f64),
+}
+
+pub struct ColorProfiles;
+
+impl ColorProfiles {
+    /// Generate one of the builtin ICC profiles by name
+    pub fn builtin(name: &str) -> Option<Vec<u8>> {
+        match name {
+            "srgb" => Some(Self::build_matrix_profile("sRGB", &SRGB_COLORANTS, Trc::Srgb)),
+            "adobe-rgb" => Some(Self::build_matrix_profile(
+                "Adobe RGB (1998)",
+                &ADOBE_RGB_COLORANTS,
+                Trc::Gamma(563.0 / 256.0),
+            )),
+            "display-p3" => Some(Self::build_matrix_profile("Display P3", &DISPLAY_P3_COLORANTS, Trc::Srgb)),
+            _ => None,
+        }
+    }
+
+    /// Resolve an assign_profile value: a builtin name or a path to an .icc file
+    pub fn resolve(spec: &str) -> Result<Vec<u8>> {
+        let icc = match Self::builtin(spec) {
+            Some(icc) => icc,
+            None => std::fs::read(spec)
+                .with_context(|| format!("Unknown profile '{}' and no such ICC file", spec))?,
+        };
+
+        if icc.len() < 132 || &icc[36..40] != b"acsp" {
+            anyhow::bail!("'{}' is not a valid ICC profile", spec);
+        }
+        if &icc[16..20] != b"RGB " {
+            anyhow::bail!("Only RGB ICC profiles can be assigned");
+        }
+
+        Ok(icc)
+    }
+
+    /// Read the embedded ICC profile of a source file, if any
+    pub fn read_source_profile(path: &str) -> Result<Option<Vec<u8>>> {
+        if ImageProcessor::is_heif_format(&ImageProcessor::get_format(path)?) {
+            HeifLimits::check_file(path)?;
+            let ctx = libheif_rs::HeifContext::read_from_file(path)
+                .context("Failed to read HEIC file")?;
+            let handle = ctx.primary_image_handle()
+                .context("Failed to get primary image handle")?;
+            return Ok(handle.color_profile_raw().map(|profile| profile.data));
+        }
+
+        let data = std::fs::read(path).context("Failed to read file")?;
+        Ok(MetadataProcessor::read_jpeg_icc(&data).or_else(|| MetadataProcessor::read_png_icc(&data)))
+    }
+
+    /// ICC profile equivalent to a PNG's gAMA and cHRM chunks, for outputs that can
+    /// only carry color information as ICC. Missing chromaticities mean sRGB
+    /// primaries and a missing gamma means the sRGB curve.
+    pub fn from_png_color(color: &PngColorChunks) -> Vec<u8> {
+        let colorants = color.chromaticities
+            .and_then(|values| Self::colorants_from_chromaticities(&values.map(|v| v as f64 / 100000.0)))
+            .unwrap_or(SRGB_COLORANTS);
+        let trc = match color.gamma {
+            Some(gamma) => Trc::Gamma(100000.0 / gamma as f64),
+            None => Trc::Srgb,
+        };
+        Self::build_matrix_profile("PNG gAMA/cHRM", &colorants, trc)
+    }
+
+    /// D50 colorants for primaries and a white point given as x, y pairs (white,
+    /// red, green, blue). None when the chromaticities are degenerate.
+    fn colorants_from_chromaticities(xy: &[f64; 8]) -> Option<[[f64; 3]; 3]> {
+        let to_xyz = |x: f64, y: f64| [x / y, 1.0, (1.0 - x - y) / y];
+        if [xy[1], xy[3], xy[5], xy[7]].iter().any(|&y| y <= 0.0) {
+            return None;
+        }
+        let white = to_xyz(xy[0], xy[1]);
+        let primaries = [to_xyz(xy[2], xy[3]), to_xyz(xy[4], xy[5]), to_xyz(xy[6], xy[7])];
+
+        // Scale the primaries so that full RGB adds up to the white point
+        let primary_matrix = transpose(&primaries);
+        let scale = apply(&invert(&primary_matrix)?, &white);
+
+        // Adapt from the source white to the D50 profile connection space
+        let source_cone = apply(&BRADFORD, &white);
+        let target_cone = apply(&BRADFORD, &D50_WHITE);
+        let ratio = [
+            [target_cone[0] / source_cone[0], 0.0, 0.0],
+            [0.0, target_cone[1] / source_cone[1], 0.0],
+            [0.0, 0.0, target_cone[2] / source_cone[2]],
+        ];
+        let adapt = multiply(&invert(&BRADFORD)?, &multiply(&ratio, &BRADFORD));
+
+        Some(std::array::from_fn(|i| {
+            apply(&adapt, &primaries[i].map(|component| component * scale[i]))
+        }))
+    }
+
+    /// Human readable profile name from the 'desc' tag (v2 'desc' or v4 'mluc')
+    pub fn description(icc: &[u8]) -> Option<String> {
+        let read_u32 = |pos: usize| -> Option<usize> {
+            icc.get(pos..pos + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        };
+
+        let tag_count = read_u32(128)?;
+        let (offset, size) = (0..tag_count.min(256))
+            .map(|i| 132 + i * 12)
+            .find(|&entry| icc.get(entry..entry + 4) == Some(b"desc".as_slice()))
+            .and_then(|entry| Some((read_u32(entry + 4)?, read_u32(entry + 8)?)))?;
+        let tag = icc.get(offset..offset.checked_add(size)?)?;
+
+        let text = match tag.get(0..4)? {
+            b"desc" => {
+                let length = read_u32(offset + 8)?;
+                let ascii = tag.get(12..12 + length)?;
+                String::from_utf8_lossy(ascii).to_string()
+            }
+            b"mluc" => {
+                let length = read_u32(offset + 20)?;
+                let start = read_u32(offset + 24)?;
+                let utf16: Vec<u16> = tag.get(start..start + length)?
+                    .chunks_exact(2)
+                    .map(|b| u16::from_be_bytes([b[0], b[1]]))
+                    .collect();
+                String::from_utf16_lossy(&utf16)
+            }
+            _ => return None,
+        };
+
+        let text = text.trim_end_matches('\0').trim().to_string();
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Build a minimal ICC v2.1 display profile from D50 colorants and a tone curve
+    fn build_matrix_profile(description: &str, colorants: &[[f64; 3]; 3], trc: Trc) -> Vec<u8> {
+        let xyz = |value: &[f64; 3]| -> Vec<u8> {
+            let mut tag = b"XYZ \0\0\0\0".to_vec();
+            for component in value {
+                tag.extend_from_slice(&((component * 65536.0).round() as i32).to_be_bytes());
+            }
+            tag
+        };
+
+        let curve = {
+            let mut tag = b"curv\0\0\0\0".to_vec();
+            match trc {
+                Trc::Gamma(gamma) => {
+                    tag.extend_from_slice(&1u32.to_be_bytes());
+                    tag.extend_from_slice(&((gamma * 256.0).round() as u16).to_be_bytes());
+                }
+                Trc::Srgb => {
+                    let entries = 1024u32;
+                    tag.extend_from_slice(&entries.to_be_bytes());
+                    for i in 0..entries {
+                        let v = i as f64 / (entries - 1) as f64;
+                        let linear = if v <= 0.04045 {
+                            v / 12.92
+                        } else {
+                            ((v + 0.055) / 1.055).powf(2.4)
+                        };
+                        tag.extend_from_slice(&((linear * 65535.0).round() as u16).to_be_bytes());
+                    }
+                }
+            }
+            tag
+        };
+
+        let desc = {
+            let mut tag = b"desc\0\0\0\0".to_vec();
+            tag.extend_from_slice(&(description.len() as u32 + 1).to_be_bytes());
+            tag.extend_from_slice(description.as_bytes());
+            tag.push(0);
+            // Empty Unicode and ScriptCode descriptions
+            tag.extend_from_slice(&[0u8; 8]);
+            tag.extend_from_slice(&[0u8; 3]);
+            tag.extend_from_slice(&[0u8; 67]);
+            tag
+        };
+
+        let copyright = {
+            let mut tag = b"text\0\0\0\0".to_vec();
+            tag.extend_from_slice(b"No copyright, use freely\0");
+            tag
+        };
+
+        let tags: Vec<(&[u8; 4], Vec<u8>)> = vec![
+            (b"desc", desc),
+            (b"cprt", copyright),
+            (b"wtpt", xyz(&D50_WHITE)),
+            (b"rXYZ", xyz(&colorants[0])),
+            (b"gXYZ", xyz(&colorants[1])),
+            (b"bXYZ", xyz(&colorants[2])),
+            (b"rTRC", curve.clone()),
+            (b"gTRC", curve.clone()),
+            (b"bTRC", curve),
+        ];
+
+        let mut table = Vec::new();
+        let mut body = Vec::new();
+        let data_start = 128 + 4 + tags.len() * 12;
+        for (signature, tag) in &tags {
+            table.extend_from_slice(*signature);
+            table.extend_from_slice(&((data_start + body.len()) as u32).to_be_bytes());
+            table.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+            body.extend_from_slice(tag);
+            // Tags are 4-byte aligned
+            while body.len() % 4 != 0 {
+                body.push(0);
+            }
+        }
+
+        let total = data_start + body.len();
+        let mut header = vec![0u8; 128];
+        header[0..4].copy_from_slice(&(total as u32).to_be_bytes());
+        header[8..12].copy_from_slice(&[0x02, 0x10, 0x00, 0x00]);
+        header[12..16].copy_from_slice(b"mntr");
+        header[16..20].copy_from_slice(b"RGB ");
+        header[20..24].copy_from_slice(b"XYZ ");
+        header[36..40].copy_from_slice(b"acsp");
+        for (i, component) in D50_WHITE.iter().enumerate() {
+            let pos = 68 + i * 4;
+            header[pos..pos + 4].copy_from_slice(&((component * 65536.0).round() as i32).to_be_bytes());
+        }
+
+        let mut profile = header;
+        profile.extend_from_slice(&(tags.len() as u32).to_be_bytes());
+        profile.extend_from_slice(&table);
+        profile.extend_from_slice(&body);
+        profile
+    }
+}
+
+fn transpose(m: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    std::array::from_fn(|row| std::array::from_fn(|col| m[col][row]))
+}
+
+fn multiply(a: &[[f64; 3]; 3], b: &[[f64; 3]; 3]) -> [[f64; 3]; 3] {
+    std::array::from_fn(|row| std::array::from_fn(|col| (0..3).map(|k| a[row][k] * b[k][col]).sum()))
+}
+
+fn apply(m: &[[f64; 3]; 3], v: &[f64; 3]) -> [f64; 3] {
+    std::array::from_fn(|row| (0..3).map(|k| m[row][k] * v[k]).sum())
+}
+
+/// Inverse of a 3x3 matrix, or None when it is singular
+fn invert(m: &[[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cofactor = |row: usize, col: usize| {
+        let (r0, r1) = ((row + 1) % 3, (row + 2) % 3);
+        let (c0, c1) = ((col + 1) % 3, (col + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let det: f64 = (0..3).map(|col| m[0][col] * cofactor(0, col)).sum();
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    Some(std::array::from_fn(|row| std::array::from_fn(|col| cofactor(col, row) / det)))
+}
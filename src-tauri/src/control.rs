@@ -0,0 +1,114 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// Minimum time between free-space checks of one batch
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq)]
+enum RunState {
+    Running,
+    Paused,
+    Cancelled,
+}
+
+/// Pause/resume/cancel switch for one running batch. Workers check it before
+/// starting each item; items already converting finish normally.
+pub struct BatchControl {
+    pub job_id: String,
+    state: Mutex<RunState>,
+    changed: Condvar,
+    last_disk_check: Mutex<Option<Instant>>,
+}
+
+impl BatchControl {
+    fn new(job_id: &str) -> Self {
+        BatchControl {
+            job_id: job_id.to_string(),
+            state: Mutex::new(RunState::Running),
+            changed: Condvar::new(),
+            last_disk_check: Mutex::new(None),
+        }
+    }
+
+    /// Pause a running batch. True only for the call that actually paused it, so
+    /// workers that notice the same condition report it once.
+    pub fn pause(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let paused = *state == RunState::Running;
+        if paused {
+            *state = RunState::Paused;
+        }
+        paused
+    }
+
+    pub fn resume(&self) {
+        self.set(RunState::Running, |state| state == RunState::Paused);
+    }
+
+    pub fn cancel(&self) {
+        self.set(RunState::Cancelled, |_| true);
+    }
+
+    fn set(&self, next: RunState, allowed: impl Fn(RunState) -> bool) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if allowed(*state) {
+            *state = next;
+            self.changed.notify_all();
+        }
+    }
+
+    /// Block while the batch is paused. False once it has been cancelled.
+    pub fn wait_until_running(&self) -> bool {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        while *state == RunState::Paused {
+            state = self.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+        }
+        *state == RunState::Running
+    }
+
+    /// Whether enough time has passed to poll free space again
+    pub fn disk_check_due(&self) -> bool {
+        let mut last = self.last_disk_check.lock().unwrap_or_else(|e| e.into_inner());
+        let due = !matches!(*last, Some(at) if at.elapsed() < DISK_CHECK_INTERVAL);
+        if due {
+            *last = Some(Instant::now());
+        }
+        due
+    }
+}
+
+/// Controls of the batches running right now, by job id
+#[derive(Default)]
+pub struct BatchControls(Mutex<HashMap<String, Arc<BatchControl>>>);
+
+impl BatchControls {
+    pub fn register(&self, job_id: &str) -> Arc<BatchControl> {
+        let control = Arc::new(BatchControl::new(job_id));
+        let mut controls = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        controls.insert(job_id.to_string(), control.clone());
+        control
+    }
+
+    pub fn get(&self, job_id: &str) -> Option<Arc<BatchControl>> {
+        let controls = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        controls.get(job_id).cloned()
+    }
+
+    /// Forget a finished batch, unless a re-run under the same id has replaced it
+    pub fn remove(&self, control: &Arc<BatchControl>) {
+        let mut controls = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        if controls.get(&control.job_id).is_some_and(|current| Arc::ptr_eq(current, control)) {
+            controls.remove(&control.job_id);
+        }
+    }
+}
+
+/// Free bytes on the volume `output_path` will be written to, checked at its nearest
+/// existing ancestor since the output directory may not exist yet
+pub fn available_space(output_path: &str) -> Option<(String, u64)> {
+    let volume = Path::new(output_path).ancestors().skip(1).find(|dir| dir.exists())?;
+    let available = fs2::available_space(volume).ok()?;
+    Some((volume.to_string_lossy().to_string(), available))
+}
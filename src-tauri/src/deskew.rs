@@ -0,0 +1,149 @@
+use crate::adjustments::Adjustments;
+use image::{DynamicImage, GenericImageView};
+
+/// Longest side the skew search runs at
+const ANALYSIS_SIZE: u32 = 1024;
+/// Largest skew searched for either way, in degrees
+const MAX_SKEW: f32 = 10.0;
+/// Coarse search step, then the fine step used around the coarse winner
+const COARSE_STEP: f32 = 0.5;
+const FINE_STEP: f32 = 0.05;
+/// Share of ink pixels outside which the image is not treated as a scanned page
+const INK_RANGE: (f32, f32) = (0.005, 0.4);
+/// The best angle's profile must be this much sharper than the average angle's
+const MIN_PEAK_RATIO: f32 = 1.25;
+/// Skews smaller than this aren't worth resampling the image for
+const MIN_CORRECTION: f32 = 0.05;
+
+pub struct Deskewer;
+
+impl Deskewer {
+    /// Estimate the skew of a scanned page and return the clockwise rotation in
+    /// degrees that straightens it, or None when no angle within ±10° stands out
+    /// clearly or the skew is negligible.
+    ///
+    /// Ink pixels of a downscaled, binarized copy are projected onto the page's
+    /// vertical axis at each candidate angle; text lines pile up into sharp peaks
+    /// only when the angle matches their slope, which the sum of squared bin counts
+    /// rewards.
+    pub fn detect(img: &DynamicImage) -> Option<f32> {
+        let gray = if img.width().max(img.height()) > ANALYSIS_SIZE {
+            img.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, image::imageops::FilterType::Triangle).to_luma8()
+        } else {
+            img.to_luma8()
+        };
+        let (mask, ink_share) = Adjustments::ink_mask(&gray);
+        if !(INK_RANGE.0..=INK_RANGE.1).contains(&ink_share) {
+            return None;
+        }
+
+        let ink: Vec<(f32, f32)> = mask.enumerate_pixels()
+            .filter(|(_, _, pixel)| pixel.0[0] == 1)
+            .map(|(x, y, _)| (x as f32, y as f32))
+            .collect();
+        let diagonal = (gray.width() as f32).hypot(gray.height() as f32).ceil() as usize;
+
+        let coarse_steps = (MAX_SKEW / COARSE_STEP).round() as i32;
+        let coarse: Vec<(f32, f64)> = (-coarse_steps..=coarse_steps)
+            .map(|step| {
+                let angle = step as f32 * COARSE_STEP;
+                (angle, Self::sharpness(&ink, angle, diagonal))
+            })
+            .collect();
+        let (coarse_best, _) = coarse.iter().copied().max_by(|a, b| a.1.total_cmp(&b.1))?;
+        let average = coarse.iter().map(|&(_, score)| score).sum::<f64>() / coarse.len() as f64;
+
+        let fine_steps = (COARSE_STEP / FINE_STEP).round() as i32;
+        let (skew, best) = (-fine_steps..=fine_steps)
+            .map(|step| {
+                let angle = coarse_best + step as f32 * FINE_STEP;
+                (angle, Self::sharpness(&ink, angle, diagonal))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))?;
+
+        // A winner at the edge of the range is more likely a photo or a border than text
+        let confident = best >= average * MIN_PEAK_RATIO as f64 && skew.abs() < MAX_SKEW;
+        (confident && skew.abs() >= MIN_CORRECTION).then_some(-skew)
+    }
+
+    /// Sum of squared counts of ink pixels per projected row for lines sloping
+    /// clockwise by `degrees`
+    fn sharpness(ink: &[(f32, f32)], degrees: f32, diagonal: usize) -> f64 {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        let mut bins = vec![0u32; diagonal * 2 + 1];
+        for &(x, y) in ink {
+            let rho = (y * cos - x * sin).round() as isize + diagonal as isize;
+            bins[rho as usize] += 1;
+        }
+        bins.iter().map(|&count| count as f64 * count as f64).sum()
+    }
+
+    /// Median color of the outermost pixels, taken as the paper color to fill the
+    /// corners a rotation uncovers (RGBA, 0 to 1)
+    pub fn background(img: &DynamicImage) -> [f32; 4] {
+        let (width, height) = img.dimensions();
+        let border = (0..width)
+            .flat_map(|x| [(x, 0), (x, height - 1)])
+            .chain((0..height).flat_map(|y| [(0, y), (width - 1, y)]));
+        let mut channels: [Vec<u8>; 4] = Default::default();
+        for (x, y) in border {
+            for (channel, value) in channels.iter_mut().zip(img.get_pixel(x, y).0) {
+                channel.push(value);
+            }
+        }
+        channels.map(|mut values| {
+            values.sort_unstable();
+            values[values.len() / 2] as f32 / 255.0
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{photo, text_page};
+    use image::{GrayImage, Luma};
+
+    const WHITE: [f32; 4] = [1.0; 4];
+
+    fn tilted_page(degrees: f32) -> DynamicImage {
+        let page = DynamicImage::ImageLuma8(text_page(1000, 1414));
+        Adjustments::rotate_with_fill(&page, degrees, WHITE)
+    }
+
+    #[test]
+    fn recovers_a_synthetic_tilt_within_a_third_of_a_degree() {
+        for tilt in [-8.4f32, -0.7, 1.3, 9.2] {
+            let correction = Deskewer::detect(&tilted_page(tilt))
+                .unwrap_or_else(|| panic!("no angle found for a {}° tilt", tilt));
+            assert!((correction + tilt).abs() <= 0.3, "{}° tilt, correction {}°", tilt, correction);
+        }
+    }
+
+    #[test]
+    fn correcting_the_detected_angle_straightens_the_page() {
+        let tilted = tilted_page(3.0);
+        let correction = Deskewer::detect(&tilted).unwrap();
+        let straightened = Adjustments::rotate_with_fill(&tilted, correction, Deskewer::background(&tilted));
+        assert_eq!(Deskewer::detect(&straightened), None);
+    }
+
+    #[test]
+    fn pages_without_a_confident_angle_are_left_alone() {
+        // Already straight, blank, and no text at all
+        assert_eq!(Deskewer::detect(&DynamicImage::ImageLuma8(text_page(1000, 1414))), None);
+        assert_eq!(Deskewer::detect(&DynamicImage::ImageLuma8(GrayImage::from_pixel(800, 1000, Luma([250])))), None);
+        assert_eq!(Deskewer::detect(&photo(1000, 750)), None);
+    }
+
+    #[test]
+    fn background_is_the_border_median() {
+        let mut img = GrayImage::from_pixel(50, 40, Luma([230]));
+        // A dark smudge along part of one edge doesn't change the median
+        for x in 0..20 {
+            img.put_pixel(x, 0, Luma([10]));
+        }
+        let [r, g, b, a] = Deskewer::background(&DynamicImage::ImageLuma8(img));
+        assert_eq!([r, g, b, a], [230.0 / 255.0, 230.0 / 255.0, 230.0 / 255.0, 1.0]);
+    }
+}
@@ -0,0 +1,389 @@
+use crate::heif_limits::LimitExceeded;
+use crate::raw::NoAdequatePreview;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// Stable identifiers for every failure the backend reports. The frontend keys its
+/// translations on these strings, so existing codes must never be renamed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// A file or directory doesn't exist. Params: path
+    IoNotFound,
+    /// The OS refused access. Params: path
+    IoPermissionDenied,
+    /// The destination volume is out of space. Params: path
+    IoDiskFull,
+    /// Any other filesystem failure. Params: path
+    IoFailed,
+    /// The file's format or codec can't be decoded. Params: path, format
+    DecodeUnsupportedCodec,
+    /// The file is truncated or malformed. Params: path
+    DecodeCorrupt,
+    /// The source is animated and animation_policy is "error". Params: path, frames
+    DecodeAnimated,
+    /// A RAW file embeds no JPEG preview near its full size, and the sensor data
+    /// can't be decoded. Params: path, plus preview and sensor ("WxH") when known
+    DecodeRawPreviewInadequate,
+    /// The image exceeds decoder limits. Params: path, plus setting, limit and actual
+    /// when a configured HEIF limit tripped, or setting "decode_limits" when the image
+    /// crate's decoders hit theirs
+    DecodeLimitExceeded,
+    /// The encoder rejected the image. Params: path, format
+    EncodeFailed,
+    /// Even the lowest searched quality exceeds max_output_bytes. Params: path, limit, actual
+    TargetSizeUnreachable,
+    /// keep_smaller skipped an output larger than its source. Params: path, limit
+    /// (source bytes), actual (output bytes)
+    OutputLarger,
+    /// The requested output format isn't supported. Params: format
+    UnsupportedFormat,
+    /// target_format and the output file extension name different formats.
+    /// Params: format, extension, path
+    FormatMismatch,
+    /// A setting is out of range or conflicts with another. Params: setting, limit, actual
+    InvalidSettings,
+    /// An ICC profile couldn't be loaded or isn't usable. Params: profile
+    InvalidProfile,
+    /// The written output didn't decode back as expected. Params: path
+    VerificationFailed,
+    /// The operation was cancelled by the user
+    Cancelled,
+    /// No retained or persisted batch job has the given id. Params: job_id
+    JobNotFound,
+    /// The clipboard holds no image
+    #[cfg_attr(not(feature = "clipboard"), allow(dead_code))]
+    ClipboardEmpty,
+    /// Clipboard access failed or isn't compiled in
+    ClipboardUnavailable,
+    /// Previews are only generated for formats the webview can't show. Params: format
+    PreviewNotNeeded,
+    /// The converted file couldn't be opened with the default app. Params: path
+    OpenFailed,
+    /// The path is outside every directory the app has worked in. Params: path
+    PathNotPermitted,
+    /// Anything not covered above
+    Internal,
+}
+
+impl ErrorCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ErrorCode::IoNotFound => "io.not_found",
+            ErrorCode::IoPermissionDenied => "io.permission_denied",
+            ErrorCode::IoDiskFull => "io.disk_full",
+            ErrorCode::IoFailed => "io.failed",
+            ErrorCode::DecodeUnsupportedCodec => "decode.unsupported_codec",
+            ErrorCode::DecodeCorrupt => "decode.corrupt",
+            ErrorCode::DecodeAnimated => "decode.animated",
+            ErrorCode::DecodeRawPreviewInadequate => "decode.raw_preview_inadequate",
+            ErrorCode::DecodeLimitExceeded => "decode.limit_exceeded",
+            ErrorCode::EncodeFailed => "encode.failed",
+            ErrorCode::TargetSizeUnreachable => "encode.target_size_unreachable",
+            ErrorCode::OutputLarger => "encode.output_larger",
+            ErrorCode::UnsupportedFormat => "settings.unsupported_format",
+            ErrorCode::FormatMismatch => "settings.format_mismatch",
+            ErrorCode::InvalidSettings => "settings.invalid",
+            ErrorCode::InvalidProfile => "settings.invalid_profile",
+            ErrorCode::VerificationFailed => "verify.failed",
+            ErrorCode::Cancelled => "job.cancelled",
+            ErrorCode::JobNotFound => "job.not_found",
+            ErrorCode::ClipboardEmpty => "clipboard.empty",
+            ErrorCode::ClipboardUnavailable => "clipboard.unavailable",
+            ErrorCode::PreviewNotNeeded => "preview.not_needed",
+            ErrorCode::OpenFailed => "open.failed",
+            ErrorCode::PathNotPermitted => "open.not_permitted",
+            ErrorCode::Internal => "internal",
+        }
+    }
+}
+
+impl Serialize for ErrorCode {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+/// Error returned to the frontend: a stable code plus params to build a localized
+/// message from, with the English message as a fallback
+#[derive(Serialize, Clone, Debug)]
+pub struct AppError {
+    pub code: ErrorCode,
+    pub message: String,
+    pub params: BTreeMap<&'static str, String>,
+}
+
+impl AppError {
+    pub fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        AppError {
+            code,
+            message: message.into(),
+            params: BTreeMap::new(),
+        }
+    }
+
+    /// Attach a param, keeping any value already set closer to the failure
+    pub fn with_param(mut self, key: &'static str, value: impl ToString) -> Self {
+        self.params.entry(key).or_insert_with(|| value.to_string());
+        self
+    }
+
+    /// Use `code` when the cause couldn't be classified more precisely
+    pub fn or_code(mut self, code: ErrorCode) -> Self {
+        if self.code == ErrorCode::Internal {
+            self.code = code;
+        }
+        self
+    }
+
+    fn io_code(error: &std::io::Error) -> ErrorCode {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => ErrorCode::IoNotFound,
+            std::io::ErrorKind::PermissionDenied => ErrorCode::IoPermissionDenied,
+            std::io::ErrorKind::StorageFull => ErrorCode::IoDiskFull,
+            _ => ErrorCode::IoFailed,
+        }
+    }
+
+    fn image_code(error: &image::ImageError) -> ErrorCode {
+        match error {
+            image::ImageError::Unsupported(_) => ErrorCode::DecodeUnsupportedCodec,
+            image::ImageError::Decoding(_) => ErrorCode::DecodeCorrupt,
+            image::ImageError::Limits(_) => ErrorCode::DecodeLimitExceeded,
+            image::ImageError::Encoding(_) => ErrorCode::EncodeFailed,
+            image::ImageError::IoError(io) => Self::io_code(io),
+            image::ImageError::Parameter(_) => ErrorCode::Internal,
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Classify by the first I/O or image error in the chain
+impl From<anyhow::Error> for AppError {
+    fn from(error: anyhow::Error) -> Self {
+        if let Some(exceeded) = error.downcast_ref::<LimitExceeded>() {
+            return AppError::new(ErrorCode::DecodeLimitExceeded, error.to_string())
+                .with_param("setting", exceeded.limit)
+                .with_param("limit", exceeded.max)
+                .with_param("actual", exceeded.actual);
+        }
+
+        if let Some(inadequate) = error.downcast_ref::<NoAdequatePreview>() {
+            let size = |(width, height): (u32, u32)| format!("{}x{}", width, height);
+            let mut app_error = AppError::new(ErrorCode::DecodeRawPreviewInadequate, error.to_string());
+            if let Some(preview) = inadequate.preview {
+                app_error = app_error.with_param("preview", size(preview));
+            }
+            if let Some(sensor) = inadequate.sensor {
+                app_error = app_error.with_param("sensor", size(sensor));
+            }
+            return app_error;
+        }
+        let code = error.chain()
+            .find_map(|cause| {
+                if let Some(io) = cause.downcast_ref::<std::io::Error>() {
+                    Some(Self::io_code(io))
+                } else {
+                    cause.downcast_ref::<image::ImageError>().map(Self::image_code)
+                }
+            })
+            .unwrap_or(ErrorCode::Internal);
+
+        if code == ErrorCode::DecodeLimitExceeded {
+            return AppError::new(code, error.to_string()).with_param("setting", "decode_limits");
+        }
+        AppError::new(code, error.to_string())
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(error: std::io::Error) -> Self {
+        AppError::new(Self::io_code(&error), error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    /// Every code, in declaration order
+    const ALL: [ErrorCode; 25] = [
+        ErrorCode::IoNotFound,
+        ErrorCode::IoPermissionDenied,
+        ErrorCode::IoDiskFull,
+        ErrorCode::IoFailed,
+        ErrorCode::DecodeUnsupportedCodec,
+        ErrorCode::DecodeCorrupt,
+        ErrorCode::DecodeAnimated,
+        ErrorCode::DecodeRawPreviewInadequate,
+        ErrorCode::DecodeLimitExceeded,
+        ErrorCode::EncodeFailed,
+        ErrorCode::TargetSizeUnreachable,
+        ErrorCode::OutputLarger,
+        ErrorCode::UnsupportedFormat,
+        ErrorCode::FormatMismatch,
+        ErrorCode::InvalidSettings,
+        ErrorCode::InvalidProfile,
+        ErrorCode::VerificationFailed,
+        ErrorCode::Cancelled,
+        ErrorCode::JobNotFound,
+        ErrorCode::ClipboardEmpty,
+        ErrorCode::ClipboardUnavailable,
+        ErrorCode::PreviewNotNeeded,
+        ErrorCode::OpenFailed,
+        ErrorCode::PathNotPermitted,
+        ErrorCode::Internal,
+    ];
+
+    /// Position in ALL. Exhaustive, so a new variant doesn't compile until it's listed.
+    fn position(code: ErrorCode) -> usize {
+        match code {
+            ErrorCode::IoNotFound => 0,
+            ErrorCode::IoPermissionDenied => 1,
+            ErrorCode::IoDiskFull => 2,
+            ErrorCode::IoFailed => 3,
+            ErrorCode::DecodeUnsupportedCodec => 4,
+            ErrorCode::DecodeCorrupt => 5,
+            ErrorCode::DecodeAnimated => 6,
+            ErrorCode::DecodeRawPreviewInadequate => 7,
+            ErrorCode::DecodeLimitExceeded => 8,
+            ErrorCode::EncodeFailed => 9,
+            ErrorCode::TargetSizeUnreachable => 10,
+            ErrorCode::OutputLarger => 11,
+            ErrorCode::UnsupportedFormat => 12,
+            ErrorCode::FormatMismatch => 13,
+            ErrorCode::InvalidSettings => 14,
+            ErrorCode::InvalidProfile => 15,
+            ErrorCode::VerificationFailed => 16,
+            ErrorCode::Cancelled => 17,
+            ErrorCode::JobNotFound => 18,
+            ErrorCode::ClipboardEmpty => 19,
+            ErrorCode::ClipboardUnavailable => 20,
+            ErrorCode::PreviewNotNeeded => 21,
+            ErrorCode::OpenFailed => 22,
+            ErrorCode::PathNotPermitted => 23,
+            ErrorCode::Internal => 24,
+        }
+    }
+
+    #[test]
+    fn every_code_is_listed_once() {
+        for (index, code) in ALL.into_iter().enumerate() {
+            assert_eq!(position(code), index, "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn codes_are_unique_dotted_identifiers() {
+        let mut seen = std::collections::HashSet::new();
+        for code in ALL {
+            let name = code.as_str();
+            assert!(seen.insert(name), "{} used twice", name);
+            assert!(
+                name.split('.').all(|part| !part.is_empty() && part.bytes().all(|b| b.is_ascii_lowercase() || b == b'_')),
+                "{:?} has malformed code {}", code, name
+            );
+        }
+    }
+
+    #[test]
+    fn every_code_is_documented() {
+        let source = include_str!("errors.rs");
+        let lines: Vec<&str> = source.lines().collect();
+        for code in ALL {
+            let variant = format!("    {:?},", code);
+            let at = lines.iter().position(|line| *line == variant)
+                .unwrap_or_else(|| panic!("{:?} not found in the enum", code));
+            let doc = lines[..at].iter().rev()
+                .map(|line| line.trim())
+                .find(|line| !line.starts_with("#["));
+            assert!(doc.is_some_and(|line| line.starts_with("///")), "{:?} has no doc comment", code);
+        }
+    }
+
+    #[test]
+    fn io_errors_map_by_kind() {
+        use std::io::{Error, ErrorKind};
+
+        for (kind, code) in [
+            (ErrorKind::NotFound, "io.not_found"),
+            (ErrorKind::PermissionDenied, "io.permission_denied"),
+            (ErrorKind::StorageFull, "io.disk_full"),
+            (ErrorKind::UnexpectedEof, "io.failed"),
+        ] {
+            assert_eq!(AppError::from(Error::from(kind)).code.as_str(), code);
+            // Also when wrapped in context, as most of the processors return them
+            let wrapped = Err::<(), _>(Error::from(kind)).context("Failed to read file").unwrap_err();
+            assert_eq!(AppError::from(wrapped).code.as_str(), code);
+        }
+    }
+
+    #[test]
+    fn image_errors_map_by_variant() {
+        let corrupt = image::load_from_memory_with_format(b"\x89PNG\r\n\x1a\nnot a png", image::ImageFormat::Png)
+            .context("Failed to decode image")
+            .unwrap_err();
+        assert_eq!(AppError::from(corrupt).code, ErrorCode::DecodeCorrupt);
+
+        let limits = image::ImageError::Limits(image::error::LimitError::from_kind(
+            image::error::LimitErrorKind::DimensionError,
+        ));
+        let error = AppError::from(anyhow::Error::from(limits));
+        assert_eq!(error.code, ErrorCode::DecodeLimitExceeded);
+        assert_eq!(error.params["setting"], "decode_limits");
+
+        assert_eq!(AppError::from(anyhow::anyhow!("something else")).code, ErrorCode::Internal);
+    }
+
+    #[test]
+    fn heif_limits_carry_their_params() {
+        let exceeded = LimitExceeded { limit: "max_items", max: 100, actual: 5000 };
+        let error = AppError::from(anyhow::Error::from(exceeded));
+        assert_eq!(error.code, ErrorCode::DecodeLimitExceeded);
+        assert_eq!(error.params["setting"], "max_items");
+        assert_eq!(error.params["limit"], "100");
+        assert_eq!(error.params["actual"], "5000");
+    }
+
+    #[test]
+    fn inadequate_raw_previews_carry_their_sizes() {
+        let inadequate = NoAdequatePreview { preview: Some((160, 120)), sensor: Some((6048, 4024)) };
+        let error = AppError::from(anyhow::Error::from(inadequate).context("Failed to load image"));
+        assert_eq!(error.code, ErrorCode::DecodeRawPreviewInadequate);
+        assert_eq!(error.params["preview"], "160x120");
+        assert_eq!(error.params["sensor"], "6048x4024");
+
+        let missing = AppError::from(anyhow::Error::from(NoAdequatePreview { preview: None, sensor: None }));
+        assert_eq!(missing.code, ErrorCode::DecodeRawPreviewInadequate);
+        assert!(missing.params.is_empty());
+    }
+
+    #[test]
+    fn or_code_and_params_keep_the_more_specific_value() {
+        let error = AppError::new(ErrorCode::Internal, "failed")
+            .with_param("path", "/inner.png")
+            .or_code(ErrorCode::EncodeFailed)
+            .or_code(ErrorCode::IoFailed)
+            .with_param("path", "/outer.png");
+        assert_eq!(error.code, ErrorCode::EncodeFailed);
+        assert_eq!(error.params["path"], "/inner.png");
+    }
+
+    #[test]
+    fn serializes_code_params_and_fallback_message() {
+        let error = AppError::new(ErrorCode::IoDiskFull, "No space left on device").with_param("path", "/out.jpg");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "code": "io.disk_full",
+                "message": "No space left on device",
+                "params": { "path": "/out.jpg" },
+            })
+        );
+    }
+}
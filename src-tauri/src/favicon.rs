@@ -0,0 +1,103 @@
+use crate::adjustments::CropRect;
+use crate::file_ops::FileOps;
+use crate::ico::IconProcessor;
+use crate::image_processor::{ImageProcessor, PngCompression};
+use anyhow::{Context, Result};
+use image::{DynamicImage, GenericImageView};
+use serde::Serialize;
+use std::path::Path;
+
+/// Shortest source side that still downsamples cleanly to every icon
+pub const MIN_SOURCE_SIZE: u32 = 256;
+/// Sizes written as favicon-NxN.png and packed into favicon.ico
+const FAVICON_SIZES: &[u32] = &[16, 32, 48];
+const APPLE_TOUCH_SIZE: u32 = 180;
+/// Home-screen icons listed in site.webmanifest; 512 needs a source at least that large
+const MANIFEST_SIZES: &[u32] = &[192, 512];
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FaviconBundle {
+    /// Every file written, PNGs first, then favicon.ico and site.webmanifest
+    pub files: Vec<String>,
+    /// `<link>` tags referencing the bundle, for the page's <head>
+    pub html: String,
+    /// Centered square the icons were cut from, when the source wasn't square
+    pub cropped: Option<CropRect>,
+}
+
+/// Favicons, touch icons and a web app manifest from one source image
+pub struct FaviconGenerator;
+
+impl FaviconGenerator {
+    /// Write favicon-16x16.png, -32x32 and -48x48, favicon.ico holding the same three,
+    /// apple-touch-icon.png (180px), android-chrome-192x192.png and -512x512 (when the
+    /// source is large enough), and a site.webmanifest listing the Android icons.
+    /// Non-square sources are center-cropped to a square first. Icon names are the
+    /// ones browsers and generators conventionally use, so existing files are replaced.
+    pub fn generate(img: &DynamicImage, output_dir: &Path) -> Result<FaviconBundle> {
+        let (width, height) = img.dimensions();
+        let side = width.min(height);
+        let cropped = (width != height).then(|| CropRect {
+            x: (width - side) / 2,
+            y: (height - side) / 2,
+            width: side,
+            height: side,
+        });
+        let square = match cropped {
+            Some(rect) => img.crop_imm(rect.x, rect.y, rect.width, rect.height),
+            None => img.clone(),
+        };
+        std::fs::create_dir_all(output_dir).context("Failed to create output directory")?;
+
+        let mut files = Vec::new();
+        let mut write = |name: &str, data: &[u8]| -> Result<()> {
+            let path = output_dir.join(name);
+            FileOps::write_atomic(&path, data).with_context(|| format!("Failed to write {}", name))?;
+            files.push(path.to_string_lossy().to_string());
+            Ok(())
+        };
+        let icon = |size: u32| -> Result<Vec<u8>> {
+            let resized = square.resize_exact(size, size, image::imageops::FilterType::Lanczos3);
+            ImageProcessor::encode_png(&resized, PngCompression::Best)
+        };
+
+        let mut ico_frames = Vec::new();
+        for &size in FAVICON_SIZES {
+            let png = icon(size)?;
+            write(&format!("favicon-{0}x{0}.png", size), &png)?;
+            ico_frames.push((size, png));
+        }
+        write("apple-touch-icon.png", &icon(APPLE_TOUCH_SIZE)?)?;
+
+        let manifest_sizes: Vec<u32> = MANIFEST_SIZES.iter().copied().filter(|&size| size <= side).collect();
+        for &size in &manifest_sizes {
+            write(&format!("android-chrome-{0}x{0}.png", size), &icon(size)?)?;
+        }
+        write("favicon.ico", &IconProcessor::pack_png(&ico_frames))?;
+
+        let manifest = serde_json::json!({
+            "name": "",
+            "short_name": "",
+            "icons": manifest_sizes.iter().map(|size| serde_json::json!({
+                "src": format!("/android-chrome-{0}x{0}.png", size),
+                "sizes": format!("{0}x{0}", size),
+                "type": "image/png",
+            })).collect::<Vec<_>>(),
+            "theme_color": "#ffffff",
+            "background_color": "#ffffff",
+            "display": "standalone",
+        });
+        let manifest = serde_json::to_vec_pretty(&manifest).context("Failed to serialize web manifest")?;
+        write("site.webmanifest", &manifest)?;
+
+        let html = [
+            "<link rel=\"apple-touch-icon\" sizes=\"180x180\" href=\"/apple-touch-icon.png\">".to_string(),
+            "<link rel=\"icon\" type=\"image/png\" sizes=\"32x32\" href=\"/favicon-32x32.png\">".to_string(),
+            "<link rel=\"icon\" type=\"image/png\" sizes=\"16x16\" href=\"/favicon-16x16.png\">".to_string(),
+            "<link rel=\"manifest\" href=\"/site.webmanifest\">".to_string(),
+        ]
+        .join("\n");
+
+        Ok(FaviconBundle { files, html, cropped })
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Digest used for output checksums
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ChecksumAlgorithm {
+    #[default]
+    Sha256,
+    /// Several times faster than SHA-256 on large files
+    Blake3,
+}
+
+pub struct FileOps;
+
+impl FileOps {
+    /// Find sidecar files next to `path` with any of the given extensions.
+    /// Both `IMG_1.xmp` and `IMG_1.jpg.xmp` (Google Takeout style) naming are recognized.
+    /// Returns (sidecar path, whether it includes the full source file name).
+    pub fn find_sidecars(path: &str, extensions: &[String]) -> Vec<(PathBuf, bool)> {
+        let source = Path::new(path);
+        let (Some(dir), Some(stem), Some(file_name)) = (
+            source.parent(),
+            source.file_stem().and_then(|s| s.to_str()),
+            source.file_name().and_then(|s| s.to_str()),
+        ) else {
+            return Vec::new();
+        };
+
+        let mut found = Vec::new();
+        for extension in extensions {
+            let extension = extension.trim_start_matches('.');
+            for candidate_ext in [extension.to_lowercase(), extension.to_uppercase()] {
+                for (base, full_name) in [(stem, false), (file_name, true)] {
+                    let candidate = dir.join(format!("{}.{}", base, candidate_ext));
+                    if candidate.is_file() && !found.iter().any(|(p, _)| p == &candidate) {
+                        found.push((candidate, full_name));
+                    }
+                }
+            }
+        }
+
+        found
+    }
+
+    /// Copy sidecars of `path` next to `output_path`, renamed to match the output.
+    /// Existing files at the destination are never overwritten.
+    pub fn copy_sidecars(path: &str, output_path: &str, extensions: &[String]) -> Result<Vec<String>> {
+        let output = Path::new(output_path);
+        let dir = output.parent().context("Output path has no parent directory")?;
+        let out_stem = output.file_stem().and_then(|s| s.to_str()).context("Invalid output path")?;
+        let out_name = output.file_name().and_then(|s| s.to_str()).context("Invalid output path")?;
+
+        let mut copied = Vec::new();
+        for (sidecar, full_name) in Self::find_sidecars(path, extensions) {
+            let extension = sidecar.extension().and_then(|s| s.to_str()).unwrap_or("");
+            let base = if full_name { out_name } else { out_stem };
+            let destination = dir.join(format!("{}.{}", base, extension));
+
+            if destination.exists() {
+                continue;
+            }
+
+            std::fs::copy(&sidecar, &destination)
+                .with_context(|| format!("Failed to copy sidecar {}", sidecar.display()))?;
+            copied.push(destination.to_string_lossy().to_string());
+        }
+
+        Ok(copied)
+    }
+
+    /// Write through a temporary file in the same directory and rename it into place,
+    /// so a crash never leaves a truncated file at `path`
+    pub fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+        let file_name = path.file_name().and_then(|s| s.to_str()).context("Invalid output path")?;
+        let temp = path.with_file_name(format!(".{}.partial", file_name));
+
+        let written = std::fs::File::create(&temp)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .and_then(|_| std::fs::rename(&temp, path));
+        if let Err(e) = written {
+            std::fs::remove_file(&temp).ok();
+            return Err(e).context("Failed to write file");
+        }
+
+        Ok(())
+    }
+
+    /// Map a file read-only instead of reading it into memory, so its pages come from
+    /// the page cache and can be dropped again under memory pressure
+    pub fn map(path: &str) -> Result<memmap2::Mmap> {
+        let file = std::fs::File::open(path).context("Failed to open file")?;
+        // SAFETY: the mapping is read-only. If another process truncates the file
+        // while it's mapped, reads past the new end fault; callers opt into that risk.
+        unsafe { memmap2::Mmap::map(&file) }.context("Failed to map file")
+    }
+
+    /// Hex digest of a file, streamed in chunks so large files aren't held in memory
+    pub fn checksum_file(path: &str, algorithm: ChecksumAlgorithm) -> Result<String> {
+        let mut file = std::fs::File::open(path).context("Failed to open file for hashing")?;
+        let mut sha256 = Sha256::new();
+        let mut blake3 = blake3::Hasher::new();
+        let mut buffer = vec![0u8; 1024 * 1024];
+
+        loop {
+            let read = file.read(&mut buffer).context("Failed to read file for hashing")?;
+            if read == 0 {
+                break;
+            }
+            match algorithm {
+                ChecksumAlgorithm::Sha256 => sha256.update(&buffer[..read]),
+                ChecksumAlgorithm::Blake3 => {
+                    blake3.update(&buffer[..read]);
+                }
+            }
+        }
+
+        Ok(match algorithm {
+            ChecksumAlgorithm::Sha256 => format!("{:x}", sha256.finalize()),
+            ChecksumAlgorithm::Blake3 => blake3.finalize().to_hex().to_string(),
+        })
+    }
+
+    pub fn sha256_bytes(data: &[u8]) -> String {
+        format!("{:x}", Sha256::digest(data))
+    }
+}
+
+/// Replaces the file at a path only once everything that depends on the new content
+/// has succeeded. The content is staged beside the target; commit moves it into
+/// place while holding on to the file that was there, and finish lets that go.
+/// Dropped before finish, the target is left as it was before: the previous file
+/// back in place, or no file when there was none.
+pub struct OutputTransaction {
+    target: PathBuf,
+    staged: PathBuf,
+    backup: PathBuf,
+    had_previous: bool,
+    committed: bool,
+    finished: bool,
+}
+
+impl OutputTransaction {
+    /// Write `data` to a hidden file next to `target` and sync it
+    pub fn stage(target: &Path, data: &[u8]) -> Result<Self> {
+        let file_name = target.file_name().and_then(|s| s.to_str()).context("Invalid output path")?;
+        let transaction = OutputTransaction {
+            target: target.to_path_buf(),
+            staged: target.with_file_name(format!(".{}.partial", file_name)),
+            backup: target.with_file_name(format!(".{}.previous", file_name)),
+            had_previous: false,
+            committed: false,
+            finished: false,
+        };
+
+        // A failed write is cleaned up when `transaction` drops
+        std::fs::File::create(&transaction.staged)
+            .and_then(|mut file| {
+                file.write_all(data)?;
+                file.sync_all()
+            })
+            .context("Failed to write file")?;
+        Ok(transaction)
+    }
+
+    /// Where the new content sits until commit, for checks that read it back
+    pub fn staged_path(&self) -> &Path {
+        &self.staged
+    }
+
+    /// Move the staged content to the target. A file already there is kept under a
+    /// hidden name, as a hard link where the filesystem allows so nothing is copied.
+    pub fn commit(&mut self) -> Result<()> {
+        self.had_previous = self.target.is_file();
+        if self.had_previous {
+            std::fs::remove_file(&self.backup).ok();
+            std::fs::hard_link(&self.target, &self.backup)
+                .or_else(|_| std::fs::copy(&self.target, &self.backup).map(|_| ()))
+                .context("Failed to keep the previous file")?;
+        }
+        std::fs::rename(&self.staged, &self.target).context("Failed to write file")?;
+        self.committed = true;
+        Ok(())
+    }
+
+    /// Keep the committed content and drop the previous file
+    pub fn finish(mut self) {
+        if self.had_previous {
+            std::fs::remove_file(&self.backup).ok();
+        }
+        self.finished = true;
+    }
+}
+
+impl Drop for OutputTransaction {
+    fn drop(&mut self) {
+        if self.finished {
+            return;
+        }
+        if !self.committed {
+            std::fs::remove_file(&self.staged).ok();
+            if self.had_previous {
+                std::fs::remove_file(&self.backup).ok();
+            }
+        } else if self.had_previous {
+            std::fs::rename(&self.backup, &self.target).ok();
+        } else {
+            std::fs::remove_file(&self.target).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    /// Names in the directory of `path`, so tests can check no hidden file is left behind
+    fn siblings(path: &str) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(Path::new(path).parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn staged_content_only_reaches_the_target_on_commit() {
+        let dir = TempDir::new();
+        let target = dir.write("photo.jpg", b"original");
+
+        let mut transaction = OutputTransaction::stage(Path::new(&target), b"converted").unwrap();
+        assert_eq!(std::fs::read(transaction.staged_path()).unwrap(), b"converted");
+        assert_eq!(std::fs::read(&target).unwrap(), b"original");
+
+        transaction.commit().unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), b"converted");
+        transaction.finish();
+        assert_eq!(std::fs::read(&target).unwrap(), b"converted");
+        assert_eq!(siblings(&target), ["photo.jpg"]);
+    }
+
+    #[test]
+    fn dropping_before_commit_leaves_the_target_untouched() {
+        let dir = TempDir::new();
+        let target = dir.write("photo.jpg", b"original");
+
+        drop(OutputTransaction::stage(Path::new(&target), b"converted").unwrap());
+        assert_eq!(std::fs::read(&target).unwrap(), b"original");
+        assert_eq!(siblings(&target), ["photo.jpg"]);
+
+        let missing = dir.file("new.png");
+        drop(OutputTransaction::stage(Path::new(&missing), b"converted").unwrap());
+        assert!(!Path::new(&missing).exists());
+        assert_eq!(siblings(&target), ["photo.jpg"]);
+    }
+
+    #[test]
+    fn dropping_after_commit_puts_the_previous_file_back() {
+        let dir = TempDir::new();
+        let original: Vec<u8> = (0..=255).cycle().take(10_000).collect();
+        let target = dir.write("photo.jpg", &original);
+
+        let mut transaction = OutputTransaction::stage(Path::new(&target), b"converted").unwrap();
+        transaction.commit().unwrap();
+        // A later step such as writing the sidecar fails
+        drop(transaction);
+
+        assert_eq!(std::fs::read(&target).unwrap(), original);
+        assert_eq!(siblings(&target), ["photo.jpg"]);
+    }
+
+    #[test]
+    fn dropping_after_commit_removes_a_target_that_did_not_exist() {
+        let dir = TempDir::new();
+        let target = dir.file("new.png");
+
+        let mut transaction = OutputTransaction::stage(Path::new(&target), b"converted").unwrap();
+        transaction.commit().unwrap();
+        assert!(Path::new(&target).exists());
+        drop(transaction);
+
+        assert!(!Path::new(&target).exists());
+        assert!(siblings(&target).is_empty());
+    }
+
+    #[test]
+    fn a_failed_stage_leaves_no_partial_file() {
+        let dir = TempDir::new();
+        let target = dir.file("missing/photo.jpg");
+
+        assert!(OutputTransaction::stage(Path::new(&target), b"converted").is_err());
+        assert!(std::fs::read_dir(dir.file("")).unwrap().next().is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::Result;
+#[cfg(feature = "fits")]
+use anyhow::Context;
+#[cfg(feature = "fits")]
+use image::{DynamicImage, ImageBuffer, Luma, Rgb};
+use serde::{Deserialize, Serialize};
+
+pub const FITS_EXTENSIONS: &[&str] = &["fits", "fit", "fts"];
+
+/// Curve applied after the black/white points have normalized the data to 0..1
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum FitsStretch {
+    Linear,
+    /// ln(1 + scale * x) / ln(1 + scale); larger scales lift faint detail more
+    Log { scale: f64 },
+    /// asinh(x / softening) / asinh(1 / softening); linear near black, logarithmic
+    /// for bright stars, so cores don't saturate as quickly as with log
+    Asinh { softening: f64 },
+}
+
+/// How high dynamic range FITS data is mapped to 16-bit output
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct FitsOptions {
+    pub stretch: FitsStretch,
+    /// Percentile of the data mapped to black
+    pub black_percentile: f64,
+    /// Percentile of the data mapped to white
+    pub white_percentile: f64,
+}
+
+impl Default for FitsOptions {
+    fn default() -> Self {
+        FitsOptions {
+            stretch: FitsStretch::Asinh { softening: 0.1 },
+            black_percentile: 0.5,
+            white_percentile: 99.5,
+        }
+    }
+}
+
+impl FitsOptions {
+    /// Describe the first out-of-range parameter as (setting, message)
+    pub fn invalid_parameter(&self) -> Option<(&'static str, &'static str)> {
+        let percentiles = 0.0..=100.0;
+        if !percentiles.contains(&self.black_percentile) || !percentiles.contains(&self.white_percentile) {
+            return Some(("fits.black_percentile", "FITS percentiles must be between 0 and 100"));
+        }
+        if self.black_percentile >= self.white_percentile {
+            return Some(("fits.white_percentile", "FITS white percentile must be above the black percentile"));
+        }
+        match self.stretch {
+            FitsStretch::Log { scale } if scale.is_nan() || scale <= 0.0 => {
+                Some(("fits.stretch.scale", "Log stretch scale must be greater than 0"))
+            }
+            FitsStretch::Asinh { softening } if softening.is_nan() || softening <= 0.0 => {
+                Some(("fits.stretch.softening", "Asinh softening must be greater than 0"))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct FitsProcessor;
+
+impl FitsProcessor {
+    pub fn is_fits_extension(extension: &str) -> bool {
+        FITS_EXTENSIONS.contains(&extension)
+    }
+
+    /// Decode the primary HDU. 2D data becomes 16-bit grayscale; a cube with three
+    /// planes becomes 16-bit RGB, and any other cube uses its first plane.
+    #[cfg(feature = "fits")]
+    pub fn load(path: &str, options: &FitsOptions) -> Result<DynamicImage> {
+        let fits = fitrs::Fits::open(path).context("Failed to read FITS file")?;
+        let hdu = fits.get(0).context("FITS file has no primary HDU")?;
+
+        let (shape, values): (Vec<usize>, Vec<f64>) = match hdu.read_data() {
+            fitrs::FitsData::Characters(array) => {
+                (array.shape.clone(), array.data.iter().map(|&c| c as u32 as f64).collect())
+            }
+            fitrs::FitsData::IntegersI32(array) => {
+                (array.shape.clone(), array.data.iter().map(|v| v.map_or(f64::NAN, f64::from)).collect())
+            }
+            fitrs::FitsData::IntegersU32(array) => {
+                (array.shape.clone(), array.data.iter().map(|v| v.map_or(f64::NAN, f64::from)).collect())
+            }
+            fitrs::FitsData::FloatingPoint32(array) => {
+                (array.shape.clone(), array.data.iter().map(|&v| v as f64).collect())
+            }
+            fitrs::FitsData::FloatingPoint64(array) => (array.shape.clone(), array.data.clone()),
+        };
+
+        let (width, height) = match shape.as_slice() {
+            [width, height, ..] if *width > 0 && *height > 0 => (*width, *height),
+            _ => anyhow::bail!("FITS primary HDU has no 2D image data"),
+        };
+        let plane = width * height;
+        let planes = if shape.get(2) == Some(&3) { 3 } else { 1 };
+        if values.len() < plane * planes {
+            anyhow::bail!("FITS data is shorter than its declared shape");
+        }
+
+        let stretched = Self::stretch(&values[..plane * planes], options);
+
+        // FITS rows run bottom to top
+        let pixel = |x: usize, y: usize, p: usize| stretched[p * plane + (height - 1 - y) * width + x];
+        let (width_u32, height_u32) = (width as u32, height as u32);
+        let img = if planes == 3 {
+            DynamicImage::ImageRgb16(ImageBuffer::from_fn(width_u32, height_u32, |x, y| {
+                let (x, y) = (x as usize, y as usize);
+                Rgb([pixel(x, y, 0), pixel(x, y, 1), pixel(x, y, 2)])
+            }))
+        } else {
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(width_u32, height_u32, |x, y| {
+                Luma([pixel(x as usize, y as usize, 0)])
+            }))
+        };
+
+        Ok(img)
+    }
+
+    /// Map raw values to 16 bits using percentile black/white points and the stretch
+    /// curve. Blank (NaN) samples become black.
+    #[cfg(feature = "fits")]
+    fn stretch(values: &[f64], options: &FitsOptions) -> Vec<u16> {
+        // Percentiles from a bounded sample keep this cheap on large frames
+        const SAMPLE_LIMIT: usize = 1 << 20;
+        let step = (values.len() / SAMPLE_LIMIT).max(1);
+        let mut sample: Vec<f64> = values.iter().step_by(step).copied().filter(|v| v.is_finite()).collect();
+        sample.sort_unstable_by(f64::total_cmp);
+
+        let percentile = |p: f64| -> f64 {
+            if sample.is_empty() {
+                return 0.0;
+            }
+            let index = ((p / 100.0) * (sample.len() - 1) as f64).round() as usize;
+            sample[index.min(sample.len() - 1)]
+        };
+        let black = percentile(options.black_percentile);
+        let range = (percentile(options.white_percentile) - black).max(f64::EPSILON);
+
+        let curve = |x: f64| match options.stretch {
+            FitsStretch::Linear => x,
+            FitsStretch::Log { scale } => (scale * x).ln_1p() / scale.ln_1p(),
+            FitsStretch::Asinh { softening } => (x / softening).asinh() / (1.0 / softening).asinh(),
+        };
+
+        values.iter()
+            .map(|&v| {
+                if !v.is_finite() {
+                    return 0;
+                }
+                let normalized = ((v - black) / range).clamp(0.0, 1.0);
+                (curve(normalized) * 65535.0).round() as u16
+            })
+            .collect()
+    }
+
+    #[cfg(not(feature = "fits"))]
+    pub fn load(_path: &str, _options: &FitsOptions) -> Result<image::DynamicImage> {
+        anyhow::bail!("FITS support is not enabled in this build")
+    }
+}
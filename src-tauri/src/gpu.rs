@@ -0,0 +1,384 @@
+use image::DynamicImage;
+#[cfg(feature = "gpu")]
+use image::RgbaImage;
+use serde::Serialize;
+#[cfg(feature = "gpu")]
+use std::sync::{Mutex, OnceLock};
+
+/// Separable Lanczos3 matching image's resize_exact: a vertical pass into an f32
+/// intermediate, then a horizontal pass that rounds and clamps to 8 bits. Pixels are
+/// packed RGBA8, red in the low byte.
+#[cfg(feature = "gpu")]
+const SHADER: &str = r#"
+struct Params {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> source: array<u32>;
+@group(0) @binding(2) var<storage, read_write> intermediate: array<vec4<f32>>;
+@group(0) @binding(3) var<storage, read_write> result: array<u32>;
+
+const PI: f32 = 3.14159265358979;
+
+fn sinc(x: f32) -> f32 {
+    if (x == 0.0) {
+        return 1.0;
+    }
+    let a = x * PI;
+    return sin(a) / a;
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if (abs(x) < 3.0) {
+        return sinc(x) * sinc(x / 3.0);
+    }
+    return 0.0;
+}
+
+fn unpack(pixel: u32) -> vec4<f32> {
+    return vec4<f32>(
+        f32(pixel & 0xffu),
+        f32((pixel >> 8u) & 0xffu),
+        f32((pixel >> 16u) & 0xffu),
+        f32(pixel >> 24u),
+    );
+}
+
+fn pack(value: vec4<f32>) -> u32 {
+    let c = vec4<u32>(round(clamp(value, vec4<f32>(0.0), vec4<f32>(255.0))));
+    return c.x | (c.y << 8u) | (c.z << 16u) | (c.w << 24u);
+}
+
+struct Window {
+    left: u32,
+    right: u32,
+    center: f32,
+    scale: f32,
+}
+
+// Source taps for one output position, as image::imageops computes them
+fn window(position: u32, src_len: u32, dst_len: u32) -> Window {
+    let ratio = f32(src_len) / f32(dst_len);
+    let scale = max(ratio, 1.0);
+    let support = 3.0 * scale;
+    let center = (f32(position) + 0.5) * ratio;
+    let left = u32(clamp(floor(center - support), 0.0, f32(src_len - 1u)));
+    let right = u32(clamp(ceil(center + support), f32(left + 1u), f32(src_len)));
+    return Window(left, right, center - 0.5, scale);
+}
+
+@compute @workgroup_size(8, 8)
+fn vertical(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.src_width || id.y >= params.dst_height) {
+        return;
+    }
+    let w = window(id.y, params.src_height, params.dst_height);
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = w.left; i < w.right; i++) {
+        let weight = lanczos3((f32(i) - w.center) / w.scale);
+        sum += weight * unpack(source[i * params.src_width + id.x]);
+        total += weight;
+    }
+    intermediate[id.y * params.src_width + id.x] = sum / total;
+}
+
+@compute @workgroup_size(8, 8)
+fn horizontal(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.dst_width || id.y >= params.dst_height) {
+        return;
+    }
+    let w = window(id.x, params.src_width, params.dst_width);
+    var sum = vec4<f32>(0.0);
+    var total = 0.0;
+    for (var i = w.left; i < w.right; i++) {
+        let weight = lanczos3((f32(i) - w.center) / w.scale);
+        sum += weight * intermediate[id.y * params.src_width + i];
+        total += weight;
+    }
+    result[id.y * params.dst_width + id.x] = pack(sum / total);
+}
+"#;
+
+#[cfg(feature = "gpu")]
+const WORKGROUP_SIZE: u32 = 8;
+
+/// Whether resizes can run on the GPU in this build and session
+#[derive(Serialize, Clone, Debug)]
+pub struct GpuStatus {
+    /// Built with the `gpu` feature
+    pub compiled: bool,
+    /// An adapter was found and the resize pipelines were created
+    pub active: bool,
+    pub adapter: Option<String>,
+    /// Graphics API in use (e.g. "Vulkan", "Metal", "Dx12")
+    pub backend: Option<String>,
+}
+
+#[cfg(feature = "gpu")]
+struct GpuContext {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    vertical: wgpu::ComputePipeline,
+    horizontal: wgpu::ComputePipeline,
+    adapter: String,
+    backend: String,
+    /// One resize at a time: error scopes are per device, and parallel batch workers
+    /// would otherwise oversubscribe video memory
+    busy: Mutex<()>,
+}
+
+/// Adapter and pipelines, set up on first use; None when there's no usable adapter
+#[cfg(feature = "gpu")]
+static CONTEXT: OnceLock<Option<GpuContext>> = OnceLock::new();
+
+pub struct GpuResizer;
+
+impl GpuResizer {
+    #[cfg(feature = "gpu")]
+    pub fn status() -> GpuStatus {
+        let context = Self::context();
+        GpuStatus {
+            compiled: true,
+            active: context.is_some(),
+            adapter: context.map(|context| context.adapter.clone()),
+            backend: context.map(|context| context.backend.clone()),
+        }
+    }
+
+    #[cfg(not(feature = "gpu"))]
+    pub fn status() -> GpuStatus {
+        GpuStatus {
+            compiled: false,
+            active: false,
+            adapter: None,
+            backend: None,
+        }
+    }
+
+    /// Lanczos3 resize on the GPU. None whenever the CPU path should be used instead:
+    /// no adapter, a pixel layout other than 8-bit RGB(A), buffers past the device's
+    /// limits, or any device error.
+    #[cfg(feature = "gpu")]
+    pub fn resize_exact(img: &DynamicImage, width: u32, height: u32) -> Option<DynamicImage> {
+        let context = Self::context()?;
+        let resized = match img {
+            DynamicImage::ImageRgba8(rgba) => DynamicImage::ImageRgba8(context.resize(rgba, width, height)?),
+            DynamicImage::ImageRgb8(_) => {
+                let rgba = context.resize(&img.to_rgba8(), width, height)?;
+                DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba).to_rgb8())
+            }
+            _ => return None,
+        };
+        Some(resized)
+    }
+
+    #[cfg(not(feature = "gpu"))]
+    pub fn resize_exact(_img: &DynamicImage, _width: u32, _height: u32) -> Option<DynamicImage> {
+        None
+    }
+
+    #[cfg(feature = "gpu")]
+    fn context() -> Option<&'static GpuContext> {
+        CONTEXT.get_or_init(|| pollster::block_on(GpuContext::new(false))).as_ref()
+    }
+}
+
+#[cfg(feature = "gpu")]
+impl GpuContext {
+    /// Software rasterizers are only accepted with `allow_software`, which the tests
+    /// use to run the shaders where there's no GPU
+    async fn new(allow_software: bool) -> Option<GpuContext> {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                compatible_surface: None,
+                force_fallback_adapter: false,
+            })
+            .await?;
+        let info = adapter.get_info();
+        // A software rasterizer is slower than the CPU resize it would replace
+        if info.device_type == wgpu::DeviceType::Cpu && !allow_software {
+            return None;
+        }
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("resize"),
+                    required_features: wgpu::Features::empty(),
+                    // Ask for everything the adapter offers so large images fit in one binding
+                    required_limits: adapter.limits(),
+                    memory_hints: wgpu::MemoryHints::Performance,
+                },
+                None,
+            )
+            .await
+            .ok()?;
+        // Errors are caught through error scopes; never let one abort the process
+        device.on_uncaptured_error(Box::new(|_| {}));
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("lanczos3"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(entry_point),
+                layout: None,
+                module: &module,
+                entry_point,
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let (vertical, horizontal) = (pipeline("vertical"), pipeline("horizontal"));
+
+        Some(GpuContext {
+            device,
+            queue,
+            vertical,
+            horizontal,
+            adapter: info.name,
+            backend: format!("{:?}", info.backend),
+            busy: Mutex::new(()),
+        })
+    }
+
+    fn resize(&self, source: &RgbaImage, width: u32, height: u32) -> Option<RgbaImage> {
+        use wgpu::util::DeviceExt;
+
+        let (src_width, src_height) = source.dimensions();
+        let input_bytes = src_width as u64 * src_height as u64 * 4;
+        let intermediate_bytes = src_width as u64 * height as u64 * 16;
+        let output_bytes = width as u64 * height as u64 * 4;
+
+        let limits = self.device.limits();
+        let max_binding = (limits.max_storage_buffer_binding_size as u64).min(limits.max_buffer_size);
+        let max_groups = limits.max_compute_workgroups_per_dimension;
+        if [input_bytes, intermediate_bytes, output_bytes].into_iter().any(|bytes| bytes == 0 || bytes > max_binding)
+            || [src_width, height, width].into_iter().any(|side| side.div_ceil(WORKGROUP_SIZE) > max_groups)
+        {
+            return None;
+        }
+
+        let _busy = self.busy.lock().unwrap_or_else(|e| e.into_inner());
+        self.device.push_error_scope(wgpu::ErrorFilter::OutOfMemory);
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+
+        let params: Vec<u8> = [src_width, src_height, width, height]
+            .into_iter()
+            .flat_map(u32::to_le_bytes)
+            .collect();
+        let params = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: &params,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let input = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("source"),
+            contents: source.as_raw(),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let buffer = |label: &str, size: u64, usage: wgpu::BufferUsages| {
+            self.device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size,
+                usage,
+                mapped_at_creation: false,
+            })
+        };
+        let intermediate = buffer("intermediate", intermediate_bytes, wgpu::BufferUsages::STORAGE);
+        let output = buffer("result", output_bytes, wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC);
+        let staging = buffer("staging", output_bytes, wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST);
+
+        // Auto layouts only contain the bindings each entry point uses
+        let bind_group = |pipeline: &wgpu::ComputePipeline, buffers: &[(u32, &wgpu::Buffer)]| {
+            let entries: Vec<wgpu::BindGroupEntry> = buffers.iter()
+                .map(|&(binding, buffer)| wgpu::BindGroupEntry { binding, resource: buffer.as_entire_binding() })
+                .collect();
+            self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: None,
+                layout: &pipeline.get_bind_group_layout(0),
+                entries: &entries,
+            })
+        };
+        let vertical_group = bind_group(&self.vertical, &[(0, &params), (1, &input), (2, &intermediate)]);
+        let horizontal_group = bind_group(&self.horizontal, &[(0, &params), (2, &intermediate), (3, &output)]);
+
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: None, timestamp_writes: None });
+            pass.set_pipeline(&self.vertical);
+            pass.set_bind_group(0, &vertical_group, &[]);
+            pass.dispatch_workgroups(src_width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE), 1);
+            pass.set_pipeline(&self.horizontal);
+            pass.set_bind_group(0, &horizontal_group, &[]);
+            pass.dispatch_workgroups(width.div_ceil(WORKGROUP_SIZE), height.div_ceil(WORKGROUP_SIZE), 1);
+        }
+        encoder.copy_buffer_to_buffer(&output, 0, &staging, 0, output_bytes);
+        self.queue.submit([encoder.finish()]);
+
+        let validation = pollster::block_on(self.device.pop_error_scope());
+        let out_of_memory = pollster::block_on(self.device.pop_error_scope());
+        if validation.is_some() || out_of_memory.is_some() {
+            return None;
+        }
+
+        let slice = staging.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |mapped| {
+            sender.send(mapped).ok();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv().ok()?.ok()?;
+
+        let pixels = slice.get_mapped_range().to_vec();
+        staging.unmap();
+        RgbaImage::from_raw(width, height, pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::photo;
+
+    #[cfg(feature = "gpu")]
+    #[test]
+    fn gpu_resize_matches_the_cpu_path() {
+        use image::imageops::FilterType;
+
+        // Any adapter will do to check the shaders, software ones included
+        let Some(context) = pollster::block_on(GpuContext::new(true)) else {
+            eprintln!("No wgpu adapter; skipping the GPU/CPU comparison");
+            return;
+        };
+
+        let img = DynamicImage::ImageRgba8(photo(301, 203).to_rgba8());
+        // Downscaling, upscaling and different factors per axis
+        for (width, height) in [(100, 67), (97, 203), (450, 150), (512, 400)] {
+            let gpu = context.resize(&img.to_rgba8(), width, height).expect("a GPU resize");
+            let cpu = img.resize_exact(width, height, FilterType::Lanczos3).to_rgba8();
+            assert_eq!(gpu.dimensions(), (width, height));
+
+            let worst = gpu.as_raw().iter().zip(cpu.as_raw())
+                .map(|(&a, &b)| a.abs_diff(b))
+                .max()
+                .unwrap();
+            assert!(worst <= 2, "{}x{}: off by up to {}", width, height, worst);
+        }
+    }
+
+    #[test]
+    fn unsupported_layouts_fall_back_to_the_cpu() {
+        let img = DynamicImage::ImageRgb16(photo(16, 16).to_rgb16());
+        assert!(GpuResizer::resize_exact(&img, 8, 8).is_none());
+        assert_eq!(GpuResizer::status().compiled, cfg!(feature = "gpu"));
+    }
+}
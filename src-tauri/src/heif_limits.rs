@@ -0,0 +1,329 @@
+use crate::metadata::{find_box, iter_boxes, read_be};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::RwLock;
+
+/// Structural caps checked before a HEIF file is handed to libheif. libheif-rs 1.x
+/// allocates and reads a HeifContext in one call, so libheif's own limits (its
+/// maximum image size and, from libheif 1.19, the context security limits) can't be
+/// set on it; these checks stand in for them. The defaults for the limits libheif
+/// also has are the values it uses for them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct HeifLimits {
+    /// Largest declared image (ispe) in pixels
+    pub max_image_pixels: u64,
+    /// Number of items in the file (images, tiles, metadata)
+    pub max_items: u32,
+    /// Extents a single item may be split into
+    pub max_iloc_extents_per_item: u32,
+    /// Total bytes a single item's extents may cover
+    pub max_item_bytes: u64,
+    /// Size of the meta box, which is read into memory whole before it's checked
+    pub max_meta_bytes: u64,
+}
+
+impl Default for HeifLimits {
+    fn default() -> Self {
+        HeifLimits {
+            max_image_pixels: 32768 * 32768,
+            max_items: 1000,
+            max_iloc_extents_per_item: 32,
+            max_item_bytes: 512 * 1024 * 1024,
+            max_meta_bytes: 64 * 1024 * 1024,
+        }
+    }
+}
+
+static LIMITS: RwLock<Option<HeifLimits>> = RwLock::new(None);
+
+/// A HEIF file declared more than a configured limit allows
+#[derive(Debug)]
+pub struct LimitExceeded {
+    pub limit: &'static str,
+    pub max: u64,
+    pub actual: u64,
+}
+
+impl std::fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HEIF file exceeds {} ({} > {})", self.limit, self.actual, self.max)
+    }
+}
+
+impl std::error::Error for LimitExceeded {}
+
+impl HeifLimits {
+    pub fn current() -> HeifLimits {
+        LIMITS.read().unwrap_or_else(|e| e.into_inner()).unwrap_or_default()
+    }
+
+    /// Replace the limits used for every HEIF file opened from now on
+    pub fn set(limits: HeifLimits) {
+        *LIMITS.write().unwrap_or_else(|e| e.into_inner()) = Some(limits);
+    }
+
+    /// Check a HEIF file's structure against the current limits. Called before
+    /// every HeifContext is created so libheif never parses an oversized file.
+    pub fn check_file(path: &str) -> Result<()> {
+        let limits = Self::current();
+        // Files without a meta box are left for libheif to reject
+        if let Some(meta) = limits.read_meta(path)? {
+            limits.check_meta(&meta)?;
+        }
+        Ok(())
+    }
+
+    /// Read the body of the top-level meta box, seeking past mdat and anything else
+    fn read_meta(&self, path: &str) -> Result<Option<Vec<u8>>> {
+        let mut file = std::fs::File::open(path).context("Failed to read HEIC file")?;
+        let len = file.metadata().context("Failed to read HEIC file")?.len();
+
+        let mut pos = 0u64;
+        while pos + 8 <= len {
+            let mut header = [0u8; 16];
+            file.seek(SeekFrom::Start(pos))?;
+            file.read_exact(&mut header[..8])?;
+
+            let (header_len, size) = match read_be(&header, 0, 4).unwrap_or(0) {
+                0 => (8, len - pos),
+                1 => {
+                    file.read_exact(&mut header[8..])?;
+                    let high = read_be(&header, 8, 4).unwrap_or(0) as u64;
+                    (16, (high << 32) | read_be(&header, 12, 4).unwrap_or(0) as u64)
+                }
+                size => (8, size as u64),
+            };
+            if size < header_len || size > len - pos {
+                return Ok(None);
+            }
+
+            if &header[4..8] == b"meta" {
+                exceeds("max_meta_bytes", self.max_meta_bytes, size - header_len)?;
+                let mut meta = vec![0; (size - header_len) as usize];
+                file.read_exact(&mut meta)?;
+                return Ok(Some(meta));
+            }
+            pos += size;
+        }
+
+        Ok(None)
+    }
+
+    /// Walk iinf, iloc and the ispe properties of a meta box body without decoding anything
+    pub fn check_meta(&self, meta: &[u8]) -> Result<(), LimitExceeded> {
+        // meta is a full box: skip version and flags
+        let Some(meta) = meta.get(4..) else {
+            return Ok(());
+        };
+
+        if let Some(iinf) = find_box(meta, b"iinf") {
+            let items = match iinf.first() {
+                Some(0) => read_be(iinf, 4, 2),
+                _ => read_be(iinf, 4, 4),
+            };
+            exceeds("max_items", self.max_items as u64, items.unwrap_or(0) as u64)?;
+        }
+
+        if let Some(iloc) = find_box(meta, b"iloc") {
+            self.check_iloc(iloc)?;
+        }
+
+        let properties = find_box(meta, b"iprp").and_then(|iprp| find_box(iprp, b"ipco"));
+        for (kind, body) in properties.into_iter().flat_map(iter_boxes) {
+            if kind == b"ispe" {
+                let width = read_be(body, 4, 4).unwrap_or(0) as u64;
+                let height = read_be(body, 8, 4).unwrap_or(0) as u64;
+                exceeds("max_image_pixels", self.max_image_pixels, width * height)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_iloc(&self, iloc: &[u8]) -> Result<(), LimitExceeded> {
+        let field = |pos: usize, size: usize| -> Option<u64> {
+            match size {
+                0 => Some(0),
+                4 => read_be(iloc, pos, 4).map(u64::from),
+                8 => Some((u64::from(read_be(iloc, pos, 4)?) << 32) | u64::from(read_be(iloc, pos + 4, 4)?)),
+                _ => None,
+            }
+        };
+
+        // A malformed table is left for libheif to reject
+        let parse = || -> Option<Result<(), LimitExceeded>> {
+            let version = *iloc.first()?;
+            let sizes = *iloc.get(4)?;
+            let (offset_size, length_size) = ((sizes >> 4) as usize, (sizes & 0x0f) as usize);
+            let sizes = *iloc.get(5)?;
+            let base_offset_size = (sizes >> 4) as usize;
+            let index_size = if version >= 1 { (sizes & 0x0f) as usize } else { 0 };
+
+            let id_size = if version < 2 { 2 } else { 4 };
+            let item_count = read_be(iloc, 6, id_size)?;
+            let mut pos = 6 + id_size;
+
+            for _ in 0..item_count {
+                pos += id_size;
+                if version >= 1 {
+                    // construction_method
+                    pos += 2;
+                }
+                // data_reference_index and base_offset
+                pos += 2 + base_offset_size;
+
+                let extent_count = read_be(iloc, pos, 2)?;
+                pos += 2;
+                if let Err(e) = exceeds("max_iloc_extents_per_item", self.max_iloc_extents_per_item as u64, extent_count as u64) {
+                    return Some(Err(e));
+                }
+
+                let mut item_bytes = 0u64;
+                for _ in 0..extent_count {
+                    pos += index_size + offset_size;
+                    item_bytes = item_bytes.saturating_add(field(pos, length_size)?);
+                    pos += length_size;
+                }
+                if let Err(e) = exceeds("max_item_bytes", self.max_item_bytes, item_bytes) {
+                    return Some(Err(e));
+                }
+            }
+
+            Some(Ok(()))
+        };
+
+        parse().unwrap_or(Ok(()))
+    }
+}
+
+fn exceeds(limit: &'static str, max: u64, actual: u64) -> Result<(), LimitExceeded> {
+    if actual > max {
+        return Err(LimitExceeded { limit, max, actual });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processor::ImageProcessor;
+    use crate::test_util::{gradient, TempDir};
+
+    fn boxed(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut data = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(body);
+        data
+    }
+
+    /// Full box with version 0 and no flags
+    fn full_box(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        boxed(kind, &[&[0u8; 4][..], body].concat())
+    }
+
+    /// Body of a version 0 meta box with `items` items of `extents` 100-byte extents
+    /// each, plus one ispe property of the given size
+    fn meta(items: u16, extents: u16, (width, height): (u32, u32)) -> Vec<u8> {
+        let mut iinf = items.to_be_bytes().to_vec();
+        for id in 1..=items {
+            let infe = [&id.to_be_bytes()[..], &[0, 0], b"hvc1", b"\0"].concat();
+            iinf.extend(boxed(b"infe", &[&[2, 0, 0, 0][..], &infe].concat()));
+        }
+
+        // 4-byte offsets and lengths, no base offset
+        let mut iloc = vec![0x44, 0x00];
+        iloc.extend_from_slice(&items.to_be_bytes());
+        for id in 1..=items {
+            iloc.extend_from_slice(&id.to_be_bytes());
+            iloc.extend_from_slice(&[0, 0]);
+            iloc.extend_from_slice(&extents.to_be_bytes());
+            for extent in 0..extents as u32 {
+                iloc.extend_from_slice(&(extent * 100).to_be_bytes());
+                iloc.extend_from_slice(&100u32.to_be_bytes());
+            }
+        }
+
+        let ispe = full_box(b"ispe", &[width.to_be_bytes(), height.to_be_bytes()].concat());
+        let iprp = boxed(b"iprp", &boxed(b"ipco", &ispe));
+
+        let mut body = vec![0u8; 4];
+        body.extend(full_box(b"hdlr", &[&[0u8; 4][..], b"pict", &[0u8; 13]].concat()));
+        body.extend(full_box(b"iinf", &iinf));
+        body.extend(full_box(b"iloc", &iloc));
+        body.extend(iprp);
+        body
+    }
+
+    /// A complete file around `meta`, with an mdat after it
+    fn heif_file(meta: &[u8]) -> Vec<u8> {
+        let mut file = boxed(b"ftyp", b"heic\0\0\0\0mif1heic");
+        file.extend(boxed(b"meta", meta));
+        file.extend(boxed(b"mdat", &[0u8; 64]));
+        file
+    }
+
+    fn rejection(meta: &[u8], limits: HeifLimits) -> Option<(&'static str, u64, u64)> {
+        limits.check_meta(meta).err().map(|e| (e.limit, e.max, e.actual))
+    }
+
+    #[test]
+    fn many_item_file_is_rejected_before_libheif_sees_it() {
+        let dir = TempDir::new();
+        let path = dir.write("many.heic", &heif_file(&meta(5000, 1, (64, 64))));
+
+        let error = HeifLimits::check_file(&path).unwrap_err();
+        let exceeded = error.downcast_ref::<LimitExceeded>().expect("a LimitExceeded error");
+        assert_eq!((exceeded.limit, exceeded.max, exceeded.actual), ("max_items", 1000, 5000));
+
+        // Loading goes through the same check
+        let error = ImageProcessor::load_image(&path).unwrap_err();
+        assert!(error.chain().any(|cause| cause.downcast_ref::<LimitExceeded>().is_some()), "{:#}", error);
+    }
+
+    #[test]
+    fn each_limit_names_itself() {
+        let limits = HeifLimits::default();
+        assert_eq!(rejection(&meta(3, 1, (64, 64)), limits), None);
+        assert_eq!(rejection(&meta(1001, 1, (64, 64)), limits), Some(("max_items", 1000, 1001)));
+        assert_eq!(rejection(&meta(2, 33, (64, 64)), limits), Some(("max_iloc_extents_per_item", 32, 33)));
+        assert_eq!(
+            rejection(&meta(1, 1, (40000, 40000)), limits),
+            Some(("max_image_pixels", 32768 * 32768, 1_600_000_000))
+        );
+
+        let small = HeifLimits { max_item_bytes: 1000, ..limits };
+        assert_eq!(rejection(&meta(1, 10, (64, 64)), small), None);
+        assert_eq!(rejection(&meta(1, 11, (64, 64)), small), Some(("max_item_bytes", 1000, 1100)));
+    }
+
+    #[test]
+    fn oversized_meta_box_names_its_own_limit() {
+        let dir = TempDir::new();
+        let body = meta(3, 1, (64, 64));
+        let path = dir.write("meta.heic", &heif_file(&body));
+
+        let small = HeifLimits { max_meta_bytes: body.len() as u64 - 1, ..HeifLimits::default() };
+        let error = small.read_meta(&path).unwrap_err();
+        let exceeded = error.downcast_ref::<LimitExceeded>().expect("a LimitExceeded error");
+        assert_eq!((exceeded.limit, exceeded.max, exceeded.actual), ("max_meta_bytes", body.len() as u64 - 1, body.len() as u64));
+
+        // Item sizes don't bound the meta box
+        let tight_items = HeifLimits { max_item_bytes: 1, ..HeifLimits::default() };
+        assert_eq!(tight_items.read_meta(&path).unwrap(), Some(body));
+    }
+
+    #[test]
+    fn limits_missing_from_saved_settings_take_their_defaults() {
+        let limits: HeifLimits = serde_json::from_value(serde_json::json!({ "max_items": 10 })).unwrap();
+        assert_eq!(limits, HeifLimits { max_items: 10, ..HeifLimits::default() });
+    }
+
+    #[test]
+    fn encoded_heic_passes_the_defaults() {
+        let dir = TempDir::new();
+        let heic = ImageProcessor::encode_heic(&gradient(64, 48), 80, None).unwrap();
+        HeifLimits::check_file(&dir.write("photo.heic", &heic)).unwrap();
+    }
+}
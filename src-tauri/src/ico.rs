@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use image::{DynamicImage, ImageFormat};
+use serde::{Deserialize, Serialize};
+
+pub const ICON_EXTENSIONS: &[&str] = &["ico", "cur"];
+
+/// One image stored in an ICO/CUR directory
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct IconFrame {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u16,
+    /// Stored as an embedded PNG rather than a BMP DIB
+    pub png: bool,
+}
+
+/// A directory entry with its location in the file
+struct Entry {
+    frame: IconFrame,
+    offset: usize,
+    size: usize,
+}
+
+pub struct IconProcessor;
+
+impl IconProcessor {
+    pub fn is_icon_extension(extension: &str) -> bool {
+        ICON_EXTENSIONS.contains(&extension)
+    }
+
+    /// List the frames of an ICO/CUR file in directory order
+    pub fn frames(path: &str) -> Result<Vec<IconFrame>> {
+        let data = std::fs::read(path).context("Failed to read icon file")?;
+        Ok(Self::entries(&data)?.into_iter().map(|entry| entry.frame).collect())
+    }
+
+    /// Decode one frame: the one matching `size` (the larger side in pixels), else the
+    /// smallest frame above it, else the largest frame. Without a size the largest
+    /// frame is used. Ties go to the higher bit depth.
+    pub fn load(path: &str, size: Option<u32>) -> Result<DynamicImage> {
+        let data = std::fs::read(path).context("Failed to read icon file")?;
+        let entries = Self::entries(&data)?;
+
+        let side = |entry: &&Entry| entry.frame.width.max(entry.frame.height);
+        let depth = |entry: &&Entry| entry.frame.bits_per_pixel;
+        let largest = entries.iter().max_by_key(|entry| (side(entry), depth(entry)));
+        let chosen = match size {
+            Some(size) => entries.iter()
+                .filter(|entry| side(entry) >= size)
+                .min_by_key(|entry| (side(entry), std::cmp::Reverse(depth(entry))))
+                .or(largest),
+            None => largest,
+        }
+        .context("Icon file has no frames")?;
+
+        let frame_data = data.get(chosen.offset..chosen.offset + chosen.size)
+            .context("Icon frame data is past the end of the file")?;
+        if chosen.frame.png {
+            return image::load_from_memory_with_format(frame_data, ImageFormat::Png)
+                .context("Failed to decode PNG icon frame");
+        }
+
+        // Re-wrap the DIB as a single-frame ICO so the image crate applies the AND mask.
+        // Cursors become plain icons here, which drops the hotspot.
+
+        // A directory size byte of 0 means 256
+        let size_byte = |side: u32| if side >= 256 { 0 } else { side as u8 };
+        let mut single = Vec::with_capacity(22 + frame_data.len());
+        single.extend_from_slice(&[0, 0, 1, 0, 1, 0]);
+        single.extend_from_slice(&[size_byte(chosen.frame.width), size_byte(chosen.frame.height), 0, 0]);
+        single.extend_from_slice(&1u16.to_le_bytes());
+        single.extend_from_slice(&chosen.frame.bits_per_pixel.to_le_bytes());
+        single.extend_from_slice(&(frame_data.len() as u32).to_le_bytes());
+        single.extend_from_slice(&22u32.to_le_bytes());
+        single.extend_from_slice(frame_data);
+
+        image::load_from_memory_with_format(&single, ImageFormat::Ico)
+            .context("Failed to decode icon frame")
+    }
+
+    /// Pack square PNG images, given as (side in pixels, PNG data), into an ICO file.
+    /// PNG frames are read by every Windows release since Vista and all browsers.
+    pub fn pack_png(frames: &[(u32, Vec<u8>)]) -> Vec<u8> {
+        let header_len = 6 + frames.len() * 16;
+        let mut ico = Vec::with_capacity(header_len + frames.iter().map(|(_, png)| png.len()).sum::<usize>());
+        ico.extend_from_slice(&[0, 0, 1, 0]);
+        ico.extend_from_slice(&(frames.len() as u16).to_le_bytes());
+
+        let mut offset = header_len;
+        for (side, png) in frames {
+            // A directory size byte of 0 means 256
+            let size_byte = if *side >= 256 { 0 } else { *side as u8 };
+            ico.extend_from_slice(&[size_byte, size_byte, 0, 0]);
+            ico.extend_from_slice(&1u16.to_le_bytes());
+            ico.extend_from_slice(&32u16.to_le_bytes());
+            ico.extend_from_slice(&(png.len() as u32).to_le_bytes());
+            ico.extend_from_slice(&(offset as u32).to_le_bytes());
+            offset += png.len();
+        }
+        for (_, png) in frames {
+            ico.extend_from_slice(png);
+        }
+        ico
+    }
+
+    /// Parse the ICONDIR header and entries. Dimensions and bit depth come from the
+    /// frame data itself, since directory bytes can't express sizes above 256 and
+    /// cursor entries reuse the planes/depth fields for the hotspot.
+    fn entries(data: &[u8]) -> Result<Vec<Entry>> {
+        let u16_at = |pos: usize| data.get(pos..pos + 2).map(|b| u16::from_le_bytes([b[0], b[1]]));
+        let u32_at = |pos: usize| data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+
+        if u16_at(0) != Some(0) || !matches!(u16_at(2), Some(1 | 2)) {
+            anyhow::bail!("Not an ICO or CUR file");
+        }
+        let count = u16_at(4).context("Icon header is truncated")? as usize;
+
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let pos = 6 + i * 16;
+            let (Some(size), Some(offset)) = (u32_at(pos + 8), u32_at(pos + 12)) else {
+                anyhow::bail!("Icon directory is truncated");
+            };
+            let (size, offset) = (size as usize, offset as usize);
+            let Some(frame_data) = data.get(offset..offset.saturating_add(size)) else {
+                // Skip entries pointing outside the file rather than failing the whole icon
+                continue;
+            };
+
+            let frame = if frame_data.starts_with(b"\x89PNG\r\n\x1a\n") {
+                let read = |pos: usize| frame_data.get(pos..pos + 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]));
+                let bits = match (frame_data.get(24), frame_data.get(25)) {
+                    // Bit depth times channels from IHDR
+                    (Some(&depth), Some(&color)) => depth as u16 * match color {
+                        2 => 3,
+                        4 => 2,
+                        6 => 4,
+                        _ => 1,
+                    },
+                    _ => 0,
+                };
+                IconFrame {
+                    width: read(16).unwrap_or(0),
+                    height: read(20).unwrap_or(0),
+                    bits_per_pixel: bits,
+                    png: true,
+                }
+            } else {
+                let read = |pos: usize| frame_data.get(pos..pos + 4).map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]));
+                // DIB height covers the color and AND mask bitmaps
+                IconFrame {
+                    width: read(4).unwrap_or(0),
+                    height: read(8).unwrap_or(0) / 2,
+                    bits_per_pixel: frame_data.get(14..16).map_or(0, |b| u16::from_le_bytes([b[0], b[1]])),
+                    png: false,
+                }
+            };
+
+            entries.push(Entry { frame, offset, size });
+        }
+
+        Ok(entries)
+    }
+}
@@ -1,101 +1,990 @@
-use image::{DynamicImage, ImageFormat, RgbaImage};
+use image::{ColorType, DynamicImage, GrayImage, ImageFormat, ImageOutputFormat, RgbImage, RgbaImage};
+use std::borrow::Cow;
+use std::io::{Cursor, Read, Seek, SeekFrom};
 use std::path::Path;
 use anyhow::{Context, Result};
-use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+use serde::{Deserialize, Serialize};
+use libheif_rs::{ColorSpace, DecodingOptions, HeifContext, ImageHandle, LibHeif, RgbChroma};
+use std::cell::RefCell;
+use std::sync::LazyLock;
+use crate::adjustments::{Adjustments, CropRect};
+use crate::bmp::BmpProcessor;
+use crate::ccitt::CcittG4;
+use crate::pcx::PcxProcessor;
+use crate::cancel::CancelToken;
+use crate::file_ops::FileOps;
+use crate::fits::{FitsOptions, FitsProcessor};
+use crate::heif_limits::{HeifLimits, LimitExceeded};
+use crate::ico::IconProcessor;
+use crate::metadata::{find_box, HeifTransformation, MetadataProcessor, TiffReader};
+use crate::raw::{self, RawProcessor};
+
+/// Shared libheif instance. It only holds the global plugin registry (decoding state
+/// lives in per-file contexts), so one instance serves every thread.
+static LIB_HEIF: LazyLock<LibHeif> = LazyLock::new(LibHeif::new);
+
+/// ftyp brands handled by libheif and the format reported for them, in priority
+/// order for files that only list them as compatible brands
+const HEIF_BRANDS: &[(&[u8; 4], &str)] = &[
+    (b"heic", "heic"),
+    (b"heix", "heic"),
+    (b"heim", "heic"),
+    (b"heis", "heic"),
+    (b"hevc", "heic"),
+    (b"hevx", "heic"),
+    (b"hevm", "heic"),
+    (b"hevs", "heic"),
+    (b"avif", "avif"),
+    (b"avis", "avif"),
+    (b"mif1", "heif"),
+    (b"mif2", "heif"),
+    (b"msf1", "heif"),
+];
+
+/// Extensions of the HEIF family, used when the file itself can't be sniffed
+const HEIF_EXTENSIONS: &[(&str, &str)] = &[
+    ("heic", "heic"),
+    ("heics", "heic"),
+    ("hif", "heic"),
+    ("heif", "heif"),
+    ("heifs", "heif"),
+    ("avci", "heif"),
+    ("avcs", "heif"),
+    ("avif", "avif"),
+    ("avifs", "avif"),
+];
+
+thread_local! {
+    /// TurboJPEG compressor per worker thread, created on first use and reused so
+    /// batches don't set up and tear down a handle per image
+    static JPEG_COMPRESSOR: RefCell<Option<turbojpeg::Compressor>> = const { RefCell::new(None) };
+}
+
+/// Options controlling how source images are decoded
+#[derive(Clone, Copy)]
+pub struct LoadOptions {
+    /// Rotate/flip pixels according to the EXIF orientation tag
+    pub auto_orient: bool,
+    pub heic: HeicDecodeOptions,
+    pub fits: FitsOptions,
+    /// Preferred ICO/CUR frame size; the largest frame when unset
+    pub icon_size: Option<u32>,
+    /// Decode RAW and image-crate formats from a memory-mapped file
+    pub memory_map: bool,
+    /// Caps for the image crate's decoders; its defaults when unset
+    pub limits: Option<DecodeLimits>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        LoadOptions {
+            auto_orient: true,
+            heic: HeicDecodeOptions::default(),
+            fits: FitsOptions::default(),
+            icon_size: None,
+            memory_map: false,
+            limits: None,
+        }
+    }
+}
+
+/// Caps the image crate's decoders enforce while decoding non-HEIF sources, so a
+/// small file declaring huge dimensions fails before anything is allocated. Unset
+/// fields keep image's defaults: no dimension cap and 512 MiB of allocations.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(default)]
+pub struct DecodeLimits {
+    pub max_image_width: Option<u32>,
+    pub max_image_height: Option<u32>,
+    /// Bytes a decoder may allocate, the decoded image included
+    pub max_alloc: Option<u64>,
+}
+
+impl DecodeLimits {
+    /// Whether an 8-bit RGB image of this size stays within the limits
+    fn allows(self, width: u32, height: u32) -> bool {
+        self.max_image_width.is_none_or(|max| width <= max)
+            && self.max_image_height.is_none_or(|max| height <= max)
+            && self.max_alloc.is_none_or(|max| width as u64 * height as u64 * 3 <= max)
+    }
+
+    fn to_image_limits(self) -> image::io::Limits {
+        let mut limits = image::io::Limits::default();
+        limits.max_image_width = self.max_image_width;
+        limits.max_image_height = self.max_image_height;
+        if let Some(max_alloc) = self.max_alloc {
+            limits.max_alloc = Some(max_alloc);
+        }
+        limits
+    }
+}
+
+/// libheif decoding options; all off by default, matching libheif's own defaults.
+///
+/// Orientation: every libheif release libheif-rs 1.x binds (the bundled build is
+/// 1.18) applies the irot, imir and clap properties during decode unless
+/// ignore_transformations is set, which skips all three together. libheif never
+/// applies the EXIF orientation tag of a HEIF, since the spec makes irot/imir
+/// authoritative and phones write both. To not depend on the installed version's
+/// option set, stored orientation is obtained by undoing irot/imir after a normal
+/// decode, so the clean aperture crop still applies.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct HeicDecodeOptions {
+    /// Skip the irot/imir/clap properties and return pixels in stored orientation
+    pub ignore_transformations: bool,
+    /// Whether the irot/imir orientation ends up applied to the pixels. Unset follows
+    /// auto_orient, so HEIC and JPEG sources come out the same way round.
+    pub apply_orientation: Option<bool>,
+    /// Let libheif reduce high bit depth images to 8 bits during decode
+    pub convert_hdr_to_8bit: bool,
+    /// Fail on spec violations libheif would otherwise tolerate
+    pub strict_decoding: bool,
+}
+
+impl HeicDecodeOptions {
+    /// Whether decoded pixels are in display orientation for the given auto_orient
+    pub fn orientation_applied(&self, auto_orient: bool) -> bool {
+        !self.ignore_transformations && self.apply_orientation.unwrap_or(auto_orient)
+    }
+}
+
+/// JPEG encoding beyond the baseline turbojpeg defaults, which every decoder reads
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct JpegEncodeOptions {
+    /// Arithmetic instead of Huffman entropy coding: typically 5-10% smaller, but
+    /// browsers and many other viewers can't decode arithmetic-coded JPEGs
+    pub arithmetic: bool,
+    /// Trellis quantization through mozjpeg, usually 10-15% smaller at the same
+    /// visual quality and still baseline-compatible. Slower, and only available in
+    /// builds with the "mozjpeg" feature. Selects mozjpeg when jpeg_encoder is unset.
+    pub trellis: bool,
+    /// Backend the JPEG is written with, resolved from the jpeg_encoder setting
+    #[serde(skip)]
+    pub encoder: JpegEncoder,
+}
+
+/// Library that writes JPEG output. Each produces slightly different files from the
+/// same pixels, so pinning one keeps outputs reproducible.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum JpegEncoder {
+    /// libjpeg-turbo: fast, and the only one offering arithmetic coding
+    #[default]
+    Turbo,
+    /// The pure-Rust encoder from the image crate
+    Image,
+    /// mozjpeg's trellis-quantized, progressive profile; builds with the "mozjpeg"
+    /// feature only
+    Mozjpeg,
+}
+
+/// Explicit output pixel layout
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    Luma8,
+    LumaA8,
+    Rgb8,
+    Rgba8,
+    Luma16,
+    LumaA16,
+    Rgb16,
+    Rgba16,
+}
+
+/// PNG speed/size tradeoff
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PngCompression {
+    /// fdeflate with a single fixed filter; several times faster on large photos
+    #[default]
+    Fast,
+    /// zlib default level with a fixed Paeth filter
+    Balanced,
+    /// Maximum zlib level with per-row adaptive filtering; slowest, smallest
+    Best,
+}
+
+impl PngCompression {
+    /// Apply the zlib level and row filtering of this tradeoff to a png encoder
+    pub fn configure<W: std::io::Write>(self, encoder: &mut png::Encoder<W>) {
+        let (level, filter, adaptive) = match self {
+            PngCompression::Fast => (png::Compression::Fast, png::FilterType::Up, png::AdaptiveFilterType::NonAdaptive),
+            PngCompression::Balanced => (png::Compression::Default, png::FilterType::Paeth, png::AdaptiveFilterType::NonAdaptive),
+            PngCompression::Best => (png::Compression::Best, png::FilterType::Paeth, png::AdaptiveFilterType::Adaptive),
+        };
+        encoder.set_compression(level);
+        encoder.set_filter(filter);
+        encoder.set_adaptive_filter(adaptive);
+    }
+}
+
+/// How max_width/max_height shape the output, after CSS object-fit
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ResizeMode {
+    /// Scale down until the image fits inside the box, keeping the aspect ratio
+    #[default]
+    Fit,
+    /// Scale until the image covers the box, keeping the aspect ratio, then cut the
+    /// centered box out of it. Needs both max_width and max_height.
+    Fill,
+    /// Resize to exactly the box, distorting the image when the aspect ratio
+    /// differs. Needs both max_width and max_height.
+    Stretch,
+}
+
+/// TIFF output compression
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TiffCompression {
+    None,
+    Lzw,
+    /// zlib; usually the smallest of the lossless options for photos
+    #[default]
+    Deflate,
+    /// CCITT Group 4 fax coding, for bilevel output only; a fraction of the size of
+    /// the other options on scanned text
+    CcittG4,
+}
+
+/// What to do with an animated source when the output is a single still image
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum AnimationPolicy {
+    /// Convert the first frame and report a warning in the result
+    #[default]
+    FirstFrame,
+    /// Fail the item
+    Error,
+}
+
+/// Writes one TIFF image with the shared tags encode_tiff adds
+struct TiffImageWriter<'a, W: std::io::Write + Seek> {
+    encoder: &'a mut tiff::encoder::TiffEncoder<W>,
+    width: u32,
+    height: u32,
+    orientation: u16,
+}
+
+impl<W: std::io::Write + Seek> TiffImageWriter<'_, W> {
+    /// Compressed integer samples are stored with the horizontal differencing
+    /// predictor, without which LZW and Deflate barely shrink photos
+    fn image<C, D>(&mut self, compression: D, samples: &[C::Inner]) -> tiff::TiffResult<()>
+    where
+        C: tiff::encoder::colortype::ColorType,
+        C::Inner: TiffSample,
+        D: tiff::encoder::compression::Compression,
+        [C::Inner]: tiff::encoder::TiffValue,
+    {
+        let compressed = D::COMPRESSION_METHOD != tiff::tags::CompressionMethod::None;
+        let channels = C::BITS_PER_SAMPLE.len();
+        let differenced = compressed
+            .then(|| C::Inner::differenced(samples, self.width as usize * channels, channels))
+            .flatten();
+
+        let mut image = self.encoder.new_image_with_compression::<C, D>(self.width, self.height, compression)?;
+        image.encoder().write_tag(tiff::tags::Tag::Orientation, self.orientation)?;
+        if differenced.is_some() {
+            image.encoder().write_tag(tiff::tags::Tag::Predictor, tiff::tags::Predictor::Horizontal.to_u16())?;
+        }
+        image.write_data(differenced.as_deref().unwrap_or(samples))
+    }
+}
+
+/// Sample types of TIFF output
+trait TiffSample: Copy {
+    /// Each sample minus the same channel of the pixel to its left (TIFF predictor
+    /// 2), for rows of `row_len` samples with `channels` per pixel. None for sample
+    /// types the predictor doesn't apply to.
+    fn differenced(samples: &[Self], row_len: usize, channels: usize) -> Option<Vec<Self>>;
+}
+
+impl TiffSample for u8 {
+    fn differenced(samples: &[Self], row_len: usize, channels: usize) -> Option<Vec<Self>> {
+        Some(difference_rows(samples, row_len, channels, u8::wrapping_sub))
+    }
+}
+
+impl TiffSample for u16 {
+    fn differenced(samples: &[Self], row_len: usize, channels: usize) -> Option<Vec<Self>> {
+        Some(difference_rows(samples, row_len, channels, u16::wrapping_sub))
+    }
+}
+
+impl TiffSample for f32 {
+    fn differenced(_: &[Self], _: usize, _: usize) -> Option<Vec<Self>> {
+        None
+    }
+}
+
+fn difference_rows<T: Copy>(samples: &[T], row_len: usize, channels: usize, sub: fn(T, T) -> T) -> Vec<T> {
+    let mut differenced = samples.to_vec();
+    for row in differenced.chunks_exact_mut(row_len) {
+        // Right to left, so every sample is differenced against its original neighbor
+        for i in (channels..row.len()).rev() {
+            row[i] = sub(row[i], row[i - channels]);
+        }
+    }
+    differenced
+}
+
+/// Netpbm extensions; the variant is taken from the file's magic number
+pub const PNM_EXTENSIONS: &[&str] = &["pbm", "pgm", "ppm", "pnm", "pam"];
+
+/// libwebp's own default method, a balance between encode speed and size
+pub const DEFAULT_WEBP_METHOD: u8 = 4;
+
+/// Longest side of the preview avif_preview embeds
+pub const AVIF_PREVIEW_SIZE: u32 = 320;
 
 pub struct ImageProcessor;
 
+/// Where one decoded TIFF chunk sits relative to a requested region
+struct ChunkCopy {
+    rect: CropRect,
+    /// Top-left of the chunk in the image
+    origin: (u32, u32),
+    data_width: u32,
+    data_height: u32,
+    samples: usize,
+}
+
+impl ChunkCopy {
+    /// Copy the part of `chunk` inside the region into `region`, both row-major
+    fn copy<T: Copy>(&self, chunk: &[T], region: &mut [T]) {
+        let rect = self.rect;
+        let left = rect.x.max(self.origin.0);
+        let right = (rect.x + rect.width).min(self.origin.0 + self.data_width);
+        let top = rect.y.max(self.origin.1);
+        let bottom = (rect.y + rect.height).min(self.origin.1 + self.data_height);
+        if left >= right {
+            return;
+        }
+
+        let run = (right - left) as usize * self.samples;
+        for y in top..bottom {
+            let from = ((y - self.origin.1) as usize * self.data_width as usize + (left - self.origin.0) as usize) * self.samples;
+            let to = ((y - rect.y) as usize * rect.width as usize + (left - rect.x) as usize) * self.samples;
+            if let (Some(source), Some(target)) = (chunk.get(from..from + run), region.get_mut(to..to + run)) {
+                target.copy_from_slice(source);
+            }
+        }
+    }
+}
+
 impl ImageProcessor {
     pub fn load_image(path: &str) -> Result<DynamicImage> {
-        let extension = Path::new(path)
-            .extension()
-            .and_then(|s| s.to_str())
-            .unwrap_or("")
-            .to_lowercase();
+        Self::load_image_with_options(path, &LoadOptions::default())
+            .map(|(img, _)| img)
+    }
 
-        // Check if HEIC/HEIF format
-        let mut img = if extension == "heic" || extension == "heif" {
-            Self::load_heic(path)?
-        } else {
-            image::open(path).context("Failed to open image")?
+    /// Decode a source image, also returning the name of the decoder that was used.
+    /// When the decoder picked from the extension fails, the file's leading bytes
+    /// choose a second one (see decode_fallback); the name then says so.
+    pub fn load_image_with_options(path: &str, options: &LoadOptions) -> Result<(DynamicImage, &'static str)> {
+        let extension = Self::get_format(path)?;
+        let heif = Self::is_heif_format(&extension);
+
+        let (mut img, decoder, heif) = match Self::decode(path, &extension, options) {
+            Ok((img, decoder)) => (img, decoder, heif),
+            Err(e) if Self::retryable(&e) => match Self::decode_fallback(path, heif, options) {
+                Some(decoded) => decoded,
+                // The first decoder's error says more about what's wrong with the file
+                None => return Err(e),
+            },
+            Err(e) => return Err(e),
         };
 
-        // Apply EXIF orientation (for non-HEIC, HEIC orientation is handled during decode)
-        if extension != "heic" && extension != "heif" {
+        // Apply EXIF orientation (HEIC orientation comes from irot/imir, see load_heic_oriented)
+        if options.auto_orient && !heif {
             img = Self::apply_exif_orientation(path, img)?;
         }
 
-        Ok(img)
+        Ok((img, decoder))
+    }
+
+    /// Decode with the decoder the format reported by get_format calls for
+    fn decode(path: &str, extension: &str, options: &LoadOptions) -> Result<(DynamicImage, &'static str)> {
+        let decoded = if Self::is_heif_format(extension) {
+            (Self::load_heic_oriented(path, options)?, "libheif")
+        } else if RawProcessor::is_raw_extension(extension) {
+            // Camera-rendered embedded JPEG instead of demosaicing the sensor data
+            let preview = match options.memory_map.then(|| FileOps::map(path).ok()).flatten() {
+                Some(map) => RawProcessor::preview_from(&map)?,
+                None => RawProcessor::extract_preview(path)?,
+            };
+            let reader = image::io::Reader::with_format(Cursor::new(&preview), ImageFormat::Jpeg);
+            let img = Self::decode_limited(reader, options.limits)
+                .context("Failed to decode embedded RAW preview")?;
+            (img, raw::PREVIEW_DECODER)
+        } else if FitsProcessor::is_fits_extension(extension) {
+            (FitsProcessor::load(path, &options.fits)?, "fitrs")
+        } else if extension == "webp" && Self::frame_count(path)? > 1 {
+            // Take the first composited frame explicitly rather than relying on
+            // whatever the still-image path does with an animation
+            let file = std::fs::File::open(path).context("Failed to open image")?;
+            let decoder = image::codecs::webp::WebPDecoder::new(std::io::BufReader::new(file))
+                .context("Failed to read WebP header")?;
+            let frame = image::AnimationDecoder::into_frames(decoder)
+                .next()
+                .context("Animated WebP has no frames")?
+                .context("Failed to decode first WebP frame")?;
+            (DynamicImage::ImageRgba8(frame.into_buffer()), "image-webp (first frame)")
+        } else if PNM_EXTENSIONS.contains(&extension) {
+            let reader = image::io::Reader::open(path)
+                .context("Failed to open image")?
+                .with_guessed_format()
+                .context("Failed to read netpbm header")?;
+            let img = Self::decode_limited(reader, options.limits)
+                .context("Failed to decode netpbm image")?;
+            (img, "image-pnm")
+        } else if IconProcessor::is_icon_extension(extension) {
+            (IconProcessor::load(path, options.icon_size)?, "image-ico")
+        } else if extension == "pcx" {
+            (PcxProcessor::load(path)?, "pcx (built-in)")
+        } else if extension == "bmp" && BmpProcessor::needs_fallback(path)? {
+            (BmpProcessor::load(path)?, "bmp (built-in)")
+        } else if extension == "bmp" {
+            // Anything the image crate still can't read gets a second chance
+            match Self::open_limited(path, options.limits) {
+                Ok(img) => (img, "image-bmp"),
+                Err(_) => (BmpProcessor::load(path)?, "bmp (built-in)"),
+            }
+        } else {
+            // image::open picks its decoder from the extension
+            let format = ImageFormat::from_path(path).ok();
+            let decoder = match format {
+                Some(ImageFormat::Jpeg) => "image-jpeg",
+                Some(ImageFormat::Png) => "image-png",
+                Some(ImageFormat::Bmp) => "image-bmp",
+                Some(ImageFormat::Tiff) => "image-tiff",
+                Some(ImageFormat::WebP) => "image-webp",
+                // The TGA decoder expands RLE and flips to a top-left origin itself
+                Some(ImageFormat::Tga) => "image-tga",
+                _ => "image",
+            };
+            // Files that can't be mapped (pipes, some network shares) are read as usual
+            let mapped = options.memory_map.then(|| FileOps::map(path).ok()).flatten();
+            let img = match (mapped, format) {
+                (Some(map), Some(format)) => {
+                    Self::decode_limited(image::io::Reader::with_format(Cursor::new(&map[..]), format), options.limits)
+                }
+                _ => Self::open_limited(path, options.limits),
+            };
+            (img.context("Failed to open image")?, decoder)
+        };
+
+        Ok(decoded)
+    }
+
+    /// Whether another decoder could succeed where the first one failed. Missing or
+    /// unreadable files and exceeded limits fail the same way whatever decodes them.
+    fn retryable(error: &anyhow::Error) -> bool {
+        !error.chain().any(|cause| {
+            let io = cause.downcast_ref::<std::io::Error>().or(match cause.downcast_ref::<image::ImageError>() {
+                Some(image::ImageError::IoError(io)) => Some(io),
+                _ => None,
+            });
+            cause.is::<LimitExceeded>()
+                || matches!(cause.downcast_ref::<image::ImageError>(), Some(image::ImageError::Limits(_)))
+                || io.is_some_and(|io| matches!(io.kind(), std::io::ErrorKind::NotFound | std::io::ErrorKind::PermissionDenied))
+        })
+    }
+
+    /// Second attempt at a file the extension's decoder couldn't read, picked from
+    /// its contents: turbojpeg for anything that starts like a JPEG, which also
+    /// covers JPEGs named .heic and ones the image crate rejects, and libheif for
+    /// ISOBMFF files whose brands didn't mark them as HEIF. Returns the image, the
+    /// decoder name and whether it was decoded as HEIF, or None when neither applies
+    /// or the second decoder fails too.
+    fn decode_fallback(path: &str, primary_heif: bool, options: &LoadOptions) -> Option<(DynamicImage, &'static str, bool)> {
+        let mut header = [0u8; 12];
+        let mut file = std::fs::File::open(path).ok()?;
+        file.read_exact(&mut header).ok()?;
+
+        if header.starts_with(&[0xFF, 0xD8, 0xFF]) {
+            let data = std::fs::read(path).ok()?;
+            // turbojpeg doesn't take image's limits, so check them against the header
+            let jpeg = turbojpeg::read_header(&data).ok()?;
+            if !options.limits.unwrap_or_default().allows(jpeg.width as u32, jpeg.height as u32) {
+                return None;
+            }
+            // No scale fits u32::MAX, so the full size is decoded
+            let img = Self::decode_jpeg_scaled(&data, u32::MAX).ok()?;
+            return Some((img, "turbojpeg (fallback)", false));
+        }
+
+        if &header[4..8] == b"ftyp" && !primary_heif {
+            let img = Self::load_heic_oriented(path, options).ok()?;
+            return Some((img, "libheif (fallback)", true));
+        }
+
+        None
+    }
+
+    /// Decode a HEIF through libheif, keeping stored orientation unless the options
+    /// have it applied
+    fn load_heic_oriented(path: &str, options: &LoadOptions) -> Result<DynamicImage> {
+        let img = Self::load_heic(path, &options.heic)?;
+        if options.heic.ignore_transformations {
+            return Ok(img);
+        }
+        let transformations = MetadataProcessor::heif_transformations(path)?;
+        if options.heic.orientation_applied(options.auto_orient) {
+            // libheif has applied irot/imir, if any; an orientation only EXIF records is left to us
+            return Ok(match Self::heif_exif_orientation(path, &transformations)? {
+                Some(orientation) => Self::orient(img, orientation),
+                None => img,
+            });
+        }
+        // libheif rotated and mirrored during decode; turn the pixels back
+        Ok(Self::orient(img, inverse_orientation(MetadataProcessor::heif_orientation(&transformations))))
+    }
+
+    /// EXIF orientation of a HEIF whose primary image has no irot/imir. libheif
+    /// orients by those properties alone, so files that record orientation only in
+    /// their EXIF block would otherwise come out sideways. When irot/imir are present
+    /// they're authoritative and libheif has already applied them, so the EXIF tag is
+    /// ignored rather than applied a second time.
+    pub fn heif_exif_orientation(path: &str, transformations: &[HeifTransformation]) -> Result<Option<u32>> {
+        if transformations.iter().any(|t| !matches!(t, HeifTransformation::CleanAperture)) {
+            return Ok(None);
+        }
+        Ok(Self::read_exif_orientation(path)?.filter(|orientation| (2..=8).contains(orientation)))
+    }
+
+    /// image::open with `limits` applied; the decoder is still picked by extension
+    fn open_limited(path: &str, limits: Option<DecodeLimits>) -> image::ImageResult<DynamicImage> {
+        let reader = image::io::Reader::open(path).map_err(image::ImageError::IoError)?;
+        Self::decode_limited(reader, limits)
+    }
+
+    /// Decode with `limits` in place of image's default limits
+    fn decode_limited<R: std::io::BufRead + Seek>(
+        mut reader: image::io::Reader<R>,
+        limits: Option<DecodeLimits>,
+    ) -> image::ImageResult<DynamicImage> {
+        if let Some(limits) = limits {
+            reader.limits(limits.to_image_limits());
+        }
+        reader.decode()
+    }
+
+    /// Decode an image held in memory, such as one piped in on stdin. The format is
+    /// sniffed from the data; HEIF goes through libheif and everything else through
+    /// the image crate. Orientation is always applied, since there's no file to carry
+    /// an orientation tag over from.
+    pub fn load_from_memory(data: &[u8], options: &LoadOptions) -> Result<(DynamicImage, &'static str)> {
+        if Self::heif_brand(data).is_some() {
+            // Same structural checks as files get before libheif sees them
+            if let Some(meta) = find_box(data, b"meta") {
+                HeifLimits::current().check_meta(meta)?;
+            }
+            let ctx = HeifContext::read_from_bytes(data)
+                .context("Failed to read HEIC data")?;
+            let handle = ctx.primary_image_handle()
+                .context("Failed to get primary image handle")?;
+            return Ok((Self::decode_heic(&handle, &options.heic)?, "libheif"));
+        }
+
+        let format = image::guess_format(data).context("Unrecognized image data")?;
+        let img = Self::decode_limited(image::io::Reader::with_format(Cursor::new(data), format), options.limits)
+            .context("Failed to decode image")?;
+        let orientation = exif::Reader::new()
+            .read_from_container(&mut Cursor::new(data))
+            .ok()
+            .and_then(|exif| exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY)?.value.get_uint(0));
+
+        Ok((match orientation {
+            Some(orientation) => Self::orient(img, orientation),
+            None => img,
+        }, "image"))
+    }
+
+    /// Read the image currently on the system clipboard, if there is one
+    #[cfg(feature = "clipboard")]
+    pub fn load_clipboard_image() -> Result<Option<DynamicImage>> {
+        let mut clipboard = arboard::Clipboard::new().context("Failed to access clipboard")?;
+        let data = match clipboard.get_image() {
+            Ok(data) => data,
+            Err(arboard::Error::ContentNotAvailable) => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read clipboard image"),
+        };
+
+        let rgba = RgbaImage::from_raw(data.width as u32, data.height as u32, data.bytes.into_owned())
+            .context("Clipboard image has an unexpected size")?;
+        Ok(Some(DynamicImage::ImageRgba8(rgba)))
+    }
+
+    /// Read image dimensions from the file header without decoding pixels
+    pub fn peek_dimensions(path: &str) -> Result<(u32, u32)> {
+        let extension = Self::get_format(path)?;
+
+        if Self::is_heif_format(&extension) {
+            HeifLimits::check_file(path)?;
+            let ctx = HeifContext::read_from_file(path)
+                .context("Failed to read HEIC file")?;
+            let handle = ctx.primary_image_handle()
+                .context("Failed to get primary image handle")?;
+            return Ok((handle.width(), handle.height()));
+        }
+
+        if RawProcessor::is_raw_extension(&extension) {
+            let data = std::fs::read(path).context("Failed to read RAW file")?;
+            let preview = RawProcessor::adequate_preview(&data)?;
+            return Ok((preview.width, preview.height));
+        }
+
+        if extension == "bmp" {
+            return BmpProcessor::dimensions(path);
+        }
+
+        if extension == "pcx" {
+            return PcxProcessor::dimensions(path);
+        }
+
+        image::image_dimensions(path).context("Failed to read image dimensions")
+    }
+
+    /// Number of animation frames; 1 for still images and formats without animation.
+    /// WebP is the only animated input format read so far.
+    pub fn frame_count(path: &str) -> Result<u32> {
+        if Self::get_format(path)? != "webp" {
+            return Ok(1);
+        }
+
+        let mut file = std::fs::File::open(path).context("Failed to open image")?;
+        let mut header = [0u8; 12];
+        if file.read_exact(&mut header).is_err() || &header[0..4] != b"RIFF" || &header[8..12] != b"WEBP" {
+            return Ok(1);
+        }
+
+        // Walk the RIFF chunks: only a VP8X header with the animation flag can
+        // introduce ANMF frame chunks
+        let mut animated = false;
+        let mut frames = 0u32;
+        let mut chunk = [0u8; 9];
+        while file.read_exact(&mut chunk[..8]).is_ok() {
+            let size = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as i64;
+            // Chunks are padded to an even size
+            let mut skip = size + (size & 1);
+            match &chunk[0..4] {
+                b"VP8X" => {
+                    if file.read_exact(&mut chunk[8..9]).is_err() {
+                        break;
+                    }
+                    animated = chunk[8] & 0x02 != 0;
+                    skip -= 1;
+                }
+                b"ANMF" => frames += 1,
+                _ => {}
+            }
+            if file.seek(SeekFrom::Current(skip)).is_err() {
+                break;
+            }
+        }
+
+        Ok(if animated { frames.max(1) } else { 1 })
+    }
+
+    /// Number of images/pages in the file (TIFF IFD chain, HEIC top-level images), 1 otherwise
+    pub fn page_count(path: &str) -> Result<u32> {
+        let extension = Self::get_format(path)?;
+
+        if Self::is_heif_format(&extension) {
+            HeifLimits::check_file(path)?;
+            let ctx = HeifContext::read_from_file(path)
+                .context("Failed to read HEIC file")?;
+            return Ok(ctx.number_of_top_level_images().max(1) as u32);
+        }
+
+        if extension == "tif" || extension == "tiff" {
+            let data = std::fs::read(path).context("Failed to read TIFF file")?;
+            let reader = TiffReader::new(&data).context("Invalid TIFF header")?;
+
+            let mut count = 0u32;
+            let mut visited = Vec::new();
+            let mut next = reader.first_ifd();
+            while let Some(ifd) = next.filter(|&ifd| ifd != 0 && !visited.contains(&ifd)) {
+                visited.push(ifd);
+                count += 1;
+                next = reader.u16(ifd)
+                    .and_then(|entries| reader.u32(ifd + 2 + entries as usize * 12))
+                    .map(|offset| offset as usize);
+            }
+
+            return Ok(count.max(1));
+        }
+
+        Ok(1)
     }
 
-    /// Load HEIC thumbnail for fast preview (doesn't decode full image)
-    pub fn load_heic_thumbnail(path: &str, max_size: u32) -> Result<DynamicImage> {
-        let lib_heif = LibHeif::new();
+    /// Load HEIC preview for display. Uses the smallest embedded thumbnail that still
+    /// covers `max_size` so decode cost scales with the preview size, and only falls
+    /// back to a full decode when no thumbnail is large enough.
+    pub fn load_heic_thumbnail(path: &str, max_size: u32, cancel: &CancelToken) -> Result<DynamicImage> {
+        HeifLimits::check_file(path)?;
         let ctx = HeifContext::read_from_file(path)
             .context("Failed to read HEIC file")?;
 
         let handle = ctx.primary_image_handle()
             .context("Failed to get primary image handle")?;
+        // Cheaper to turn the thumbnail than the image it's made from
+        let exif_orientation = Self::heif_exif_orientation(path, &MetadataProcessor::heif_transformations(path)?)?;
+        let finish = |img: DynamicImage| match exif_orientation {
+            Some(orientation) => Self::orient(Self::thumbnail(&img, max_size), orientation),
+            None => Self::thumbnail(&img, max_size),
+        };
 
-        // Try to get embedded thumbnail first (much faster)
         let thumb_count = handle.number_of_thumbnails();
-        if thumb_count > 0 {
-            let mut thumb_ids = vec![0u32; thumb_count];
-            let actual_count = handle.thumbnail_ids(&mut thumb_ids);
-            if actual_count > 0 {
-                if let Ok(thumb_handle) = handle.thumbnail(thumb_ids[0]) {
-                    if let Ok(thumb_image) = lib_heif.decode(&thumb_handle, ColorSpace::Rgb(RgbChroma::Rgba), None) {
-                        let planes = thumb_image.planes();
-                        if let Some(interleaved) = planes.interleaved {
-                            let width = thumb_image.width();
-                            let height = thumb_image.height();
-                            let stride = interleaved.stride;
-                            let data = interleaved.data;
-
-                            let mut rgba_data = Vec::with_capacity((width * height * 4) as usize);
-                            for y in 0..height {
-                                let row_start = (y as usize) * stride;
-                                let row_end = row_start + (width as usize * 4);
-                                if row_end <= data.len() {
-                                    rgba_data.extend_from_slice(&data[row_start..row_end]);
-                                }
-                            }
-
-                            if let Some(rgba_image) = RgbaImage::from_raw(width, height, rgba_data) {
-                                return Ok(DynamicImage::ImageRgba8(rgba_image));
-                            }
-                        }
-                    }
+        let mut thumb_ids = vec![0u32; thumb_count];
+        let actual_count = handle.thumbnail_ids(&mut thumb_ids);
+
+        let best_thumbnail = thumb_ids[..actual_count]
+            .iter()
+            .filter_map(|&id| handle.thumbnail(id).ok())
+            .filter(|thumb| thumb.width().max(thumb.height()) >= max_size)
+            .min_by_key(|thumb| thumb.width() as u64 * thumb.height() as u64);
+
+        if let Some(thumb_handle) = best_thumbnail {
+            if let Ok(thumb_image) = LIB_HEIF.decode(&thumb_handle, ColorSpace::Rgb(RgbChroma::Rgba), None) {
+                if let Ok(img) = Self::heif_image_to_dynamic(&thumb_image) {
+                    return Ok(finish(img));
+                }
+            }
+        }
+
+        if cancel.is_cancelled() {
+            anyhow::bail!("Preview cancelled");
+        }
+
+        // Fallback: decode full image and resize, reusing the open context
+        let img = Self::decode_heic(&handle, &HeicDecodeOptions::default())?;
+
+        if cancel.is_cancelled() {
+            anyhow::bail!("Preview cancelled");
+        }
+
+        Ok(finish(img))
+    }
+
+    /// Oriented thumbnail whose longest side is at most `max_size`, taking the cheapest
+    /// route available: an embedded HEIF thumbnail, the EXIF thumbnail or a DCT-scaled
+    /// decode for JPEG, and a full decode otherwise. Also returns the route taken.
+    pub fn load_thumbnail(path: &str, max_size: u32) -> Result<(DynamicImage, &'static str)> {
+        let extension = Self::get_format(path)?;
+        if Self::is_heif_format(&extension) {
+            let img = Self::load_heic_thumbnail(path, max_size, &CancelToken::default())?;
+            return Ok((img, "libheif (thumbnail)"));
+        }
+
+        if matches!(extension.as_str(), "jpg" | "jpeg") {
+            let data = std::fs::read(path).context("Failed to read image")?;
+            let exif_thumbnail = MetadataProcessor::exif_thumbnail(&data)
+                .and_then(|thumbnail| image::load_from_memory_with_format(&thumbnail, ImageFormat::Jpeg).ok())
+                .filter(|thumbnail| thumbnail.width().max(thumbnail.height()) >= max_size);
+            let (img, decoder) = match exif_thumbnail {
+                Some(img) => (img, "exif thumbnail"),
+                None => match Self::decode_jpeg_scaled(&data, max_size) {
+                    Ok(img) => (img, "turbojpeg (scaled)"),
+                    // Fall through to the image crate for anything turbojpeg refuses
+                    Err(_) => (image::load_from_memory_with_format(&data, ImageFormat::Jpeg)
+                        .context("Failed to decode JPEG")?, "image-jpeg"),
+                },
+            };
+            // The EXIF orientation covers the thumbnail too
+            let img = Self::apply_exif_orientation(path, img)?;
+            return Ok((Self::thumbnail(&img, max_size), decoder));
+        }
+
+        let (img, decoder) = Self::load_image_with_options(path, &LoadOptions::default())?;
+        Ok((Self::thumbnail(&img, max_size), decoder))
+    }
+
+    /// Decode just `rect` (in oriented source coordinates) of an image without decoding
+    /// the rest. Only TIFFs stored upright in tiles or strips with interleaved samples
+    /// allow this; None for anything else, which needs a full decode and crop.
+    pub fn read_region(path: &str, rect: CropRect) -> Result<Option<DynamicImage>> {
+        let extension = Self::get_format(path)?;
+        if !matches!(extension.as_str(), "tif" | "tiff") {
+            return Ok(None);
+        }
+        use tiff::decoder::{Decoder, DecodingResult};
+        use tiff::tags::Tag;
+        use tiff::ColorType as TiffColor;
+
+        let file = std::fs::File::open(path).context("Failed to open TIFF")?;
+        let mut decoder = Decoder::new(std::io::BufReader::new(file)).context("Failed to read TIFF header")?;
+
+        let tag = |decoder: &mut Decoder<_>, tag| decoder.find_tag_unsigned::<u16>(tag).ok().flatten();
+        let rotated = tag(&mut decoder, Tag::Orientation).is_some_and(|orientation| orientation != 1);
+        let planar = tag(&mut decoder, Tag::PlanarConfiguration) == Some(2);
+        let white_is_zero = tag(&mut decoder, Tag::PhotometricInterpretation) == Some(0);
+        if rotated || planar || white_is_zero {
+            return Ok(None);
+        }
+
+        let (samples, bits) = match decoder.colortype().context("Failed to read TIFF color type")? {
+            TiffColor::Gray(bits @ (8 | 16)) => (1, bits),
+            TiffColor::GrayA(bits @ (8 | 16)) => (2, bits),
+            TiffColor::RGB(bits @ (8 | 16)) => (3, bits),
+            TiffColor::RGBA(bits @ (8 | 16)) => (4, bits),
+            _ => return Ok(None),
+        };
+        let sixteen_bit = bits == 16;
+
+        let (width, height) = decoder.dimensions()?;
+        let fits = rect.width > 0
+            && rect.height > 0
+            && rect.x as u64 + rect.width as u64 <= width as u64
+            && rect.y as u64 + rect.height as u64 <= height as u64;
+        if !fits {
+            return Ok(None);
+        }
+        let (chunk_width, chunk_height) = decoder.chunk_dimensions();
+        let chunks_across = width.div_ceil(chunk_width);
+        let region_len = rect.width as usize * rect.height as usize * samples;
+        let (mut bytes, mut words) = if sixteen_bit {
+            (Vec::new(), vec![0u16; region_len])
+        } else {
+            (vec![0u8; region_len], Vec::new())
+        };
+
+        for chunk_row in rect.y / chunk_height..=(rect.y + rect.height - 1) / chunk_height {
+            for chunk_column in rect.x / chunk_width..=(rect.x + rect.width - 1) / chunk_width {
+                let index = chunk_row * chunks_across + chunk_column;
+                let (data_width, data_height) = decoder.chunk_data_dimensions(index);
+                let origin = (chunk_column * chunk_width, chunk_row * chunk_height);
+                let layout = ChunkCopy { rect, origin, data_width, data_height, samples };
+                match decoder.read_chunk(index).context("Failed to read TIFF chunk")? {
+                    DecodingResult::U8(data) if !sixteen_bit => layout.copy(&data, &mut bytes),
+                    DecodingResult::U16(data) if sixteen_bit => layout.copy(&data, &mut words),
+                    _ => return Ok(None),
                 }
             }
         }
 
-        // Fallback: decode full image and resize
-        let img = Self::load_heic(path)?;
+        let (w, h) = (rect.width, rect.height);
+        let img = match (samples, sixteen_bit) {
+            (1, false) => GrayImage::from_raw(w, h, bytes).map(DynamicImage::ImageLuma8),
+            (2, false) => image::GrayAlphaImage::from_raw(w, h, bytes).map(DynamicImage::ImageLumaA8),
+            (3, false) => RgbImage::from_raw(w, h, bytes).map(DynamicImage::ImageRgb8),
+            (4, false) => RgbaImage::from_raw(w, h, bytes).map(DynamicImage::ImageRgba8),
+            (1, true) => image::ImageBuffer::from_raw(w, h, words).map(DynamicImage::ImageLuma16),
+            (2, true) => image::ImageBuffer::from_raw(w, h, words).map(DynamicImage::ImageLumaA16),
+            (3, true) => image::ImageBuffer::from_raw(w, h, words).map(DynamicImage::ImageRgb16),
+            _ => image::ImageBuffer::from_raw(w, h, words).map(DynamicImage::ImageRgba16),
+        };
+        img.context("TIFF region has an unexpected size").map(Some)
+    }
+
+    /// Decode a JPEG at the smallest DCT scale (1/8 to 1) whose longest side still
+    /// covers `max_size`; far cheaper than decoding every pixel of a large photo
+    fn decode_jpeg_scaled(data: &[u8], max_size: u32) -> Result<DynamicImage> {
+        let mut decompressor = turbojpeg::Decompressor::new().context("Failed to create JPEG decompressor")?;
+        let header = decompressor.read_header(data).context("Failed to read JPEG header")?;
+
+        let factor = [1, 2, 4, 8]
+            .into_iter()
+            .map(|denominator| turbojpeg::ScalingFactor::new(1, denominator))
+            .rev()
+            .find(|&factor| {
+                let scaled = header.scaled(factor);
+                scaled.width.max(scaled.height) as u32 >= max_size
+            })
+            .unwrap_or(turbojpeg::ScalingFactor::ONE);
+        decompressor.set_scaling_factor(factor).context("Unsupported JPEG scaling factor")?;
+
+        let scaled = header.scaled(factor);
+        let mut image = turbojpeg::Image {
+            pixels: vec![0u8; scaled.width * scaled.height * 3],
+            width: scaled.width,
+            pitch: scaled.width * 3,
+            height: scaled.height,
+            format: turbojpeg::PixelFormat::RGB,
+        };
+        decompressor.decompress(data, image.as_deref_mut()).context("Failed to decode JPEG")?;
+
+        let rgb = RgbImage::from_raw(scaled.width as u32, scaled.height as u32, image.pixels)
+            .context("Scaled JPEG has an unexpected size")?;
+        Ok(DynamicImage::ImageRgb8(rgb))
+    }
+
+    /// Dimensions that fit within the optional caps while preserving aspect ratio (never upscales).
+    /// Callers must pass post-orientation dimensions so caps apply to the displayed axes.
+    pub fn fit_dimensions(
+        width: u32,
+        height: u32,
+        max_width: Option<u32>,
+        max_height: Option<u32>,
+    ) -> (u32, u32) {
+        let scale_w = max_width.map_or(1.0, |m| (m as f64 / width as f64).min(1.0));
+        let scale_h = max_height.map_or(1.0, |m| (m as f64 / height as f64).min(1.0));
+        let scale = scale_w.min(scale_h);
+
+        if scale >= 1.0 {
+            return (width, height);
+        }
+
+        (
+            ((width as f64 * scale).round() as u32).max(1),
+            ((height as f64 * scale).round() as u32).max(1),
+        )
+    }
+
+    /// Smallest aspect-preserving size that covers the whole box; at least one side
+    /// matches it and the other overshoots. Upscales when the source is smaller.
+    pub fn fill_dimensions(width: u32, height: u32, box_width: u32, box_height: u32) -> (u32, u32) {
+        let scale = (box_width as f64 / width as f64).max(box_height as f64 / height as f64);
+        (
+            ((width as f64 * scale).round() as u32).max(box_width),
+            ((height as f64 * scale).round() as u32).max(box_height),
+        )
+    }
+
+    /// Downscale so the longest side is at most `max_size` (never upscales)
+    pub fn thumbnail(img: &DynamicImage, max_size: u32) -> DynamicImage {
         let (width, height) = (img.width(), img.height());
 
         if width > max_size || height > max_size {
             let ratio = max_size as f32 / width.max(height) as f32;
-            let new_width = (width as f32 * ratio) as u32;
-            let new_height = (height as f32 * ratio) as u32;
-            Ok(img.resize(new_width, new_height, image::imageops::FilterType::Triangle))
+            let new_width = ((width as f32 * ratio) as u32).max(1);
+            let new_height = ((height as f32 * ratio) as u32).max(1);
+            img.resize(new_width, new_height, image::imageops::FilterType::Triangle)
         } else {
-            Ok(img)
+            img.clone()
         }
     }
 
-    fn load_heic(path: &str) -> Result<DynamicImage> {
-        let lib_heif = LibHeif::new();
+    fn load_heic(path: &str, options: &HeicDecodeOptions) -> Result<DynamicImage> {
+        HeifLimits::check_file(path)?;
         let ctx = HeifContext::read_from_file(path)
             .context("Failed to read HEIC file")?;
 
         let handle = ctx.primary_image_handle()
             .context("Failed to get primary image handle")?;
 
+        Self::decode_heic(&handle, options)
+    }
+
+    fn decode_heic(handle: &ImageHandle, options: &HeicDecodeOptions) -> Result<DynamicImage> {
+        let mut decoding = DecodingOptions::new()
+            .context("Failed to allocate libheif decoding options")?;
+        decoding.set_ignore_transformations(options.ignore_transformations);
+        decoding.set_convert_hdr_to_8bit(options.convert_hdr_to_8bit);
+        decoding.set_strict_decoding(options.strict_decoding);
+
         // Decode to RGBA
-        let image = lib_heif.decode(&handle, ColorSpace::Rgb(RgbChroma::Rgba), None)
+        let image = LIB_HEIF.decode(handle, ColorSpace::Rgb(RgbChroma::Rgba), Some(decoding))
             .context("Failed to decode HEIC image")?;
 
+        Self::heif_image_to_dynamic(&image)
+    }
+
+    /// Copy a decoded interleaved RGBA libheif image into a DynamicImage
+    fn heif_image_to_dynamic(image: &libheif_rs::Image) -> Result<DynamicImage> {
         let planes = image.planes();
         let interleaved = planes.interleaved
             .context("Failed to get interleaved plane")?;
@@ -105,13 +994,14 @@ impl ImageProcessor {
         let stride = interleaved.stride;
         let data = interleaved.data;
 
-        // Convert to DynamicImage
         // libheif returns data with stride, we need to remove padding
-        let mut rgba_data = Vec::with_capacity((width * height * 4) as usize);
-        for y in 0..height {
-            let row_start = (y as usize) * stride;
-            let row_end = row_start + (width as usize * 4);
-            rgba_data.extend_from_slice(&data[row_start..row_end]);
+        let row_len = width as usize * 4;
+        let mut rgba_data = Vec::with_capacity(row_len * height as usize);
+        for y in 0..height as usize {
+            let row_start = y * stride;
+            let row = data.get(row_start..row_start + row_len)
+                .context("HEIC image data is truncated")?;
+            rgba_data.extend_from_slice(row);
         }
 
         let rgba_image = RgbaImage::from_raw(width, height, rgba_data)
@@ -120,7 +1010,8 @@ impl ImageProcessor {
         Ok(DynamicImage::ImageRgba8(rgba_image))
     }
 
-    fn apply_exif_orientation(path: &str, img: DynamicImage) -> Result<DynamicImage> {
+    /// EXIF Orientation of a source file, if it has one
+    pub fn read_exif_orientation(path: &str) -> Result<Option<u32>> {
         // Try to read EXIF data
         let file = std::fs::File::open(path)?;
         let mut bufreader = std::io::BufReader::new(&file);
@@ -128,20 +1019,24 @@ impl ImageProcessor {
         let exifreader = exif::Reader::new();
         let exif_data = match exifreader.read_from_container(&mut bufreader) {
             Ok(data) => data,
-            Err(_) => return Ok(img), // No EXIF data, return original
+            Err(_) => return Ok(None), // No EXIF data
         };
 
-        // Get orientation tag
-        let orientation = match exif_data.get_field(exif::Tag::Orientation, exif::In::PRIMARY) {
-            Some(field) => match field.value.get_uint(0) {
-                Some(v) => v,
-                None => return Ok(img),
-            },
-            None => return Ok(img), // No orientation tag
+        Ok(exif_data.get_field(exif::Tag::Orientation, exif::In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0)))
+    }
+
+    fn apply_exif_orientation(path: &str, img: DynamicImage) -> Result<DynamicImage> {
+        let Some(orientation) = Self::read_exif_orientation(path)? else {
+            return Ok(img); // No orientation tag
         };
 
-        // Apply transformation based on orientation
-        let transformed = match orientation {
+        Ok(Self::orient(img, orientation))
+    }
+
+    /// Transform stored pixels to display orientation per an EXIF orientation value
+    pub fn orient(img: DynamicImage, orientation: u32) -> DynamicImage {
+        match orientation {
             1 => img, // Normal
             2 => img.fliph(), // Flip horizontal
             3 => img.rotate180(), // Rotate 180
@@ -151,19 +1046,127 @@ impl ImageProcessor {
             7 => img.rotate270().fliph(), // Rotate 270 CW and flip horizontal
             8 => img.rotate270(), // Rotate 270 CW
             _ => img, // Unknown orientation
-        };
+        }
+    }
+
+    /// Whether a format reported by get_format is decoded through libheif
+    pub fn is_heif_format(format: &str) -> bool {
+        matches!(format, "heic" | "heif" | "avif")
+    }
+
+    /// Whether some decoder here handles `path`, whose format get_format reported as
+    /// `format`: a format with a decoder of its own, or contents that start like a
+    /// JPEG, which the fallback reads whatever the file is called. Anything else
+    /// isn't an image we can read, as opposed to an image that turns out corrupt.
+    pub fn is_supported_input(path: &str, format: &str) -> bool {
+        let known = Self::is_heif_format(format)
+            || RawProcessor::is_raw_extension(format)
+            || FitsProcessor::is_fits_extension(format)
+            || PNM_EXTENSIONS.contains(&format)
+            || IconProcessor::is_icon_extension(format)
+            || format == "pcx"
+            || ImageFormat::from_extension(format).is_some_and(|format| format.reading_enabled());
+        if known {
+            return true;
+        }
 
-        Ok(transformed)
+        let mut header = [0u8; 3];
+        match std::fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)) {
+            Ok(()) => header == [0xFF, 0xD8, 0xFF],
+            // Missing or unreadable files are left for the decoder to report
+            Err(e) => e.kind() != std::io::ErrorKind::UnexpectedEof,
+        }
+    }
+
+    /// Whether the source's pixels went through lossy compression: JPEG (including the
+    /// JPEG previews RAW files are decoded from), HEIF, lossy (VP8) WebP and
+    /// JPEG-compressed TIFF. Everything else is stored losslessly.
+    pub fn is_lossy(path: &str) -> Result<bool> {
+        let format = Self::get_format(path)?;
+        if matches!(format.as_str(), "jpg" | "jpeg") || Self::is_heif_format(&format) || RawProcessor::is_raw_extension(&format) {
+            return Ok(true);
+        }
+
+        match format.as_str() {
+            "webp" => {
+                let data = std::fs::read(path).context("Failed to read WebP file")?;
+                let chunks = data.get(12..).filter(|_| data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP"));
+                Ok(chunks.is_some_and(Self::has_vp8_chunk))
+            }
+            "tif" | "tiff" => {
+                const TAG_COMPRESSION: u16 = 0x0103;
+                let data = std::fs::read(path).context("Failed to read TIFF file")?;
+                let compression = TiffReader::new(&data).and_then(|reader| {
+                    let entry = reader.find_entry(reader.first_ifd()?, TAG_COMPRESSION)?;
+                    reader.values(entry).first().copied()
+                });
+                // 6 is old-style JPEG, 7 is JPEG
+                Ok(matches!(compression, Some(6 | 7)))
+            }
+            _ => Ok(false),
+        }
+    }
+
+    /// Whether RIFF chunks hold a lossy VP8 bitstream, directly or inside an
+    /// animation frame (ANMF, whose bitstream follows a 16-byte frame header)
+    fn has_vp8_chunk(mut chunks: &[u8]) -> bool {
+        while let (Some(id), Some(size)) = (chunks.get(0..4), chunks.get(4..8)) {
+            let size = u32::from_le_bytes([size[0], size[1], size[2], size[3]]) as usize;
+            let body = chunks.get(8..).unwrap_or_default();
+            let body = &body[..size.min(body.len())];
+            match id {
+                b"VP8 " => return true,
+                b"ANMF" if Self::has_vp8_chunk(body.get(16..).unwrap_or_default()) => return true,
+                _ => {}
+            }
+            // Chunks are padded to an even size
+            chunks = chunks.get(8 + size + (size & 1)..).unwrap_or_default();
+        }
+        false
+    }
+
+    /// Format of the HEIF family the file's ftyp brands declare, if any
+    fn sniff_heif_brand(path: &str) -> Option<&'static str> {
+        let mut header = [0u8; 256];
+        let mut file = std::fs::File::open(path).ok()?;
+        let read = std::io::Read::read(&mut file, &mut header).ok()?;
+        Self::heif_brand(&header[..read])
+    }
+
+    /// HEIF format declared by the ftyp box at the start of `header`
+    fn heif_brand(header: &[u8]) -> Option<&'static str> {
+        if header.get(4..8) != Some(b"ftyp") {
+            return None;
+        }
+        let size = (u32::from_be_bytes(header[0..4].try_into().ok()?) as usize).min(header.len());
+        let major = header.get(8..12)?;
+        // Compatible brands follow the major brand and minor version
+        let compatible: Vec<&[u8]> = header.get(16..size).unwrap_or_default().chunks_exact(4).collect();
+
+        HEIF_BRANDS.iter()
+            .find(|(brand, _)| major == brand.as_slice())
+            .or_else(|| HEIF_BRANDS.iter().find(|(brand, _)| compatible.contains(&brand.as_slice())))
+            .map(|&(_, format)| format)
     }
 
+    /// Lowercase extension, except that HEIF-family files report "heic", "heif" or
+    /// "avif" based on their ftyp brand, so renamed files are still recognized
     pub fn get_format(path: &str) -> Result<String> {
+        if let Some(format) = Self::sniff_heif_brand(path) {
+            return Ok(format.to_string());
+        }
+
         let path_obj = Path::new(path);
         let extension = path_obj
             .extension()
             .and_then(|s| s.to_str())
             .unwrap_or("");
 
-        Ok(extension.to_lowercase())
+        let extension = extension.to_lowercase();
+        let format = HEIF_EXTENSIONS.iter()
+            .find(|(heif_extension, _)| *heif_extension == extension)
+            .map_or(extension, |(_, format)| format.to_string());
+        Ok(format)
     }
 
     pub fn save_image(
@@ -172,30 +1175,912 @@ impl ImageProcessor {
         format: ImageFormat,
         quality: u8,
     ) -> Result<()> {
+        let data = Self::encode_image(img, format, quality)?;
+
+        std::fs::write(output_path, data)
+            .context("Failed to write output file")?;
+
+        Ok(())
+    }
+
+    /// Encode image into memory without touching the filesystem
+    pub fn encode_image(img: &DynamicImage, format: ImageFormat, quality: u8) -> Result<Vec<u8>> {
         match format {
-            ImageFormat::Jpeg => {
-                Self::save_jpeg_turbo(img, output_path, quality)?;
-            }
-            ImageFormat::Png => {
-                img.save_with_format(output_path, ImageFormat::Png)
-                    .context("Failed to save PNG")?;
+            ImageFormat::Jpeg => Self::encode_jpeg_turbo(img, quality, false),
+            ImageFormat::Png => Self::encode_png(img, PngCompression::default()),
+            ImageFormat::Bmp => Self::encode_bmp(img, None),
+            ImageFormat::WebP => Self::encode_webp(img, quality, DEFAULT_WEBP_METHOD),
+            ImageFormat::Tiff => Self::encode_tiff(img, TiffCompression::default(), 1),
+            ImageFormat::Pnm => {
+                let graymap = matches!(img.color(), ColorType::L8 | ColorType::La8 | ColorType::L16 | ColorType::La16);
+                Self::encode_pnm(img, graymap)
             }
+            ImageFormat::Tga => Self::encode_tga(img),
+            ImageFormat::Avif => Self::encode_avif(img, quality, false).map(|(data, _)| data),
             _ => anyhow::bail!("Unsupported output format"),
         }
-        Ok(())
     }
 
-    /// Save JPEG using turbojpeg (2-3x faster than standard encoder)
-    fn save_jpeg_turbo(img: &DynamicImage, output_path: &str, quality: u8) -> Result<()> {
-        let rgb_image = img.to_rgb8();
-
-        let jpeg_data = turbojpeg::compress_image(&rgb_image, quality as i32, turbojpeg::Subsamp::Sub2x2)
-            .context("Failed to compress JPEG with turbojpeg")?;
+    /// 24-bit or 32-bit BMP. Without an explicit depth, 32-bit is used only when the
+    /// image has alpha. Alpha is dropped (not composited) for 24-bit.
+    pub fn encode_bmp(img: &DynamicImage, bits_per_pixel: Option<u8>) -> Result<Vec<u8>> {
+        let alpha = bits_per_pixel.map_or(img.color().has_alpha(), |bits| bits == 32);
 
-        std::fs::write(output_path, jpeg_data.as_ref())
-            .context("Failed to write JPEG file")?;
+        // The BMP encoder only handles 8-bit layouts
+        let img = match img {
+            DynamicImage::ImageRgba8(_) if alpha => Cow::Borrowed(img),
+            DynamicImage::ImageRgb8(_) if !alpha => Cow::Borrowed(img),
+            _ if alpha => Cow::Owned(DynamicImage::ImageRgba8(img.to_rgba8())),
+            _ => Cow::Owned(DynamicImage::ImageRgb8(img.to_rgb8())),
+        };
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Bmp)
+            .context("Failed to encode BMP")?;
+        Ok(data)
+    }
 
-        Ok(())
+    /// TGA with a top-left origin, as 8-bit gray or RGB, with alpha when the image has it
+    pub fn encode_tga(img: &DynamicImage) -> Result<Vec<u8>> {
+        let img = match img {
+            DynamicImage::ImageLuma8(_) | DynamicImage::ImageLumaA8(_)
+            | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => Cow::Borrowed(img),
+            _ if img.color().has_alpha() => Cow::Owned(DynamicImage::ImageRgba8(img.to_rgba8())),
+            _ => Cow::Owned(DynamicImage::ImageRgb8(img.to_rgb8())),
+        };
+        let mut data = Vec::new();
+        img.write_to(&mut Cursor::new(&mut data), ImageOutputFormat::Tga)
+            .context("Failed to encode TGA")?;
+        Ok(data)
+    }
+
+    /// Apply an EXIF orientation to JPEG data without re-encoding. Edge blocks that
+    /// don't fill a whole MCU can't be moved losslessly and are trimmed, as
+    /// `jpegtran -trim` does. Markers (EXIF, ICC) are copied unchanged.
+    pub fn transform_jpeg_lossless(jpeg: &[u8], orientation: u32) -> Result<Vec<u8>> {
+        use turbojpeg::TransformOp;
+
+        let op = match orientation {
+            2 => TransformOp::Hflip,
+            3 => TransformOp::Rot180,
+            4 => TransformOp::Vflip,
+            5 => TransformOp::Transpose,
+            6 => TransformOp::Rot90,
+            7 => TransformOp::Transverse,
+            8 => TransformOp::Rot270,
+            _ => TransformOp::None,
+        };
+        let mut transform = turbojpeg::Transform::op(op);
+        transform.trim = true;
+
+        let transformed = turbojpeg::transform(&transform, jpeg).context("Failed to transform JPEG")?;
+        Ok(transformed.to_vec())
+    }
+
+    /// HEVC-coded HEIC through libheif. `exif` is in APP1 form, as read_exif_segment
+    /// returns it.
+    pub fn encode_heic(img: &DynamicImage, quality: u8, exif: Option<&[u8]>) -> Result<Vec<u8>> {
+        let image = Self::heif_image(img)?;
+        let mut context = HeifContext::new().context("Failed to create HEIF context")?;
+        let mut encoder = LIB_HEIF.encoder_for_format(libheif_rs::CompressionFormat::Hevc)
+            .context("No HEVC encoder available")?;
+        encoder.set_quality(libheif_rs::EncoderQuality::Lossy(quality))
+            .context("Failed to configure HEVC encoder")?;
+        let handle = context.encode_image(&image, &mut encoder, None)
+            .context("Failed to encode HEIC")?;
+
+        // libheif wants the TIFF structure without the APP1 "Exif\0\0" header
+        if let Some(tiff) = exif.and_then(|exif| exif.get(6..)) {
+            context.add_exif_metadata(&handle, tiff)
+                .context("Failed to add EXIF to HEIC")?;
+        }
+
+        context.write_to_bytes().context("Failed to write HEIC")
+    }
+
+    /// AV1-coded AVIF through libheif; alpha is dropped. With `preview`, a thumbnail
+    /// whose longest side is AVIF_PREVIEW_SIZE is stored alongside the image, which
+    /// viewers and browsers that read HEIF thumbnails can show before the full image
+    /// has arrived and decoded. Encoders that can't produce it leave it out rather
+    /// than failing. Also returns whether the preview was embedded.
+    pub fn encode_avif(img: &DynamicImage, quality: u8, preview: bool) -> Result<(Vec<u8>, bool)> {
+        let image = Self::heif_image(img)?;
+        let mut context = HeifContext::new().context("Failed to create HEIF context")?;
+        let mut encoder = LIB_HEIF.encoder_for_format(libheif_rs::CompressionFormat::Av1)
+            .context("No AV1 encoder available")?;
+        encoder.set_quality(libheif_rs::EncoderQuality::Lossy(quality))
+            .context("Failed to configure AV1 encoder")?;
+        let handle = context.encode_image(&image, &mut encoder, None)
+            .context("Failed to encode AVIF")?;
+
+        // libheif skips the thumbnail for images already within the size
+        let embedded = preview
+            && img.width().max(img.height()) > AVIF_PREVIEW_SIZE
+            && matches!(context.encode_thumbnail(&image, &handle, AVIF_PREVIEW_SIZE, &mut encoder, None), Ok(Some(_)));
+
+        Ok((context.write_to_bytes().context("Failed to write AVIF")?, embedded))
+    }
+
+    /// Interleaved 8-bit RGB libheif image for encoding
+    fn heif_image(img: &DynamicImage) -> Result<libheif_rs::Image> {
+        use libheif_rs::{Channel, Image};
+
+        let rgb = Self::as_rgb8(img);
+        let (width, height) = rgb.dimensions();
+        let mut image = Image::new(width, height, ColorSpace::Rgb(RgbChroma::Rgb))
+            .context("Failed to create HEIF image")?;
+        image.create_plane(Channel::Interleaved, width, height, 8)
+            .context("Failed to create HEIF image")?;
+
+        let planes = image.planes_mut();
+        let plane = planes.interleaved.context("HEIF image has no interleaved plane")?;
+        let row_bytes = width as usize * 3;
+        for (row, source) in plane.data.chunks_mut(plane.stride).zip(rgb.as_raw().chunks_exact(row_bytes)) {
+            row[..row_bytes].copy_from_slice(source);
+        }
+        Ok(image)
+    }
+
+    /// Binary PGM (P5) or PPM (P6). Sources deeper than 8 bits are written with a
+    /// maxval of 65535; alpha is dropped.
+    pub fn encode_pnm(img: &DynamicImage, graymap: bool) -> Result<Vec<u8>> {
+        let wide = !matches!(
+            img.color(),
+            ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8
+        );
+        let magic = if graymap { "P5" } else { "P6" };
+        let maxval = if wide { 65535 } else { 255 };
+        let mut data = format!("{}\n{} {}\n{}\n", magic, img.width(), img.height(), maxval).into_bytes();
+
+        match (graymap, wide) {
+            (true, false) => match img {
+                DynamicImage::ImageLuma8(gray) => data.extend_from_slice(gray.as_raw()),
+                _ => data.extend_from_slice(img.to_luma8().as_raw()),
+            },
+            (false, false) => data.extend_from_slice(Self::as_rgb8(img).as_raw()),
+            // Netpbm samples are big-endian
+            (true, true) => data.extend(img.to_luma16().as_raw().iter().flat_map(|v| v.to_be_bytes())),
+            (false, true) => data.extend(img.to_rgb16().as_raw().iter().flat_map(|v| v.to_be_bytes())),
+        }
+
+        Ok(data)
+    }
+
+    /// TIFF through the tiff crate, keeping 16-bit and float samples
+    /// `orientation` is written as the Orientation tag (1 for upright pixels).
+    pub fn encode_tiff(img: &DynamicImage, compression: TiffCompression, orientation: u16) -> Result<Vec<u8>> {
+        use tiff::encoder::compression::{Deflate, Lzw, Uncompressed};
+
+        match compression {
+            TiffCompression::None => Self::encode_tiff_with(img, Uncompressed, orientation),
+            TiffCompression::Lzw => Self::encode_tiff_with(img, Lzw, orientation),
+            TiffCompression::Deflate => Self::encode_tiff_with(img, Deflate::default(), orientation),
+            TiffCompression::CcittG4 => anyhow::bail!("CCITT G4 compression only applies to bilevel output"),
+        }
+    }
+
+    /// 1-bit TIFF of a bilevel image, pixels below 128 black, in a single strip.
+    /// Stored WhiteIsZero as CCITT G4 expects; the other compressions apply to the
+    /// packed rows.
+    pub fn encode_tiff_bilevel(luma: &GrayImage, compression: TiffCompression, orientation: u16) -> Result<Vec<u8>> {
+        use tiff::encoder::compression::{CompressionAlgorithm, Deflate, Lzw};
+        use tiff::encoder::Rational;
+        use tiff::tags::{CompressionMethod, PhotometricInterpretation, ResolutionUnit, Tag};
+
+        let (width, height) = luma.dimensions();
+        let packed = || Self::pack_bits(luma, |value| value < 128);
+        let (method, strip) = match compression {
+            TiffCompression::None => (CompressionMethod::None, packed()),
+            TiffCompression::Lzw => {
+                let mut strip = Vec::new();
+                Lzw.write_to(&mut strip, &packed()).context("Failed to compress TIFF strip")?;
+                (CompressionMethod::LZW, strip)
+            }
+            TiffCompression::Deflate => {
+                let mut strip = Vec::new();
+                Deflate::default().write_to(&mut strip, &packed()).context("Failed to compress TIFF strip")?;
+                (CompressionMethod::Deflate, strip)
+            }
+            TiffCompression::CcittG4 => (CompressionMethod::Fax4, CcittG4::encode(luma)),
+        };
+
+        // tiff's image encoder has no 1-bit color type, so the directory is written by hand
+        let mut data = Vec::new();
+        let written = (|| -> tiff::TiffResult<()> {
+            let mut encoder = tiff::encoder::TiffEncoder::new(Cursor::new(&mut data))?;
+            let mut directory = encoder.new_directory()?;
+            // Written first, so the strip directly follows the 8-byte header
+            let offset = directory.write_data(strip.as_slice())? as u32;
+            directory.write_tag(Tag::ImageWidth, width)?;
+            directory.write_tag(Tag::ImageLength, height)?;
+            directory.write_tag(Tag::BitsPerSample, 1u16)?;
+            directory.write_tag(Tag::Compression, method.to_u16())?;
+            directory.write_tag(Tag::PhotometricInterpretation, PhotometricInterpretation::WhiteIsZero.to_u16())?;
+            directory.write_tag(Tag::StripOffsets, offset)?;
+            directory.write_tag(Tag::Orientation, orientation)?;
+            directory.write_tag(Tag::SamplesPerPixel, 1u16)?;
+            directory.write_tag(Tag::RowsPerStrip, height)?;
+            directory.write_tag(Tag::StripByteCounts, strip.len() as u32)?;
+            directory.write_tag(Tag::XResolution, Rational { n: 1, d: 1 })?;
+            directory.write_tag(Tag::YResolution, Rational { n: 1, d: 1 })?;
+            directory.write_tag(Tag::ResolutionUnit, ResolutionUnit::None.to_u16())?;
+            directory.finish()
+        })();
+        written.context("Failed to encode TIFF")?;
+
+        Ok(data)
+    }
+
+    fn encode_tiff_with<D: tiff::encoder::compression::Compression>(
+        img: &DynamicImage,
+        compression: D,
+        orientation: u16,
+    ) -> Result<Vec<u8>> {
+        use tiff::encoder::colortype;
+
+        let (width, height) = (img.width(), img.height());
+        let mut data = Vec::new();
+        {
+            let mut encoder = tiff::encoder::TiffEncoder::new(Cursor::new(&mut data))
+                .context("Failed to start TIFF encoder")?;
+            let mut write = TiffImageWriter { encoder: &mut encoder, width, height, orientation };
+            let written = match img {
+                DynamicImage::ImageLuma8(buf) => write.image::<colortype::Gray8, _>(compression, buf.as_raw()),
+                DynamicImage::ImageLuma16(buf) => write.image::<colortype::Gray16, _>(compression, buf.as_raw()),
+                DynamicImage::ImageRgb8(buf) => write.image::<colortype::RGB8, _>(compression, buf.as_raw()),
+                DynamicImage::ImageRgb16(buf) => write.image::<colortype::RGB16, _>(compression, buf.as_raw()),
+                DynamicImage::ImageRgba16(buf) => write.image::<colortype::RGBA16, _>(compression, buf.as_raw()),
+                DynamicImage::ImageLumaA16(_) => {
+                    let rgba = img.to_rgba16();
+                    write.image::<colortype::RGBA16, _>(compression, rgba.as_raw())
+                }
+                DynamicImage::ImageRgb32F(buf) => write.image::<colortype::RGB32Float, _>(compression, buf.as_raw()),
+                DynamicImage::ImageRgba32F(buf) => write.image::<colortype::RGBA32Float, _>(compression, buf.as_raw()),
+                _ => {
+                    let rgba = Self::as_rgba8(img);
+                    write.image::<colortype::RGBA8, _>(compression, rgba.as_raw())
+                }
+            };
+            written.context("Failed to encode TIFF")?;
+        }
+
+        Ok(data)
+    }
+
+    /// JPEG encoder used for the given options
+    pub fn jpeg_encoder_name(options: JpegEncodeOptions) -> &'static str {
+        match (options.encoder, options.arithmetic) {
+            (JpegEncoder::Mozjpeg, _) => "mozjpeg",
+            (JpegEncoder::Image, _) => "image-jpeg",
+            (JpegEncoder::Turbo, true) => "turbojpeg (arithmetic)",
+            (JpegEncoder::Turbo, false) => "turbojpeg",
+        }
+    }
+
+    /// Name of the encoder encode_image uses for a format
+    pub fn encoder_name(format: ImageFormat) -> &'static str {
+        match format {
+            ImageFormat::Jpeg => "turbojpeg",
+            ImageFormat::Png => "png",
+            ImageFormat::Bmp => "image-bmp",
+            ImageFormat::WebP => "libwebp",
+            ImageFormat::Tiff => "tiff",
+            ImageFormat::Pnm => "netpbm",
+            ImageFormat::Tga => "image-tga",
+            ImageFormat::Avif => "libheif",
+            _ => "unsupported",
+        }
+    }
+
+    /// PNG through the png crate with an explicit compression/filter configuration.
+    /// image's default encoder filters every row adaptively at the default zlib level,
+    /// which dominates batch time on large photos.
+    pub fn encode_png(img: &DynamicImage, compression: PngCompression) -> Result<Vec<u8>> {
+        // PNG has no float samples
+        let converted = matches!(img, DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_))
+            .then(|| DynamicImage::ImageRgba16(img.to_rgba16()));
+        let img = converted.as_ref().unwrap_or(img);
+
+        let (color, depth) = match img {
+            DynamicImage::ImageLuma8(_) => (png::ColorType::Grayscale, png::BitDepth::Eight),
+            DynamicImage::ImageLumaA8(_) => (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight),
+            DynamicImage::ImageRgb8(_) => (png::ColorType::Rgb, png::BitDepth::Eight),
+            DynamicImage::ImageLuma16(_) => (png::ColorType::Grayscale, png::BitDepth::Sixteen),
+            DynamicImage::ImageLumaA16(_) => (png::ColorType::GrayscaleAlpha, png::BitDepth::Sixteen),
+            DynamicImage::ImageRgb16(_) => (png::ColorType::Rgb, png::BitDepth::Sixteen),
+            DynamicImage::ImageRgba16(_) => (png::ColorType::Rgba, png::BitDepth::Sixteen),
+            _ => (png::ColorType::Rgba, png::BitDepth::Eight),
+        };
+
+        let pixels = match depth {
+            // PNG samples are big-endian
+            png::BitDepth::Sixteen => img.as_bytes()
+                .chunks_exact(2)
+                .flat_map(|sample| u16::from_ne_bytes([sample[0], sample[1]]).to_be_bytes())
+                .collect(),
+            _ if color == png::ColorType::Rgba && !matches!(img, DynamicImage::ImageRgba8(_)) => img.to_rgba8().into_raw(),
+            _ => img.as_bytes().to_vec(),
+        };
+
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, img.width(), img.height());
+        encoder.set_color(color);
+        encoder.set_depth(depth);
+        compression.configure(&mut encoder);
+
+        let mut writer = encoder.write_header()
+            .context("Failed to write PNG header")?;
+        writer.write_image_data(&pixels)
+            .context("Failed to encode PNG")?;
+        writer.finish()
+            .context("Failed to finish PNG")?;
+
+        Ok(data)
+    }
+
+    /// Lossy WebP through libwebp, keeping alpha when the source has it. `method` (0-6)
+    /// trades encode time for size: 0 is several times faster than 6, whose files are
+    /// typically 5-10% smaller at the same quality.
+    pub fn encode_webp(img: &DynamicImage, quality: u8, method: u8) -> Result<Vec<u8>> {
+        let mut config = webp::WebPConfig::new()
+            .map_err(|_| anyhow::anyhow!("Failed to initialize WebP encoder config"))?;
+        config.lossless = 0;
+        config.quality = quality as f32;
+        config.method = method.min(6) as i32;
+
+        let (width, height) = (img.width(), img.height());
+        let encoded = if img.color().has_alpha() {
+            let rgba = Self::as_rgba8(img);
+            webp::Encoder::from_rgba(rgba.as_raw(), width, height).encode_advanced(&config)
+        } else {
+            let rgb = Self::as_rgb8(img);
+            webp::Encoder::from_rgb(rgb.as_raw(), width, height).encode_advanced(&config)
+        };
+
+        encoded
+            .map(|memory| memory.to_vec())
+            .map_err(|e| anyhow::anyhow!("Failed to encode WebP: {:?}", e))
+    }
+
+    /// Encode at the highest quality between `floor` and `max_quality` whose output fits
+    /// in `max_bytes`, using a bounded binary search. Returns the data and the quality
+    /// used; when nothing fits, the smallest attempt is returned. WebP is encoded
+    /// with `webp_method` and JPEG with `jpeg`.
+    pub fn encode_to_size(
+        img: &DynamicImage,
+        format: ImageFormat,
+        webp_method: u8,
+        jpeg: JpegEncodeOptions,
+        max_quality: u8,
+        floor: u8,
+        max_bytes: u64,
+    ) -> Result<(Vec<u8>, u8)> {
+        // JPEG gains little below ~10 and falls apart visually; WebP keeps shrinking
+        // smoothly down to 0 but flattens near the top, so it gets an extra probe
+        let (min_quality, max_attempts) = match format {
+            ImageFormat::Jpeg => (10u8, 7),
+            ImageFormat::WebP => (0u8, 8),
+            _ => anyhow::bail!("Target size is only supported for JPEG and WebP output"),
+        };
+        let min_quality = min_quality.max(floor);
+
+        let encode = |quality: u8| match format {
+            ImageFormat::WebP => Self::encode_webp(img, quality, webp_method),
+            ImageFormat::Jpeg => Self::encode_jpeg(img, quality, jpeg),
+            _ => Self::encode_image(img, format, quality),
+        };
+
+        let first = encode(max_quality)?;
+        if first.len() as u64 <= max_bytes {
+            return Ok((first, max_quality));
+        }
+
+        // `high` is always known to be too large; `best` is the largest fitting attempt
+        let (mut low, mut high) = (min_quality.min(max_quality), max_quality);
+        let mut best: Option<(Vec<u8>, u8)> = None;
+        let mut smallest = (first, max_quality);
+
+        for _ in 0..max_attempts {
+            if low >= high {
+                break;
+            }
+            let quality = low + (high - low) / 2;
+            let data = encode(quality)?;
+
+            if data.len() as u64 <= max_bytes {
+                best = Some((data, quality));
+                low = quality + 1;
+            } else {
+                if data.len() < smallest.0.len() {
+                    smallest = (data, quality);
+                }
+                high = quality;
+            }
+        }
+
+        Ok(best.unwrap_or(smallest))
+    }
+
+    /// Like encode_to_size, but when even the lowest allowed quality is too large the
+    /// image is progressively downscaled and searched again. Also returns the
+    /// downscaled image, if one was needed.
+    pub fn encode_to_size_downscaling(
+        img: &DynamicImage,
+        format: ImageFormat,
+        webp_method: u8,
+        jpeg: JpegEncodeOptions,
+        max_quality: u8,
+        floor: u8,
+        max_bytes: u64,
+    ) -> Result<(Vec<u8>, u8, Option<DynamicImage>)> {
+        const MAX_STEPS: usize = 8;
+        const MIN_SIDE: u32 = 16;
+
+        let (mut data, mut quality) = Self::encode_to_size(img, format, webp_method, jpeg, max_quality, floor, max_bytes)?;
+        let mut scaled: Option<DynamicImage> = None;
+
+        for _ in 0..MAX_STEPS {
+            if data.len() as u64 <= max_bytes {
+                break;
+            }
+
+            // Encoded size tracks pixel count, so aim at the square root of the overshoot,
+            // stepping at least 10% and at most half per side
+            let current = scaled.as_ref().unwrap_or(img);
+            let factor = ((max_bytes as f64 / data.len() as f64).sqrt() * 0.95).clamp(0.5, 0.9);
+            let width = (current.width() as f64 * factor).round() as u32;
+            let height = (current.height() as f64 * factor).round() as u32;
+            if width.min(height) < MIN_SIDE {
+                break;
+            }
+
+            // Always resample from the full-size image to avoid compounding blur
+            let next = img.resize_exact(width, height, image::imageops::FilterType::Lanczos3);
+            (data, quality) = Self::encode_to_size(&next, format, webp_method, jpeg, max_quality, floor, max_bytes)?;
+            scaled = Some(next);
+        }
+
+        Ok((data, quality, scaled))
+    }
+
+    /// Encode an 8-bit indexed (paletted) BMP for legacy consumers
+    pub fn encode_bmp_indexed(img: &DynamicImage) -> Result<Vec<u8>> {
+        let (palette, indices) = Adjustments::quantize(img, 256);
+        if palette.len() > 256 {
+            anyhow::bail!("Indexed BMP palette exceeds 256 colors");
+        }
+
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let row_size = (width + 3) & !3;
+        let pixel_offset = 14 + 40 + 256 * 4;
+        let file_size = pixel_offset + row_size * height;
+
+        let mut data = Vec::with_capacity(file_size);
+
+        // BITMAPFILEHEADER
+        data.extend_from_slice(b"BM");
+        data.extend_from_slice(&(file_size as u32).to_le_bytes());
+        data.extend_from_slice(&[0u8; 4]);
+        data.extend_from_slice(&(pixel_offset as u32).to_le_bytes());
+
+        // BITMAPINFOHEADER
+        data.extend_from_slice(&40u32.to_le_bytes());
+        data.extend_from_slice(&(width as i32).to_le_bytes());
+        data.extend_from_slice(&(height as i32).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&8u16.to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes()); // BI_RGB
+        data.extend_from_slice(&((row_size * height) as u32).to_le_bytes());
+        data.extend_from_slice(&2835i32.to_le_bytes()); // 72 DPI
+        data.extend_from_slice(&2835i32.to_le_bytes());
+        data.extend_from_slice(&(palette.len() as u32).to_le_bytes());
+        data.extend_from_slice(&0u32.to_le_bytes());
+
+        // Palette as BGRX, padded to 256 entries
+        for index in 0..256 {
+            let [r, g, b] = palette.get(index).copied().unwrap_or([0, 0, 0]);
+            data.extend_from_slice(&[b, g, r, 0]);
+        }
+
+        // Rows bottom-up, each padded to 4 bytes
+        for y in (0..height).rev() {
+            let row = &indices[y * width..(y + 1) * width];
+            data.extend_from_slice(row);
+            data.resize(data.len() + row_size - width, 0);
+        }
+
+        Ok(data)
+    }
+
+    /// Encode palette indices as an indexed PNG, at the smallest bit depth that holds the palette
+    pub fn encode_png_indexed(
+        width: u32,
+        height: u32,
+        palette: &[[u8; 3]],
+        indices: &[u8],
+        compression: PngCompression,
+    ) -> Result<Vec<u8>> {
+        let (depth, bits) = match palette.len() {
+            0..=2 => (png::BitDepth::One, 1),
+            3..=4 => (png::BitDepth::Two, 2),
+            5..=16 => (png::BitDepth::Four, 4),
+            _ => (png::BitDepth::Eight, 8),
+        };
+
+        // Pack pixels MSB first; each row starts on a byte boundary
+        let row_bytes = (width as usize * bits).div_ceil(8);
+        let mut packed = vec![0u8; row_bytes * height as usize];
+        for (i, &index) in indices.iter().enumerate() {
+            let (x, y) = (i % width as usize, i / width as usize);
+            let bit = x * bits;
+            packed[y * row_bytes + bit / 8] |= index << (8 - bits - bit % 8);
+        }
+
+        let level = match compression {
+            PngCompression::Fast => png::Compression::Fast,
+            PngCompression::Balanced => png::Compression::Default,
+            PngCompression::Best => png::Compression::Best,
+        };
+
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(depth);
+        encoder.set_palette(palette.concat());
+        encoder.set_compression(level);
+        // Filtering rarely helps index data
+        encoder.set_filter(png::FilterType::NoFilter);
+
+        let mut writer = encoder.write_header()
+            .context("Failed to write PNG header")?;
+        writer.write_image_data(&packed)
+            .context("Failed to encode indexed PNG")?;
+        writer.finish()
+            .context("Failed to finish PNG")?;
+
+        Ok(data)
+    }
+
+    /// Encode a black and white image as a 1-bit grayscale PNG
+    pub fn encode_png_bilevel(luma: &GrayImage) -> Result<Vec<u8>> {
+        let (width, height) = luma.dimensions();
+        // 1 = white
+        let packed = Self::pack_bits(luma, |value| value >= 128);
+
+        let mut data = Vec::new();
+        let mut encoder = png::Encoder::new(&mut data, width, height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+        // Byte-wise filters scramble packed pixels; unfiltered rows deflate far better
+        encoder.set_filter(png::FilterType::NoFilter);
+        encoder.set_compression(png::Compression::Best);
+
+        let mut writer = encoder.write_header()
+            .context("Failed to write PNG header")?;
+        writer.write_image_data(&packed)
+            .context("Failed to encode 1-bit PNG")?;
+        writer.finish()
+            .context("Failed to finish PNG")?;
+
+        Ok(data)
+    }
+
+    /// One bit per pixel, 8 to a byte MSB first, rows padded to whole bytes. Bits are
+    /// set where `set` holds for the pixel's value.
+    fn pack_bits(luma: &GrayImage, set: impl Fn(u8) -> bool) -> Vec<u8> {
+        let row_bytes = (luma.width() as usize).div_ceil(8);
+        let mut packed = vec![0u8; row_bytes * luma.height() as usize];
+        for (x, y, pixel) in luma.enumerate_pixels() {
+            if set(pixel.0[0]) {
+                packed[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        packed
+    }
+
+    /// JPEG through the encoder `options` select; turbojpeg unless told otherwise
+    pub fn encode_jpeg(img: &DynamicImage, quality: u8, options: JpegEncodeOptions) -> Result<Vec<u8>> {
+        match options.encoder {
+            JpegEncoder::Turbo => Self::encode_jpeg_turbo(img, quality, options.arithmetic),
+            JpegEncoder::Image => Self::encode_jpeg_image(img, quality),
+            JpegEncoder::Mozjpeg => Self::encode_jpeg_mozjpeg(img, quality),
+        }
+    }
+
+    /// The image crate's baseline encoder: slower than turbojpeg, but the same
+    /// output on every platform since it doesn't depend on a system library
+    fn encode_jpeg_image(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        let mut encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(&mut data, quality.clamp(1, 100));
+        let written = match img {
+            DynamicImage::ImageLuma8(gray) => encoder.encode(gray.as_raw(), gray.width(), gray.height(), ColorType::L8),
+            _ => {
+                let rgb = Self::as_rgb8(img);
+                encoder.encode(rgb.as_raw(), rgb.width(), rgb.height(), ColorType::Rgb8)
+            }
+        };
+        written.context("Failed to encode JPEG")?;
+        Ok(data)
+    }
+
+    /// mozjpeg's default profile: trellis quantization, optimized Huffman tables and
+    /// progressive scans. mozjpeg reports errors by panicking, hence catch_unwind.
+    #[cfg(feature = "mozjpeg")]
+    fn encode_jpeg_mozjpeg(img: &DynamicImage, quality: u8) -> Result<Vec<u8>> {
+        let (color_space, pixels) = match img {
+            DynamicImage::ImageLuma8(gray) => (mozjpeg::ColorSpace::JCS_GRAYSCALE, Cow::Borrowed(gray.as_raw())),
+            _ => (mozjpeg::ColorSpace::JCS_RGB, Cow::Owned(Self::as_rgb8(img).into_owned().into_raw())),
+        };
+        let (width, height) = (img.width() as usize, img.height() as usize);
+
+        std::panic::catch_unwind(|| -> std::io::Result<Vec<u8>> {
+            let mut compress = mozjpeg::Compress::new(color_space);
+            compress.set_size(width, height);
+            compress.set_quality(quality as f32);
+            let mut started = compress.start_compress(Vec::new())?;
+            started.write_scanlines(&pixels)?;
+            started.finish()
+        })
+        .map_err(|_| anyhow::anyhow!("mozjpeg failed to encode the image"))?
+        .context("Failed to compress JPEG with mozjpeg")
+    }
+
+    #[cfg(not(feature = "mozjpeg"))]
+    fn encode_jpeg_mozjpeg(_img: &DynamicImage, _quality: u8) -> Result<Vec<u8>> {
+        anyhow::bail!("The mozjpeg encoder is not enabled in this build")
+    }
+
+    fn encode_jpeg_turbo(img: &DynamicImage, quality: u8, arithmetic: bool) -> Result<Vec<u8>> {
+        // Grayscale sources stay single-channel instead of being expanded to RGB
+        if let DynamicImage::ImageLuma8(gray) = img {
+            let image = turbojpeg::Image {
+                pixels: gray.as_raw().as_slice(),
+                width: gray.width() as usize,
+                pitch: gray.width() as usize,
+                height: gray.height() as usize,
+                format: turbojpeg::PixelFormat::GRAY,
+            };
+            return Self::compress_jpeg(image, quality, turbojpeg::Subsamp::Gray, arithmetic);
+        }
+
+        // RGBA is handed over as-is; turbojpeg skips the padding byte just as
+        // to_rgb8 would drop alpha, without a full-size intermediate copy
+        if let DynamicImage::ImageRgba8(rgba) = img {
+            let image = turbojpeg::Image {
+                pixels: rgba.as_raw().as_slice(),
+                width: rgba.width() as usize,
+                pitch: rgba.width() as usize * 4,
+                height: rgba.height() as usize,
+                format: turbojpeg::PixelFormat::RGBX,
+            };
+            return Self::compress_jpeg(image, quality, turbojpeg::Subsamp::Sub2x2, arithmetic);
+        }
+
+        let rgb_image = Self::as_rgb8(img);
+        let image = turbojpeg::Image {
+            pixels: rgb_image.as_raw().as_slice(),
+            width: rgb_image.width() as usize,
+            pitch: rgb_image.width() as usize * 3,
+            height: rgb_image.height() as usize,
+            format: turbojpeg::PixelFormat::RGB,
+        };
+
+        Self::compress_jpeg(image, quality, turbojpeg::Subsamp::Sub2x2, arithmetic)
+    }
+
+    /// Borrow the buffer when the image is already 8-bit RGB, converting otherwise
+    pub fn as_rgb8(img: &DynamicImage) -> Cow<'_, RgbImage> {
+        match img {
+            DynamicImage::ImageRgb8(rgb) => Cow::Borrowed(rgb),
+            _ => Cow::Owned(img.to_rgb8()),
+        }
+    }
+
+    /// Borrow the buffer when the image is already 8-bit RGBA, converting otherwise
+    pub fn as_rgba8(img: &DynamicImage) -> Cow<'_, RgbaImage> {
+        match img {
+            DynamicImage::ImageRgba8(rgba) => Cow::Borrowed(rgba),
+            _ => Cow::Owned(img.to_rgba8()),
+        }
+    }
+
+    /// Compress with this thread's reusable compressor; quality and subsampling are
+    /// set on every call so no state leaks between images
+    fn compress_jpeg(image: turbojpeg::Image<&[u8]>, quality: u8, subsamp: turbojpeg::Subsamp, arithmetic: bool) -> Result<Vec<u8>> {
+        if arithmetic {
+            return Self::compress_jpeg_arithmetic(image, quality, subsamp);
+        }
+
+        JPEG_COMPRESSOR.with(|cell| {
+            let mut compressor = match cell.borrow_mut().take() {
+                Some(compressor) => compressor,
+                None => turbojpeg::Compressor::new()
+                    .context("Failed to create turbojpeg compressor")?,
+            };
+
+            compressor.set_quality(quality as i32)
+                .context("Failed to set JPEG quality")?;
+            compressor.set_subsamp(subsamp)
+                .context("Failed to set JPEG subsampling")?;
+            let data = compressor.compress_to_vec(image)
+                .context("Failed to compress JPEG with turbojpeg")?;
+
+            // Only a compressor that finished cleanly goes back for reuse
+            *cell.borrow_mut() = Some(compressor);
+            Ok(data)
+        })
+    }
+
+    /// The turbojpeg crate doesn't expose TJPARAM_ARITHMETIC, so arithmetic coding
+    /// goes through the TurboJPEG 3 C API directly with a one-off handle
+    fn compress_jpeg_arithmetic(image: turbojpeg::Image<&[u8]>, quality: u8, subsamp: turbojpeg::Subsamp) -> Result<Vec<u8>> {
+        use turbojpeg_sys as raw;
+        use std::os::raw::c_int;
+
+        let pixel_format = match image.format {
+            turbojpeg::PixelFormat::GRAY => raw::TJPF_TJPF_GRAY,
+            turbojpeg::PixelFormat::RGBX => raw::TJPF_TJPF_RGBX,
+            _ => raw::TJPF_TJPF_RGB,
+        };
+        let subsamp = match subsamp {
+            turbojpeg::Subsamp::Gray => raw::TJSAMP_TJSAMP_GRAY,
+            _ => raw::TJSAMP_TJSAMP_420,
+        };
+
+        // SAFETY: the handle is checked before use and destroyed on every path; the
+        // pixel buffer outlives the call and width, pitch and height describe it
+        unsafe {
+            let handle = raw::tj3Init(raw::TJINIT_TJINIT_COMPRESS as c_int);
+            if handle.is_null() {
+                anyhow::bail!("Failed to create turbojpeg compressor");
+            }
+            let error = |handle| std::ffi::CStr::from_ptr(raw::tj3GetErrorStr(handle)).to_string_lossy().to_string();
+
+            let configured = raw::tj3Set(handle, raw::TJPARAM_TJPARAM_QUALITY as c_int, quality as c_int) == 0
+                && raw::tj3Set(handle, raw::TJPARAM_TJPARAM_SUBSAMP as c_int, subsamp as c_int) == 0
+                && raw::tj3Set(handle, raw::TJPARAM_TJPARAM_ARITHMETIC as c_int, 1) == 0;
+            if !configured {
+                let message = error(handle);
+                raw::tj3Destroy(handle);
+                anyhow::bail!("Failed to configure arithmetic JPEG coding: {}", message);
+            }
+
+            let mut buffer: *mut u8 = std::ptr::null_mut();
+            let mut size: raw::size_t = 0;
+            let status = raw::tj3Compress8(
+                handle,
+                image.pixels.as_ptr(),
+                image.width as c_int,
+                image.pitch as c_int,
+                image.height as c_int,
+                pixel_format as c_int,
+                &mut buffer,
+                &mut size,
+            );
+            let result = if status == 0 && !buffer.is_null() {
+                Ok(std::slice::from_raw_parts(buffer, size as usize).to_vec())
+            } else {
+                Err(anyhow::anyhow!("Failed to compress arithmetic-coded JPEG: {}", error(handle)))
+            };
+            raw::tj3Free(buffer.cast());
+            raw::tj3Destroy(handle);
+            result
+        }
+    }
+
+    /// Dimensions of a 1-bit TIFF, which the image crate can't open, or None for
+    /// any other TIFF
+    fn bilevel_tiff_dimensions(path: &str) -> Result<Option<(u32, u32)>> {
+        let file = std::fs::File::open(path).context("Failed to reopen output")?;
+        let mut decoder = tiff::decoder::Decoder::new(std::io::BufReader::new(file))
+            .context("Output is not a valid Tiff file")?;
+        if decoder.colortype().context("Output header is unreadable")? != tiff::ColorType::Gray(1) {
+            return Ok(None);
+        }
+        Ok(Some(decoder.dimensions().context("Output header is unreadable")?))
+    }
+
+    /// Re-open a written output and check it decodes as the expected format and size.
+    /// Small files are fully decoded; large ones only have their header checked.
+    pub fn verify_output(output_path: &str, format: ImageFormat, expected: (u32, u32)) -> Result<()> {
+        const FULL_DECODE_LIMIT: u64 = 20 * 1024 * 1024;
+
+        let size = std::fs::metadata(output_path)
+            .context("Output file is missing")?
+            .len();
+        if size == 0 {
+            anyhow::bail!("Output file is empty");
+        }
+
+        let bilevel_tiff = match format {
+            ImageFormat::Tiff => Self::bilevel_tiff_dimensions(output_path)?,
+            _ => None,
+        };
+        let dimensions = if let Some(dimensions) = bilevel_tiff {
+            // Nothing here decodes 1-bit TIFF samples, so only the header is checked
+            dimensions
+        } else if format == ImageFormat::Avif {
+            // The image crate is built without an AVIF decoder
+            let img = Self::load_heic(output_path, &HeicDecodeOptions::default())
+                .context("Output failed to decode")?;
+            (img.width(), img.height())
+        } else {
+            let reader = image::io::Reader::open(output_path)
+                .context("Failed to reopen output")?
+                .with_guessed_format()
+                .context("Failed to read output header")?;
+            if reader.format() != Some(format) {
+                anyhow::bail!("Output is not a valid {:?} file", format);
+            }
+
+            if size <= FULL_DECODE_LIMIT {
+                reader.decode()
+                    .map(|img| (img.width(), img.height()))
+                    .context("Output failed to decode")?
+            } else {
+                reader.into_dimensions()
+                    .context("Output header is unreadable")?
+            }
+        };
+
+        if dimensions != expected {
+            anyhow::bail!(
+                "Output is {}x{}, expected {}x{}",
+                dimensions.0, dimensions.1, expected.0, expected.1
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Whether the given output format can store the pixel layout
+    pub fn supports_pixel_format(format: ImageFormat, pixel_format: PixelFormat) -> bool {
+        match format {
+            ImageFormat::Jpeg => matches!(pixel_format, PixelFormat::Rgb8 | PixelFormat::Luma8),
+            ImageFormat::Png => true,
+            // Gray with alpha is widened to RGBA
+            ImageFormat::Tiff => !matches!(pixel_format, PixelFormat::LumaA8 | PixelFormat::LumaA16),
+            ImageFormat::Bmp | ImageFormat::WebP => matches!(pixel_format, PixelFormat::Rgb8 | PixelFormat::Rgba8),
+            ImageFormat::Avif => pixel_format == PixelFormat::Rgb8,
+            ImageFormat::Tga => matches!(
+                pixel_format,
+                PixelFormat::Luma8 | PixelFormat::LumaA8 | PixelFormat::Rgb8 | PixelFormat::Rgba8
+            ),
+            ImageFormat::Pnm => matches!(
+                pixel_format,
+                PixelFormat::Luma8 | PixelFormat::Luma16 | PixelFormat::Rgb8 | PixelFormat::Rgb16
+            ),
+            _ => false,
+        }
+    }
+
+    /// Convert to an explicit pixel layout before encoding.
+    /// None when the image is already in that layout.
+    pub fn convert_pixel_format(img: &DynamicImage, pixel_format: PixelFormat) -> Option<DynamicImage> {
+        let already = matches!(
+            (pixel_format, img),
+            (PixelFormat::Luma8, DynamicImage::ImageLuma8(_))
+                | (PixelFormat::LumaA8, DynamicImage::ImageLumaA8(_))
+                | (PixelFormat::Rgb8, DynamicImage::ImageRgb8(_))
+                | (PixelFormat::Rgba8, DynamicImage::ImageRgba8(_))
+                | (PixelFormat::Luma16, DynamicImage::ImageLuma16(_))
+                | (PixelFormat::LumaA16, DynamicImage::ImageLumaA16(_))
+                | (PixelFormat::Rgb16, DynamicImage::ImageRgb16(_))
+                | (PixelFormat::Rgba16, DynamicImage::ImageRgba16(_))
+        );
+        if already {
+            return None;
+        }
+
+        Some(match pixel_format {
+            PixelFormat::Luma8 => DynamicImage::ImageLuma8(img.to_luma8()),
+            PixelFormat::LumaA8 => DynamicImage::ImageLumaA8(img.to_luma_alpha8()),
+            PixelFormat::Rgb8 => DynamicImage::ImageRgb8(img.to_rgb8()),
+            PixelFormat::Rgba8 => DynamicImage::ImageRgba8(img.to_rgba8()),
+            PixelFormat::Luma16 => DynamicImage::ImageLuma16(img.to_luma16()),
+            PixelFormat::LumaA16 => DynamicImage::ImageLumaA16(img.to_luma_alpha16()),
+            PixelFormat::Rgb16 => DynamicImage::ImageRgb16(img.to_rgb16()),
+            PixelFormat::Rgba16 => DynamicImage::ImageRgba16(img.to_rgba16()),
+        })
     }
 
     pub fn estimate_size(
@@ -215,7 +2100,419 @@ impl ImageProcessor {
             "png" => {
                 (pixel_count * 3.5) as u64
             }
+            "bmp" => {
+                (pixel_count * 3.0) as u64
+            }
+            "tga" => {
+                // Uncompressed; RGBA sources take 4 bytes per pixel
+                (pixel_count * 3.5) as u64
+            }
+            "ppm" => {
+                (pixel_count * 3.0) as u64
+            }
+            "pgm" => {
+                pixel_count as u64
+            }
+            "tiff" => {
+                // Deflate on photos typically keeps a bit over half of the raw size
+                (pixel_count * 1.8) as u64
+            }
+            "avif" => {
+                // Roughly half the size of JPEG at the same nominal quality
+                let quality_factor = quality as f64 / 100.0;
+                let bytes_per_pixel = 0.25 + (quality_factor * 1.25);
+                (pixel_count * bytes_per_pixel) as u64
+            }
+            "webp" => {
+                // Roughly 30% smaller than JPEG at the same nominal quality
+                let quality_factor = quality as f64 / 100.0;
+                let bytes_per_pixel = 0.35 + (quality_factor * 1.75);
+                (pixel_count * bytes_per_pixel) as u64
+            }
             _ => 0,
         }
     }
 }
+
+/// EXIF orientation that undoes `orientation`; only the two quarter turns differ
+/// from their own inverse
+fn inverse_orientation(orientation: u32) -> u32 {
+    match orientation {
+        6 => 8,
+        8 => 6,
+        other => other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{allocated_by, gradient, photo, text_page, TempDir};
+
+    /// A4 at 300 dpi
+    const PAGE: (u32, u32) = (2480, 3508);
+
+    #[test]
+    fn bilevel_png_of_a_text_page_is_small_and_lossless() {
+        let page = text_page(PAGE.0, PAGE.1);
+        let data = ImageProcessor::encode_png_bilevel(&page).unwrap();
+        // Tens of kilobytes, against a megabyte of raw 1-bit rows
+        assert!(data.len() < 100_000, "{} bytes", data.len());
+
+        let decoded = image::load_from_memory(&data).unwrap().to_luma8();
+        assert_eq!(decoded, page);
+    }
+
+    #[test]
+    fn g4_tiff_of_a_text_page_is_small() {
+        use tiff::tags::Tag;
+
+        let page = text_page(PAGE.0, PAGE.1);
+        let data = ImageProcessor::encode_tiff_bilevel(&page, TiffCompression::CcittG4, 1).unwrap();
+        assert!(data.len() < 100_000, "{} bytes", data.len());
+
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(&data)).unwrap();
+        assert_eq!(decoder.get_tag_u32(Tag::Compression).unwrap(), 4);
+        assert_eq!(decoder.get_tag_u32(Tag::BitsPerSample).unwrap(), 1);
+        assert_eq!(decoder.get_tag_u32(Tag::ImageWidth).unwrap(), PAGE.0);
+        assert_eq!(decoder.get_tag_u32(Tag::ImageLength).unwrap(), PAGE.1);
+    }
+
+    #[test]
+    fn png_round_trips_every_layout_at_every_compression() {
+        let rgb = gradient(37, 23);
+        let layouts = [
+            DynamicImage::ImageLuma8(rgb.to_luma8()),
+            DynamicImage::ImageLumaA8(rgb.to_luma_alpha8()),
+            rgb.clone(),
+            DynamicImage::ImageRgba8(rgb.to_rgba8()),
+            DynamicImage::ImageLuma16(rgb.to_luma16()),
+            DynamicImage::ImageLumaA16(rgb.to_luma_alpha16()),
+            DynamicImage::ImageRgb16(rgb.to_rgb16()),
+            DynamicImage::ImageRgba16(rgb.to_rgba16()),
+        ];
+        for compression in [PngCompression::Fast, PngCompression::Balanced, PngCompression::Best] {
+            for img in &layouts {
+                let data = ImageProcessor::encode_png(img, compression).unwrap();
+                let decoded = image::load_from_memory_with_format(&data, ImageFormat::Png).unwrap();
+                assert_eq!(decoded.color(), img.color(), "{:?}", compression);
+                assert_eq!(decoded.as_bytes(), img.as_bytes(), "{:?} {:?}", compression, img.color());
+            }
+        }
+
+        // Float samples are stored as 16-bit
+        let float = DynamicImage::ImageRgb32F(rgb.to_rgb32f());
+        let decoded = image::load_from_memory(&ImageProcessor::encode_png(&float, PngCompression::Fast).unwrap()).unwrap();
+        assert_eq!(decoded.to_rgba16(), float.to_rgba16());
+    }
+
+    /// Timing comparison against image's default PNG encoder on a 48MP photo; not
+    /// a pass/fail gate. Run with
+    /// `cargo test --release png_encode_benchmark -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn png_encode_benchmark() {
+        use std::time::Instant;
+
+        let img = photo(8000, 6000);
+
+        let start = Instant::now();
+        let mut baseline = Vec::new();
+        img.write_to(&mut Cursor::new(&mut baseline), ImageOutputFormat::Png).unwrap();
+        let baseline_time = start.elapsed();
+        println!("image default: {:>8.0?} {:>10} bytes", baseline_time, baseline.len());
+
+        for compression in [PngCompression::Fast, PngCompression::Balanced, PngCompression::Best] {
+            let start = Instant::now();
+            let data = ImageProcessor::encode_png(&img, compression).unwrap();
+            let time = start.elapsed();
+            println!(
+                "{:<13} {:>8.0?} {:>10} bytes  {:.1}x faster, {:.2}x the size",
+                format!("{:?}:", compression),
+                time,
+                data.len(),
+                baseline_time.as_secs_f64() / time.as_secs_f64(),
+                data.len() as f64 / baseline.len() as f64,
+            );
+        }
+    }
+
+    /// Output of a one-off turbojpeg handle, as encoding worked before compressors
+    /// were reused
+    fn one_off_jpeg(img: &DynamicImage, quality: u8) -> Vec<u8> {
+        match img {
+            DynamicImage::ImageLuma8(gray) => {
+                let image = turbojpeg::Image {
+                    pixels: gray.as_raw().as_slice(),
+                    width: gray.width() as usize,
+                    pitch: gray.width() as usize,
+                    height: gray.height() as usize,
+                    format: turbojpeg::PixelFormat::GRAY,
+                };
+                turbojpeg::compress(image, quality as i32, turbojpeg::Subsamp::Gray).unwrap().to_vec()
+            }
+            _ => turbojpeg::compress_image(&img.to_rgb8(), quality as i32, turbojpeg::Subsamp::Sub2x2).unwrap().to_vec(),
+        }
+    }
+
+    #[test]
+    fn reused_jpeg_compressor_matches_one_off_handles() {
+        let rgb = photo(67, 45);
+        let images = [
+            DynamicImage::ImageLuma8(rgb.to_luma8()),
+            rgb.clone(),
+            DynamicImage::ImageRgba8(rgb.to_rgba8()),
+            DynamicImage::ImageRgb16(rgb.to_rgb16()),
+        ];
+        // Alternating settings, so anything left over from the previous call would show
+        for quality in [95, 40, 75, 95] {
+            for img in &images {
+                assert_eq!(
+                    ImageProcessor::encode_jpeg_turbo(img, quality, false).unwrap(),
+                    one_off_jpeg(img, quality),
+                    "{:?} at quality {}", img.color(), quality
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn jpeg_compressors_are_independent_per_thread() {
+        let img = photo(64, 48);
+        let expected: Vec<_> = (0..4).map(|i| one_off_jpeg(&img, 50 + i * 10)).collect();
+        std::thread::scope(|scope| {
+            for (i, expected) in expected.iter().enumerate() {
+                let img = &img;
+                scope.spawn(move || {
+                    for _ in 0..5 {
+                        let quality = 50 + i as u8 * 10;
+                        assert_eq!(&ImageProcessor::encode_jpeg_turbo(img, quality, false).unwrap(), expected);
+                    }
+                });
+            }
+        });
+    }
+
+    #[test]
+    fn rgb8_and_rgba8_sources_are_borrowed() {
+        let rgb = photo(8, 8);
+        let DynamicImage::ImageRgb8(buffer) = &rgb else { unreachable!() };
+        assert!(matches!(ImageProcessor::as_rgb8(&rgb), Cow::Borrowed(borrowed) if std::ptr::eq(borrowed, buffer)));
+        assert!(matches!(ImageProcessor::as_rgb8(&DynamicImage::ImageRgb16(rgb.to_rgb16())), Cow::Owned(_)));
+
+        let rgba = DynamicImage::ImageRgba8(rgb.to_rgba8());
+        assert!(matches!(ImageProcessor::as_rgba8(&rgba), Cow::Borrowed(_)));
+        assert!(matches!(ImageProcessor::as_rgba8(&rgb), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn jpeg_encoding_does_not_copy_8_bit_sources() {
+        let rgb = photo(2000, 1500);
+        let raw = 2000 * 1500 * 3;
+        let rgba = DynamicImage::ImageRgba8(rgb.to_rgba8());
+        // Warm up this thread's compressor so its setup isn't counted
+        ImageProcessor::encode_jpeg_turbo(&rgb, 90, false).unwrap();
+
+        for img in [&rgb, &rgba] {
+            let (data, allocated) = allocated_by(|| ImageProcessor::encode_jpeg_turbo(img, 90, false).unwrap());
+            // The output itself is the only sizable allocation left
+            assert!(allocated < data.len() * 2 + 64 * 1024, "{:?}: {} bytes allocated", img.color(), allocated);
+            assert!(allocated < raw / 2, "{:?}: {} bytes allocated", img.color(), allocated);
+        }
+
+        // Other layouts still need a converted copy
+        let rgb16 = DynamicImage::ImageRgb16(rgb.to_rgb16());
+        let (_, allocated) = allocated_by(|| ImageProcessor::encode_jpeg_turbo(&rgb16, 90, false).unwrap());
+        assert!(allocated >= raw);
+    }
+
+    fn tiff_tag(data: &[u8], tag: tiff::tags::Tag) -> Option<u32> {
+        let mut decoder = tiff::decoder::Decoder::new(Cursor::new(data)).unwrap();
+        decoder.find_tag(tag).unwrap().map(|value| value.into_u32().unwrap())
+    }
+
+    fn tiff_compression_tag(data: &[u8]) -> u32 {
+        tiff_tag(data, tiff::tags::Tag::Compression).unwrap()
+    }
+
+    #[test]
+    fn tiff_compression_is_tagged_and_lossless() {
+        let rgb = photo(121, 80);
+        let layouts = [
+            DynamicImage::ImageLuma8(rgb.to_luma8()),
+            rgb.clone(),
+            DynamicImage::ImageRgba8(rgb.to_rgba8()),
+            DynamicImage::ImageRgb16(rgb.to_rgb16()),
+            DynamicImage::ImageRgba32F(rgb.to_rgba32f()),
+        ];
+        for (compression, tag) in [(TiffCompression::None, 1), (TiffCompression::Lzw, 5), (TiffCompression::Deflate, 8)] {
+            for img in &layouts {
+                let context = format!("{:?} {:?}", compression, img.color());
+                let data = ImageProcessor::encode_tiff(img, compression, 1).unwrap();
+                assert_eq!(tiff_compression_tag(&data), tag, "{}", context);
+                // Horizontal differencing for compressed integer samples only
+                let predictor = (tag != 1 && img.color() != ColorType::Rgba32F).then_some(2);
+                assert_eq!(tiff_tag(&data, tiff::tags::Tag::Predictor), predictor, "{}", context);
+
+                if let DynamicImage::ImageRgba32F(float) = img {
+                    // image's TIFF decoder doesn't read float samples
+                    let mut decoder = tiff::decoder::Decoder::new(Cursor::new(&data)).unwrap();
+                    let tiff::decoder::DecodingResult::F32(samples) = decoder.read_image().unwrap() else {
+                        panic!("{}: not float samples", context);
+                    };
+                    assert_eq!(&samples, float.as_raw(), "{}", context);
+                    continue;
+                }
+                let decoded = image::load_from_memory_with_format(&data, ImageFormat::Tiff).unwrap();
+                assert_eq!(decoded.as_bytes(), img.as_bytes(), "{}", context);
+            }
+        }
+        assert!(ImageProcessor::encode_tiff(&rgb, TiffCompression::CcittG4, 1).is_err());
+        assert_eq!(TiffCompression::default(), TiffCompression::Deflate);
+    }
+
+    #[test]
+    fn deflate_shrinks_a_10mp_photo_well_below_raw() {
+        let img = photo(3872, 2592);
+        let raw = 3872 * 2592 * 3;
+        let data = ImageProcessor::encode_tiff(&img, TiffCompression::Deflate, 1).unwrap();
+        assert_eq!(tiff_compression_tag(&data), 8);
+        // The photo's noise alone keeps it above about 45%
+        assert!(data.len() < raw * 3 / 5, "{} of {} bytes", data.len(), raw);
+    }
+
+    /// An ftyp box with the given major and compatible brands
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let mut data = ((16 + 4 * compatible.len()) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(b"ftyp");
+        data.extend_from_slice(major);
+        data.extend_from_slice(&[0; 4]);
+        for brand in compatible {
+            data.extend_from_slice(*brand);
+        }
+        data
+    }
+
+    #[test]
+    fn every_heif_brand_maps_to_its_format() {
+        let expected: [(&[u8; 4], &str); 13] = [
+            (b"heic", "heic"), (b"heix", "heic"), (b"heim", "heic"), (b"heis", "heic"),
+            (b"hevc", "heic"), (b"hevx", "heic"), (b"hevm", "heic"), (b"hevs", "heic"),
+            (b"avif", "avif"), (b"avis", "avif"),
+            (b"mif1", "heif"), (b"mif2", "heif"), (b"msf1", "heif"),
+        ];
+        assert_eq!(HEIF_BRANDS, expected.as_slice());
+
+        for (brand, format) in expected {
+            let name = String::from_utf8_lossy(brand);
+            assert_eq!(ImageProcessor::heif_brand(&ftyp(brand, &[])), Some(format), "major {}", name);
+            assert_eq!(ImageProcessor::heif_brand(&ftyp(b"isom", &[brand])), Some(format), "compatible {}", name);
+            assert!(ImageProcessor::is_heif_format(format));
+        }
+    }
+
+    #[test]
+    fn major_brand_wins_then_table_order() {
+        assert_eq!(ImageProcessor::heif_brand(&ftyp(b"avif", &[b"mif1", b"heic"])), Some("avif"));
+        assert_eq!(ImageProcessor::heif_brand(&ftyp(b"isom", &[b"mif1", b"heic"])), Some("heic"));
+        assert_eq!(ImageProcessor::heif_brand(&ftyp(b"isom", &[b"mif1", b"avis"])), Some("avif"));
+    }
+
+    #[test]
+    fn non_heif_headers_have_no_brand() {
+        assert_eq!(ImageProcessor::heif_brand(&ftyp(b"isom", &[b"mp41", b"avc1"])), None);
+        assert_eq!(ImageProcessor::heif_brand(&ftyp(b"qt  ", &[])), None);
+        assert_eq!(ImageProcessor::heif_brand(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), None);
+        assert_eq!(ImageProcessor::heif_brand(b"\0\0"), None);
+        // Brands past the box's declared size belong to whatever follows it
+        let mut truncated = ftyp(b"isom", &[]);
+        truncated.extend_from_slice(b"heic");
+        assert_eq!(ImageProcessor::heif_brand(&truncated), None);
+    }
+
+    #[test]
+    fn get_format_sniffs_renamed_files_and_falls_back_to_extensions() {
+        let dir = TempDir::new();
+        let renamed = dir.write("photo.jpg", &ftyp(b"heic", &[b"mif1", b"heic"]));
+        assert_eq!(ImageProcessor::get_format(&renamed).unwrap(), "heic");
+
+        for (extension, format) in HEIF_EXTENSIONS {
+            let path = dir.write(&format!("unsniffable.{}", extension.to_uppercase()), b"not an ftyp box");
+            assert_eq!(ImageProcessor::get_format(&path).unwrap(), *format, "{}", extension);
+        }
+        assert_eq!(ImageProcessor::get_format(&dir.write("plain.PNG", b"")).unwrap(), "png");
+    }
+
+    fn bmp_u16(data: &[u8], offset: usize) -> u16 {
+        u16::from_le_bytes([data[offset], data[offset + 1]])
+    }
+
+    fn bmp_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn bmp_round_trips_at_both_bit_depths() {
+        let rgb = photo(37, 19);
+        let rgba = DynamicImage::ImageRgba8(RgbaImage::from_fn(37, 19, |x, y| {
+            let [r, g, b] = rgb.to_rgb8().get_pixel(x, y).0;
+            image::Rgba([r, g, b, (x * 7 + y) as u8])
+        }));
+
+        for (img, bits, expected_bits) in [
+            (&rgb, None, 24),
+            (&rgba, None, 32),
+            (&rgb, Some(24), 24),
+            (&rgba, Some(24), 24),
+            (&rgb, Some(32), 32),
+            (&rgba, Some(32), 32),
+        ] {
+            let context = format!("{:?} requested {:?}", img.color(), bits);
+            let data = ImageProcessor::encode_bmp(img, bits).unwrap();
+            assert_eq!(bmp_u16(&data, 28), expected_bits, "{}", context);
+
+            let decoded = image::load_from_memory_with_format(&data, ImageFormat::Bmp).unwrap();
+            let fallback = BmpProcessor::decode(&data).unwrap();
+            if expected_bits == 32 {
+                // Opaque sources gain an opaque alpha channel
+                assert_eq!(decoded.to_rgba8(), img.to_rgba8(), "{}", context);
+                assert_eq!(fallback.to_rgba8(), img.to_rgba8(), "{} (fallback decoder)", context);
+            } else {
+                assert!(!decoded.color().has_alpha(), "{}", context);
+                assert_eq!(decoded.to_rgb8(), img.to_rgb8(), "{}", context);
+                assert_eq!(fallback.to_rgb8(), img.to_rgb8(), "{} (fallback decoder)", context);
+            }
+        }
+    }
+
+    #[test]
+    fn indexed_bmp_round_trips_exactly_with_up_to_256_colors() {
+        // 256 distinct colors, and an odd width so every row needs padding
+        let img = DynamicImage::ImageRgb8(RgbImage::from_fn(37, 19, |x, y| {
+            image::Rgb([(x % 16 * 17) as u8, (y % 16 * 17) as u8, 99])
+        }));
+        let data = ImageProcessor::encode_bmp_indexed(&img).unwrap();
+        assert_eq!(bmp_u16(&data, 28), 8);
+        assert_eq!(bmp_u32(&data, 46), 256);
+
+        let decoded = image::load_from_memory_with_format(&data, ImageFormat::Bmp).unwrap();
+        assert_eq!(decoded.to_rgb8(), img.to_rgb8());
+    }
+
+    #[test]
+    fn indexed_bmp_quantizes_more_than_256_colors() {
+        let img = gradient(64, 64);
+        let data = ImageProcessor::encode_bmp_indexed(&img).unwrap();
+        assert_eq!(bmp_u16(&data, 28), 8);
+        assert!(bmp_u32(&data, 46) <= 256);
+        assert_eq!(data.len(), 14 + 40 + 256 * 4 + 64 * 64);
+
+        let decoded = image::load_from_memory_with_format(&data, ImageFormat::Bmp).unwrap().to_rgb8();
+        let source = img.to_rgb8();
+        let error: u64 = decoded.as_raw().iter().zip(source.as_raw())
+            .map(|(&a, &b)| a.abs_diff(b) as u64)
+            .sum();
+        let mean = error as f64 / source.as_raw().len() as f64;
+        assert!(mean < 8.0, "mean error {mean}");
+    }
+}
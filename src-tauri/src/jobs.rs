@@ -0,0 +1,289 @@
+use crate::{BatchConversionItem, BatchConversionResult, ConversionSettings};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How many completed batch jobs are kept for reporting
+const RETAINED_JOBS: usize = 10;
+
+/// A completed batch run with everything needed to report on it
+pub struct BatchJob {
+    pub job_id: String,
+    pub settings: ConversionSettings,
+    pub items: Vec<BatchConversionItem>,
+    pub results: Vec<BatchConversionResult>,
+    pub finished_at: u64,
+    pub duration_ms: u64,
+}
+
+impl BatchJob {
+    pub fn summary(&self) -> BatchJobSummary {
+        let converted = self.results.iter().filter_map(|r| r.result.as_ref());
+        let (input_bytes, output_bytes) = converted
+            .fold((0, 0), |(input, output), c| (input + c.input_bytes, output + c.output_bytes));
+        BatchJobSummary {
+            job_id: self.job_id.clone(),
+            total: self.results.len(),
+            succeeded: self.results.iter().filter(|r| r.success).count(),
+            skipped: self.results.iter().filter(|r| r.skipped).count(),
+            finished_at: self.finished_at,
+            input_bytes,
+            output_bytes,
+            saved_bytes: input_bytes as i64 - output_bytes as i64,
+            compression_ratio: compression_ratio(input_bytes, output_bytes),
+        }
+    }
+}
+
+/// Counts and sizes for one run of a batch, returned with its results
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchSummary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    /// Sizes summed over the converted files only
+    pub total_input_bytes: u64,
+    pub total_output_bytes: u64,
+    pub elapsed_ms: u64,
+}
+
+impl BatchSummary {
+    pub fn new(results: &[BatchConversionResult], elapsed_ms: u64) -> Self {
+        let converted = results.iter().filter_map(|r| r.result.as_ref());
+        let (total_input_bytes, total_output_bytes) = converted
+            .fold((0, 0), |(input, output), c| (input + c.input_bytes, output + c.output_bytes));
+        let succeeded = results.iter().filter(|r| r.success).count();
+        let skipped = results.iter().filter(|r| r.skipped).count();
+        BatchSummary {
+            total: results.len(),
+            succeeded,
+            failed: results.len() - succeeded - skipped,
+            skipped,
+            total_input_bytes,
+            total_output_bytes,
+            elapsed_ms,
+        }
+    }
+}
+
+/// Input size over output size, e.g. 4.0 when the output is a quarter of the input.
+/// None when either size is unknown (0).
+pub fn compression_ratio(input_bytes: u64, output_bytes: u64) -> Option<f64> {
+    (input_bytes > 0 && output_bytes > 0).then(|| input_bytes as f64 / output_bytes as f64)
+}
+
+/// Most recent batch jobs, oldest first
+#[derive(Default)]
+pub struct BatchJobs(Mutex<VecDeque<BatchJob>>);
+
+#[derive(Serialize)]
+pub struct BatchJobSummary {
+    pub job_id: String,
+    pub total: usize,
+    pub succeeded: usize,
+    pub skipped: usize,
+    pub finished_at: u64,
+    /// Sizes summed over the converted files only
+    pub input_bytes: u64,
+    pub output_bytes: u64,
+    pub saved_bytes: i64,
+    pub compression_ratio: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct ReportTotals {
+    files: usize,
+    succeeded: usize,
+    failed: usize,
+    skipped: usize,
+    input_bytes: u64,
+    output_bytes: u64,
+    saved_bytes: i64,
+    compression_ratio: Option<f64>,
+    duration_ms: u64,
+}
+
+#[derive(Serialize)]
+struct ReportRow<'a> {
+    file_id: &'a str,
+    source: &'a str,
+    destination: &'a str,
+    success: bool,
+    skipped: bool,
+    input_bytes: Option<u64>,
+    output_bytes: Option<u64>,
+    saved_bytes: Option<i64>,
+    compression_ratio: Option<f64>,
+    duration_ms: Option<u64>,
+    error_code: Option<&'static str>,
+    error: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    job_id: &'a str,
+    finished_at: u64,
+    settings: &'a ConversionSettings,
+    totals: ReportTotals,
+    files: Vec<ReportRow<'a>>,
+}
+
+/// Identifier for a batch started without one
+pub fn new_job_id() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    format!("batch-{}", millis)
+}
+
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+impl BatchJobs {
+    /// Retain a finished job, evicting the oldest beyond the retention limit.
+    /// A job re-run under the same id replaces the earlier record.
+    pub fn insert(&self, job: BatchJob) {
+        let mut jobs = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.retain(|existing| existing.job_id != job.job_id);
+        jobs.push_back(job);
+        while jobs.len() > RETAINED_JOBS {
+            jobs.pop_front();
+        }
+    }
+
+    pub fn contains(&self, job_id: &str) -> bool {
+        let jobs = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.iter().any(|job| job.job_id == job_id)
+    }
+
+    pub fn list(&self) -> Vec<BatchJobSummary> {
+        let jobs = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        jobs.iter().map(BatchJob::summary).collect()
+    }
+
+    /// Write a report of a retained job as "csv" or "json"
+    pub fn export_report(&self, job_id: &str, path: &str, format: &str) -> Result<()> {
+        let jobs = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let job = jobs.iter()
+            .find(|job| job.job_id == job_id)
+            .with_context(|| format!("Unknown batch job '{}' (only the last {} jobs are kept)", job_id, RETAINED_JOBS))?;
+
+        let report = build_report(job);
+        let contents = match format {
+            "json" => serde_json::to_string_pretty(&report).context("Failed to serialize report")?,
+            "csv" => render_csv(&report)?,
+            other => anyhow::bail!("Unsupported report format: {}", other),
+        };
+
+        std::fs::write(path, contents).context("Failed to write report")
+    }
+}
+
+fn build_report(job: &BatchJob) -> Report<'_> {
+    let files: Vec<ReportRow> = job.items.iter()
+        .zip(&job.results)
+        .map(|(item, result)| {
+            let converted = result.result.as_ref();
+            let input_bytes = converted.map(|c| c.input_bytes);
+            let output_bytes = converted.map(|c| c.output_bytes);
+            ReportRow {
+                file_id: &item.file_id,
+                source: &item.path,
+                destination: converted.map(|c| c.output_path.as_str()).unwrap_or(&item.output_path),
+                success: result.success,
+                skipped: result.skipped,
+                input_bytes,
+                output_bytes,
+                saved_bytes: input_bytes.zip(output_bytes).map(|(i, o)| i as i64 - o as i64),
+                compression_ratio: converted.and_then(|c| c.compression_ratio),
+                duration_ms: converted.map(|c| c.duration_ms),
+                error_code: result.error.as_ref().map(|e| e.code.as_str()),
+                error: result.error.as_ref().map(|e| e.message.as_str()),
+            }
+        })
+        .collect();
+
+    let input_bytes: u64 = files.iter().filter_map(|row| row.input_bytes).sum();
+    let output_bytes: u64 = files.iter().filter_map(|row| row.output_bytes).sum();
+    let succeeded = files.iter().filter(|row| row.success).count();
+    let skipped = files.iter().filter(|row| row.skipped).count();
+
+    Report {
+        job_id: &job.job_id,
+        finished_at: job.finished_at,
+        settings: &job.settings,
+        totals: ReportTotals {
+            files: files.len(),
+            succeeded,
+            failed: files.len() - succeeded - skipped,
+            skipped,
+            input_bytes,
+            output_bytes,
+            saved_bytes: input_bytes as i64 - output_bytes as i64,
+            compression_ratio: compression_ratio(input_bytes, output_bytes),
+            duration_ms: job.duration_ms,
+        },
+        files,
+    }
+}
+
+/// Quote a CSV field when it contains a delimiter, quote or line break
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+fn render_csv(report: &Report) -> Result<String> {
+    let optional = |value: Option<String>| value.unwrap_or_default();
+    let settings = serde_json::to_string(report.settings).context("Failed to serialize settings")?;
+    let totals = &report.totals;
+
+    // Summary block first, then a blank line and the per-file table
+    let mut lines = vec![
+        format!("job_id,{}", csv_field(report.job_id)),
+        format!("finished_at,{}", report.finished_at),
+        format!("settings,{}", csv_field(&settings)),
+        format!("files,{}", totals.files),
+        format!("succeeded,{}", totals.succeeded),
+        format!("failed,{}", totals.failed),
+        format!("skipped,{}", totals.skipped),
+        format!("input_bytes,{}", totals.input_bytes),
+        format!("output_bytes,{}", totals.output_bytes),
+        format!("saved_bytes,{}", totals.saved_bytes),
+        format!("compression_ratio,{}", optional(totals.compression_ratio.map(|v| format!("{:.3}", v)))),
+        format!("duration_ms,{}", totals.duration_ms),
+        String::new(),
+        "file_id,source,destination,success,skipped,input_bytes,output_bytes,saved_bytes,compression_ratio,duration_ms,error_code,error".to_string(),
+    ];
+
+    for row in &report.files {
+        let fields = [
+            csv_field(row.file_id),
+            csv_field(row.source),
+            csv_field(row.destination),
+            row.success.to_string(),
+            row.skipped.to_string(),
+            optional(row.input_bytes.map(|v| v.to_string())),
+            optional(row.output_bytes.map(|v| v.to_string())),
+            optional(row.saved_bytes.map(|v| v.to_string())),
+            optional(row.compression_ratio.map(|v| format!("{:.3}", v))),
+            optional(row.duration_ms.map(|v| v.to_string())),
+            row.error_code.unwrap_or("").to_string(),
+            csv_field(row.error.unwrap_or("")),
+        ];
+        lines.push(fields.join(","));
+    }
+
+    Ok(lines.join("\r\n") + "\r\n")
+}
@@ -1,24 +1,382 @@
+mod access;
+mod adjustments;
+mod apng;
+mod auto_quality;
+mod bmp;
+mod budget;
+mod cancel;
+mod ccitt;
+mod cli;
+mod color;
+mod control;
+mod deskew;
+mod errors;
+mod favicon;
+mod file_ops;
+mod fits;
+mod gpu;
+mod heif_limits;
+mod ico;
 mod image_processor;
+mod jobs;
+mod manifest;
+mod metadata;
+mod naming;
+mod orientation;
+mod pcx;
+mod progress;
+mod raw;
+mod recommend;
+mod resources;
+mod sidecar;
+mod straighten;
+#[cfg(test)]
+mod test_util;
+mod tiles;
+mod upright;
+mod work_dir;
 
-use image_processor::ImageProcessor;
-use image::{GenericImageView, ImageFormat};
+use access::PermittedDirs;
+use adjustments::{Adjustments, BilevelMode, CropRect, DitherMode, FixedPalette, FlattenMode, Histogram, PreviewTransform, Redaction};
+use apng::{ApngEncoder, ApngSummary};
+use auto_quality::{AutoQuality, Fidelity};
+use bmp::{BmpInfo, BmpProcessor};
+use budget::MemoryBudget;
+use cancel::CancelToken;
+use color::ColorProfiles;
+use control::{BatchControl, BatchControls};
+use deskew::Deskewer;
+use errors::{AppError, ErrorCode};
+use favicon::{FaviconBundle, FaviconGenerator};
+use file_ops::{ChecksumAlgorithm, FileOps, OutputTransaction};
+use fits::{FitsOptions, FitsProcessor};
+use gpu::{GpuResizer, GpuStatus};
+use heif_limits::HeifLimits;
+use ico::{IconFrame, IconProcessor};
+use image_processor::{AnimationPolicy, DecodeLimits, HeicDecodeOptions, DEFAULT_WEBP_METHOD, ImageProcessor, JpegEncodeOptions, JpegEncoder, LoadOptions, PixelFormat, PngCompression, ResizeMode, TiffCompression};
+use jobs::{compression_ratio, new_job_id, unix_time, BatchJob, BatchJobSummary, BatchJobs, BatchSummary};
+use manifest::{BatchManifest, ItemStatus};
+use metadata::{HeifTransformation, MetadataProcessor, MetadataReport, MetadataStatus};
+use naming::ExtensionCase;
+use orientation::OrientationNormalizer;
+use progress::ProgressTracker;
+use raw::RawProcessor;
+use recommend::{ContentStats, FormatAdvisor, FormatSuggestion};
+use resources::{ResourceMonitor, ResourceUsage};
+use sidecar::{SidecarImage, SidecarRecord};
+use straighten::Straightener;
+use tiles::{TileGenerator, TilePyramid};
+use upright::UprightDetector;
+use work_dir::{WorkDir, WorkDirInfo};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use image::{DynamicImage, GenericImageView, ImageFormat};
 use serde::{Deserialize, Serialize};
-use tauri::Emitter;
+use tauri::{Emitter, Manager};
+use tauri_plugin_opener::OpenerExt;
 use rayon::prelude::*;
-use std::sync::Arc;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// In-flight preview decodes, keyed by source path
+#[derive(Default)]
+struct PreviewJobs(Mutex<HashMap<String, Arc<CancelToken>>>);
+
+/// Latest transform preview written for each source path; the previous one is
+/// deleted when it is replaced
+#[derive(Default)]
+struct TransformPreviews(Mutex<HashMap<String, std::path::PathBuf>>);
+
+/// Latest region preview written for each source path, replaced like TransformPreviews
+#[derive(Default)]
+struct RegionPreviews(Mutex<HashMap<String, std::path::PathBuf>>);
+
+/// File name prefix of transform previews in the working directory
+const TRANSFORM_PREVIEW_PREFIX: &str = "transform_preview_";
+/// File name prefix of region previews in the working directory
+const REGION_PREVIEW_PREFIX: &str = "region_preview_";
+/// How often batches emit a `resource_usage` event
+const RESOURCE_USAGE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+/// File name prefix of generate_thumbnails output in the working directory
+const THUMBNAIL_PREFIX: &str = "thumbnail_";
 
 #[derive(Serialize, Deserialize)]
 struct ImageMetadata {
     width: u32,
     height: u32,
     format: String,
+    comment: Option<String>,
+    /// For RAW inputs, how the image was decoded ("embedded_preview"). A RAW without
+    /// a near full-size preview fails with decode.raw_preview_inadequate instead.
+    raw_decode_path: Option<String>,
+    /// Description of the embedded ICC profile
+    color_profile: Option<String>,
+    /// Physical resolution (horizontal, vertical) in DPI
+    dpi: Option<(f64, f64)>,
+    /// Number of pages/images in the file; 1 for single-image formats
+    page_count: u32,
+    /// Number of animation frames; 1 for still images
+    frame_count: u32,
+    /// Transformations a HEIC/HEIF declares for its primary image
+    heic_transformations: Vec<HeifTransformation>,
+    /// Header, bit depth, compression and row order of a BMP
+    bmp_format: Option<BmpInfo>,
+    /// Frames available in an ICO/CUR file
+    icon_frames: Vec<IconFrame>,
+    /// Percentage of pure-white pixels (by luminance), for blown-highlight warnings
+    clipped_highlights_percent: Option<f32>,
+    /// Percentage of pure-black pixels (by luminance), for crushed-shadow warnings
+    clipped_shadows_percent: Option<f32>,
+    /// Whether the source was stored with lossy compression (see ImageProcessor::is_lossy)
+    lossy: bool,
+    /// Whether this is the still of an iPhone Live Photo; only the still is converted
+    is_live_photo: bool,
+    /// Content identifier shared by a Live Photo's still and its MOV
+    live_photo_id: Option<String>,
+    /// The Live Photo's MOV, when it sits next to the still under the same name
+    live_photo_video: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 struct ConversionSettings {
-    target_format: String,
+    /// Output format. When unset it's inferred from the output path's extension
+    /// (see resolve_target_format).
+    #[serde(default)]
+    target_format: Option<String>,
     quality: u8,
     preserve_metadata: bool,
+    /// Text written to the JPEG COM marker. When unset and metadata is preserved,
+    /// the source's comment is carried over.
+    #[serde(default)]
+    jpeg_comment: Option<String>,
+    /// Author written into every JPEG (EXIF Artist, XMP dc:creator) and PNG (Author
+    /// text, XMP) output, whether or not source metadata is preserved
+    #[serde(default)]
+    author: Option<String>,
+    /// Copyright notice, written like `author` (EXIF Copyright, XMP dc:rights, PNG
+    /// Copyright text)
+    #[serde(default)]
+    copyright: Option<String>,
+    /// Sidecar extensions (e.g. "xmp", "aae", "json") copied next to each output
+    #[serde(default)]
+    copy_sidecars: Vec<String>,
+    /// Resize caps applied to the displayed (post-EXIF-orientation) image
+    #[serde(default)]
+    max_width: Option<u32>,
+    #[serde(default)]
+    max_height: Option<u32>,
+    /// Resize to exactly [width, height], stretching or squashing the image when the
+    /// aspect ratio differs (e.g. power-of-two textures). Upscales as needed. Can't be
+    /// combined with max_width/max_height, which always keep the aspect ratio.
+    #[serde(default)]
+    exact_resize: Option<(u32, u32)>,
+    /// Whether max_width/max_height is a box to fit inside (the default), to cover
+    /// and crop to, or to stretch to
+    #[serde(default)]
+    resize_mode: ResizeMode,
+    /// Bake EXIF orientation into pixels. Every orientation marker in the output
+    /// (EXIF, TIFF tag) then says upright; when disabled, pixels are carried over
+    /// untouched and the markers repeat the source orientation. See output_orientation.
+    #[serde(default = "default_true")]
+    auto_orient: bool,
+    /// Cap on decoded image memory across parallel batch items, in megabytes.
+    /// Concurrency adapts to each item's size instead of a fixed thread count.
+    #[serde(default)]
+    memory_budget_mb: Option<u64>,
+    /// Pause the batch when free space on the output volume drops below this many
+    /// megabytes, until resume_paused_batch or cancel_batch
+    #[serde(default)]
+    min_free_disk_mb: Option<u64>,
+    /// Tag the output with an ICC profile without altering pixels: a builtin name
+    /// ("srgb", "adobe-rgb", "display-p3") or a path to an .icc file
+    #[serde(default)]
+    assign_profile: Option<String>,
+    /// Reduce to pure black and white (1-bit PNG output) for document scans
+    #[serde(default)]
+    bilevel: Option<BilevelMode>,
+    /// Write BMP output as 8-bit indexed color (palette of at most 256 entries)
+    #[serde(default)]
+    bmp_indexed: bool,
+    /// BMP bits per pixel: 24 (no alpha) or 32 (with alpha). Defaults to 32 only
+    /// when the image has alpha.
+    #[serde(default)]
+    bmp_bit_depth: Option<u8>,
+    /// Write PNG output as indexed color using exactly these palette entries
+    #[serde(default)]
+    png_palette: Option<FixedPalette>,
+    /// Carry the source's print resolution over to the output
+    #[serde(default = "default_true")]
+    preserve_dpi: bool,
+    /// Explicit output resolution, overriding the source's
+    #[serde(default)]
+    output_dpi: Option<f64>,
+    /// Extract just this region of the displayed (post-EXIF-orientation) image
+    #[serde(default)]
+    crop_rect: Option<CropRect>,
+    /// Crop away uniform borders (within this per-channel tolerance) before resizing
+    #[serde(default)]
+    trim_borders: Option<u8>,
+    /// Stretch contrast so the image spans black to white, ignoring this percentage
+    /// of the darkest and brightest samples
+    #[serde(default)]
+    auto_levels: Option<f32>,
+    /// Detect a tilted horizon and rotate to level it, correcting at most this many
+    /// degrees. Requires the `straighten` feature.
+    #[serde(default)]
+    auto_straighten: Option<f32>,
+    /// For sources without an EXIF orientation, detect sideways or upside-down text
+    /// and rotate it upright. Only rotates when the evidence is strong. Requires the
+    /// `upright` feature.
+    #[serde(default)]
+    detect_upright: bool,
+    /// Straighten scans tilted by up to 10 degrees, filling the uncovered corners with
+    /// the paper color. Images without a clear text skew are left untouched.
+    #[serde(default)]
+    deskew: bool,
+    /// Regions to blur, pixelate or fill before encoding
+    #[serde(default)]
+    redactions: Vec<Redaction>,
+    /// Force the encoded pixel layout (e.g. drop alpha, force 8-bit)
+    #[serde(default)]
+    output_pixel_format: Option<PixelFormat>,
+    /// Dither 16-bit and float sources when they're written as 8-bit, instead of
+    /// rounding, to hide banding in smooth gradients. Off when unset.
+    #[serde(default)]
+    bit_depth_dither: Option<DitherMode>,
+    /// Decode the written output back and fail the item if it doesn't match
+    #[serde(default)]
+    verify_output: bool,
+    /// Include digests of the input and output in results
+    #[serde(default)]
+    compute_checksums: bool,
+    /// Digest used by compute_checksums and the JSON sidecar. The output is hashed
+    /// from the written file, so the digest covers what storage actually holds.
+    #[serde(default)]
+    checksum_algorithm: ChecksumAlgorithm,
+    /// Upper bound on the output file size for JPEG and WebP; quality is searched
+    /// downward from `quality` until the output fits
+    #[serde(default)]
+    max_output_bytes: Option<u64>,
+    /// Lowest quality the max_output_bytes search may use
+    #[serde(default)]
+    min_quality: Option<u8>,
+    /// Instead of `quality`, encode JPEG and WebP at the lowest quality whose output
+    /// stays this close to the processed image, measured by SSIM
+    #[serde(default)]
+    auto_quality: Option<Fidelity>,
+    /// When max_output_bytes can't be met at min_quality, shrink the image until it is
+    #[serde(default)]
+    downscale_to_fit: bool,
+    /// PNG encoding speed/size tradeoff
+    #[serde(default)]
+    png_compression: PngCompression,
+    /// Embed a low-resolution preview in AVIF output so viewers can show something
+    /// while the full image loads. Left out when the encoder can't produce one,
+    /// which the encoder name in the result reflects.
+    #[serde(default)]
+    avif_preview: bool,
+    /// Resize on the GPU when one is available (requires the `gpu` feature); falls
+    /// back to the CPU whenever the GPU can't take the image
+    #[serde(default)]
+    gpu_resize: bool,
+    /// WebP encoder effort, 0 (fastest) to 6 (smallest files). Each step up costs
+    /// encode time for a few percent smaller output; 0 suits very large batches.
+    #[serde(default = "default_webp_method")]
+    webp_method: u8,
+    /// TIFF output compression
+    #[serde(default)]
+    tiff_compression: TiffCompression,
+    /// Dimension and allocation caps for decoding non-HEIF sources; HEIF files are
+    /// bounded by set_decoder_limits instead
+    #[serde(default)]
+    decode_limits: Option<DecodeLimits>,
+    /// Decode sources from a memory-mapped file rather than through read buffers,
+    /// lowering peak memory on gigabyte-scale TIFFs, PNGs and RAW files. Sources
+    /// that can't be mapped are read normally; HEIC is always read by libheif.
+    #[serde(default)]
+    memory_map_sources: bool,
+    /// Write a versioned `<output>.json` provenance record next to each output
+    #[serde(default)]
+    write_json_sidecar: bool,
+    /// How transparent pixels are blended when the output format has no alpha
+    #[serde(default)]
+    flatten_mode: FlattenMode,
+    /// Color transparent pixels are composited onto
+    #[serde(default = "default_flatten_background")]
+    flatten_background: [u8; 3],
+    /// libheif options for HEIC/HEIF sources
+    #[serde(default)]
+    heic_decode: HeicDecodeOptions,
+    /// Arithmetic coding and trellis quantization for JPEG output. Both off gives
+    /// baseline JPEGs every decoder reads.
+    #[serde(default)]
+    jpeg_encoding: JpegEncodeOptions,
+    /// Library that writes JPEG output. Unset means mozjpeg when trellis
+    /// quantization is on and turbojpeg otherwise.
+    #[serde(default)]
+    jpeg_encoder: Option<JpegEncoder>,
+    /// Never leave an output larger than its source file; larger_output decides what
+    /// happens instead. Has no effect on clipboard sources.
+    #[serde(default)]
+    keep_smaller: bool,
+    /// What keep_smaller does with an output that came out larger than its source
+    #[serde(default)]
+    larger_output: LargerOutput,
+    /// In batches, report inputs that aren't a readable image format as skipped
+    /// rather than failed
+    #[serde(default)]
+    skip_unsupported: bool,
+    /// How animated sources are handled; output is always a still image
+    #[serde(default)]
+    animation_policy: AnimationPolicy,
+    /// Black/white points and stretch curve for FITS sources
+    #[serde(default)]
+    fits: FitsOptions,
+    /// Batch output file names built from {exif_date:FORMAT}, {camera_model}, {name}
+    /// and {index}; each item's directory and extension are kept
+    #[serde(default)]
+    output_name_template: Option<String>,
+    /// Subfolders for batch outputs, e.g. "{exif_date:%Y}/{exif_date:%Y-%m}", using
+    /// the same tokens as output_name_template. Undated files go to "unknown".
+    #[serde(default)]
+    folder_template: Option<String>,
+    /// Name batch outputs exactly after their source file's stem, whatever stem the
+    /// requested output path has. Can't be combined with output_name_template.
+    #[serde(default)]
+    keep_source_stem: bool,
+    /// Letter case of batch output extensions, e.g. upper for `.JPG`
+    #[serde(default)]
+    extension_case: ExtensionCase,
+    /// ICO/CUR frame to convert, by its larger side in pixels. The closest larger
+    /// frame is used when there's no exact match; the largest frame when unset.
+    #[serde(default)]
+    icon_size: Option<u32>,
+}
+
+impl ConversionSettings {
+    /// Canonical output format name; empty until resolve_target_format has run
+    fn format_name(&self) -> &str {
+        self.target_format.as_deref().unwrap_or_default()
+    }
+
+    /// jpeg_encoding with the encoder jpeg_encoder resolves to
+    fn jpeg_options(&self) -> JpegEncodeOptions {
+        let implied = if self.jpeg_encoding.trellis { JpegEncoder::Mozjpeg } else { JpegEncoder::Turbo };
+        JpegEncodeOptions { encoder: self.jpeg_encoder.unwrap_or(implied), ..self.jpeg_encoding }
+    }
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_webp_method() -> u8 {
+    DEFAULT_WEBP_METHOD
+}
+
+fn default_flatten_background() -> [u8; 3] {
+    [255, 255, 255]
 }
 
 #[derive(Clone, Serialize)]
@@ -27,54 +385,465 @@ struct ConversionProgress {
     progress: u8,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 struct BatchConversionItem {
     file_id: String,
     path: String,
     output_path: String,
 }
 
+#[derive(Serialize, Clone)]
+struct ConversionResult {
+    output_path: String,
+    sidecars: Vec<String>,
+    /// For RAW inputs, how the image was decoded (see RawProcessor::decode_path)
+    raw_decode_path: Option<String>,
+    /// Region kept by trim_borders, if anything was trimmed
+    trim_rect: Option<CropRect>,
+    /// Redactions as applied after clamping to the image bounds
+    redactions: Vec<Redaction>,
+    /// Which source metadata was stripped or kept
+    metadata_report: MetadataReport,
+    input_sha256: Option<String>,
+    output_sha256: Option<String>,
+    /// Set instead of the SHA-256 fields when checksum_algorithm is blake3
+    input_blake3: Option<String>,
+    output_blake3: Option<String>,
+    input_bytes: u64,
+    output_bytes: u64,
+    /// input_bytes / output_bytes; None when the input size is unknown (clipboard)
+    compression_ratio: Option<f64>,
+    /// Wall time spent on this file, from decode to the last write
+    duration_ms: u64,
+    /// Decoder that produced the source pixels (e.g. "libheif", "image-jpeg"); a
+    /// "(fallback)" suffix means the decoder for the file's extension failed first
+    decoder: Option<String>,
+    /// Encoder that wrote the output (e.g. "turbojpeg", "png")
+    encoder: String,
+    /// Path of the `<output>.json` record, when write_json_sidecar is set
+    json_sidecar: Option<String>,
+    /// Conditions that didn't stop the conversion but changed its result
+    warnings: Vec<ConversionWarning>,
+    output_width: u32,
+    output_height: u32,
+    /// Quality the max_output_bytes or auto_quality search settled on
+    quality_used: Option<u8>,
+    /// Luma SSIM of the output against the processed image, when auto_quality chose it
+    ssim: Option<f64>,
+    /// Which file ended up at output_path, when keep_smaller compared them
+    size_winner: Option<SizeWinner>,
+    /// Clockwise rotation in degrees applied by auto_straighten
+    straighten_angle: Option<f32>,
+    /// Clockwise quarter turns in degrees (90, 180, 270) applied by detect_upright
+    detected_rotation: Option<u16>,
+    /// Clockwise rotation in degrees applied by deskew
+    deskew_angle: Option<f32>,
+}
+
+#[derive(Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum ConversionWarning {
+    /// Only the first frame of an animated source was converted
+    FirstFrameOnly { frames: u32 },
+}
+
+/// What keep_smaller does when the converted file is larger than its source
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum LargerOutput {
+    /// Copy the source next to the output path under its own extension, since it
+    /// keeps its original format
+    #[default]
+    KeepOriginal,
+    /// Write nothing and fail the item with OutputLarger; batches report it as skipped
+    Skip,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum SizeWinner {
+    Converted,
+    Original,
+}
+
+/// What to do once a conversion or batch has finished
+#[derive(Deserialize, Default, Clone, Copy)]
+#[serde(default)]
+struct PostActions {
+    /// Show the first converted file in the file manager
+    reveal: bool,
+    /// Open the first converted file with the default app
+    open: bool,
+    /// Move the sources to the trash, only when every item succeeded (which, with
+    /// verify_output, includes passing verification)
+    trash_originals: bool,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum PostActionStatus {
+    Done,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Clone)]
+struct PostActionOutcome {
+    /// "reveal", "open" or "trash_originals"
+    action: &'static str,
+    status: PostActionStatus,
+    /// Why the action was skipped
+    reason: Option<String>,
+    error: Option<AppError>,
+}
+
+/// Sent as `post_actions` once a batch's post actions have run
+#[derive(Serialize, Clone)]
+struct PostActionReport {
+    job_id: String,
+    outcomes: Vec<PostActionOutcome>,
+}
+
+#[derive(Serialize)]
+struct ConvertImageResult {
+    #[serde(flatten)]
+    result: ConversionResult,
+    post_actions: Vec<PostActionOutcome>,
+}
+
+#[derive(Serialize)]
+struct ConvertAndOpenResult {
+    #[serde(flatten)]
+    result: ConversionResult,
+    opened: bool,
+    open_error: Option<AppError>,
+}
+
 #[derive(Serialize)]
+struct PlaceholderConversionResult {
+    output_path: String,
+    sidecars: Vec<String>,
+    /// Base64 data URI of a heavily downscaled, low-quality JPEG
+    placeholder: String,
+    placeholder_width: u32,
+    placeholder_height: u32,
+    decoder: String,
+    encoder: String,
+}
+
+#[derive(Serialize, Clone)]
 struct BatchConversionResult {
     file_id: String,
+    /// Output path the item was converted to, after output_name_template and folder_template
+    destination: String,
     success: bool,
+    /// The input isn't an image format we can read and skip_unsupported is set, or
+    /// keep_smaller skipped a larger output; `error` still says why
+    skipped: bool,
+    error: Option<AppError>,
+    #[serde(flatten)]
+    result: Option<ConversionResult>,
+}
+
+/// Everything convert_images_batch produced, in input order, with the totals over it
+#[derive(Serialize)]
+struct BatchConversionResponse {
+    results: Vec<BatchConversionResult>,
+    summary: BatchSummary,
+}
+
+#[derive(Serialize)]
+struct PlannedOutput {
+    file_id: String,
+    source: String,
+    destination: String,
+    warnings: Vec<PreflightWarning>,
+}
+
+/// Something worth knowing about an item before the batch runs
+#[derive(Serialize, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+enum PreflightWarning {
+    /// A lossy source re-encoded to a lossy format, which compounds compression
+    /// artifacts, often for little size gain. `suggested_format` is a lossless target;
+    /// `keep_original` is set when the output format is the source's own, where
+    /// keeping the file as it is loses nothing.
+    GenerationLoss {
+        source_format: String,
+        suggested_format: &'static str,
+        keep_original: bool,
+    },
+}
+
+#[derive(Serialize)]
+struct PlannedFolder {
+    folder: String,
+    files: usize,
+}
+
+/// Where a batch would write, without converting anything
+#[derive(Serialize)]
+struct BatchPlan {
+    outputs: Vec<PlannedOutput>,
+    /// Output folders with how many files each would receive, sorted by path
+    folders: Vec<PlannedFolder>,
+}
+
+/// Sent when a batch pauses because its output volume is nearly full
+#[derive(Serialize, Clone)]
+struct LowDiskSpace {
+    job_id: String,
+    /// Directory the free space was measured at
+    volume: String,
+    available_bytes: u64,
+    threshold_bytes: Option<u64>,
+}
+
+#[derive(Serialize)]
+struct FileCompressionEstimate {
+    path: String,
+    input_bytes: u64,
+    estimated_bytes: u64,
+    compression_ratio: Option<f64>,
+    error: Option<AppError>,
+}
+
+#[derive(Serialize)]
+struct CompressionEstimate {
+    files: Vec<FileCompressionEstimate>,
+    input_bytes: u64,
+    estimated_bytes: u64,
+    saved_bytes: i64,
+    compression_ratio: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct RegionPreview {
+    /// Temporary JPEG, unless `inline` was requested
+    path: Option<String>,
+    data_uri: Option<String>,
+    width: u32,
+    height: u32,
+    /// Only the region was decoded, rather than the whole image
+    partial_decode: bool,
+}
+
+#[derive(Serialize)]
+struct ThumbnailResult {
+    source_path: String,
+    /// Cached JPEG in the temp directory; None when `inline` was requested
+    thumb_path: Option<String>,
+    /// Base64 JPEG data URI, when `inline` was requested
+    data_uri: Option<String>,
+    width: u32,
+    height: u32,
+    error: Option<AppError>,
+}
+
+#[derive(Serialize, Clone)]
+struct FormatRecommendation {
+    path: String,
+    source_format: String,
+    source_bytes: u64,
+    width: u32,
+    height: u32,
+    animated: bool,
+    stats: ContentStats,
+    /// Best first
+    suggestions: Vec<FormatSuggestion>,
+    /// estimate_size of the top suggestion at full resolution
+    estimated_bytes: u64,
+}
+
+#[derive(Serialize, Clone)]
+struct FormatRecommendationResult {
+    path: String,
+    recommendation: Option<FormatRecommendation>,
+    error: Option<AppError>,
+}
+
+#[derive(Serialize, Clone)]
+struct OrientationResult {
+    path: String,
+    /// EXIF orientation the file declared; 1 when it had none
+    orientation: u32,
+    /// The file wasn't upright and a normalized copy was written
+    changed: bool,
+    /// JPEG rotated without re-encoding
+    lossless: bool,
     output_path: Option<String>,
-    error: Option<String>,
+    backup_path: Option<String>,
+    error: Option<AppError>,
 }
 
 #[tauri::command]
-async fn analyze_image(path: String) -> Result<ImageMetadata, String> {
-    let img = ImageProcessor::load_image(&path)
-        .map_err(|e| e.to_string())?;
+async fn analyze_image(path: String) -> Result<ImageMetadata, AppError> {
+    let (img, decoder) = ImageProcessor::load_image_with_options(&path, &LoadOptions::default())?;
 
     let (width, height) = img.dimensions();
-    let format = ImageProcessor::get_format(&path)
-        .map_err(|e| e.to_string())?;
+    let format = ImageProcessor::get_format(&path)?;
+
+    let comment = MetadataProcessor::read_jpeg_comment(&path)?;
+
+    let raw_decode_path = RawProcessor::decode_path(decoder).map(str::to_string);
+
+    let color_profile = ColorProfiles::read_source_profile(&path)?
+        .and_then(|icc| ColorProfiles::description(&icc));
+
+    let dpi = MetadataProcessor::read_dpi(&path)?;
+
+    let page_count = ImageProcessor::page_count(&path)?;
+
+    let frame_count = ImageProcessor::frame_count(&path)?;
+
+    let heic_transformations = if ImageProcessor::is_heif_format(&format) {
+        MetadataProcessor::heif_transformations(&path)?
+    } else {
+        Vec::new()
+    };
+
+    let bmp_format = (format == "bmp").then(|| BmpProcessor::info(&path)).transpose()?;
+
+    let icon_frames = if IconProcessor::is_icon_extension(&format) {
+        IconProcessor::frames(&path)?
+    } else {
+        Vec::new()
+    };
+
+    // 256 bins so the end bins hold exactly the 8-bit extremes
+    let clipping = Adjustments::histogram(&img, 256).clipped_percent();
+
+    let live_photo_id = if matches!(format.as_str(), "jpg" | "jpeg") || ImageProcessor::is_heif_format(&format) {
+        MetadataProcessor::live_photo_identifier(&path)?
+    } else {
+        None
+    };
+    let live_photo_video = live_photo_id.as_ref()
+        .and_then(|_| FileOps::find_sidecars(&path, &["mov".to_string()]).into_iter().next())
+        .map(|(video, _)| video.to_string_lossy().to_string());
+
+    Ok(ImageMetadata {
+        width,
+        height,
+        format,
+        comment,
+        raw_decode_path,
+        color_profile,
+        dpi,
+        page_count,
+        frame_count,
+        heic_transformations,
+        bmp_format,
+        icon_frames,
+        clipped_highlights_percent: clipping.map(|(highlights, _)| highlights),
+        clipped_shadows_percent: clipping.map(|(_, shadows)| shadows),
+        lossy: ImageProcessor::is_lossy(&path)?,
+        is_live_photo: live_photo_id.is_some(),
+        live_photo_id,
+        live_photo_video,
+    })
+}
+
+/// Per-channel and luminance histograms of the decoded image, with `bins` bins each
+#[tauri::command]
+async fn compute_histogram(path: String, bins: u32) -> Result<Histogram, AppError> {
+    if !(2..=4096).contains(&bins) {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "bins must be between 2 and 4096")
+            .with_param("setting", "bins")
+            .with_param("limit", "2-4096")
+            .with_param("actual", bins));
+    }
 
-    Ok(ImageMetadata { width, height, format })
+    let img = ImageProcessor::load_image(&path)
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt).with_param("path", &path))?;
+    Ok(Adjustments::histogram(&img, bins))
 }
 
 #[tauri::command]
-async fn get_file_size(path: String) -> Result<u64, String> {
+async fn get_file_size(path: String) -> Result<u64, AppError> {
     std::fs::metadata(&path)
         .map(|m| m.len())
-        .map_err(|e| e.to_string())
+        .map_err(|e| AppError::from(e).with_param("path", &path))
+}
+
+/// Hex digest of a file, for checking an archived output against the checksum
+/// recorded when it was converted. SHA-256 when no algorithm is given.
+#[tauri::command]
+async fn checksum_file(path: String, algorithm: Option<ChecksumAlgorithm>) -> Result<String, AppError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        FileOps::checksum_file(&path, algorithm.unwrap_or_default())
+            .map_err(|e| AppError::from(e).with_param("path", &path))
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))?
 }
 
 #[tauri::command]
 async fn estimate_output_size(
     path: String,
-    settings: ConversionSettings,
-) -> Result<u64, String> {
-    let img = ImageProcessor::load_image(&path)
-        .map_err(|e| e.to_string())?;
+    mut settings: ConversionSettings,
+) -> Result<u64, AppError> {
+    resolve_target_format(&mut settings, None)?;
+    estimate_bytes(&path, &settings)
+}
 
-    let (width, height) = img.dimensions();
+/// Dry run of the savings a conversion would bring, from estimate_size rather than
+/// actually encoding. Files that can't be read carry an error and are left out of
+/// the totals.
+#[tauri::command]
+async fn estimate_compression(paths: Vec<String>, mut settings: ConversionSettings) -> Result<CompressionEstimate, AppError> {
+    resolve_target_format(&mut settings, None)?;
+    tauri::async_runtime::spawn_blocking(move || {
+        let files: Vec<FileCompressionEstimate> = paths.par_iter()
+            .map(|path| {
+                let estimate = std::fs::metadata(path)
+                    .map_err(AppError::from)
+                    .and_then(|m| Ok((m.len(), estimate_bytes(path, &settings)?)));
+                match estimate {
+                    Ok((input_bytes, estimated_bytes)) => FileCompressionEstimate {
+                        path: path.clone(),
+                        input_bytes,
+                        estimated_bytes,
+                        compression_ratio: compression_ratio(input_bytes, estimated_bytes),
+                        error: None,
+                    },
+                    Err(e) => FileCompressionEstimate {
+                        path: path.clone(),
+                        input_bytes: 0,
+                        estimated_bytes: 0,
+                        compression_ratio: None,
+                        error: Some(e.with_param("path", path)),
+                    },
+                }
+            })
+            .collect();
+
+        let input_bytes: u64 = files.iter().map(|f| f.input_bytes).sum();
+        let estimated_bytes: u64 = files.iter().map(|f| f.estimated_bytes).sum();
+        CompressionEstimate {
+            files,
+            input_bytes,
+            estimated_bytes,
+            saved_bytes: input_bytes as i64 - estimated_bytes as i64,
+            compression_ratio: compression_ratio(input_bytes, estimated_bytes),
+        }
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))
+}
+
+fn estimate_bytes(path: &str, settings: &ConversionSettings) -> Result<u64, AppError> {
+    let img = ImageProcessor::load_image(path)?;
+    let (source_width, source_height) = settings.crop_rect
+        .map_or(img.dimensions(), |rect| (rect.width, rect.height));
+
+    // load_image has already applied EXIF orientation, so caps apply to the displayed axes
+    let (_, (width, height)) = resize_target(source_width, source_height, settings);
 
     let estimated_bytes = ImageProcessor::estimate_size(
         width,
         height,
-        &settings.target_format,
+        settings.format_name(),
         settings.quality,
     );
 
@@ -82,8 +851,8 @@ async fn estimate_output_size(
 }
 
 #[tauri::command]
-async fn save_temp_file(file_name: String, data: Vec<u8>) -> Result<String, String> {
-    let temp_dir = std::env::temp_dir();
+async fn save_temp_file(file_name: String, data: Vec<u8>) -> Result<String, AppError> {
+    let temp_dir = WorkDir::path();
     let timestamp = std::time::SystemTime::now()
         .duration_since(std::time::UNIX_EPOCH)
         .unwrap()
@@ -93,13 +862,13 @@ async fn save_temp_file(file_name: String, data: Vec<u8>) -> Result<String, Stri
     let temp_path = temp_dir.join(temp_file_name);
 
     let mut file = std::fs::File::create(&temp_path)
-        .map_err(|e| format!("Failed to create temp file: {}", e))?;
+        .map_err(|e| AppError::from(e).with_param("path", temp_path.display()))?;
 
     std::io::Write::write_all(&mut file, &data)
-        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+        .map_err(|e| AppError::from(e).with_param("path", temp_path.display()))?;
 
     temp_path.to_str()
-        .ok_or_else(|| "Invalid path".to_string())
+        .ok_or_else(|| AppError::new(ErrorCode::Internal, "Invalid path"))
         .map(|s| s.to_string())
 }
 
@@ -107,154 +876,2842 @@ async fn save_temp_file(file_name: String, data: Vec<u8>) -> Result<String, Stri
 /// Returns path to a temporary JPEG file (smaller and faster than PNG)
 /// Uses embedded thumbnail when available for maximum speed
 #[tauri::command]
-async fn generate_preview(path: String) -> Result<String, String> {
-    let format = ImageProcessor::get_format(&path)
-        .map_err(|e| e.to_string())?;
+async fn generate_preview(
+    path: String,
+    preview_jobs: tauri::State<'_, PreviewJobs>,
+) -> Result<String, AppError> {
+    let format = ImageProcessor::get_format(&path)?;
 
-    // Only generate preview for HEIC/HEIF
-    if format != "heic" && format != "heif" {
-        return Err("Preview generation only needed for HEIC/HEIF files".to_string());
+    // Only generate preview for the HEIF family
+    if !ImageProcessor::is_heif_format(&format) {
+        return Err(AppError::new(ErrorCode::PreviewNotNeeded, "Preview generation only needed for HEIC/HEIF files")
+            .with_param("format", format));
     }
 
-    // Use thumbnail extraction (much faster than full decode)
-    let preview_img = ImageProcessor::load_heic_thumbnail(&path, 800)
-        .map_err(|e| e.to_string())?;
+    // A newer request for the same file supersedes any in-flight one
+    let token = Arc::new(CancelToken::default());
+    if let Some(previous) = preview_jobs.0.lock().unwrap().insert(path.clone(), token.clone()) {
+        previous.cancel();
+    }
 
-    // Create temp preview file
-    let temp_dir = std::env::temp_dir();
-    let timestamp = std::time::SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)
-        .unwrap()
-        .as_nanos();
-    let preview_path = temp_dir.join(format!("preview_{}.jpg", timestamp));
+    let worker_token = token.clone();
+    let worker_path = path.clone();
+    let decode = tauri::async_runtime::spawn_blocking(move || -> Result<String, AppError> {
+        // Use thumbnail extraction (much faster than full decode)
+        let preview_img = ImageProcessor::load_heic_thumbnail(&worker_path, 800, &worker_token)?;
+
+        // Create temp preview file
+        let temp_dir = WorkDir::path();
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let preview_path = temp_dir.join(format!("preview_{}.jpg", timestamp));
+
+        // Save as JPEG with turbojpeg
+        ImageProcessor::save_image(&preview_img, preview_path.to_str().unwrap(), ImageFormat::Jpeg, 75)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed))?;
+
+        // The caller already gave up; don't leave an orphaned preview behind
+        if worker_token.is_cancelled() {
+            std::fs::remove_file(&preview_path).ok();
+            return Err(AppError::new(ErrorCode::Cancelled, "Preview cancelled"));
+        }
+
+        preview_path.to_str()
+            .ok_or_else(|| AppError::new(ErrorCode::Internal, "Invalid path"))
+            .map(|s| s.to_string())
+    });
+
+    // Abandon the decode as soon as it is cancelled; the worker finishes in the background
+    let result = tokio::select! {
+        result = decode => result
+            .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))
+            .and_then(|r| r),
+        _ = token.cancelled() => Err(AppError::new(ErrorCode::Cancelled, "Preview cancelled")),
+    };
+
+    let mut jobs = preview_jobs.0.lock().unwrap();
+    if jobs.get(&path).is_some_and(|current| Arc::ptr_eq(current, &token)) {
+        jobs.remove(&path);
+    }
+
+    result
+}
+
+/// Render `transform` applied to a downscaled copy of the image into a temporary
+/// JPEG, without touching any output path. Returns the preview's path.
+#[tauri::command]
+async fn preview_transform(
+    path: String,
+    transform: PreviewTransform,
+    max_size: Option<u32>,
+    previews: tauri::State<'_, TransformPreviews>,
+) -> Result<String, AppError> {
+    let worker_path = path.clone();
+    let preview_path = tauri::async_runtime::spawn_blocking(move || -> Result<std::path::PathBuf, AppError> {
+        let img = ImageProcessor::load_image(&worker_path)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt).with_param("path", &worker_path))?;
+
+        // Crop in source coordinates, then shrink before the remaining per-pixel work
+        let cropped = transform.crop
+            .map(|rect| crop_to_rect(&img, rect, "crop"))
+            .transpose()?;
+        let img = cropped.as_ref().unwrap_or(&img);
+        let img = ImageProcessor::thumbnail(img, max_size.unwrap_or(1200));
+        let img = Adjustments::apply_preview_transform(img, &transform);
+        let img = DynamicImage::ImageRgb8(Adjustments::flatten(&img, [255, 255, 255], FlattenMode::Fast));
+
+        let timestamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let preview_path = WorkDir::path().join(format!("{}{}.jpg", TRANSFORM_PREVIEW_PREFIX, timestamp));
+        ImageProcessor::save_image(&img, preview_path.to_str().unwrap(), ImageFormat::Jpeg, 80)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed))?;
 
-    // Save as JPEG with turbojpeg
-    ImageProcessor::save_image(&preview_img, preview_path.to_str().unwrap(), ImageFormat::Jpeg, 75)
-        .map_err(|e| format!("Failed to save preview: {}", e))?;
+        Ok(preview_path)
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))??;
+
+    if let Some(previous) = previews.0.lock().unwrap().insert(path, preview_path.clone()) {
+        std::fs::remove_file(previous).ok();
+    }
 
     preview_path.to_str()
-        .ok_or_else(|| "Invalid path".to_string())
+        .ok_or_else(|| AppError::new(ErrorCode::Internal, "Invalid path"))
         .map(|s| s.to_string())
 }
 
+/// Render `rect` of a (typically huge) image at no more than `max_size` pixels on its
+/// longest side, for a pan/zoom inspector. Tiled and stripped TIFFs only decode the
+/// part under the region; other formats are decoded in full and cropped. Returns a
+/// temporary JPEG, or with `inline` a base64 data URI.
 #[tauri::command]
-async fn convert_image(
-    file_id: String,
+async fn preview_region(
     path: String,
-    output_path: String,
-    settings: ConversionSettings,
-    app_handle: tauri::AppHandle,
-) -> Result<String, String> {
-    // Load image
-    let img = ImageProcessor::load_image(&path)
-        .map_err(|e| e.to_string())?;
+    rect: CropRect,
+    max_size: Option<u32>,
+    inline: Option<bool>,
+    previews: tauri::State<'_, RegionPreviews>,
+) -> Result<RegionPreview, AppError> {
+    let max_size = max_size.unwrap_or(1200);
+    if max_size == 0 {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "max_size must be greater than 0")
+            .with_param("setting", "max_size"));
+    }
+    let inline = inline.unwrap_or(false);
 
-    // Emit progress
-    app_handle.emit("conversion_progress", ConversionProgress {
-        file_id: file_id.clone(),
-        progress: 50,
-    }).ok();
+    let worker_path = path.clone();
+    let (data, width, height, partial_decode) = tauri::async_runtime::spawn_blocking(move || -> Result<_, AppError> {
+        let decode_error = |e: anyhow::Error| AppError::from(e).or_code(ErrorCode::DecodeCorrupt).with_param("path", &worker_path);
+        let (region, partial_decode) = match ImageProcessor::read_region(&worker_path, rect).map_err(decode_error)? {
+            Some(region) => (region, true),
+            None => {
+                let img = ImageProcessor::load_image(&worker_path).map_err(decode_error)?;
+                (crop_to_rect(&img, rect, "rect")?, false)
+            }
+        };
 
-    // Determine output format
-    let format = match settings.target_format.as_str() {
-        "jpeg" => ImageFormat::Jpeg,
-        "png" => ImageFormat::Png,
-        _ => return Err("Unsupported format".to_string()),
-    };
+        let img = ImageProcessor::thumbnail(&region, max_size);
+        let img = DynamicImage::ImageRgb8(Adjustments::flatten(&img, [255, 255, 255], FlattenMode::Fast));
+        let data = ImageProcessor::encode_image(&img, ImageFormat::Jpeg, 80)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed))?;
+        Ok((data, img.width(), img.height(), partial_decode))
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))??;
 
-    // Save image
-    ImageProcessor::save_image(&img, &output_path, format, settings.quality)
-        .map_err(|e| e.to_string())?;
+    if inline {
+        return Ok(RegionPreview {
+            path: None,
+            data_uri: Some(format!("data:image/jpeg;base64,{}", BASE64.encode(&data))),
+            width,
+            height,
+            partial_decode,
+        });
+    }
 
-    // Emit completion
-    app_handle.emit("conversion_progress", ConversionProgress {
-        file_id,
-        progress: 100,
-    }).ok();
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_nanos();
+    let preview_path = WorkDir::path().join(format!("{}{}.jpg", REGION_PREVIEW_PREFIX, timestamp));
+    FileOps::write_atomic(&preview_path, &data)
+        .map_err(|e| AppError::from(e).with_param("path", preview_path.display()))?;
+    if let Some(previous) = previews.0.lock().unwrap().insert(path, preview_path.clone()) {
+        std::fs::remove_file(previous).ok();
+    }
 
-    Ok(output_path)
+    Ok(RegionPreview {
+        path: Some(preview_path.to_string_lossy().to_string()),
+        data_uri: None,
+        width,
+        height,
+        partial_decode,
+    })
 }
 
-/// Batch convert multiple images in parallel
+/// Split an image into a Deep Zoom tile pyramid for web viewers: `<name>.dzi` plus
+/// `<name>_files/<level>/<column>_<row>.<ext>` in `output_dir`, named after the
+/// source file. The source is decoded once and every level is derived from the one
+/// above it.
 #[tauri::command]
-async fn convert_images_batch(
-    items: Vec<BatchConversionItem>,
-    settings: ConversionSettings,
-    app_handle: tauri::AppHandle,
-) -> Result<Vec<BatchConversionResult>, String> {
-    let format = match settings.target_format.as_str() {
-        "jpeg" => ImageFormat::Jpeg,
-        "png" => ImageFormat::Png,
-        _ => return Err("Unsupported format".to_string()),
-    };
+async fn generate_tile_pyramid(
+    path: String,
+    tile_size: u32,
+    output_dir: String,
+    format: String,
+    quality: u8,
+    permitted: tauri::State<'_, PermittedDirs>,
+) -> Result<TilePyramid, AppError> {
+    let image_format = parse_target_format(&format)?;
+    TileGenerator::extension(image_format)
+        .map_err(|e| AppError::new(ErrorCode::UnsupportedFormat, e.to_string()).with_param("format", &format))?;
+    let (min_tile, max_tile) = tiles::TILE_SIZE_RANGE;
+    if !(min_tile..=max_tile).contains(&tile_size) {
+        return Err(AppError::new(ErrorCode::InvalidSettings, format!("tile_size must be between {} and {}", min_tile, max_tile))
+            .with_param("setting", "tile_size")
+            .with_param("limit", max_tile)
+            .with_param("actual", tile_size));
+    }
 
-    let app_handle = Arc::new(app_handle);
-    let quality = settings.quality;
+    let name = std::path::Path::new(&path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "image".to_string());
+    let pyramid = tauri::async_runtime::spawn_blocking(move || -> Result<_, AppError> {
+        let img = ImageProcessor::load_image(&path)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt).with_param("path", &path))?;
+        TileGenerator::generate(img, &name, tile_size, std::path::Path::new(&output_dir), image_format, quality)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed).with_param("path", &output_dir))
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))??;
 
-    // Process images in parallel using rayon
-    let results: Vec<BatchConversionResult> = items
-        .par_iter()
-        .map(|item| {
-            let result = (|| -> Result<String, String> {
-                // Load image
-                let img = ImageProcessor::load_image(&item.path)
-                    .map_err(|e| e.to_string())?;
-
-                // Emit progress (50%)
-                app_handle.emit("conversion_progress", ConversionProgress {
-                    file_id: item.file_id.clone(),
-                    progress: 50,
-                }).ok();
+    permitted.allow_parent_of(&pyramid.descriptor);
+    Ok(pyramid)
+}
 
-                // Save image
-                ImageProcessor::save_image(&img, &item.output_path, format, quality)
-                    .map_err(|e| e.to_string())?;
+/// Build an animated PNG from `paths`, in order, each shown for `delay_ms`. `loops`
+/// defaults to 0, repeating forever. `delta_frames` (on by default) stores each frame
+/// as the region that changed since the one before, which shrinks recordings of
+/// mostly static content such as screen captures; see ApngEncoder::write.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+async fn build_apng(
+    paths: Vec<String>,
+    output_path: String,
+    delay_ms: u32,
+    loops: Option<u32>,
+    delta_frames: Option<bool>,
+    png_compression: Option<PngCompression>,
+    permitted: tauri::State<'_, PermittedDirs>,
+) -> Result<ApngSummary, AppError> {
+    if paths.is_empty() {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "An animation needs at least one frame")
+            .with_param("setting", "paths"));
+    }
 
-                // Emit completion (100%)
-                app_handle.emit("conversion_progress", ConversionProgress {
-                    file_id: item.file_id.clone(),
-                    progress: 100,
-                }).ok();
+    let summary = tauri::async_runtime::spawn_blocking(move || -> Result<_, AppError> {
+        let frames = paths
+            .par_iter()
+            .map(|path| {
+                ImageProcessor::load_image(path)
+                    .map(|img| img.to_rgba8())
+                    .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt).with_param("path", path))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let (width, height) = frames[0].dimensions();
+        if let Some((path, frame)) = paths.iter().zip(&frames).find(|(_, frame)| frame.dimensions() != (width, height)) {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "Every frame must have the same dimensions as the first")
+                .with_param("setting", "paths")
+                .with_param("path", path)
+                .with_param("limit", format!("{}x{}", width, height))
+                .with_param("actual", format!("{}x{}", frame.width(), frame.height())));
+        }
 
-                Ok(item.output_path.clone())
-            })();
+        ApngEncoder::write(
+            &frames,
+            std::path::Path::new(&output_path),
+            delay_ms,
+            loops.unwrap_or(0),
+            delta_frames.unwrap_or(true),
+            png_compression.unwrap_or_default(),
+        )
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed).with_param("path", &output_path))
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))??;
 
-            match result {
-                Ok(output_path) => BatchConversionResult {
-                    file_id: item.file_id.clone(),
-                    success: true,
-                    output_path: Some(output_path),
+    permitted.allow_parent_of(&summary.output_path);
+    Ok(summary)
+}
+
+/// Write a favicon set for web developers into `output_dir`: 16, 32 and 48 pixel
+/// PNGs, a favicon.ico holding all three, a 180 pixel apple-touch-icon, Android
+/// home-screen icons and a site.webmanifest. The source must be at least 256 pixels
+/// on its shorter side; non-square sources are center-cropped, which the result reports.
+#[tauri::command]
+async fn generate_favicon_bundle(
+    path: String,
+    output_dir: String,
+    permitted: tauri::State<'_, PermittedDirs>,
+) -> Result<FaviconBundle, AppError> {
+    let bundle = tauri::async_runtime::spawn_blocking(move || -> Result<_, AppError> {
+        let img = ImageProcessor::load_image(&path)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt).with_param("path", &path))?;
+        let side = img.width().min(img.height());
+        if side < favicon::MIN_SOURCE_SIZE {
+            return Err(AppError::new(ErrorCode::InvalidSettings, format!("Favicon sources must be at least {} pixels on each side", favicon::MIN_SOURCE_SIZE))
+                .with_param("setting", "path")
+                .with_param("limit", favicon::MIN_SOURCE_SIZE)
+                .with_param("actual", side));
+        }
+        FaviconGenerator::generate(&img, std::path::Path::new(&output_dir))
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed).with_param("path", &output_dir))
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))??;
+
+    if let Some(file) = bundle.files.first() {
+        permitted.allow_parent_of(file);
+    }
+    Ok(bundle)
+}
+
+/// Thumbnails of many files in one call, generated in parallel. Each JPEG is cached
+/// in the working directory under a name derived from the source path, its modification
+/// time and `max_size`, so asking again for an unchanged file reuses it. With `inline`,
+/// base64 data URIs are returned instead of paths. Results are in input order and a
+/// file that fails only sets its own `error`.
+#[tauri::command]
+async fn generate_thumbnails(paths: Vec<String>, max_size: u32, inline: Option<bool>) -> Result<Vec<ThumbnailResult>, AppError> {
+    if max_size == 0 {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "max_size must be greater than 0")
+            .with_param("setting", "max_size"));
+    }
+    let inline = inline.unwrap_or(false);
+
+    tauri::async_runtime::spawn_blocking(move || {
+        paths.par_iter()
+            .map(|path| match cached_thumbnail(path, max_size) {
+                Ok((cached, data)) => {
+                    let (width, height) = image::io::Reader::with_format(std::io::Cursor::new(&data), ImageFormat::Jpeg)
+                        .into_dimensions()
+                        .unwrap_or((0, 0));
+                    ThumbnailResult {
+                        source_path: path.clone(),
+                        thumb_path: (!inline).then(|| cached.to_string_lossy().to_string()),
+                        data_uri: inline.then(|| format!("data:image/jpeg;base64,{}", BASE64.encode(&data))),
+                        width,
+                        height,
+                        error: None,
+                    }
+                }
+                Err(e) => ThumbnailResult {
+                    source_path: path.clone(),
+                    thumb_path: None,
+                    data_uri: None,
+                    width: 0,
+                    height: 0,
+                    error: Some(e.with_param("path", path)),
+                },
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))
+}
+
+/// Suggest target formats for an image from its content: color count, transparency,
+/// how much of it is flat or hard-edged, whether it's animated. The analysis runs
+/// on a proxy of at most 256 pixels a side.
+#[tauri::command]
+async fn recommend_format(path: String) -> Result<FormatRecommendation, AppError> {
+    tauri::async_runtime::spawn_blocking(move || analyze_format(&path))
+        .await
+        .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))?
+}
+
+/// recommend_format for many files in parallel, in input order. A file that can't
+/// be analyzed carries an error instead of failing the call.
+#[tauri::command]
+async fn recommend_formats(paths: Vec<String>) -> Result<Vec<FormatRecommendationResult>, AppError> {
+    tauri::async_runtime::spawn_blocking(move || {
+        paths.par_iter()
+            .map(|path| match analyze_format(path) {
+                Ok(recommendation) => FormatRecommendationResult {
+                    path: path.clone(),
+                    recommendation: Some(recommendation),
                     error: None,
                 },
-                Err(e) => BatchConversionResult {
-                    file_id: item.file_id.clone(),
-                    success: false,
-                    output_path: None,
-                    error: Some(e),
+                Err(e) => FormatRecommendationResult {
+                    path: path.clone(),
+                    recommendation: None,
+                    error: Some(e.with_param("path", path)),
                 },
-            }
-        })
-        .collect();
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))
+}
+
+fn analyze_format(path: &str) -> Result<FormatRecommendation, AppError> {
+    let source_format = ImageProcessor::get_format(path)?;
+    let source_bytes = std::fs::metadata(path).map_err(AppError::from)?.len();
+    let (width, height) = ImageProcessor::peek_dimensions(path)?;
+    let animated = ImageProcessor::frame_count(path)? > 1;
+    let proxy = FormatAdvisor::proxy(path)
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt))?;
 
-    Ok(results)
+    let stats = FormatAdvisor::analyze(&proxy);
+    let suggestions = FormatAdvisor::rank(&stats, animated, &source_format);
+    let estimated_bytes = ImageProcessor::estimate_size(width, height, suggestions[0].format, recommend::ESTIMATE_QUALITY);
+
+    Ok(FormatRecommendation {
+        path: path.to_string(),
+        source_format,
+        source_bytes,
+        width,
+        height,
+        animated,
+        stats,
+        suggestions,
+        estimated_bytes,
+    })
 }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_updater::Builder::new().build())
-        .plugin(tauri_plugin_process::init())
-        .invoke_handler(tauri::generate_handler![
-            analyze_image,
-            get_file_size,
-            estimate_output_size,
-            convert_image,
-            convert_images_batch,
-            save_temp_file,
-            generate_preview,
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+/// Path and contents of the cached thumbnail JPEG for `path`, generating it when missing
+fn cached_thumbnail(path: &str, max_size: u32) -> Result<(std::path::PathBuf, Vec<u8>), AppError> {
+    let modified = std::fs::metadata(path)
+        .and_then(|m| m.modified())
+        .map_err(AppError::from)?
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let key = FileOps::sha256_bytes(format!("{}|{}|{}", path, modified, max_size).as_bytes());
+    let cached = WorkDir::path().join(format!("{}{}.jpg", THUMBNAIL_PREFIX, &key[..24]));
+
+    let data = match std::fs::read(&cached) {
+        Ok(data) => data,
+        Err(_) => {
+            let (img, _) = ImageProcessor::load_thumbnail(path, max_size)
+                .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt))?;
+            let img = DynamicImage::ImageRgb8(Adjustments::flatten(&img, [255, 255, 255], FlattenMode::Fast));
+            let data = ImageProcessor::encode_image(&img, ImageFormat::Jpeg, 75)
+                .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed))?;
+            FileOps::write_atomic(&cached, &data)
+                .map_err(|e| AppError::from(e).with_param("path", cached.display()))?;
+            data
+        }
+    };
+
+    Ok((cached, data))
+}
+
+/// Delete transform previews and thumbnails from `dir`, left there by earlier sessions
+/// or abandoned when the working directory moves
+fn remove_stale_transform_previews(dir: &std::path::Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_preview = entry.file_name().to_str()
+            .is_some_and(|name| [TRANSFORM_PREVIEW_PREFIX, REGION_PREVIEW_PREFIX, THUMBNAIL_PREFIX]
+                .iter()
+                .any(|prefix| name.starts_with(prefix)));
+        if is_preview {
+            std::fs::remove_file(entry.path()).ok();
+        }
+    }
+}
+
+/// Cancel an in-flight generate_preview for the given file
+#[tauri::command]
+fn cancel_preview(path: String, preview_jobs: tauri::State<'_, PreviewJobs>) {
+    if let Some(token) = preview_jobs.0.lock().unwrap().remove(&path) {
+        token.cancel();
+    }
+}
+
+fn parse_target_format(target_format: &str) -> Result<ImageFormat, AppError> {
+    match target_format {
+        "jpeg" => Ok(ImageFormat::Jpeg),
+        "png" => Ok(ImageFormat::Png),
+        "bmp" => Ok(ImageFormat::Bmp),
+        "webp" => Ok(ImageFormat::WebP),
+        "tiff" => Ok(ImageFormat::Tiff),
+        "ppm" | "pgm" => Ok(ImageFormat::Pnm),
+        "tga" => Ok(ImageFormat::Tga),
+        "avif" => Ok(ImageFormat::Avif),
+        _ => Err(AppError::new(ErrorCode::UnsupportedFormat, "Unsupported format")
+            .with_param("format", target_format)),
+    }
+}
+
+/// Canonical format name (as accepted by parse_target_format) for an output
+/// file extension
+fn format_for_extension(extension: &str) -> Option<&'static str> {
+    match extension.to_lowercase().as_str() {
+        "jpg" | "jpeg" => Some("jpeg"),
+        "png" => Some("png"),
+        "bmp" => Some("bmp"),
+        "webp" => Some("webp"),
+        "tif" | "tiff" => Some("tiff"),
+        "ppm" => Some("ppm"),
+        "pgm" => Some("pgm"),
+        "tga" => Some("tga"),
+        "avif" => Some("avif"),
+        _ => None,
+    }
+}
+
+/// Settle the output format of a conversion: target_format when given, otherwise
+/// the format named by the output path's extension. Both present and naming
+/// different formats is a FormatMismatch rather than mislabelled output; an
+/// extension that names no format is left alone. The resolved name is written back
+/// to settings.target_format, so for a batch the first output decides and every
+/// later one is checked against it.
+fn resolve_target_format(settings: &mut ConversionSettings, output_path: Option<&str>) -> Result<ImageFormat, AppError> {
+    let extension = output_path
+        .and_then(|path| std::path::Path::new(path).extension())
+        .map(|extension| extension.to_string_lossy().to_string());
+    let inferred = extension.as_deref().and_then(format_for_extension);
+
+    match (settings.target_format.as_deref(), inferred) {
+        (Some(format), Some(inferred)) if format != inferred => {
+            return Err(AppError::new(
+                ErrorCode::FormatMismatch,
+                format!("Output extension .{} doesn't match target format {}", extension.as_deref().unwrap_or_default(), format),
+            )
+                .with_param("format", format)
+                .with_param("extension", extension.unwrap_or_default())
+                .with_param("path", output_path.unwrap_or_default()));
+        }
+        (Some(_), _) => {}
+        (None, Some(inferred)) => settings.target_format = Some(inferred.to_string()),
+        (None, None) => {
+            return Err(match (output_path, extension) {
+                (Some(path), Some(extension)) => AppError::new(ErrorCode::UnsupportedFormat, "Unsupported format")
+                    .with_param("format", extension)
+                    .with_param("path", path),
+                _ => AppError::new(ErrorCode::InvalidSettings, "target_format is required when no output extension names a format")
+                    .with_param("setting", "target_format"),
+            });
+        }
+    }
+    parse_target_format(settings.format_name())
+}
+
+/// resolve_target_format over every output of a batch
+fn resolve_batch_format<'a>(
+    settings: &mut ConversionSettings,
+    output_paths: impl IntoIterator<Item = &'a str>,
+) -> Result<ImageFormat, AppError> {
+    for path in output_paths {
+        resolve_target_format(settings, Some(path))?;
+    }
+    resolve_target_format(settings, None)
+}
+
+/// Reject invalid setting combinations before any file is touched
+fn validate_settings(settings: &ConversionSettings) -> Result<(), AppError> {
+    for (setting, text) in [("author", &settings.author), ("copyright", &settings.copyright)] {
+        if text.as_deref().is_some_and(|text| text.is_empty() || text.contains('\0')) {
+            return Err(AppError::new(ErrorCode::InvalidSettings, format!("{} must be non-empty text without NUL characters", setting))
+                .with_param("setting", setting));
+        }
+    }
+
+    if settings.bmp_indexed && settings.format_name() != "bmp" {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "Indexed output is only available for BMP")
+            .with_param("setting", "bmp_indexed")
+            .with_param("format", settings.format_name()));
+    }
+
+    if let Some(bits) = settings.bmp_bit_depth {
+        if settings.format_name() != "bmp" || settings.bmp_indexed {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "bmp_bit_depth only applies to 24/32-bit BMP output")
+                .with_param("setting", "bmp_bit_depth")
+                .with_param("format", settings.format_name()));
+        }
+        if !matches!(bits, 24 | 32) {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "bmp_bit_depth must be 24 or 32")
+                .with_param("setting", "bmp_bit_depth")
+                .with_param("actual", bits));
+        }
+    }
+
+    if settings.tiff_compression == TiffCompression::CcittG4
+        && (settings.format_name() != "tiff" || settings.bilevel.is_none())
+    {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "CCITT G4 compression only applies to bilevel TIFF output")
+            .with_param("setting", "tiff_compression")
+            .with_param("format", settings.format_name()));
+    }
+
+    if let Some(palette) = &settings.png_palette {
+        if settings.format_name() != "png" || settings.bilevel.is_some() {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "A fixed palette only applies to PNG output without bilevel")
+                .with_param("setting", "png_palette")
+                .with_param("format", settings.format_name()));
+        }
+        if !(1..=256).contains(&palette.colors.len()) {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "A fixed palette must have between 1 and 256 colors")
+                .with_param("setting", "png_palette")
+                .with_param("limit", "1-256")
+                .with_param("actual", palette.colors.len()));
+        }
+    }
+
+    if let Some(dpi) = settings.output_dpi.filter(|dpi| !(1.0..=65535.0).contains(dpi)) {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "Output DPI must be between 1 and 65535")
+            .with_param("setting", "output_dpi")
+            .with_param("limit", "1-65535")
+            .with_param("actual", dpi));
+    }
+
+    if let Some(pixel_format) = settings.output_pixel_format {
+        if settings.bilevel.is_some() {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "output_pixel_format can't be combined with bilevel output")
+                .with_param("setting", "output_pixel_format"));
+        }
+        let format = parse_target_format(settings.format_name())?;
+        if !ImageProcessor::supports_pixel_format(format, pixel_format) {
+            let message = format!("{:?} output can't be written as {}", pixel_format, settings.format_name());
+            return Err(AppError::new(ErrorCode::InvalidSettings, message)
+                .with_param("setting", "output_pixel_format")
+                .with_param("actual", format!("{:?}", pixel_format))
+                .with_param("format", settings.format_name()));
+        }
+    }
+
+    if let Some(max_bytes) = settings.max_output_bytes {
+        if !matches!(settings.format_name(), "jpeg" | "webp") || settings.bilevel.is_some() {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "max_output_bytes only applies to JPEG and WebP output")
+                .with_param("setting", "max_output_bytes")
+                .with_param("format", settings.format_name()));
+        }
+        if max_bytes == 0 {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "max_output_bytes must be greater than 0")
+                .with_param("setting", "max_output_bytes")
+                .with_param("actual", max_bytes));
+        }
+    }
+
+    if settings.auto_quality.is_some() {
+        if !matches!(settings.format_name(), "jpeg" | "webp") || settings.bilevel.is_some() {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "auto_quality only applies to JPEG and WebP output")
+                .with_param("setting", "auto_quality")
+                .with_param("format", settings.format_name()));
+        }
+        if settings.max_output_bytes.is_some() {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "auto_quality can't be combined with max_output_bytes")
+                .with_param("setting", "auto_quality"));
+        }
+    }
+
+    if settings.max_output_bytes.is_none() && (settings.min_quality.is_some() || settings.downscale_to_fit) {
+        let setting = if settings.downscale_to_fit { "downscale_to_fit" } else { "min_quality" };
+        return Err(AppError::new(ErrorCode::InvalidSettings, "min_quality and downscale_to_fit require max_output_bytes")
+            .with_param("setting", setting));
+    }
+
+    if let Some(min_quality) = settings.min_quality.filter(|&q| q == 0 || q > settings.quality) {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "min_quality must be between 1 and quality")
+            .with_param("setting", "min_quality")
+            .with_param("limit", format!("1-{}", settings.quality))
+            .with_param("actual", min_quality));
+    }
+
+    if let Some((setting, message)) = settings.fits.invalid_parameter() {
+        return Err(AppError::new(ErrorCode::InvalidSettings, message)
+            .with_param("setting", setting));
+    }
+
+    if let Some(clip_percent) = settings.auto_levels.filter(|clip| !(0.0..50.0).contains(clip)) {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "auto_levels must be at least 0 and below 50 percent")
+            .with_param("setting", "auto_levels")
+            .with_param("limit", "0-50")
+            .with_param("actual", clip_percent));
+    }
+
+    if let Some(max_angle) = settings.auto_straighten {
+        if !cfg!(feature = "straighten") {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "Automatic straightening is not enabled in this build")
+                .with_param("setting", "auto_straighten"));
+        }
+        if !(max_angle > 0.0 && max_angle <= 45.0) {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "auto_straighten must be above 0 and at most 45 degrees")
+                .with_param("setting", "auto_straighten")
+                .with_param("limit", "0-45")
+                .with_param("actual", max_angle));
+        }
+    }
+
+    if settings.deskew && settings.auto_straighten.is_some() {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "deskew and auto_straighten can't be combined")
+            .with_param("setting", "deskew"));
+    }
+
+    let jpeg = settings.jpeg_options();
+    if jpeg.encoder == JpegEncoder::Mozjpeg && !cfg!(feature = "mozjpeg") {
+        let setting = if settings.jpeg_encoder.is_some() { "jpeg_encoder" } else { "jpeg_encoding.trellis" };
+        return Err(AppError::new(ErrorCode::InvalidSettings, "The mozjpeg encoder is not enabled in this build")
+            .with_param("setting", setting));
+    }
+    if jpeg.trellis && jpeg.encoder != JpegEncoder::Mozjpeg {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "Trellis quantization is only available with the mozjpeg encoder")
+            .with_param("setting", "jpeg_encoding.trellis"));
+    }
+    if jpeg.arithmetic && jpeg.encoder != JpegEncoder::Turbo {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "Arithmetic coding is only available with the turbojpeg encoder")
+            .with_param("setting", "jpeg_encoding.arithmetic"));
+    }
+
+    if settings.detect_upright && !cfg!(feature = "upright") {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "Orientation detection is not enabled in this build")
+            .with_param("setting", "detect_upright"));
+    }
+
+    if let Some(limits) = settings.decode_limits {
+        let zero = [
+            ("max_image_width", limits.max_image_width.map(u64::from)),
+            ("max_image_height", limits.max_image_height.map(u64::from)),
+            ("max_alloc", limits.max_alloc),
+        ]
+        .into_iter()
+        .find(|&(_, value)| value == Some(0));
+        if let Some((setting, _)) = zero {
+            return Err(AppError::new(ErrorCode::InvalidSettings, format!("decode_limits.{} must be greater than 0", setting))
+                .with_param("setting", format!("decode_limits.{}", setting))
+                .with_param("actual", 0));
+        }
+    }
+
+    if let Some((width, height)) = settings.exact_resize {
+        if width == 0 || height == 0 {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "exact_resize dimensions must be greater than 0")
+                .with_param("setting", "exact_resize")
+                .with_param("actual", format!("{}x{}", width, height)));
+        }
+        if settings.max_width.is_some() || settings.max_height.is_some() {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "exact_resize can't be combined with max_width or max_height")
+                .with_param("setting", "exact_resize"));
+        }
+    }
+
+    if settings.resize_mode != ResizeMode::Fit {
+        let valid = matches!((settings.max_width, settings.max_height), (Some(width), Some(height)) if width > 0 && height > 0);
+        if !valid {
+            return Err(AppError::new(ErrorCode::InvalidSettings, "Fill and stretch resizing need both max_width and max_height, greater than 0")
+                .with_param("setting", "resize_mode"));
+        }
+    }
+
+    if settings.keep_source_stem && settings.output_name_template.is_some() {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "keep_source_stem can't be combined with output_name_template")
+            .with_param("setting", "keep_source_stem"));
+    }
+
+    let templates = [
+        ("output_name_template", &settings.output_name_template),
+        ("folder_template", &settings.folder_template),
+    ];
+    for (setting, template) in templates {
+        if let Some(template) = template {
+            naming::check_template(template)
+                .map_err(|e| AppError::from(e).or_code(ErrorCode::InvalidSettings).with_param("setting", setting))?;
+        }
+    }
+
+    if settings.avif_preview && settings.format_name() != "avif" {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "avif_preview only applies to AVIF output")
+            .with_param("setting", "avif_preview"));
+    }
+
+    if settings.webp_method > 6 {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "webp_method must be between 0 and 6")
+            .with_param("setting", "webp_method")
+            .with_param("limit", "0-6")
+            .with_param("actual", settings.webp_method));
+    }
+
+    if settings.icon_size == Some(0) {
+        return Err(AppError::new(ErrorCode::InvalidSettings, "icon_size must be greater than 0")
+            .with_param("setting", "icon_size"));
+    }
+
+    if let Some(spec) = &settings.assign_profile {
+        ColorProfiles::resolve(spec)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::InvalidProfile).with_param("profile", spec))?;
+    }
+
+    Ok(())
+}
+
+/// Decode a source file according to the conversion settings
+fn load_source(path: &str, settings: &ConversionSettings) -> Result<(DynamicImage, &'static str), AppError> {
+    let source_format = ImageProcessor::get_format(path)?;
+    if FitsProcessor::is_fits_extension(&source_format) && !cfg!(feature = "fits") {
+        return Err(AppError::new(ErrorCode::DecodeUnsupportedCodec, "FITS support is not enabled in this build")
+            .with_param("format", source_format));
+    }
+    // Caught before decoding, so skip_unsupported can tell these from corrupt images
+    if !ImageProcessor::is_supported_input(path, &source_format) {
+        return Err(AppError::new(ErrorCode::DecodeUnsupportedCodec, "Not an image format that can be read")
+            .with_param("format", source_format));
+    }
+
+    ImageProcessor::load_image_with_options(path, &load_options(settings))
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt))
+}
+
+/// Decoder options the settings ask for
+fn load_options(settings: &ConversionSettings) -> LoadOptions {
+    LoadOptions {
+        auto_orient: settings.auto_orient,
+        heic: settings.heic_decode,
+        fits: settings.fits,
+        icon_size: settings.icon_size,
+        memory_map: settings.memory_map_sources,
+        limits: settings.decode_limits,
+    }
+}
+
+/// Extract `rect`, failing with InvalidSettings naming `setting` when it isn't inside the image
+fn crop_to_rect(img: &DynamicImage, rect: CropRect, setting: &'static str) -> Result<DynamicImage, AppError> {
+    let (width, height) = img.dimensions();
+    let fits = rect.width > 0
+        && rect.height > 0
+        && rect.x as u64 + rect.width as u64 <= width as u64
+        && rect.y as u64 + rect.height as u64 <= height as u64;
+    if !fits {
+        let message = format!(
+            "Crop rectangle {}x{} at ({}, {}) is outside the {}x{} image",
+            rect.width, rect.height, rect.x, rect.y, width, height
+        );
+        return Err(AppError::new(ErrorCode::InvalidSettings, message)
+            .with_param("setting", setting)
+            .with_param("limit", format!("{}x{}", width, height))
+            .with_param("actual", format!("{}x{}+{}+{}", rect.width, rect.height, rect.x, rect.y)));
+    }
+
+    Ok(img.crop_imm(rect.x, rect.y, rect.width, rect.height))
+}
+
+/// Load, convert and write a single file, emitting progress along the way
+fn convert_file(
+    app_handle: &tauri::AppHandle,
+    file_id: &str,
+    path: &str,
+    output_path: &str,
+    format: ImageFormat,
+    settings: &ConversionSettings,
+) -> Result<ConversionResult, AppError> {
+    let started = Instant::now();
+
+    let mut warnings = Vec::new();
+    let frames = ImageProcessor::frame_count(path)
+        .map_err(|e| AppError::from(e).with_param("path", path))?;
+    if frames > 1 {
+        match settings.animation_policy {
+            AnimationPolicy::FirstFrame => warnings.push(ConversionWarning::FirstFrameOnly { frames }),
+            AnimationPolicy::Error => {
+                let message = format!("Source is animated ({} frames)", frames);
+                return Err(AppError::new(ErrorCode::DecodeAnimated, message)
+                    .with_param("path", path)
+                    .with_param("frames", frames));
+            }
+        }
+    }
+
+    // Load image
+    let (img, decoder) = load_source(path, settings)
+        .map_err(|e| e.with_param("path", path))?;
+
+    convert_loaded(app_handle, file_id, &img, Some(path), output_path, format, settings, started)
+        .map(|result| ConversionResult {
+            raw_decode_path: RawProcessor::decode_path(decoder).map(str::to_string),
+            decoder: Some(decoder.to_string()),
+            warnings,
+            ..result
+        })
+        .map_err(|e| e.with_param("path", path))
+}
+
+/// Orientation recorded in the output, whatever the container. Oriented pixels are
+/// marked upright so viewers never rotate twice; unoriented pixels keep the source's
+/// orientation so they still display correctly.
+fn output_orientation(source: Option<&str>, settings: &ConversionSettings) -> Result<u16, AppError> {
+    let Some(path) = source else {
+        return Ok(1);
+    };
+    // irot/imir decide how a HEIF displays; the EXIF tag only when they're absent
+    if ImageProcessor::is_heif_format(&ImageProcessor::get_format(path)?) {
+        if settings.heic_decode.orientation_applied(settings.auto_orient) {
+            return Ok(1);
+        }
+        let transformations = MetadataProcessor::heif_transformations(path)?;
+        let orientation = ImageProcessor::heif_exif_orientation(path, &transformations)?
+            .unwrap_or_else(|| MetadataProcessor::heif_orientation(&transformations));
+        return Ok(orientation as u16);
+    }
+    if settings.auto_orient {
+        return Ok(1);
+    }
+    let orientation = ImageProcessor::read_exif_orientation(path)?
+        .filter(|orientation| (1..=8).contains(orientation));
+    Ok(orientation.unwrap_or(1) as u16)
+}
+
+/// Embed DPI, comment, EXIF and ICC metadata into encoded output bytes per the settings.
+/// Without a source file only explicitly configured metadata is written.
+fn write_metadata(
+    mut data: Vec<u8>,
+    source: Option<&str>,
+    format: ImageFormat,
+    settings: &ConversionSettings,
+) -> Result<Vec<u8>, AppError> {
+    let icc = match (&settings.assign_profile, source) {
+        (Some(spec), _) => Some(ColorProfiles::resolve(spec)
+            .map_err(|e| AppError::from(e).or_code(ErrorCode::InvalidProfile).with_param("profile", spec))?),
+        (None, Some(path)) if settings.preserve_metadata => ColorProfiles::read_source_profile(path)?,
+        _ => None,
+    };
+    // gAMA/cHRM of a PNG source without ICC: copied as chunks into PNG outputs and
+    // turned into an equivalent profile for JPEG
+    let png_color = match source {
+        Some(path) if settings.preserve_metadata && icc.is_none() => MetadataProcessor::read_png_color(path)?,
+        _ => None,
+    };
+
+    let dpi = match (settings.output_dpi, source) {
+        (Some(dpi), _) => Some((dpi, dpi)),
+        (None, Some(path)) if settings.preserve_dpi => MetadataProcessor::read_dpi(path)?,
+        _ => None,
+    };
+
+    if let Some(dpi) = dpi {
+        data = match format {
+            ImageFormat::Jpeg => MetadataProcessor::set_jpeg_dpi(data, dpi),
+            ImageFormat::Png => MetadataProcessor::set_png_dpi(data, dpi)?,
+            ImageFormat::Bmp => MetadataProcessor::set_bmp_dpi(data, dpi),
+            _ => data,
+        };
+    }
+
+    let (author, copyright) = (settings.author.as_deref(), settings.copyright.as_deref());
+
+    if format == ImageFormat::Jpeg {
+        let comment = match (&settings.jpeg_comment, source) {
+            (Some(comment), _) => Some(comment.clone()),
+            (None, Some(path)) if settings.preserve_metadata => MetadataProcessor::read_jpeg_comment(path)?,
+            _ => None,
+        };
+
+        if let Some(comment) = comment {
+            data = MetadataProcessor::set_jpeg_comment(data, &comment)?;
+        }
+
+        let mut exif = match source.filter(|_| settings.preserve_metadata) {
+            Some(path) => MetadataProcessor::read_exif_segment(path)?,
+            None => None,
+        };
+        if let Some(exif) = &mut exif {
+            // A HEIC's EXIF tag is informational only, so it may disagree with the pixels
+            MetadataProcessor::set_exif_orientation(exif, output_orientation(source, settings)?);
+        }
+        // Attribution is written even when the source's metadata is stripped
+        if author.is_some() || copyright.is_some() {
+            exif = Some(MetadataProcessor::set_exif_attribution(exif, author, copyright));
+        }
+        if let Some(exif) = &exif {
+            data = MetadataProcessor::set_jpeg_exif(data, exif)?;
+        }
+        if author.is_some() || copyright.is_some() {
+            data = MetadataProcessor::set_jpeg_xmp(data, &MetadataProcessor::attribution_xmp(author, copyright))?;
+        }
+
+        let icc = icc.clone().or_else(|| png_color.as_ref().map(ColorProfiles::from_png_color));
+        if let Some(icc) = &icc {
+            data = MetadataProcessor::set_jpeg_icc(data, icc)?;
+        }
+    }
+
+    if format == ImageFormat::Png {
+        if let Some(icc) = &icc {
+            let name = ColorProfiles::description(icc).unwrap_or_else(|| "ICC profile".to_string());
+            data = MetadataProcessor::set_png_icc(data, icc, &name)?;
+        }
+        if let Some(color) = &png_color {
+            data = MetadataProcessor::set_png_color(data, color)?;
+        }
+        if author.is_some() || copyright.is_some() {
+            data = MetadataProcessor::set_png_attribution(data, author, copyright)?;
+        }
+    }
+
+    Ok(data)
+}
+
+/// Describe which source metadata survives into the output, mirroring write_metadata
+fn build_metadata_report(
+    path: &str,
+    format: ImageFormat,
+    settings: &ConversionSettings,
+) -> Result<MetadataReport, AppError> {
+    let presence = MetadataProcessor::inspect(path)?;
+    let source_icc = ColorProfiles::read_source_profile(path)?;
+    // Only read when no ICC profile overrides the chunks, as in write_metadata
+    let source_png_color = match source_icc {
+        None => MetadataProcessor::read_png_color(path)?,
+        Some(_) => None,
+    };
+
+    // EXIF is only carried over as a whole, from JPEG or HEIC sources into JPEG outputs
+    let exif_kept = settings.preserve_metadata
+        && format == ImageFormat::Jpeg
+        && MetadataProcessor::read_exif_segment(path)?
+            .is_some();
+    let icc_kept = settings.preserve_metadata
+        && settings.assign_profile.is_none()
+        && matches!(format, ImageFormat::Jpeg | ImageFormat::Png);
+
+    Ok(MetadataReport {
+        gps: MetadataStatus::from_presence(presence.gps, exif_kept),
+        capture_time: MetadataStatus::from_presence(presence.capture_time.is_some(), exif_kept),
+        capture_time_value: presence.capture_time,
+        camera_serial: MetadataStatus::from_presence(presence.camera_serial, exif_kept),
+        thumbnail: MetadataStatus::from_presence(presence.thumbnail, exif_kept),
+        // XMP packets are never written to outputs
+        xmp: MetadataStatus::from_presence(presence.xmp, false),
+        icc_profile: MetadataStatus::from_presence(source_icc.is_some(), icc_kept),
+        gamma_chromaticity: MetadataStatus::from_presence(source_png_color.is_some(), icc_kept),
+    })
+}
+
+/// Encoded output plus what a target-size search settled on
+struct Encoded {
+    data: Vec<u8>,
+    encoder: &'static str,
+    /// Quality chosen by the max_output_bytes or auto_quality search
+    quality: Option<u8>,
+    /// SSIM the auto_quality search reached
+    ssim: Option<f64>,
+    /// Dimensions actually written, when downscale_to_fit had to shrink the image
+    downscaled_to: Option<(u32, u32)>,
+}
+
+/// Encode the processed image per the output settings
+fn encode_output(
+    img: &DynamicImage,
+    format: ImageFormat,
+    settings: &ConversionSettings,
+    orientation: u16,
+) -> Result<Encoded, AppError> {
+    let encoder = match format {
+        ImageFormat::Jpeg => ImageProcessor::jpeg_encoder_name(settings.jpeg_options()),
+        _ => ImageProcessor::encoder_name(format),
+    };
+    let to_error = |e: anyhow::Error| {
+        AppError::from(e).or_code(ErrorCode::EncodeFailed).with_param("format", format!("{:?}", format))
+    };
+
+    if let Some(max_bytes) = settings.max_output_bytes.filter(|_| settings.bilevel.is_none()) {
+        let floor = settings.min_quality.unwrap_or(0);
+        let (data, quality, downscaled) = if settings.downscale_to_fit {
+            ImageProcessor::encode_to_size_downscaling(img, format, settings.webp_method, settings.jpeg_options(), settings.quality, floor, max_bytes)
+        } else {
+            ImageProcessor::encode_to_size(img, format, settings.webp_method, settings.jpeg_options(), settings.quality, floor, max_bytes)
+                .map(|(data, quality)| (data, quality, None))
+        }
+        .map_err(to_error)?;
+
+        return Ok(Encoded {
+            data,
+            encoder,
+            quality: Some(quality),
+            ssim: None,
+            downscaled_to: downscaled.map(|img| (img.width(), img.height())),
+        });
+    }
+
+    if let Some(fidelity) = settings.auto_quality.filter(|_| settings.bilevel.is_none()) {
+        let (data, quality, ssim) = AutoQuality::encode(img, format, settings.webp_method, settings.jpeg_options(), fidelity)
+            .map_err(to_error)?;
+        return Ok(Encoded {
+            data,
+            encoder,
+            quality: Some(quality),
+            ssim: Some(ssim),
+            downscaled_to: None,
+        });
+    }
+
+    let (encoded, encoder) = match settings.bilevel {
+        Some(mode) => {
+            let bilevel = Adjustments::to_bilevel(img, mode);
+            if format == ImageFormat::Png {
+                (ImageProcessor::encode_png_bilevel(&bilevel), "png (1-bit)")
+            } else if format == ImageFormat::Tiff {
+                (ImageProcessor::encode_tiff_bilevel(&bilevel, settings.tiff_compression, orientation), "tiff (1-bit)")
+            } else if format == ImageFormat::Pnm {
+                (ImageProcessor::encode_pnm(&DynamicImage::ImageLuma8(bilevel), settings.format_name() == "pgm"), encoder)
+            } else {
+                (ImageProcessor::encode_image(&DynamicImage::ImageLuma8(bilevel), format, settings.quality), encoder)
+            }
+        }
+        None if format == ImageFormat::Bmp && settings.bmp_indexed => {
+            (ImageProcessor::encode_bmp_indexed(img), "bmp (8-bit indexed)")
+        }
+        None if format == ImageFormat::Bmp => (ImageProcessor::encode_bmp(img, settings.bmp_bit_depth), encoder),
+        None if format == ImageFormat::Tiff => (ImageProcessor::encode_tiff(img, settings.tiff_compression, orientation), encoder),
+        // Quality doesn't apply to netpbm and is ignored
+        None if format == ImageFormat::Pnm => (ImageProcessor::encode_pnm(img, settings.format_name() == "pgm"), encoder),
+        None if format == ImageFormat::Png => match &settings.png_palette {
+            Some(palette) => {
+                // Palettes have no transparency entries; composite like JPEG does
+                let flattened = img.color().has_alpha()
+                    .then(|| DynamicImage::ImageRgb8(Adjustments::flatten(img, settings.flatten_background, settings.flatten_mode)));
+                let img = flattened.as_ref().unwrap_or(img);
+                let indices = Adjustments::map_to_palette(img, palette);
+                let encoded = ImageProcessor::encode_png_indexed(img.width(), img.height(), &palette.colors, &indices, settings.png_compression);
+                (encoded, "png (indexed)")
+            }
+            None => (ImageProcessor::encode_png(img, settings.png_compression), encoder),
+        },
+        None if format == ImageFormat::WebP => (ImageProcessor::encode_webp(img, settings.quality, settings.webp_method), encoder),
+        None if format == ImageFormat::Avif => match ImageProcessor::encode_avif(img, settings.quality, settings.avif_preview) {
+            Ok((data, true)) => (Ok(data), "libheif (with preview)"),
+            encoded => (encoded.map(|(data, _)| data), encoder),
+        },
+        None if format == ImageFormat::Jpeg => (ImageProcessor::encode_jpeg(img, settings.quality, settings.jpeg_options()), encoder),
+        None => (ImageProcessor::encode_image(img, format, settings.quality), encoder),
+    };
+
+    Ok(Encoded {
+        data: encoded.map_err(to_error)?,
+        encoder,
+        quality: None,
+        ssim: None,
+        downscaled_to: None,
+    })
+}
+
+/// Whether the output ends up 8-bit per channel: an explicit 8-bit pixel format, or a
+/// format that can't store 16-bit samples at all
+fn writes_8bit(format: ImageFormat, settings: &ConversionSettings) -> bool {
+    match settings.output_pixel_format {
+        Some(pixel_format) => matches!(
+            pixel_format,
+            PixelFormat::Luma8 | PixelFormat::LumaA8 | PixelFormat::Rgb8 | PixelFormat::Rgba8
+        ),
+        None => ![PixelFormat::Luma16, PixelFormat::Rgb16, PixelFormat::Rgba16]
+            .into_iter()
+            .any(|pixel_format| ImageProcessor::supports_pixel_format(format, pixel_format)),
+    }
+}
+
+/// Size a `width` x `height` image is resampled to, and the size of the output cut
+/// from its center, per exact_resize or max_width/max_height and resize_mode. Only
+/// Fill makes the two differ.
+fn resize_target(width: u32, height: u32, settings: &ConversionSettings) -> ((u32, u32), (u32, u32)) {
+    if let Some(size) = settings.exact_resize {
+        return (size, size);
+    }
+    match (settings.resize_mode, settings.max_width, settings.max_height) {
+        (ResizeMode::Fill, Some(box_width), Some(box_height)) => {
+            (ImageProcessor::fill_dimensions(width, height, box_width, box_height), (box_width, box_height))
+        }
+        (ResizeMode::Stretch, Some(box_width), Some(box_height)) => ((box_width, box_height), (box_width, box_height)),
+        _ => {
+            let size = ImageProcessor::fit_dimensions(width, height, settings.max_width, settings.max_height);
+            (size, size)
+        }
+    }
+}
+
+/// Resize per resize_target, resampling on the GPU when requested and possible
+fn resize_to_fit(img: &DynamicImage, settings: &ConversionSettings) -> Option<DynamicImage> {
+    let ((width, height), (output_width, output_height)) = resize_target(img.width(), img.height(), settings);
+    if (width, height) == img.dimensions() && (output_width, output_height) == (width, height) {
+        return None;
+    }
+
+    let resized = if (width, height) == img.dimensions() {
+        img.clone()
+    } else {
+        settings.gpu_resize
+            .then(|| GpuResizer::resize_exact(img, width, height))
+            .flatten()
+            .unwrap_or_else(|| img.resize_exact(width, height, image::imageops::FilterType::Lanczos3))
+    };
+    // Fill overshoots the box on one axis; keep the middle of it
+    if (output_width, output_height) == (width, height) {
+        return Some(resized);
+    }
+    Some(resized.crop_imm((width - output_width) / 2, (height - output_height) / 2, output_width, output_height))
+}
+
+/// Put the source in place of an output keep_smaller rejected. It's staged next to
+/// `output_path` under the source's own extension, since its bytes are still in the
+/// source format; when that lands on the source itself there's nothing to write.
+/// Returns the path the original will sit at and the write to commit, if any.
+fn keep_original(source: &str, output_path: &str) -> Result<(String, Option<OutputTransaction>), AppError> {
+    let source_path = std::path::Path::new(source);
+    let destination = std::path::Path::new(output_path)
+        .with_extension(source_path.extension().unwrap_or_default());
+
+    let same_file = destination.canonicalize()
+        .is_ok_and(|destination| source_path.canonicalize().is_ok_and(|source| source == destination));
+    let transaction = if same_file {
+        None
+    } else {
+        let data = std::fs::read(source).map_err(|e| AppError::from(e).with_param("path", source))?;
+        Some(OutputTransaction::stage(&destination, &data)
+            .map_err(|e| AppError::from(e).with_param("path", destination.display()))?)
+    };
+    Ok((destination.to_string_lossy().to_string(), transaction))
+}
+
+/// Place a digest in the (sha256, blake3) result field matching `algorithm`
+fn split_digest(algorithm: ChecksumAlgorithm, digest: Option<String>) -> (Option<String>, Option<String>) {
+    match algorithm {
+        ChecksumAlgorithm::Sha256 => (digest, None),
+        ChecksumAlgorithm::Blake3 => (None, digest),
+    }
+}
+
+/// A decoded image after every pixel-changing setting, ready to encode, plus what
+/// the automatic steps decided
+struct Processed<'a> {
+    image: Cow<'a, DynamicImage>,
+    /// Redactions as applied after clamping to the image bounds
+    redactions: Vec<Redaction>,
+    trim_rect: Option<CropRect>,
+    detected_rotation: Option<u16>,
+    deskew_angle: Option<f32>,
+    straighten_angle: Option<f32>,
+}
+
+/// Run the processing pipeline on a decoded image, in order, up to the point where
+/// it's encoded as `format`. `source` is only read for its orientation tag. Each
+/// step replaces the image, so intermediate copies are dropped as soon as possible.
+fn process_image<'a>(
+    img: &'a DynamicImage,
+    source: Option<&str>,
+    format: ImageFormat,
+    settings: &ConversionSettings,
+) -> Result<Processed<'a>, AppError> {
+    let mut img = Cow::Borrowed(img);
+
+    // Redaction coordinates refer to the oriented source, so apply before any geometry
+    let mut redactions = Vec::new();
+    if !settings.redactions.is_empty() {
+        redactions = Adjustments::apply_redactions(img.to_mut(), &settings.redactions);
+    }
+
+    // Explicit crop shares the redaction coordinate space, so it comes next
+    if let Some(rect) = settings.crop_rect {
+        img = Cow::Owned(crop_to_rect(&img, rect, "crop_rect")?);
+    }
+
+    // Trim before resizing so later steps see the content area only
+    let trim_rect = settings.trim_borders
+        .and_then(|tolerance| Adjustments::find_trim_rect(&img, tolerance));
+    if let Some(rect) = trim_rect {
+        img = Cow::Owned(img.crop_imm(rect.x, rect.y, rect.width, rect.height));
+    }
+
+    // A declared orientation, even "normal", always wins over a guess
+    let detected_rotation = match settings.detect_upright {
+        true if source.map(ImageProcessor::read_exif_orientation).transpose()?.flatten().is_none() => {
+            UprightDetector::detect(&img)?
+        }
+        _ => None,
+    };
+    if let Some(degrees) = detected_rotation {
+        img = Cow::Owned(match degrees {
+            90 => img.rotate90(),
+            180 => img.rotate180(),
+            _ => img.rotate270(),
+        });
+    }
+
+    // Once the text runs horizontally, so the skew search sees lines
+    let deskew_angle = settings.deskew
+        .then(|| Deskewer::detect(&img))
+        .flatten();
+    if let Some(degrees) = deskew_angle {
+        img = Cow::Owned(Adjustments::rotate_with_fill(&img, degrees, Deskewer::background(&img)));
+    }
+
+    // Before resizing, so the rotation resamples full resolution pixels
+    let straighten_angle = settings.auto_straighten
+        .map(|max_angle| Straightener::detect(&img, max_angle))
+        .transpose()?
+        .flatten();
+    if let Some(degrees) = straighten_angle {
+        img = Cow::Owned(Adjustments::rotate_and_crop(&img, degrees));
+    }
+
+    if let Some(resized) = resize_to_fit(&img, settings) {
+        img = Cow::Owned(resized);
+    }
+
+    // After resizing, so the histogram covers fewer pixels
+    if let Some(leveled) = settings.auto_levels.and_then(|clip_percent| Adjustments::auto_levels(&img, clip_percent)) {
+        img = Cow::Owned(leveled);
+    }
+
+    // Before flattening, which would otherwise be the first step to truncate to 8 bits
+    let dithered = settings.bit_depth_dither
+        .filter(|_| writes_8bit(format, settings))
+        .and_then(|mode| Adjustments::reduce_to_8bit(&img, mode));
+    if let Some(dithered) = dithered {
+        img = Cow::Owned(dithered);
+    }
+
+    // JPEG, netpbm, AVIF (as encoded here) and 24-bit BMP have no alpha; composite
+    // instead of letting the encoder drop it
+    let opaque_only = matches!(format, ImageFormat::Jpeg | ImageFormat::Pnm | ImageFormat::Avif)
+        || (format == ImageFormat::Bmp && settings.bmp_bit_depth == Some(24));
+    if opaque_only && settings.bilevel.is_none() && img.color().has_alpha() {
+        img = Cow::Owned(DynamicImage::ImageRgb8(Adjustments::flatten(&img, settings.flatten_background, settings.flatten_mode)));
+    }
+
+    if let Some(converted) = settings.output_pixel_format.and_then(|pixel_format| ImageProcessor::convert_pixel_format(&img, pixel_format)) {
+        img = Cow::Owned(converted);
+    }
+
+    Ok(Processed { image: img, redactions, trim_rect, detected_rotation, deskew_angle, straighten_angle })
+}
+
+/// Convert and write an already decoded image loaded from `source`, or from memory
+/// when there is no source file
+#[allow(clippy::too_many_arguments)]
+fn convert_loaded(
+    app_handle: &tauri::AppHandle,
+    file_id: &str,
+    img: &DynamicImage,
+    source: Option<&str>,
+    output_path: &str,
+    format: ImageFormat,
+    settings: &ConversionSettings,
+    started: Instant,
+) -> Result<ConversionResult, AppError> {
+    // Emit progress
+    app_handle.emit("conversion_progress", ConversionProgress {
+        file_id: file_id.to_string(),
+        progress: 50,
+    }).ok();
+
+    let source_dimensions = (img.width(), img.height());
+    // The JSON sidecar always records checksums
+    let checksums = (settings.compute_checksums || settings.write_json_sidecar)
+        .then_some(settings.checksum_algorithm);
+
+    let Processed { image, redactions, trim_rect, detected_rotation, deskew_angle, straighten_angle } =
+        process_image(img, source, format, settings)?;
+    let img = image.as_ref();
+
+    let orientation = output_orientation(source, settings)?;
+
+    let (data, input_digest) = match source.zip(checksums) {
+        // Hash the input while the encoder runs
+        Some((path, algorithm)) => {
+            let (data, digest) = rayon::join(
+                || encode_output(img, format, settings, orientation),
+                || FileOps::checksum_file(path, algorithm),
+            );
+            (data, Some(digest))
+        }
+        None => (encode_output(img, format, settings, orientation), None),
+    };
+    let Encoded { data, encoder, quality, ssim, downscaled_to } = data?;
+    let (input_sha256, input_blake3) = split_digest(settings.checksum_algorithm, input_digest.transpose()?);
+    let output_dimensions = downscaled_to.unwrap_or((img.width(), img.height()));
+
+    let data = write_metadata(data, source, format, settings)?;
+
+    // Checked after metadata so the limit applies to the file as written
+    if let Some(max_bytes) = settings.max_output_bytes.filter(|&max| data.len() as u64 > max) {
+        let message = format!("Output can't be made smaller than {} bytes (limit {})", data.len(), max_bytes);
+        return Err(AppError::new(ErrorCode::TargetSizeUnreachable, message)
+            .with_param("limit", max_bytes)
+            .with_param("actual", data.len()));
+    }
+    let metadata_report = match source {
+        Some(path) => build_metadata_report(path, format, settings)?,
+        None => MetadataReport::default(),
+    };
+
+    let output_bytes = data.len() as u64;
+    let input_bytes = source
+        .and_then(|path| std::fs::metadata(path).ok())
+        .map_or(0, |m| m.len());
+
+    let original = source.filter(|_| settings.keep_smaller && input_bytes > 0 && output_bytes > input_bytes);
+    let size_winner = settings.keep_smaller
+        .then_some(if original.is_some() { SizeWinner::Original } else { SizeWinner::Converted });
+    // Nothing at the output path changes until the file has been checked, and a
+    // failure in any later step puts back what was there, so a failed conversion
+    // never costs the previous output or, when converting in place, the source
+    let (output_path, output_bytes, output_dimensions, mut transaction) = match original {
+        Some(_) if settings.larger_output == LargerOutput::Skip => {
+            let message = format!("Output would be larger than the original ({} > {} bytes)", output_bytes, input_bytes);
+            return Err(AppError::new(ErrorCode::OutputLarger, message)
+                .with_param("limit", input_bytes)
+                .with_param("actual", output_bytes));
+        }
+        Some(path) => {
+            let (destination, transaction) = keep_original(path, output_path)?;
+            (destination, input_bytes, source_dimensions, transaction)
+        }
+        None => {
+            let transaction = OutputTransaction::stage(std::path::Path::new(output_path), &data)
+                .map_err(|e| AppError::from(e).with_param("path", output_path))?;
+            (output_path.to_string(), output_bytes, output_dimensions, Some(transaction))
+        }
+    };
+    let output_path = output_path.as_str();
+    let written = transaction.as_ref()
+        .map_or(output_path.to_string(), |transaction| transaction.staged_path().to_string_lossy().to_string());
+    let permitted = app_handle.state::<PermittedDirs>();
+    permitted.allow_parent_of(output_path);
+    if let Some(path) = source {
+        permitted.allow_parent_of(path);
+    }
+
+    // Streamed back from disk rather than taken from `data`, so a write that didn't
+    // land intact shows up as a mismatch later
+    let output_digest = checksums
+        .map(|algorithm| FileOps::checksum_file(&written, algorithm))
+        .transpose()
+        .map_err(|e| AppError::from(e).with_param("path", output_path))?;
+    let (output_sha256, output_blake3) = split_digest(settings.checksum_algorithm, output_digest);
+
+    // A kept original was never encoded, so there's nothing to verify
+    if settings.verify_output && original.is_none() {
+        if let Err(e) = ImageProcessor::verify_output(&written, format, output_dimensions) {
+            return Err(AppError::new(ErrorCode::VerificationFailed, format!("Verification failed: {}", e))
+                .with_param("path", output_path));
+        }
+    }
+
+    if let Some(transaction) = transaction.as_mut() {
+        transaction.commit().map_err(|e| AppError::from(e).with_param("path", output_path))?;
+    }
+
+    let sidecars = match source {
+        Some(path) if !settings.copy_sidecars.is_empty() => {
+            FileOps::copy_sidecars(path, output_path, &settings.copy_sidecars)?
+        }
+        _ => Vec::new(),
+    };
+
+    let json_sidecar = if settings.write_json_sidecar {
+        let exif = match source {
+            Some(path) => MetadataProcessor::exif_summary(path)?,
+            None => None,
+        };
+        let record = SidecarRecord {
+            source: SidecarImage {
+                path: source,
+                format: source
+                    .and_then(|path| ImageProcessor::get_format(path).ok())
+                    .unwrap_or_else(|| "clipboard".to_string()),
+                width: source_dimensions.0,
+                height: source_dimensions.1,
+                bytes: input_bytes,
+                sha256: input_sha256.as_deref(),
+                blake3: input_blake3.as_deref(),
+            },
+            output: SidecarImage {
+                path: Some(output_path),
+                format: original
+                    .and_then(|path| ImageProcessor::get_format(path).ok())
+                    .unwrap_or_else(|| settings.format_name().to_string()),
+                width: output_dimensions.0,
+                height: output_dimensions.1,
+                bytes: output_bytes,
+                sha256: output_sha256.as_deref(),
+                blake3: output_blake3.as_deref(),
+            },
+            exif,
+            encoder,
+            settings,
+        };
+        Some(record.write(output_path, unix_time())
+            .map_err(|e| AppError::from(e).with_param("path", output_path))?)
+    } else {
+        None
+    };
+
+    if let Some(transaction) = transaction {
+        transaction.finish();
+    }
+
+    // Emit completion
+    app_handle.emit("conversion_progress", ConversionProgress {
+        file_id: file_id.to_string(),
+        progress: 100,
+    }).ok();
+
+    Ok(ConversionResult {
+        output_path: output_path.to_string(),
+        sidecars,
+        raw_decode_path: None,
+        trim_rect,
+        redactions,
+        metadata_report,
+        input_sha256,
+        output_sha256,
+        input_blake3,
+        output_blake3,
+        input_bytes,
+        output_bytes,
+        compression_ratio: compression_ratio(input_bytes, output_bytes),
+        duration_ms: started.elapsed().as_millis() as u64,
+        decoder: None,
+        encoder: encoder.to_string(),
+        json_sidecar,
+        warnings: Vec::new(),
+        output_width: output_dimensions.0,
+        output_height: output_dimensions.1,
+        quality_used: quality,
+        ssim,
+        size_winner,
+        straighten_angle,
+        detected_rotation,
+        deskew_angle,
+    })
+}
+
+#[tauri::command]
+async fn convert_image(
+    file_id: String,
+    path: String,
+    output_path: String,
+    mut settings: ConversionSettings,
+    post_actions: Option<PostActions>,
+    app_handle: tauri::AppHandle,
+) -> Result<ConvertImageResult, AppError> {
+    let format = resolve_target_format(&mut settings, Some(output_path.as_str()))?;
+    validate_settings(&settings)?;
+
+    let result = convert_file(&app_handle, &file_id, &path, &output_path, format, &settings)?;
+    let post_actions = run_post_actions(
+        &app_handle,
+        &post_actions.unwrap_or_default(),
+        std::slice::from_ref(&result.output_path),
+        &[path.as_str()],
+        0,
+    );
+    Ok(ConvertImageResult { result, post_actions })
+}
+
+/// Re-encode `path` in place, e.g. to recompress it at a lower quality. The target
+/// format must match the file's extension. The file is only replaced once the new
+/// content is fully written and, with verify_output, checked; any failure leaves
+/// the original byte for byte as it was.
+#[tauri::command]
+async fn reencode_image(
+    file_id: String,
+    path: String,
+    mut settings: ConversionSettings,
+    app_handle: tauri::AppHandle,
+) -> Result<ConversionResult, AppError> {
+    let format = resolve_target_format(&mut settings, Some(path.as_str()))?;
+    validate_settings(&settings)?;
+    convert_file(&app_handle, &file_id, &path, &path, format, &settings)
+}
+
+#[cfg(feature = "clipboard")]
+fn read_clipboard_image() -> Result<DynamicImage, AppError> {
+    ImageProcessor::load_clipboard_image()
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::ClipboardUnavailable))?
+        .ok_or_else(|| AppError::new(ErrorCode::ClipboardEmpty, "The clipboard does not contain an image"))
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn read_clipboard_image() -> Result<DynamicImage, AppError> {
+    Err(AppError::new(ErrorCode::ClipboardUnavailable, "Clipboard support is not enabled in this build"))
+}
+
+/// Convert the image on the clipboard (e.g. a screenshot) without going through a file
+#[tauri::command]
+async fn convert_clipboard_image(
+    file_id: String,
+    output_path: String,
+    mut settings: ConversionSettings,
+    app_handle: tauri::AppHandle,
+) -> Result<ConversionResult, AppError> {
+    let format = resolve_target_format(&mut settings, Some(output_path.as_str()))?;
+    validate_settings(&settings)?;
+
+    let started = Instant::now();
+    let img = read_clipboard_image()?;
+
+    convert_loaded(&app_handle, &file_id, &img, None, &output_path, format, &settings, started)
+        .map(|result| ConversionResult {
+            decoder: Some("arboard".to_string()),
+            ..result
+        })
+}
+
+/// Convert an image and also return a tiny low-quality placeholder (LQIP) built from
+/// the same decode, so the UI can show something while the full output loads
+#[tauri::command]
+async fn convert_with_placeholder(
+    file_id: String,
+    path: String,
+    output_path: String,
+    mut settings: ConversionSettings,
+    placeholder_size: Option<u32>,
+    app_handle: tauri::AppHandle,
+) -> Result<PlaceholderConversionResult, AppError> {
+    let format = resolve_target_format(&mut settings, Some(output_path.as_str()))?;
+    validate_settings(&settings)?;
+
+    let started = Instant::now();
+    let (img, decoder) = load_source(&path, &settings)?;
+
+    let placeholder = ImageProcessor::thumbnail(&img, placeholder_size.unwrap_or(32));
+    let placeholder_data = ImageProcessor::encode_image(&placeholder, ImageFormat::Jpeg, 40)
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::EncodeFailed))?;
+
+    let converted = convert_loaded(&app_handle, &file_id, &img, Some(&path), &output_path, format, &settings, started)?;
+
+    Ok(PlaceholderConversionResult {
+        output_path: converted.output_path,
+        sidecars: converted.sidecars,
+        placeholder: format!("data:image/jpeg;base64,{}", BASE64.encode(placeholder_data)),
+        placeholder_width: placeholder.width(),
+        placeholder_height: placeholder.height(),
+        decoder: decoder.to_string(),
+        encoder: converted.encoder,
+    })
+}
+
+/// Convert a single image and open the result with the system default app.
+/// A failure to open doesn't discard the successful conversion.
+#[tauri::command]
+async fn convert_and_open(
+    file_id: String,
+    path: String,
+    output_path: String,
+    mut settings: ConversionSettings,
+    app_handle: tauri::AppHandle,
+) -> Result<ConvertAndOpenResult, AppError> {
+    let format = resolve_target_format(&mut settings, Some(output_path.as_str()))?;
+    validate_settings(&settings)?;
+
+    let converted = convert_file(&app_handle, &file_id, &path, &output_path, format, &settings)?;
+
+    let open_error = app_handle
+        .opener()
+        .open_path(converted.output_path.clone(), None::<&str>)
+        .err()
+        .map(|e| AppError::new(ErrorCode::OpenFailed, e.to_string()).with_param("path", &converted.output_path));
+
+    Ok(ConvertAndOpenResult {
+        opened: open_error.is_none(),
+        open_error,
+        result: converted,
+    })
+}
+
+#[derive(Serialize)]
+struct ResumeBatchResult {
+    job_id: String,
+    /// Items whose outputs were already complete and were left alone
+    skipped: usize,
+    results: Vec<BatchConversionResult>,
+}
+
+/// Directory holding app preferences such as the working directory
+fn config_dir(app_handle: &tauri::AppHandle) -> Result<std::path::PathBuf, AppError> {
+    app_handle.path()
+        .app_config_dir()
+        .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))
+}
+
+/// Directory holding persisted batch manifests
+fn manifest_dir(app_handle: &tauri::AppHandle) -> Result<std::path::PathBuf, AppError> {
+    app_handle.path()
+        .app_data_dir()
+        .map(|dir| dir.join("batches"))
+        .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))
+}
+
+/// Convert `items` in parallel, recording each outcome in the manifest under the
+/// given manifest indices. Each result is also emitted as a `conversion_result`
+/// event as soon as its item finishes; events arrive in completion order, not input
+/// order, so the frontend matches them up by `file_id`. The returned results are the
+/// same values, in input order.
+fn run_batch(
+    app_handle: &tauri::AppHandle,
+    items: &[(usize, BatchConversionItem)],
+    format: ImageFormat,
+    settings: &ConversionSettings,
+    manifest: &BatchManifest,
+    control: &BatchControl,
+) -> Vec<BatchConversionResult> {
+    let budget = settings.memory_budget_mb
+        .map(|mb| MemoryBudget::new(mb.saturating_mul(1024 * 1024)));
+    // Sizes are read up front so progress can be weighted by them from the first event
+    let input_sizes: Vec<u64> = items.iter()
+        .map(|(_, item)| std::fs::metadata(&item.path).map(|m| m.len()).unwrap_or(0))
+        .collect();
+    let tracker = ProgressTracker::new(items.len(), input_sizes.iter().sum());
+
+    // Report resource usage every couple of seconds until the batch is done
+    let (stop_monitor, stopped) = std::sync::mpsc::channel::<()>();
+    let monitor_handle = app_handle.clone();
+    let monitor = std::thread::spawn(move || {
+        while let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = stopped.recv_timeout(RESOURCE_USAGE_INTERVAL) {
+            monitor_handle.emit("resource_usage", ResourceMonitor::usage()).ok();
+        }
+    });
+
+    // Decoded sizes, for the memory budget and resource reporting
+    let pixels: Vec<u64> = items.iter()
+        .map(|(_, item)| ImageProcessor::peek_dimensions(&item.path)
+            .map(|(width, height)| width as u64 * height as u64)
+            .unwrap_or(0))
+        .collect();
+    let work: Vec<_> = items.iter().zip(&input_sizes).zip(&pixels).collect();
+
+    // Process images in parallel using rayon, each holding a share of the memory
+    // budget sized to its decoded RGBA buffer
+    let results: Vec<BatchConversionResult> = MemoryBudget::map_admitted(
+        budget.as_ref(),
+        &work,
+        |&(_, &pixels)| pixels * 4,
+        |&(((index, item), &input_bytes), &pixels)| {
+            let min_free = settings.min_free_disk_mb.map(|mb| mb.saturating_mul(1024 * 1024));
+            if let Some(min_free) = min_free.filter(|_| control.disk_check_due()) {
+                match control::available_space(&item.output_path) {
+                    Some((volume, available)) if available < min_free => {
+                        pause_for_disk_space(app_handle, control, volume, available, Some(min_free));
+                    }
+                    _ => {}
+                }
+            }
+            // Cancelled items stay pending in the manifest, so resuming picks them up
+            if !control.wait_until_running() {
+                if let Some(progress) = tracker.item_done(input_bytes) {
+                    app_handle.emit("batch_progress", progress).ok();
+                }
+                let result = BatchConversionResult {
+                    file_id: item.file_id.clone(),
+                    destination: item.output_path.clone(),
+                    success: false,
+                    skipped: false,
+                    error: Some(AppError::new(ErrorCode::Cancelled, "Batch cancelled")),
+                    result: None,
+                };
+                app_handle.emit("conversion_result", &result).ok();
+                return result;
+            }
+
+            let _in_flight = ResourceMonitor::begin(pixels);
+
+            let result = convert_file(
+                app_handle,
+                &item.file_id,
+                &item.path,
+                &item.output_path,
+                format,
+                settings,
+            );
+
+            if let Some(progress) = tracker.item_done(input_bytes) {
+                app_handle.emit("batch_progress", progress).ok();
+            }
+
+            let result = match result {
+                Ok(converted) => {
+                    manifest.mark(*index, ItemStatus::Completed, None);
+                    BatchConversionResult {
+                        file_id: item.file_id.clone(),
+                        destination: item.output_path.clone(),
+                        success: true,
+                        skipped: false,
+                        error: None,
+                        result: Some(converted),
+                    }
+                }
+                Err(e) => {
+                    // The disk filled up despite the threshold (or none was set); stop
+                    // starting new items. The item is marked failed, so resuming retries it.
+                    if e.code == ErrorCode::IoDiskFull {
+                        let (volume, available) = control::available_space(&item.output_path)
+                            .unwrap_or_else(|| (item.output_path.clone(), 0));
+                        pause_for_disk_space(app_handle, control, volume, available, min_free);
+                    }
+                    let skipped = (settings.skip_unsupported && e.code == ErrorCode::DecodeUnsupportedCodec)
+                        || e.code == ErrorCode::OutputLarger;
+                    let status = if skipped { ItemStatus::Skipped } else { ItemStatus::Failed };
+                    manifest.mark(*index, status, Some(e.message.clone()));
+                    BatchConversionResult {
+                        file_id: item.file_id.clone(),
+                        destination: item.output_path.clone(),
+                        success: false,
+                        skipped,
+                        error: Some(e),
+                        result: None,
+                    }
+                }
+            };
+
+            app_handle.emit("conversion_result", &result).ok();
+            result
+        },
+    );
+
+    drop(stop_monitor);
+    monitor.join().ok();
+
+    manifest.flush().ok();
+    app_handle.emit("batch_progress", tracker.finish()).ok();
+
+    results
+}
+
+/// Pause the batch and tell the frontend, once per pause
+fn pause_for_disk_space(app_handle: &tauri::AppHandle, control: &BatchControl, volume: String, available_bytes: u64, threshold_bytes: Option<u64>) {
+    if control.pause() {
+        app_handle.emit("low_disk_space", LowDiskSpace {
+            job_id: control.job_id.clone(),
+            volume,
+            available_bytes,
+            threshold_bytes,
+        }).ok();
+    }
+}
+
+/// Rename batch outputs per output_name_template (or keep_source_stem), folder_template
+/// and extension_case, or just make the requested names valid on this platform when
+/// none is set. Runs before the manifest is written, so every name is settled (and
+/// collision-free) before conversion starts; results report the final name as `destination`.
+fn apply_output_template(items: &mut [BatchConversionItem], settings: &ConversionSettings) -> Result<(), AppError> {
+    for item in items.iter_mut() {
+        item.output_path = naming::with_extension_case(&item.output_path, &item.path, settings.extension_case);
+    }
+
+    // {name} renders the source stem, and collisions get the usual suffixes
+    let name_template = settings.output_name_template.as_deref()
+        .or(settings.keep_source_stem.then_some("{name}"));
+    let folder_template = settings.folder_template.as_deref();
+    if name_template.is_none() && folder_template.is_none() {
+        for item in items.iter_mut() {
+            item.output_path = naming::sanitize_output_path(&item.output_path);
+        }
+        return Ok(());
+    }
+
+    let pairs: Vec<(&str, &str)> = items.iter()
+        .map(|item| (item.path.as_str(), item.output_path.as_str()))
+        .collect();
+    // Templates were checked by validate_settings, so what's left are file errors
+    let resolved = naming::resolve_output_paths(name_template, folder_template, &pairs)?;
+
+    for (item, output_path) in items.iter_mut().zip(resolved) {
+        item.output_path = output_path;
+    }
+    Ok(())
+}
+
+/// Create the subfolders folder_template placed outputs in
+fn create_output_folders(items: &[BatchConversionItem], settings: &ConversionSettings) -> Result<(), AppError> {
+    if settings.folder_template.is_none() {
+        return Ok(());
+    }
+
+    let mut created = std::collections::HashSet::new();
+    for folder in items.iter().filter_map(|item| std::path::Path::new(&item.output_path).parent()) {
+        if created.insert(folder) {
+            std::fs::create_dir_all(folder)
+                .map_err(|e| AppError::from(e).with_param("path", folder.display()))?;
+        }
+    }
+    Ok(())
+}
+
+/// Resolve a batch's output paths, as convert_images_batch would, and report the
+/// resulting folders and file counts so templates can be checked before running.
+/// Items whose conversion would needlessly lose quality carry preflight warnings.
+#[tauri::command]
+async fn plan_batch(
+    mut items: Vec<BatchConversionItem>,
+    mut settings: ConversionSettings,
+) -> Result<BatchPlan, AppError> {
+    resolve_batch_format(&mut settings, items.iter().map(|item| item.output_path.as_str()))?;
+    validate_settings(&settings)?;
+    apply_output_template(&mut items, &settings)?;
+
+    let mut folders: BTreeMap<String, usize> = BTreeMap::new();
+    for item in &items {
+        let folder = std::path::Path::new(&item.output_path)
+            .parent()
+            .map(|folder| folder.to_string_lossy().to_string())
+            .unwrap_or_default();
+        *folders.entry(folder).or_default() += 1;
+    }
+
+    Ok(BatchPlan {
+        outputs: items.into_iter()
+            .map(|item| PlannedOutput {
+                warnings: preflight_warnings(&item.path, &settings),
+                file_id: item.file_id,
+                source: item.path,
+                destination: item.output_path,
+            })
+            .collect(),
+        folders: folders.into_iter()
+            .map(|(folder, files)| PlannedFolder { folder, files })
+            .collect(),
+    })
+}
+
+/// Warnings for converting `path` with `settings`. Files that can't be inspected get
+/// none here; the conversion itself reports what's wrong with them.
+fn preflight_warnings(path: &str, settings: &ConversionSettings) -> Vec<PreflightWarning> {
+    let mut warnings = Vec::new();
+
+    // Every JPEG and WebP output is lossy
+    let lossy_output = matches!(settings.format_name(), "jpeg" | "webp");
+    if lossy_output && ImageProcessor::is_lossy(path).unwrap_or(false) {
+        let source_format = ImageProcessor::get_format(path).unwrap_or_default();
+        let same_format = format_for_extension(&source_format) == Some(settings.format_name());
+        warnings.push(PreflightWarning::GenerationLoss {
+            source_format,
+            suggested_format: "png",
+            keep_original: same_format,
+        });
+    }
+
+    warnings
+}
+
+/// What every new batch goes through: resolve and validate the settings, plan the
+/// outputs, convert the items under a manifest for `job_id` (generated when not
+/// given) and run the post actions. Returns the finished job, results included.
+fn run_batch_job(
+    mut items: Vec<BatchConversionItem>,
+    mut settings: ConversionSettings,
+    job_id: Option<String>,
+    post_actions: Option<PostActions>,
+    app_handle: &tauri::AppHandle,
+    controls: &BatchControls,
+) -> Result<BatchJob, AppError> {
+    let format = resolve_batch_format(&mut settings, items.iter().map(|item| item.output_path.as_str()))?;
+    validate_settings(&settings)?;
+    apply_output_template(&mut items, &settings)?;
+    create_output_folders(&items, &settings)?;
+
+    let started = Instant::now();
+    let job_id = job_id.unwrap_or_else(new_job_id);
+    let manifest = BatchManifest::create(&manifest_dir(app_handle)?, &job_id, &items, &settings)?;
+
+    let indexed: Vec<(usize, BatchConversionItem)> = items.iter().cloned().enumerate().collect();
+    let control = controls.register(&job_id);
+    let results = run_batch(app_handle, &indexed, format, &settings, &manifest, &control);
+    controls.remove(&control);
+    if let Some(actions) = post_actions {
+        run_batch_post_actions(app_handle, &job_id, &actions, &items, &results);
+    }
+
+    Ok(BatchJob {
+        job_id,
+        settings,
+        items,
+        results,
+        finished_at: unix_time(),
+        duration_ms: started.elapsed().as_millis() as u64,
+    })
+}
+
+/// Batch convert multiple images in parallel. Progress is persisted to a manifest
+/// so the job can be resumed, and the finished job is retained under `job_id`
+/// (generated when not given) for later reporting. Each item's result is also
+/// emitted as a `conversion_result` event when it finishes (see run_batch).
+#[tauri::command]
+async fn convert_images_batch(
+    items: Vec<BatchConversionItem>,
+    settings: ConversionSettings,
+    job_id: Option<String>,
+    post_actions: Option<PostActions>,
+    app_handle: tauri::AppHandle,
+    jobs: tauri::State<'_, BatchJobs>,
+    controls: tauri::State<'_, BatchControls>,
+) -> Result<BatchConversionResponse, AppError> {
+    let job = run_batch_job(items, settings, job_id, post_actions, &app_handle, &controls)?;
+    let summary = BatchSummary::new(&job.results, job.duration_ms);
+    let results = job.results.clone();
+    jobs.insert(job);
+
+    Ok(BatchConversionResponse { results, summary })
+}
+
+/// Like convert_images_batch, but only a summary is returned; results reach the
+/// frontend through the `conversion_result` events alone. The full results stay
+/// available through the retained job.
+#[tauri::command]
+async fn convert_images_batch_streaming(
+    items: Vec<BatchConversionItem>,
+    settings: ConversionSettings,
+    job_id: Option<String>,
+    post_actions: Option<PostActions>,
+    app_handle: tauri::AppHandle,
+    jobs: tauri::State<'_, BatchJobs>,
+    controls: tauri::State<'_, BatchControls>,
+) -> Result<BatchJobSummary, AppError> {
+    let job = run_batch_job(items, settings, job_id, post_actions, &app_handle, &controls)?;
+    let summary = job.summary();
+    jobs.insert(job);
+
+    Ok(summary)
+}
+
+/// Run a batch's post actions and report them as a `post_actions` event
+fn run_batch_post_actions(
+    app_handle: &tauri::AppHandle,
+    job_id: &str,
+    actions: &PostActions,
+    items: &[BatchConversionItem],
+    results: &[BatchConversionResult],
+) {
+    let outputs: Vec<String> = results.iter()
+        .filter_map(|r| r.result.as_ref())
+        .map(|converted| converted.output_path.clone())
+        .collect();
+    let sources: Vec<&str> = items.iter().map(|item| item.path.as_str()).collect();
+    let failed = results.iter().filter(|r| !r.success).count();
+
+    let outcomes = run_post_actions(app_handle, actions, &outputs, &sources, failed);
+    if !outcomes.is_empty() {
+        app_handle.emit("post_actions", PostActionReport {
+            job_id: job_id.to_string(),
+            outcomes,
+        }).ok();
+    }
+}
+
+/// Run the requested post actions for finished conversions. `outputs` are the
+/// converted files in order, `sources` every input, and `failed` how many items
+/// didn't succeed.
+fn run_post_actions(
+    app_handle: &tauri::AppHandle,
+    actions: &PostActions,
+    outputs: &[String],
+    sources: &[&str],
+    failed: usize,
+) -> Vec<PostActionOutcome> {
+    let mut outcomes = Vec::new();
+    let outcome = |action, result: Result<(), AppError>| PostActionOutcome {
+        action,
+        status: if result.is_ok() { PostActionStatus::Done } else { PostActionStatus::Failed },
+        reason: None,
+        error: result.err(),
+    };
+    let skipped = |action, reason: String| PostActionOutcome {
+        action,
+        status: PostActionStatus::Skipped,
+        reason: Some(reason),
+        error: None,
+    };
+    let open_failed = |path: &String, message: String| AppError::new(ErrorCode::OpenFailed, message).with_param("path", path);
+
+    if actions.reveal {
+        outcomes.push(match outputs.first() {
+            Some(first) => outcome("reveal", app_handle.opener()
+                .reveal_item_in_dir(first)
+                .map_err(|e| open_failed(first, e.to_string()))),
+            None => skipped("reveal", "No file was converted".to_string()),
+        });
+    }
+    if actions.open {
+        outcomes.push(match outputs.first() {
+            Some(first) => outcome("open", app_handle.opener()
+                .open_path(first.clone(), None::<&str>)
+                .map_err(|e| open_failed(first, e.to_string()))),
+            None => skipped("open", "No file was converted".to_string()),
+        });
+    }
+    if actions.trash_originals {
+        outcomes.push(if failed > 0 {
+            skipped("trash_originals", format!("{} of {} items did not succeed", failed, sources.len()))
+        } else {
+            outcome("trash_originals", trash_originals(sources, outputs))
+        });
+    }
+
+    outcomes
+}
+
+/// Move `sources` to the trash, except any that were overwritten by an output
+fn trash_originals(sources: &[&str], outputs: &[String]) -> Result<(), AppError> {
+    let outputs: std::collections::HashSet<std::path::PathBuf> = outputs.iter()
+        .filter_map(|output| std::fs::canonicalize(output).ok())
+        .collect();
+    let originals: Vec<&str> = sources.iter()
+        .copied()
+        .filter(|source| std::fs::canonicalize(source).map_or(true, |path| !outputs.contains(&path)))
+        .collect();
+
+    let failures: Vec<(&str, trash::Error)> = originals.iter()
+        .filter_map(|source| trash::delete(source).err().map(|e| (*source, e)))
+        .collect();
+    match failures.first() {
+        None => Ok(()),
+        Some((path, e)) => Err(AppError::new(ErrorCode::IoFailed, format!("Failed to move to trash: {}", e))
+            .with_param("path", path)
+            .with_param("failed", failures.len())),
+    }
+}
+
+/// Show `path` in the file manager. Only paths inside directories conversions read
+/// from or wrote to this session, or the working directory, are accepted.
+#[tauri::command]
+async fn reveal_path(path: String, app_handle: tauri::AppHandle, permitted: tauri::State<'_, PermittedDirs>) -> Result<(), AppError> {
+    let resolved = permitted_path(&permitted, &path)?;
+    app_handle.opener()
+        .reveal_item_in_dir(resolved)
+        .map_err(|e| AppError::new(ErrorCode::OpenFailed, e.to_string()).with_param("path", &path))
+}
+
+/// Open `path` with the default app, under the same restriction as reveal_path
+#[tauri::command]
+async fn open_path(path: String, app_handle: tauri::AppHandle, permitted: tauri::State<'_, PermittedDirs>) -> Result<(), AppError> {
+    let resolved = permitted_path(&permitted, &path)?;
+    app_handle.opener()
+        .open_path(resolved.to_string_lossy(), None::<&str>)
+        .map_err(|e| AppError::new(ErrorCode::OpenFailed, e.to_string()).with_param("path", &path))
+}
+
+fn permitted_path(permitted: &PermittedDirs, path: &str) -> Result<std::path::PathBuf, AppError> {
+    permitted.check(path)
+        .map_err(|e| AppError::from(e).with_param("path", path))?
+        .ok_or_else(|| AppError::new(ErrorCode::PathNotPermitted, "Path is outside the directories the app has used")
+            .with_param("path", path))
+}
+
+/// Resume a batch from its persisted manifest, re-running only items that are
+/// pending, failed, or whose output is missing or empty. With `decode_headers`,
+/// completed outputs must also have a readable image header.
+#[tauri::command]
+async fn resume_batch_from_manifest(
+    job_id: String,
+    decode_headers: Option<bool>,
+    app_handle: tauri::AppHandle,
+    jobs: tauri::State<'_, BatchJobs>,
+    controls: tauri::State<'_, BatchControls>,
+) -> Result<ResumeBatchResult, AppError> {
+    let manifest = BatchManifest::load(&manifest_dir(&app_handle)?, &job_id)
+        .map_err(|e| match AppError::from(e) {
+            e if e.code == ErrorCode::IoNotFound => AppError::new(ErrorCode::JobNotFound, e.message),
+            e => e,
+        }.with_param("job_id", &job_id))?;
+    let mut settings = manifest.settings();
+    let unfinished = manifest.unfinished_items(decode_headers.unwrap_or(false));
+    let format = resolve_batch_format(&mut settings, unfinished.iter().map(|(_, item)| item.output_path.as_str()))?;
+    validate_settings(&settings)?;
+
+    let started = Instant::now();
+    let skipped = manifest.item_count() - unfinished.len();
+    let control = controls.register(&job_id);
+    let results = run_batch(&app_handle, &unfinished, format, &settings, &manifest, &control);
+    controls.remove(&control);
+
+    jobs.insert(BatchJob {
+        job_id: job_id.clone(),
+        settings,
+        items: unfinished.into_iter().map(|(_, item)| item).collect(),
+        results: results.clone(),
+        finished_at: unix_time(),
+        duration_ms: started.elapsed().as_millis() as u64,
+    });
+
+    Ok(ResumeBatchResult {
+        job_id,
+        skipped,
+        results,
+    })
+}
+
+/// Make JPEG, PNG and HEIC files display upright without relying on orientation
+/// metadata. JPEGs are transformed losslessly; PNG and HEIC are re-encoded. Upright
+/// files are never touched. Without `in_place` the result goes to `<name>_upright.<ext>`;
+/// with `backup` the original is first copied to `<name>.<ext>.bak`.
+#[tauri::command]
+async fn normalize_orientation(paths: Vec<String>, in_place: bool, backup: bool) -> Vec<OrientationResult> {
+    paths
+        .par_iter()
+        .map(|path| {
+            let mut result = OrientationResult {
+                path: path.clone(),
+                orientation: 1,
+                changed: false,
+                lossless: false,
+                output_path: None,
+                backup_path: None,
+                error: None,
+            };
+            if let Err(e) = normalize_file(path, in_place, backup, &mut result) {
+                result.error = Some(e.with_param("path", path));
+            }
+            result
+        })
+        .collect()
+}
+
+fn normalize_file(path: &str, in_place: bool, backup: bool, result: &mut OrientationResult) -> Result<(), AppError> {
+    let (orientation, normalized) = OrientationNormalizer::normalize(path)
+        .map_err(|e| AppError::from(e).or_code(ErrorCode::DecodeCorrupt))?;
+    result.orientation = orientation;
+    let Some(normalized) = normalized else {
+        return Ok(());
+    };
+
+    let source = std::path::Path::new(path);
+    let output_path = if in_place {
+        source.to_path_buf()
+    } else {
+        let stem = source.file_stem().and_then(|s| s.to_str()).unwrap_or("image");
+        let extension = source.extension().and_then(|s| s.to_str()).unwrap_or("");
+        source.with_file_name(format!("{}_upright.{}", stem, extension))
+    };
+
+    if in_place && backup {
+        let backup_path = format!("{}.bak", path);
+        std::fs::copy(path, &backup_path)
+            .map_err(|e| AppError::from(e).with_param("path", &backup_path))?;
+        result.backup_path = Some(backup_path);
+    }
+
+    FileOps::write_atomic(&output_path, &normalized.data)?;
+    result.changed = true;
+    result.lossless = normalized.lossless;
+    result.output_path = Some(output_path.to_string_lossy().to_string());
+    Ok(())
+}
+
+/// Stop a running batch from starting new items; items already converting finish
+#[tauri::command]
+fn pause_batch(job_id: String, controls: tauri::State<'_, BatchControls>) -> Result<(), AppError> {
+    running_batch(&controls, &job_id)?.pause();
+    Ok(())
+}
+
+/// Continue a batch paused by pause_batch or by low disk space
+#[tauri::command]
+fn resume_paused_batch(job_id: String, controls: tauri::State<'_, BatchControls>) -> Result<(), AppError> {
+    running_batch(&controls, &job_id)?.resume();
+    Ok(())
+}
+
+/// Cancel a running or paused batch. Items not yet started stay pending in its
+/// manifest, so resume_batch_from_manifest can finish the job later.
+#[tauri::command]
+fn cancel_batch(job_id: String, controls: tauri::State<'_, BatchControls>) -> Result<(), AppError> {
+    running_batch(&controls, &job_id)?.cancel();
+    Ok(())
+}
+
+fn running_batch(controls: &BatchControls, job_id: &str) -> Result<Arc<BatchControl>, AppError> {
+    controls.get(job_id).ok_or_else(|| {
+        AppError::new(ErrorCode::JobNotFound, format!("No running batch job '{}'", job_id))
+            .with_param("job_id", job_id)
+    })
+}
+
+/// Directory used for previews, thumbnails and temporary files, with its free space
+#[tauri::command]
+fn get_working_dir() -> WorkDirInfo {
+    WorkDir::info()
+}
+
+/// Move previews, thumbnails and temporary files to `path`, or back to the OS temp
+/// directory when None. The directory is created if needed and must be writable
+/// with enough free space. The choice is saved for later sessions; cached previews
+/// and thumbnails in the old directory are deleted rather than moved.
+#[tauri::command]
+async fn set_working_dir(path: Option<String>, app_handle: tauri::AppHandle) -> Result<WorkDirInfo, AppError> {
+    let dir = path.map(std::path::PathBuf::from);
+    if let Some(dir) = &dir {
+        let available = WorkDir::check(dir)
+            .map_err(|e| AppError::from(e).with_param("path", dir.display()))?;
+        if available < work_dir::MIN_FREE_BYTES {
+            return Err(AppError::new(ErrorCode::IoDiskFull, "Not enough free space for the working directory")
+                .with_param("path", dir.display())
+                .with_param("limit", work_dir::MIN_FREE_BYTES)
+                .with_param("actual", available));
+        }
+    }
+
+    let previous = WorkDir::set(&config_dir(&app_handle)?, dir)?;
+    if previous != WorkDir::path() {
+        remove_stale_transform_previews(&previous);
+    }
+    Ok(WorkDir::info())
+}
+
+/// Process memory and in-flight batch work right now; batches also emit this as a
+/// `resource_usage` event every couple of seconds
+#[tauri::command]
+fn get_resource_usage() -> ResourceUsage {
+    ResourceMonitor::usage()
+}
+
+/// Whether GPU resizing is compiled in and usable, and which adapter it runs on.
+/// The first call probes for an adapter.
+#[tauri::command]
+async fn gpu_status() -> Result<GpuStatus, AppError> {
+    tauri::async_runtime::spawn_blocking(GpuResizer::status)
+        .await
+        .map_err(|e| AppError::new(ErrorCode::Internal, e.to_string()))
+}
+
+/// Replace the structural limits checked before any HEIF file reaches libheif
+#[tauri::command]
+async fn set_decoder_limits(limits: HeifLimits) -> Result<(), AppError> {
+    HeifLimits::set(limits);
+    Ok(())
+}
+
+/// Recently completed batch jobs that can still be exported
+#[tauri::command]
+async fn list_batch_jobs(jobs: tauri::State<'_, BatchJobs>) -> Result<Vec<BatchJobSummary>, AppError> {
+    Ok(jobs.list())
+}
+
+/// Write a report of a completed batch job to `path` as "csv" or "json"
+#[tauri::command]
+async fn export_batch_report(
+    job_id: String,
+    path: String,
+    format: String,
+    jobs: tauri::State<'_, BatchJobs>,
+) -> Result<(), AppError> {
+    if !matches!(format.as_str(), "csv" | "json") {
+        return Err(AppError::new(ErrorCode::UnsupportedFormat, format!("Unsupported report format: {}", format))
+            .with_param("format", &format));
+    }
+    if !jobs.contains(&job_id) {
+        return Err(AppError::new(ErrorCode::JobNotFound, format!("Unknown batch job '{}'", job_id))
+            .with_param("job_id", &job_id));
+    }
+
+    jobs.export_report(&job_id, &path, &format)
+        .map_err(|e| AppError::from(e).with_param("path", &path))
+}
+
+/// Handle a command-line invocation such as `image-converter --to jpeg < in.heic`.
+/// Returns the exit code, or None when the arguments don't start with a flag and
+/// the app should start normally.
+pub fn run_cli(args: &[String]) -> Option<i32> {
+    args.first()
+        .filter(|arg| arg.starts_with('-'))
+        .map(|_| cli::run(args))
+}
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_updater::Builder::new().build())
+        .plugin(tauri_plugin_process::init())
+        .manage(PreviewJobs::default())
+        .manage(BatchJobs::default())
+        .manage(BatchControls::default())
+        .manage(PermittedDirs::default())
+        .manage(TransformPreviews::default())
+        .manage(RegionPreviews::default())
+        .setup(|app| {
+            if let Ok(dir) = config_dir(app.handle()) {
+                WorkDir::load(&dir);
+            }
+            remove_stale_transform_previews(&WorkDir::path());
+            if let Ok(dir) = manifest_dir(app.handle()) {
+                BatchManifest::prune(&dir);
+            }
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            analyze_image,
+            compute_histogram,
+            get_file_size,
+            checksum_file,
+            estimate_output_size,
+            estimate_compression,
+            convert_image,
+            reencode_image,
+            convert_with_placeholder,
+            convert_clipboard_image,
+            convert_and_open,
+            reveal_path,
+            open_path,
+            convert_images_batch,
+            convert_images_batch_streaming,
+            plan_batch,
+            resume_batch_from_manifest,
+            list_batch_jobs,
+            export_batch_report,
+            set_decoder_limits,
+            gpu_status,
+            get_resource_usage,
+            get_working_dir,
+            set_working_dir,
+            pause_batch,
+            resume_paused_batch,
+            cancel_batch,
+            normalize_orientation,
+            save_temp_file,
+            generate_preview,
+            generate_thumbnails,
+            generate_tile_pyramid,
+            generate_favicon_bundle,
+            build_apng,
+            recommend_format,
+            recommend_formats,
+            preview_transform,
+            preview_region,
+            cancel_preview,
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{exif_with_orientation, gradient, TempDir};
+
+    fn settings(json: serde_json::Value) -> ConversionSettings {
+        let mut settings = serde_json::json!({ "quality": 90, "preserve_metadata": false });
+        settings.as_object_mut().unwrap().extend(json.as_object().unwrap().clone());
+        serde_json::from_value(settings).unwrap()
+    }
+
+    fn jpeg(img: &DynamicImage) -> Vec<u8> {
+        ImageProcessor::encode_jpeg(img, 90, JpegEncodeOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn jpeg_comment_setting_is_written() {
+        let dir = TempDir::new();
+        let settings = settings(serde_json::json!({ "jpeg_comment": "Batch 7" }));
+        let data = write_metadata(jpeg(&gradient(16, 16)), None, ImageFormat::Jpeg, &settings).unwrap();
+        let path = dir.write("out.jpg", &data);
+
+        assert_eq!(MetadataProcessor::read_jpeg_comment(&path).unwrap().as_deref(), Some("Batch 7"));
+    }
+
+    #[test]
+    fn source_jpeg_comment_is_kept_only_when_preserving_metadata() {
+        let dir = TempDir::new();
+        let source = dir.write("source.jpg", &MetadataProcessor::set_jpeg_comment(jpeg(&gradient(16, 16)), "From camera").unwrap());
+        let encoded = jpeg(&gradient(16, 16));
+
+        let kept = write_metadata(encoded.clone(), Some(&source), ImageFormat::Jpeg, &settings(serde_json::json!({ "preserve_metadata": true }))).unwrap();
+        let stripped = write_metadata(encoded, Some(&source), ImageFormat::Jpeg, &settings(serde_json::json!({}))).unwrap();
+
+        assert_eq!(MetadataProcessor::read_jpeg_comment(&dir.write("kept.jpg", &kept)).unwrap().as_deref(), Some("From camera"));
+        assert_eq!(MetadataProcessor::read_jpeg_comment(&dir.write("stripped.jpg", &stripped)).unwrap(), None);
+    }
+
+    #[test]
+    fn attribution_is_written_even_when_metadata_is_stripped() {
+        let dir = TempDir::new();
+        let source = dir.write("source.jpg", &MetadataProcessor::set_jpeg_comment(jpeg(&gradient(16, 16)), "From camera").unwrap());
+        let settings = settings(serde_json::json!({ "author": "Ada", "copyright": "© Ada" }));
+
+        let data = write_metadata(jpeg(&gradient(16, 16)), Some(&source), ImageFormat::Jpeg, &settings).unwrap();
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)).unwrap();
+        let copyright = exif.get_field(exif::Tag::Copyright, exif::In::PRIMARY).unwrap();
+        assert!(matches!(&copyright.value, exif::Value::Ascii(strings) if strings[0] == "© Ada".as_bytes()));
+        assert_eq!(MetadataProcessor::read_jpeg_comment(&dir.write("out.jpg", &data)).unwrap(), None);
+        let xmp = MetadataProcessor::read_jpeg_segments(&data)
+            .into_iter()
+            .find(|segment| segment.data.starts_with(b"http://ns.adobe.com/xap/1.0/\0"))
+            .unwrap();
+        assert!(String::from_utf8_lossy(&xmp.data).contains(">© Ada</rdf:li></rdf:Alt></dc:rights>"));
+
+        let png = ImageProcessor::encode_png(&gradient(16, 16), PngCompression::Fast).unwrap();
+        let data = write_metadata(png, Some(&source), ImageFormat::Png, &settings).unwrap();
+        assert!(MetadataProcessor::read_png_chunks(&data)
+            .iter()
+            .any(|(chunk_type, payload)| chunk_type == b"tEXt" && payload == b"Copyright\0\xA9 Ada"));
+    }
+
+    #[test]
+    fn kept_originals_are_staged_and_never_overwrite_the_source() {
+        let dir = TempDir::new();
+        let source = dir.write("photo.jpg", &jpeg(&gradient(16, 16)));
+
+        // Converting in place: the original already sits at the destination
+        let (destination, transaction) = keep_original(&source, &dir.file("photo.png")).unwrap();
+        assert_eq!(destination, source);
+        assert!(transaction.is_none());
+
+        let output = dir.file("out/photo.png");
+        std::fs::create_dir(dir.file("out")).unwrap();
+        let (destination, transaction) = keep_original(&source, &output).unwrap();
+        assert_eq!(destination, dir.file("out/photo.jpg"));
+        assert!(!std::path::Path::new(&destination).exists());
+        // Abandoned before commit, e.g. because verification failed
+        drop(transaction);
+        assert!(std::fs::read_dir(dir.file("out")).unwrap().next().is_none());
+        assert_eq!(std::fs::read(&source).unwrap(), jpeg(&gradient(16, 16)));
+    }
+
+    /// Drive a command future that never waits on anything to its result
+    fn ready<F: std::future::Future>(future: F) -> F::Output {
+        let mut context = std::task::Context::from_waker(std::task::Waker::noop());
+        match std::pin::pin!(future).poll(&mut context) {
+            std::task::Poll::Ready(output) => output,
+            std::task::Poll::Pending => panic!("command waited"),
+        }
+    }
+
+    #[test]
+    fn analyze_image_reports_the_bmp_sub_format() {
+        let fixture = |name: &str| format!("{}/tests/fixtures/bmp/{}.bmp", env!("CARGO_MANIFEST_DIR"), name);
+
+        let rle4 = serde_json::to_value(ready(analyze_image(fixture("rle4"))).unwrap()).unwrap();
+        assert_eq!((&rle4["width"], &rle4["height"]), (&serde_json::json!(7), &serde_json::json!(5)));
+        assert_eq!(rle4["bmp_format"], serde_json::json!({
+            "header": "info", "bits_per_pixel": 4, "compression": "rle4", "top_down": false, "channel_layout": null,
+        }));
+
+        let bitfields = serde_json::to_value(ready(analyze_image(fixture("rgb444_v4_top_down"))).unwrap()).unwrap();
+        assert_eq!(bitfields["bmp_format"], serde_json::json!({
+            "header": "v4", "bits_per_pixel": 16, "compression": "bitfields", "top_down": true, "channel_layout": "444",
+        }));
+
+        let dir = TempDir::new();
+        let png = dir.write("plain.png", &ImageProcessor::encode_png(&gradient(4, 4), PngCompression::Fast).unwrap());
+        assert!(serde_json::to_value(ready(analyze_image(png)).unwrap()).unwrap()["bmp_format"].is_null());
+    }
+
+    /// Four equal vertical bands in the quadrant colors, left to right
+    fn bands(width: u32, height: u32) -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(width, height, |x, _| {
+            image::Rgb(QUADRANT_COLORS[(x * 4 / width) as usize])
+        }))
+    }
+
+    #[test]
+    fn fit_contains_the_image_in_the_box_and_never_upscales() {
+        let fit = settings(serde_json::json!({ "max_width": 100, "max_height": 100 }));
+        assert_eq!(resize_target(400, 200, &fit), ((100, 50), (100, 50)));
+        assert_eq!(resize_target(200, 400, &fit), ((50, 100), (50, 100)));
+        assert_eq!(resize_target(60, 40, &fit), ((60, 40), (60, 40)));
+        assert!(resize_to_fit(&bands(60, 40), &fit).is_none());
+
+        let width_only = settings(serde_json::json!({ "max_width": 100 }));
+        assert_eq!(resize_target(400, 200, &width_only), ((100, 50), (100, 50)));
+        assert_eq!(resize_to_fit(&bands(400, 200), &width_only).unwrap().dimensions(), (100, 50));
+    }
+
+    #[test]
+    fn fill_covers_the_box_and_crops_the_overshoot_from_the_center() {
+        let fill = settings(serde_json::json!({ "max_width": 100, "max_height": 100, "resize_mode": "fill" }));
+        assert_eq!(ImageProcessor::fill_dimensions(400, 200, 100, 100), (200, 100));
+        assert_eq!(resize_target(400, 200, &fill), ((200, 100), (100, 100)));
+
+        // Scaled to 200x100 the bands are 50 wide; the middle 100 columns are the
+        // second and third bands
+        let wide = resize_to_fit(&bands(400, 200), &fill).unwrap();
+        assert_eq!(wide.dimensions(), (100, 100));
+        assert_color(&wide, (10, 50), QUADRANT_COLORS[1], "wide, left edge");
+        assert_color(&wide, (89, 50), QUADRANT_COLORS[2], "wide, right edge");
+
+        // Tall sources lose their top and bottom instead
+        let tall = resize_to_fit(&bands(400, 200).rotate90(), &fill).unwrap();
+        assert_eq!(tall.dimensions(), (100, 100));
+        assert_color(&tall, (50, 10), QUADRANT_COLORS[1], "tall, top edge");
+        assert_color(&tall, (50, 89), QUADRANT_COLORS[2], "tall, bottom edge");
+
+        // Smaller sources are scaled up to cover the box
+        assert_eq!(resize_target(50, 20, &fill), ((250, 100), (100, 100)));
+        assert_eq!(resize_to_fit(&bands(50, 20), &fill).unwrap().dimensions(), (100, 100));
+    }
+
+    #[test]
+    fn fill_without_both_sides_falls_back_to_fit() {
+        let fill = settings(serde_json::json!({ "max_width": 100, "resize_mode": "fill" }));
+        assert_eq!(resize_target(400, 200, &fill), ((100, 50), (100, 50)));
+    }
+
+    #[test]
+    fn stretch_resizes_to_the_exact_box() {
+        let stretch = settings(serde_json::json!({ "max_width": 100, "max_height": 100, "resize_mode": "stretch" }));
+        assert_eq!(resize_target(400, 200, &stretch), ((100, 100), (100, 100)));
+
+        // Nothing is cut off: all four bands remain, each squeezed to 25 columns
+        let stretched = resize_to_fit(&bands(400, 200), &stretch).unwrap();
+        assert_eq!(stretched.dimensions(), (100, 100));
+        for (band, color) in QUADRANT_COLORS.iter().enumerate() {
+            assert_color(&stretched, (band as u32 * 25 + 12, 50), *color, "stretch");
+        }
+    }
+
+    /// A PNG source tagged with gamma 1/2.2 and Rec. 709 primaries on a D65 white
+    fn png_with_color(dir: &TempDir) -> (String, metadata::PngColorChunks) {
+        let color = metadata::PngColorChunks {
+            gamma: Some(45455),
+            chromaticities: Some([31270, 32900, 64000, 33000, 30000, 60000, 15000, 6000]),
+        };
+        let png = ImageProcessor::encode_png(&gradient(16, 16), PngCompression::Fast).unwrap();
+        let path = dir.write("tagged.png", &MetadataProcessor::set_png_color(png, &color).unwrap());
+        (path, color)
+    }
+
+    #[test]
+    fn png_gamma_and_chromaticities_survive_png_to_png() {
+        let dir = TempDir::new();
+        let (source, color) = png_with_color(&dir);
+        let encoded = ImageProcessor::encode_png(&gradient(16, 16), PngCompression::Fast).unwrap();
+
+        let kept = write_metadata(encoded.clone(), Some(&source), ImageFormat::Png, &settings(serde_json::json!({ "preserve_metadata": true }))).unwrap();
+        let kept = MetadataProcessor::read_png_color(&dir.write("kept.png", &kept)).unwrap().unwrap();
+        assert_eq!(kept.gamma, color.gamma);
+        assert_eq!(kept.chromaticities, color.chromaticities);
+
+        let stripped = write_metadata(encoded, Some(&source), ImageFormat::Png, &settings(serde_json::json!({}))).unwrap();
+        assert!(MetadataProcessor::read_png_color(&dir.write("stripped.png", &stripped)).unwrap().is_none());
+        assert_eq!(image::open(dir.file("kept.png")).unwrap().dimensions(), (16, 16));
+    }
+
+    #[test]
+    fn png_gamma_becomes_an_icc_profile_in_jpeg() {
+        let dir = TempDir::new();
+        let (source, color) = png_with_color(&dir);
+        let data = write_metadata(jpeg(&gradient(16, 16)), Some(&source), ImageFormat::Jpeg, &settings(serde_json::json!({ "preserve_metadata": true }))).unwrap();
+
+        assert_eq!(MetadataProcessor::read_jpeg_icc(&data), Some(ColorProfiles::from_png_color(&color)));
+    }
+
+    /// A JPEG stored landscape (400x200) that displays as a portrait once its
+    /// orientation 6 tag is applied
+    fn rotated_portrait(dir: &TempDir) -> String {
+        let data = MetadataProcessor::set_jpeg_exif(jpeg(&gradient(400, 200)), &exif_with_orientation(6)).unwrap();
+        dir.write("portrait.jpg", &data)
+    }
+
+    #[test]
+    fn max_width_caps_the_displayed_width_of_rotated_photos() {
+        let dir = TempDir::new();
+        let img = ImageProcessor::load_image(&rotated_portrait(&dir)).unwrap();
+        assert_eq!(img.dimensions(), (200, 400));
+
+        let resized = resize_to_fit(&img, &settings(serde_json::json!({ "max_width": 100 }))).unwrap();
+        assert_eq!(resized.dimensions(), (100, 200));
+    }
+
+    #[test]
+    fn size_estimates_use_the_displayed_dimensions() {
+        let dir = TempDir::new();
+        let settings = settings(serde_json::json!({ "target_format": "jpeg", "max_width": 100 }));
+
+        assert_eq!(
+            estimate_bytes(&rotated_portrait(&dir), &settings).unwrap(),
+            ImageProcessor::estimate_size(100, 200, "jpeg", 90)
+        );
+    }
+
+    const QUADRANT_COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    /// 32x16 with a solid color per quadrant, left to right then top to bottom, so
+    /// JPEG compression can't blur which corner is which
+    fn quadrants() -> DynamicImage {
+        DynamicImage::ImageRgb8(image::RgbImage::from_fn(32, 16, |x, y| {
+            image::Rgb(QUADRANT_COLORS[(x / 16 + 2 * (y / 8)) as usize])
+        }))
+    }
+
+    /// Where stored pixel (x, y) of a `width` x `height` image displays under an
+    /// EXIF orientation, per the tag's definition
+    fn displayed_at(orientation: u16, (x, y): (u32, u32), (width, height): (u32, u32)) -> (u32, u32) {
+        let (right, bottom) = (width - 1 - x, height - 1 - y);
+        match orientation {
+            2 => (right, y),
+            3 => (right, bottom),
+            4 => (x, bottom),
+            5 => (y, x),
+            6 => (bottom, x),
+            7 => (bottom, right),
+            8 => (y, right),
+            _ => (x, y),
+        }
+    }
+
+    fn assert_color(img: &DynamicImage, (x, y): (u32, u32), expected: [u8; 3], context: &str) {
+        let actual = img.to_rgb8().get_pixel(x, y).0;
+        assert!(
+            actual.iter().zip(expected).all(|(&a, e)| a.abs_diff(e) <= 24),
+            "{}: pixel ({}, {}) is {:?}, expected {:?}", context, x, y, actual, expected
+        );
+    }
+
+    #[test]
+    fn every_orientation_is_applied_once_with_auto_orient() {
+        let dir = TempDir::new();
+        for orientation in 1..=8u16 {
+            for auto_orient in [true, false] {
+                let context = format!("orientation {} auto_orient {}", orientation, auto_orient);
+                let source = dir.write(
+                    &format!("source_{}.jpg", orientation),
+                    &MetadataProcessor::set_jpeg_exif(jpeg(&quadrants()), &exif_with_orientation(orientation)).unwrap(),
+                );
+                let options = LoadOptions { auto_orient, ..LoadOptions::default() };
+                let (img, _) = ImageProcessor::load_image_with_options(&source, &options).unwrap();
+
+                let applied = if auto_orient { orientation } else { 1 };
+                let expected_size = if applied >= 5 { (16, 32) } else { (32, 16) };
+                assert_eq!(img.dimensions(), expected_size, "{}", context);
+                for (quadrant, center) in [(4, 4), (27, 4), (4, 11), (27, 11)].into_iter().enumerate() {
+                    let at = displayed_at(applied, center, (32, 16));
+                    assert_color(&img, at, QUADRANT_COLORS[quadrant], &context);
+                }
+
+                let settings = settings(serde_json::json!({ "preserve_metadata": true, "auto_orient": auto_orient }));
+                let output = write_metadata(jpeg(&img), Some(&source), ImageFormat::Jpeg, &settings).unwrap();
+                let written = ImageProcessor::read_exif_orientation(&dir.write("output.jpg", &output)).unwrap();
+                assert_eq!(written, Some(if auto_orient { 1 } else { orientation as u32 }), "{}", context);
+            }
+        }
+    }
+
+    /// Orientation tag of a JPEG output produced from `source` with metadata preserved
+    fn written_orientation(dir: &TempDir, img: &DynamicImage, source: &str, auto_orient: bool) -> Option<u32> {
+        let settings = settings(serde_json::json!({ "preserve_metadata": true, "auto_orient": auto_orient }));
+        let output = write_metadata(jpeg(img), Some(source), ImageFormat::Jpeg, &settings).unwrap();
+        ImageProcessor::read_exif_orientation(&dir.write("oriented.jpg", &output)).unwrap()
+    }
+
+    #[test]
+    fn jpeg_sources_are_written_upright_after_rotating_the_pixels() {
+        let dir = TempDir::new();
+        let source = dir.write(
+            "source.jpg",
+            &MetadataProcessor::set_jpeg_exif(jpeg(&quadrants()), &exif_with_orientation(6)).unwrap(),
+        );
+        let img = ImageProcessor::load_image(&source).unwrap();
+        assert_eq!(img.dimensions(), (16, 32));
+        assert_eq!(written_orientation(&dir, &img, &source, true), Some(1));
+    }
+
+    #[test]
+    fn heic_exif_orientation_is_applied_once() {
+        // Without irot/imir the EXIF tag decides how a HEIC displays. Whichever side
+        // applies it, pixels or tag, the other must not apply it again.
+        let dir = TempDir::new();
+        let heic = ImageProcessor::encode_heic(&quadrants(), 90, Some(&exif_with_orientation(6))).unwrap();
+        let source = dir.write("source.heic", &heic);
+        assert_eq!(MetadataProcessor::read_exif_segment(&source).unwrap(), Some(exif_with_orientation(6)));
+
+        for auto_orient in [true, false] {
+            let options = LoadOptions { auto_orient, ..LoadOptions::default() };
+            let (img, _) = ImageProcessor::load_image_with_options(&source, &options).unwrap();
+            let context = format!("auto_orient {}", auto_orient);
+            let applied = if auto_orient { 6 } else { 1 };
+            for (quadrant, center) in [(4, 4), (27, 4), (4, 11), (27, 11)].into_iter().enumerate() {
+                assert_color(&img, displayed_at(applied, center, (32, 16)), QUADRANT_COLORS[quadrant], &context);
+            }
+            let expected = if auto_orient { 1 } else { 6 };
+            assert_eq!(written_orientation(&dir, &img, &source, auto_orient), Some(expected), "{}", context);
+        }
+    }
+
+    /// Check that `img` shows the quadrants as a viewer would after applying `orientation`
+    fn assert_displayed(img: &DynamicImage, orientation: u16, context: &str) {
+        let expected_size = if orientation >= 5 { (16, 32) } else { (32, 16) };
+        assert_eq!(img.dimensions(), expected_size, "{}", context);
+        for (quadrant, center) in [(4, 4), (27, 4), (4, 11), (27, 11)].into_iter().enumerate() {
+            assert_color(img, displayed_at(orientation, center, (32, 16)), QUADRANT_COLORS[quadrant], context);
+        }
+    }
+
+    /// What a viewer that honors the orientation marker shows: the stored pixels
+    /// turned by the marker's orientation
+    fn as_viewed(img: &DynamicImage, marker: u16) -> DynamicImage {
+        ImageProcessor::orient(img.clone(), marker as u32)
+    }
+
+    #[test]
+    fn jpeg_to_tiff_is_viewed_upright_exactly_once() {
+        let dir = TempDir::new();
+        for orientation in [6u16, 8] {
+            let source = dir.write(
+                &format!("source_{}.jpg", orientation),
+                &MetadataProcessor::set_jpeg_exif(jpeg(&quadrants()), &exif_with_orientation(orientation)).unwrap(),
+            );
+            for auto_orient in [true, false] {
+                let context = format!("orientation {} auto_orient {}", orientation, auto_orient);
+                let settings = settings(serde_json::json!({ "target_format": "tiff", "auto_orient": auto_orient }));
+                let options = LoadOptions { auto_orient, ..LoadOptions::default() };
+                let (img, _) = ImageProcessor::load_image_with_options(&source, &options).unwrap();
+
+                let marker = output_orientation(Some(&source), &settings).unwrap();
+                let data = encode_output(&img, ImageFormat::Tiff, &settings, marker).unwrap().data;
+                let mut decoder = tiff::decoder::Decoder::new(std::io::Cursor::new(&data)).unwrap();
+                let written = decoder.get_tag_u32(tiff::tags::Tag::Orientation).unwrap() as u16;
+                assert_eq!(written, if auto_orient { 1 } else { orientation }, "{}", context);
+
+                let stored = image::load_from_memory_with_format(&data, ImageFormat::Tiff).unwrap();
+                assert_displayed(&as_viewed(&stored, written), orientation, &context);
+            }
+        }
+    }
+
+    /// A HEIC of the quadrants whose EXIF (and no irot/imir) declares `orientation`
+    fn heic_source(dir: &TempDir, orientation: u16) -> String {
+        let heic = ImageProcessor::encode_heic(&quadrants(), 90, Some(&exif_with_orientation(orientation))).unwrap();
+        dir.write(&format!("source_{}.heic", orientation), &heic)
+    }
+
+    #[test]
+    fn heic_to_jpeg_is_viewed_upright_exactly_once() {
+        let dir = TempDir::new();
+        for orientation in [6u16, 8] {
+            let source = heic_source(&dir, orientation);
+            for auto_orient in [true, false] {
+                let context = format!("orientation {} auto_orient {}", orientation, auto_orient);
+                let options = LoadOptions { auto_orient, ..LoadOptions::default() };
+                let (img, _) = ImageProcessor::load_image_with_options(&source, &options).unwrap();
+
+                let written = written_orientation(&dir, &img, &source, auto_orient).unwrap() as u16;
+                assert_eq!(written, if auto_orient { 1 } else { orientation }, "{}", context);
+                assert_displayed(&as_viewed(&img, written), orientation, &context);
+            }
+        }
+    }
+
+    #[test]
+    fn heic_to_heic_normalization_is_viewed_upright_exactly_once() {
+        let dir = TempDir::new();
+        for orientation in [6u16, 8] {
+            let context = format!("orientation {}", orientation);
+            let source = heic_source(&dir, orientation);
+            let (declared, normalized) = OrientationNormalizer::normalize(&source).unwrap();
+            assert_eq!(declared, orientation as u32);
+
+            let output = dir.write("normalized.heic", &normalized.expect("a re-encoded HEIC").data);
+            let exif = MetadataProcessor::read_exif_segment(&output).unwrap().unwrap();
+            assert_eq!(exif, exif_with_orientation(1), "{}", context);
+            // Nothing left for a viewer to apply, so the pixels alone must be upright
+            let img = ImageProcessor::load_image(&output).unwrap();
+            assert_displayed(&img, orientation, &context);
+        }
+    }
 }
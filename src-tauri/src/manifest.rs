@@ -0,0 +1,195 @@
+use crate::file_ops::FileOps;
+use crate::image_processor::ImageProcessor;
+use crate::jobs::unix_time;
+use crate::{BatchConversionItem, ConversionSettings};
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Manifests of fully completed jobs are deleted after this long
+const COMPLETED_RETENTION: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Minimum gap between manifest rewrites while a batch is running
+const SAVE_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum ItemStatus {
+    Pending,
+    Completed,
+    Failed,
+    /// Not a readable image format, or an output keep_smaller skipped; never retried
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestItem {
+    #[serde(flatten)]
+    item: BatchConversionItem,
+    status: ItemStatus,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestData {
+    job_id: String,
+    settings: ConversionSettings,
+    items: Vec<ManifestItem>,
+    created_at: u64,
+    updated_at: u64,
+}
+
+/// On-disk record of a batch job, updated as items finish so an interrupted
+/// run can be resumed. Items only become "completed" once their output has
+/// been atomically moved into place.
+pub struct BatchManifest {
+    path: PathBuf,
+    data: Mutex<ManifestData>,
+    last_save: Mutex<Option<Instant>>,
+}
+
+impl BatchManifest {
+    fn manifest_path(dir: &Path, job_id: &str) -> Result<PathBuf> {
+        if job_id.is_empty() || job_id.contains(['/', '\\', '.']) {
+            anyhow::bail!("Invalid job id '{}'", job_id);
+        }
+        Ok(dir.join(format!("{}.json", job_id)))
+    }
+
+    /// Start a manifest for a new job with every item pending
+    pub fn create(
+        dir: &Path,
+        job_id: &str,
+        items: &[BatchConversionItem],
+        settings: &ConversionSettings,
+    ) -> Result<Self> {
+        std::fs::create_dir_all(dir).context("Failed to create manifest directory")?;
+
+        let now = unix_time();
+        let manifest = BatchManifest {
+            path: Self::manifest_path(dir, job_id)?,
+            data: Mutex::new(ManifestData {
+                job_id: job_id.to_string(),
+                settings: settings.clone(),
+                items: items.iter()
+                    .map(|item| ManifestItem {
+                        item: item.clone(),
+                        status: ItemStatus::Pending,
+                        error: None,
+                    })
+                    .collect(),
+                created_at: now,
+                updated_at: now,
+            }),
+            last_save: Mutex::new(None),
+        };
+        manifest.save()?;
+
+        Ok(manifest)
+    }
+
+    pub fn load(dir: &Path, job_id: &str) -> Result<Self> {
+        let path = Self::manifest_path(dir, job_id)?;
+        let contents = std::fs::read(&path)
+            .with_context(|| format!("No manifest found for batch job '{}'", job_id))?;
+        let data: ManifestData = serde_json::from_slice(&contents)
+            .context("Failed to parse batch manifest")?;
+
+        Ok(BatchManifest {
+            path,
+            data: Mutex::new(data),
+            last_save: Mutex::new(None),
+        })
+    }
+
+    pub fn item_count(&self) -> usize {
+        self.data.lock().unwrap_or_else(|e| e.into_inner()).items.len()
+    }
+
+    pub fn settings(&self) -> ConversionSettings {
+        self.data.lock().unwrap_or_else(|e| e.into_inner()).settings.clone()
+    }
+
+    /// Items that still need converting, with their index in the manifest.
+    /// Completed items are re-run when their output is missing or empty, or
+    /// (with `decode_headers`) when the output's header can't be parsed.
+    pub fn unfinished_items(&self, decode_headers: bool) -> Vec<(usize, BatchConversionItem)> {
+        let data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        data.items.iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                match entry.status {
+                    ItemStatus::Skipped => return false,
+                    ItemStatus::Completed => {}
+                    _ => return true,
+                }
+                let output = &entry.item.output_path;
+                let has_data = std::fs::metadata(output).map(|m| m.len() > 0).unwrap_or(false);
+                !has_data || (decode_headers && ImageProcessor::peek_dimensions(output).is_err())
+            })
+            .map(|(index, entry)| (index, entry.item.clone()))
+            .collect()
+    }
+
+    /// Record the outcome of one item. Saves are throttled; call `flush` at the end.
+    pub fn mark(&self, index: usize, status: ItemStatus, error: Option<String>) {
+        {
+            let mut data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(entry) = data.items.get_mut(index) {
+                entry.status = status;
+                entry.error = error;
+            }
+        }
+
+        let due = {
+            let mut last_save = self.last_save.lock().unwrap_or_else(|e| e.into_inner());
+            let due = !matches!(*last_save, Some(at) if at.elapsed() < SAVE_INTERVAL);
+            if due {
+                *last_save = Some(Instant::now());
+            }
+            due
+        };
+        if due {
+            self.save().ok();
+        }
+    }
+
+    pub fn flush(&self) -> Result<()> {
+        self.save()
+    }
+
+    fn save(&self) -> Result<()> {
+        let mut data = self.data.lock().unwrap_or_else(|e| e.into_inner());
+        data.updated_at = unix_time();
+        let contents = serde_json::to_vec(&*data).context("Failed to serialize batch manifest")?;
+        FileOps::write_atomic(&self.path, &contents)
+    }
+
+    /// Delete manifests of fully completed jobs older than the retention period
+    pub fn prune(dir: &Path) {
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        let cutoff = unix_time().saturating_sub(COMPLETED_RETENTION.as_secs());
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().and_then(|s| s.to_str()) != Some("json") {
+                continue;
+            }
+            let Ok(contents) = std::fs::read(&path) else {
+                continue;
+            };
+            let Ok(data) = serde_json::from_slice::<ManifestData>(&contents) else {
+                continue;
+            };
+
+            let completed = data.items.iter()
+                .all(|entry| matches!(entry.status, ItemStatus::Completed | ItemStatus::Skipped));
+            if completed && data.updated_at < cutoff {
+                std::fs::remove_file(&path).ok();
+            }
+        }
+    }
+}
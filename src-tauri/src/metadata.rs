@@ -0,0 +1,1114 @@
+use crate::heif_limits::HeifLimits;
+use crate::image_processor::ImageProcessor;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use std::io::{Read, Write};
+
+const MARKER_SOI: u8 = 0xD8;
+const MARKER_SOS: u8 = 0xDA;
+const MARKER_EOI: u8 = 0xD9;
+const MARKER_COM: u8 = 0xFE;
+const MARKER_APP1: u8 = 0xE1;
+const MARKER_APP2: u8 = 0xE2;
+
+const ICC_HEADER: &[u8] = b"ICC_PROFILE\0";
+/// Max ICC bytes per APP2 segment (65535 - length field - header - sequence bytes)
+const ICC_CHUNK_SIZE: usize = 65519;
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_ARTIST: u16 = 0x013B;
+const TAG_COPYRIGHT: u16 = 0x8298;
+const TAG_EXIF_IFD: u16 = 0x8769;
+const TAG_MAKER_NOTE: u16 = 0x927C;
+/// Apple maker notes start with this, a 2-byte version and a byte order mark; their
+/// IFD follows at offset 14 and offsets are relative to the start of the note
+const APPLE_MAKER_NOTE: &[u8] = b"Apple iOS\0";
+const APPLE_MAKER_NOTE_IFD: usize = 14;
+/// Apple maker note tag holding a Live Photo's content identifier
+const APPLE_CONTENT_IDENTIFIER: u16 = 0x0011;
+/// Little-endian TIFF header followed by an empty IFD0
+const EMPTY_TIFF: &[u8] = b"II*\0\x08\0\0\0\0\0\0\0\0\0";
+
+/// What happened to a category of source metadata in the output
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MetadataStatus {
+    #[default]
+    Absent,
+    Removed,
+    Kept,
+}
+
+impl MetadataStatus {
+    pub fn from_presence(present: bool, kept: bool) -> Self {
+        match (present, kept) {
+            (false, _) => MetadataStatus::Absent,
+            (true, true) => MetadataStatus::Kept,
+            (true, false) => MetadataStatus::Removed,
+        }
+    }
+}
+
+/// Privacy-relevant metadata found in a source file
+#[derive(Default)]
+pub struct MetadataPresence {
+    pub gps: bool,
+    pub capture_time: Option<String>,
+    pub camera_serial: bool,
+    pub thumbnail: bool,
+    pub xmp: bool,
+}
+
+/// Per-file account of which metadata was stripped or carried over
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct MetadataReport {
+    pub gps: MetadataStatus,
+    pub capture_time: MetadataStatus,
+    /// DateTimeOriginal (or DateTime) as found in the source
+    pub capture_time_value: Option<String>,
+    pub camera_serial: MetadataStatus,
+    pub thumbnail: MetadataStatus,
+    pub xmp: MetadataStatus,
+    pub icc_profile: MetadataStatus,
+    /// PNG gAMA/cHRM chunks
+    pub gamma_chromaticity: MetadataStatus,
+}
+
+/// Color description of a PNG without an ICC profile, as stored in its gAMA and
+/// cHRM chunks (both scaled by 100000)
+#[derive(Clone, Debug)]
+pub struct PngColorChunks {
+    /// Encoding gamma, e.g. 45455 for 1/2.2
+    pub gamma: Option<u32>,
+    /// White point then red, green and blue primaries as x, y pairs
+    pub chromaticities: Option<[u32; 8]>,
+}
+
+/// Camera and exposure details for provenance records. GPS is only flagged,
+/// never copied, so a record can't leak a location the output itself dropped.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct ExifSummary {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub lens_model: Option<String>,
+    pub capture_time: Option<String>,
+    pub exposure_time: Option<String>,
+    pub f_number: Option<String>,
+    pub iso: Option<u32>,
+    pub focal_length: Option<String>,
+    pub orientation: Option<u32>,
+    pub has_gps: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MirrorAxis {
+    Vertical,
+    Horizontal,
+}
+
+/// Geometric transformation property a HEIF declares for its primary image.
+/// libheif applies these during decode unless told to ignore them.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum HeifTransformation {
+    /// `irot`: counter-clockwise rotation
+    Rotate { degrees: u16 },
+    /// `imir`: mirror about the vertical or horizontal axis
+    Mirror { axis: MirrorAxis },
+    /// `clap`: clean aperture crop
+    CleanAperture,
+}
+
+/// A single marker segment from a JPEG header (everything before SOS)
+pub struct JpegSegment {
+    pub marker: u8,
+    pub data: Vec<u8>,
+}
+
+pub struct MetadataProcessor;
+
+impl MetadataProcessor {
+    /// Read the header segments of a JPEG file. Returns an empty list for non-JPEG data.
+    pub fn read_jpeg_segments(data: &[u8]) -> Vec<JpegSegment> {
+        let mut segments = Vec::new();
+        if data.len() < 4 || data[0] != 0xFF || data[1] != MARKER_SOI {
+            return segments;
+        }
+
+        let mut pos = 2;
+        while pos + 4 <= data.len() {
+            if data[pos] != 0xFF {
+                break;
+            }
+            let marker = data[pos + 1];
+            // Fill bytes may precede a marker
+            if marker == 0xFF {
+                pos += 1;
+                continue;
+            }
+            if marker == MARKER_SOS || marker == MARKER_EOI {
+                break;
+            }
+
+            let length = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+            let end = pos + 2 + length;
+            if length < 2 || end > data.len() {
+                break;
+            }
+
+            segments.push(JpegSegment {
+                marker,
+                data: data[pos + 4..end].to_vec(),
+            });
+            pos = end;
+        }
+
+        segments
+    }
+
+    /// Read the text of the first COM marker in a JPEG file, if any
+    pub fn read_jpeg_comment(path: &str) -> Result<Option<String>> {
+        let data = std::fs::read(path).context("Failed to read file")?;
+
+        Ok(Self::read_jpeg_segments(&data)
+            .into_iter()
+            .find(|segment| segment.marker == MARKER_COM)
+            .map(|segment| {
+                String::from_utf8_lossy(&segment.data)
+                    .trim_end_matches('\0')
+                    .to_string()
+            }))
+    }
+
+    /// Insert a segment into encoded JPEG bytes, after SOI and any leading APPn segments
+    /// (JFIF requires APP0 to come first)
+    pub fn insert_jpeg_segment(jpeg: Vec<u8>, marker: u8, payload: &[u8]) -> Result<Vec<u8>> {
+        if jpeg.len() < 2 || jpeg[0] != 0xFF || jpeg[1] != MARKER_SOI {
+            anyhow::bail!("Not a JPEG stream");
+        }
+        if payload.len() > u16::MAX as usize - 2 {
+            anyhow::bail!("JPEG segment payload too large");
+        }
+
+        let mut insert_at = 2;
+        while insert_at + 4 <= jpeg.len()
+            && jpeg[insert_at] == 0xFF
+            && (0xE0..=0xEF).contains(&jpeg[insert_at + 1])
+        {
+            let length = u16::from_be_bytes([jpeg[insert_at + 2], jpeg[insert_at + 3]]) as usize;
+            insert_at += 2 + length;
+        }
+        let insert_at = insert_at.min(jpeg.len());
+
+        let mut output = Vec::with_capacity(jpeg.len() + payload.len() + 4);
+        output.extend_from_slice(&jpeg[..insert_at]);
+        output.extend_from_slice(&[0xFF, marker]);
+        output.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+        output.extend_from_slice(payload);
+        output.extend_from_slice(&jpeg[insert_at..]);
+
+        Ok(output)
+    }
+
+    /// Write a COM marker with the given text into encoded JPEG bytes
+    pub fn set_jpeg_comment(jpeg: Vec<u8>, comment: &str) -> Result<Vec<u8>> {
+        Self::insert_jpeg_segment(jpeg, MARKER_COM, comment.as_bytes())
+    }
+
+    /// Read the EXIF payload of a JPEG or HEIC file in APP1 form (including the "Exif\0\0" header)
+    pub fn read_exif_segment(path: &str) -> Result<Option<Vec<u8>>> {
+        if ImageProcessor::is_heif_format(&ImageProcessor::get_format(path)?) {
+            return Self::read_heic_exif(path);
+        }
+
+        let data = std::fs::read(path).context("Failed to read file")?;
+
+        Ok(Self::read_jpeg_segments(&data)
+            .into_iter()
+            .find(|segment| segment.marker == MARKER_APP1 && segment.data.starts_with(EXIF_HEADER))
+            .map(|segment| segment.data))
+    }
+
+    /// HEIF stores EXIF as a metadata item prefixed with the offset to the TIFF header
+    fn read_heic_exif(path: &str) -> Result<Option<Vec<u8>>> {
+        HeifLimits::check_file(path)?;
+        let ctx = libheif_rs::HeifContext::read_from_file(path)
+            .context("Failed to read HEIC file")?;
+        let handle = ctx.primary_image_handle()
+            .context("Failed to get primary image handle")?;
+
+        let count = handle.number_of_metadata_blocks(b"Exif").max(0) as usize;
+        let mut ids = vec![0; count];
+        handle.metadata_block_ids(&mut ids, b"Exif");
+
+        let Some(block) = ids.first().and_then(|&id| handle.metadata(id).ok()) else {
+            return Ok(None);
+        };
+        let Some(offset) = block.get(0..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize) else {
+            return Ok(None);
+        };
+        let Some(tiff) = block.get(4 + offset..).filter(|tiff| TiffReader::new(tiff).is_some()) else {
+            return Ok(None);
+        };
+
+        let mut exif = EXIF_HEADER.to_vec();
+        exif.extend_from_slice(tiff);
+        Ok(Some(exif))
+    }
+
+    /// Content identifier an iPhone writes into the still of a Live Photo (Apple maker
+    /// note tag 0x11); the paired MOV carries the same value. None for other images.
+    pub fn live_photo_identifier(path: &str) -> Result<Option<String>> {
+        let Some(exif) = Self::read_exif_segment(path)? else {
+            return Ok(None);
+        };
+        let Some(tiff) = TiffReader::new(&exif[EXIF_HEADER.len()..]) else {
+            return Ok(None);
+        };
+
+        let maker_note = tiff.first_ifd()
+            .and_then(|ifd| tiff.find_entry(ifd, TAG_EXIF_IFD))
+            .and_then(|entry| tiff.values(entry).first().copied())
+            .and_then(|exif_ifd| tiff.find_entry(exif_ifd as usize, TAG_MAKER_NOTE))
+            .and_then(|entry| {
+                let (count, offset) = (tiff.u32(entry + 4)? as usize, tiff.u32(entry + 8)? as usize);
+                tiff.data.get(offset..offset.checked_add(count)?)
+            });
+        let Some(note) = maker_note.filter(|note| note.starts_with(APPLE_MAKER_NOTE)) else {
+            return Ok(None);
+        };
+
+        let apple = TiffReader { data: note, little_endian: note.get(12..14) == Some(b"II") };
+        Ok(apple.find_entry(APPLE_MAKER_NOTE_IFD, APPLE_CONTENT_IDENTIFIER)
+            .and_then(|entry| apple.ascii(entry)))
+    }
+
+    /// Transformation properties associated with a HEIF's primary item, in the
+    /// order they are declared (which is the order libheif applies them)
+    pub fn heif_transformations(path: &str) -> Result<Vec<HeifTransformation>> {
+        let data = std::fs::read(path).context("Failed to read HEIC file")?;
+
+        let Some(meta) = find_box(&data, b"meta") else {
+            return Ok(Vec::new());
+        };
+        // meta is a full box: skip version and flags
+        let meta = meta.get(4..).unwrap_or_default();
+        let (Some(pitm), Some(iprp)) = (find_box(meta, b"pitm"), find_box(meta, b"iprp")) else {
+            return Ok(Vec::new());
+        };
+        let (Some(ipco), Some(ipma)) = (find_box(iprp, b"ipco"), find_box(iprp, b"ipma")) else {
+            return Ok(Vec::new());
+        };
+
+        let primary = match pitm.first() {
+            Some(0) => read_be(pitm, 4, 2),
+            _ => read_be(pitm, 4, 4),
+        };
+        let Some(primary) = primary else {
+            return Ok(Vec::new());
+        };
+
+        let properties: Vec<(&[u8; 4], &[u8])> = iter_boxes(ipco).collect();
+        let indices = ipma_indices(ipma, primary).unwrap_or_default();
+
+        Ok(indices.into_iter()
+            .filter_map(|index| properties.get(index.checked_sub(1)?))
+            .filter_map(|(kind, body)| match *kind {
+                b"irot" => Some(HeifTransformation::Rotate { degrees: (*body.first()? & 0x03) as u16 * 90 }),
+                b"imir" => Some(HeifTransformation::Mirror {
+                    axis: if body.first()? & 0x01 == 0 { MirrorAxis::Vertical } else { MirrorAxis::Horizontal },
+                }),
+                b"clap" => Some(HeifTransformation::CleanAperture),
+                _ => None,
+            })
+            .collect())
+    }
+
+    /// EXIF orientation value equivalent to a HEIF's irot/imir sequence; clap doesn't
+    /// affect orientation
+    pub fn heif_orientation(transformations: &[HeifTransformation]) -> u32 {
+        // Display = clockwise quarter turns after an optional left-right flip
+        let (mut flipped, mut turns) = (false, 0u16);
+        for transformation in transformations {
+            match transformation {
+                HeifTransformation::Rotate { degrees } => turns = (turns + 4 - degrees / 90 % 4) % 4,
+                // Flipping after a turn equals the opposite turn after a flip
+                HeifTransformation::Mirror { axis: MirrorAxis::Vertical } => {
+                    flipped = !flipped;
+                    turns = (4 - turns) % 4;
+                }
+                HeifTransformation::Mirror { axis: MirrorAxis::Horizontal } => {
+                    flipped = !flipped;
+                    turns = (6 - turns) % 4;
+                }
+                HeifTransformation::CleanAperture => {}
+            }
+        }
+        match (flipped, turns) {
+            (false, 0) => 1,
+            (true, 0) => 2,
+            (false, 2) => 3,
+            (true, 2) => 4,
+            (true, 3) => 5,
+            (false, 1) => 6,
+            (true, 1) => 7,
+            _ => 8,
+        }
+    }
+
+    /// Write an APP1 EXIF payload into encoded JPEG bytes
+    pub fn set_jpeg_exif(jpeg: Vec<u8>, exif: &[u8]) -> Result<Vec<u8>> {
+        Self::insert_jpeg_segment(jpeg, MARKER_APP1, exif)
+    }
+
+    /// Set the IFD0 Artist and Copyright tags of an APP1 EXIF payload, starting from an
+    /// empty one when there is none. The rewritten IFD0 and its strings are appended
+    /// after the existing data, so every offset into the original stays valid.
+    pub fn set_exif_attribution(exif: Option<Vec<u8>>, author: Option<&str>, copyright: Option<&str>) -> Vec<u8> {
+        let mut tiff = exif.as_deref()
+            .and_then(|exif| exif.strip_prefix(EXIF_HEADER))
+            .filter(|tiff| TiffReader::new(tiff).and_then(|reader| reader.first_ifd()).is_some())
+            .unwrap_or(EMPTY_TIFF)
+            .to_vec();
+        if tiff.len() % 2 == 1 {
+            tiff.push(0);
+        }
+
+        let (little_endian, entries, next_ifd) = {
+            let reader = TiffReader::new(&tiff).expect("checked above");
+            let ifd = reader.first_ifd().expect("checked above");
+            let count = reader.u16(ifd).unwrap_or(0) as usize;
+            let entries: Vec<Vec<u8>> = (0..count)
+                .filter_map(|i| tiff.get(ifd + 2 + i * 12..ifd + 14 + i * 12))
+                .map(<[u8]>::to_vec)
+                .collect();
+            let next_ifd = reader.u32(ifd + 2 + entries.len() * 12).unwrap_or(0);
+            (reader.little_endian, entries, next_ifd)
+        };
+        let u16_bytes = |value: u16| if little_endian { value.to_le_bytes() } else { value.to_be_bytes() };
+        let u32_bytes = |value: u32| if little_endian { value.to_le_bytes() } else { value.to_be_bytes() };
+        let tag_of = |entry: &[u8]| {
+            let bytes = [entry[0], entry[1]];
+            if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) }
+        };
+
+        let texts: Vec<(u16, Vec<u8>)> = [(TAG_ARTIST, author), (TAG_COPYRIGHT, copyright)]
+            .into_iter()
+            .filter_map(|(tag, text)| Some((tag, [text?.as_bytes(), b"\0"].concat())))
+            .collect();
+        let mut entries: Vec<Vec<u8>> = entries.into_iter()
+            .filter(|entry| texts.iter().all(|(tag, _)| tag_of(entry) != *tag))
+            .collect();
+
+        let ifd_offset = tiff.len();
+        let mut string_offset = ifd_offset + 2 + (entries.len() + texts.len()) * 12 + 4;
+        let mut strings = Vec::new();
+        for (tag, text) in &texts {
+            let mut entry = Vec::with_capacity(12);
+            entry.extend_from_slice(&u16_bytes(*tag));
+            entry.extend_from_slice(&u16_bytes(2)); // ASCII
+            entry.extend_from_slice(&u32_bytes(text.len() as u32));
+            if text.len() <= 4 {
+                let mut inline = [0u8; 4];
+                inline[..text.len()].copy_from_slice(text);
+                entry.extend_from_slice(&inline);
+            } else {
+                entry.extend_from_slice(&u32_bytes(string_offset as u32));
+                strings.extend_from_slice(text);
+                string_offset += text.len();
+            }
+            entries.push(entry);
+        }
+        entries.sort_by_key(|entry| tag_of(entry));
+
+        tiff.extend_from_slice(&u16_bytes(entries.len() as u16));
+        for entry in &entries {
+            tiff.extend_from_slice(entry);
+        }
+        tiff.extend_from_slice(&u32_bytes(next_ifd));
+        tiff.extend_from_slice(&strings);
+        tiff[4..8].copy_from_slice(&u32_bytes(ifd_offset as u32));
+
+        [EXIF_HEADER, &tiff].concat()
+    }
+
+    /// XMP packet carrying dc:creator and dc:rights
+    pub fn attribution_xmp(author: Option<&str>, copyright: Option<&str>) -> String {
+        let escape = |text: &str| text
+            .replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;");
+        let mut properties = String::new();
+        if let Some(author) = author {
+            properties.push_str(&format!("<dc:creator><rdf:Seq><rdf:li>{}</rdf:li></rdf:Seq></dc:creator>", escape(author)));
+        }
+        if let Some(copyright) = copyright {
+            properties.push_str(&format!(
+                "<dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:rights>",
+                escape(copyright),
+            ));
+        }
+        format!(
+            "<?xpacket begin=\"\u{FEFF}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\
+             <x:xmpmeta xmlns:x=\"adobe:ns:meta/\"><rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\
+             <rdf:Description rdf:about=\"\" xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</rdf:Description>\
+             </rdf:RDF></x:xmpmeta><?xpacket end=\"w\"?>",
+            properties,
+        )
+    }
+
+    /// Write an XMP packet as an APP1 segment into encoded JPEG bytes
+    pub fn set_jpeg_xmp(jpeg: Vec<u8>, xmp: &str) -> Result<Vec<u8>> {
+        Self::insert_jpeg_segment(jpeg, MARKER_APP1, &[XMP_HEADER, xmp.as_bytes()].concat())
+    }
+
+    /// Add Author and Copyright text chunks plus an XMP packet to encoded PNG bytes.
+    /// Text that fits Latin-1 goes in tEXt; anything else needs iTXt.
+    pub fn set_png_attribution(mut png: Vec<u8>, author: Option<&str>, copyright: Option<&str>) -> Result<Vec<u8>> {
+        let itxt = |keyword: &str, text: &str| [keyword.as_bytes(), b"\0\0\0\0\0", text.as_bytes()].concat();
+
+        for (keyword, text) in [("Author", author), ("Copyright", copyright)] {
+            let Some(text) = text else {
+                continue;
+            };
+            let latin1: Option<Vec<u8>> = text.chars().map(|c| u8::try_from(c).ok()).collect();
+            png = match latin1 {
+                Some(latin1) => Self::insert_png_chunk(png, b"tEXt", &[keyword.as_bytes(), b"\0", &latin1].concat())?,
+                None => Self::insert_png_chunk(png, b"iTXt", &itxt(keyword, text))?,
+            };
+        }
+        Self::insert_png_chunk(png, b"iTXt", &itxt("XML:com.adobe.xmp", &Self::attribution_xmp(author, copyright)))
+    }
+
+    /// Reassemble an ICC profile split across APP2 segments
+    pub fn read_jpeg_icc(data: &[u8]) -> Option<Vec<u8>> {
+        let mut chunks: Vec<(u8, Vec<u8>)> = Self::read_jpeg_segments(data)
+            .into_iter()
+            .filter(|segment| segment.marker == MARKER_APP2
+                && segment.data.len() > ICC_HEADER.len() + 2
+                && segment.data.starts_with(ICC_HEADER))
+            .map(|segment| (segment.data[ICC_HEADER.len()], segment.data[ICC_HEADER.len() + 2..].to_vec()))
+            .collect();
+
+        if chunks.is_empty() {
+            return None;
+        }
+        chunks.sort_by_key(|(sequence, _)| *sequence);
+
+        Some(chunks.into_iter().flat_map(|(_, chunk)| chunk).collect())
+    }
+
+    /// Embed an ICC profile into encoded JPEG bytes as one or more APP2 segments
+    pub fn set_jpeg_icc(mut jpeg: Vec<u8>, icc: &[u8]) -> Result<Vec<u8>> {
+        let chunks: Vec<&[u8]> = icc.chunks(ICC_CHUNK_SIZE).collect();
+        if chunks.len() > 255 {
+            anyhow::bail!("ICC profile too large to embed in JPEG");
+        }
+
+        // Insert in reverse so the segments end up in sequence order
+        for (index, chunk) in chunks.iter().enumerate().rev() {
+            let mut payload = Vec::with_capacity(chunk.len() + ICC_HEADER.len() + 2);
+            payload.extend_from_slice(ICC_HEADER);
+            payload.push(index as u8 + 1);
+            payload.push(chunks.len() as u8);
+            payload.extend_from_slice(chunk);
+            jpeg = Self::insert_jpeg_segment(jpeg, MARKER_APP2, &payload)?;
+        }
+
+        Ok(jpeg)
+    }
+
+    /// Read the chunks of a PNG stream as (type, data) pairs. Returns an empty list for non-PNG data.
+    pub fn read_png_chunks(data: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        let mut chunks = Vec::new();
+        if !data.starts_with(PNG_SIGNATURE) {
+            return chunks;
+        }
+
+        let mut pos = PNG_SIGNATURE.len();
+        while pos + 12 <= data.len() {
+            let length = u32::from_be_bytes([data[pos], data[pos + 1], data[pos + 2], data[pos + 3]]) as usize;
+            let end = pos + 12 + length;
+            if end > data.len() {
+                break;
+            }
+
+            let chunk_type = [data[pos + 4], data[pos + 5], data[pos + 6], data[pos + 7]];
+            chunks.push((chunk_type, data[pos + 8..pos + 8 + length].to_vec()));
+            if &chunk_type == b"IEND" {
+                break;
+            }
+            pos = end;
+        }
+
+        chunks
+    }
+
+    /// Insert a chunk into encoded PNG bytes right after IHDR
+    pub fn insert_png_chunk(png: Vec<u8>, chunk_type: &[u8; 4], payload: &[u8]) -> Result<Vec<u8>> {
+        if !png.starts_with(PNG_SIGNATURE) || png.len() < PNG_SIGNATURE.len() + 8 {
+            anyhow::bail!("Not a PNG stream");
+        }
+
+        let ihdr_length = u32::from_be_bytes([png[8], png[9], png[10], png[11]]) as usize;
+        let insert_at = (PNG_SIGNATURE.len() + 12 + ihdr_length).min(png.len());
+
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(chunk_type);
+        crc.update(payload);
+
+        let mut output = Vec::with_capacity(png.len() + payload.len() + 12);
+        output.extend_from_slice(&png[..insert_at]);
+        output.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        output.extend_from_slice(chunk_type);
+        output.extend_from_slice(payload);
+        output.extend_from_slice(&crc.finalize().to_be_bytes());
+        output.extend_from_slice(&png[insert_at..]);
+
+        Ok(output)
+    }
+
+    /// Read and inflate the iCCP profile of a PNG stream
+    pub fn read_png_icc(data: &[u8]) -> Option<Vec<u8>> {
+        let (_, chunk) = Self::read_png_chunks(data)
+            .into_iter()
+            .find(|(chunk_type, _)| chunk_type == b"iCCP")?;
+
+        // Profile name, NUL, compression method, zlib data
+        let name_end = chunk.iter().position(|&b| b == 0)?;
+        let compressed = chunk.get(name_end + 2..)?;
+
+        let mut icc = Vec::new();
+        ZlibDecoder::new(compressed).read_to_end(&mut icc).ok()?;
+        Some(icc)
+    }
+
+    /// Read the gAMA and cHRM chunks of a PNG file. None for other formats and for
+    /// PNGs with neither chunk.
+    pub fn read_png_color(path: &str) -> Result<Option<PngColorChunks>> {
+        if ImageProcessor::get_format(path)? != "png" {
+            return Ok(None);
+        }
+        let data = std::fs::read(path).context("Failed to read file")?;
+        let be_u32 = |bytes: &[u8]| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        let mut color = PngColorChunks { gamma: None, chromaticities: None };
+        for (chunk_type, chunk) in Self::read_png_chunks(&data) {
+            match &chunk_type {
+                // A zero gamma is invalid and would divide by zero downstream
+                b"gAMA" if chunk.len() == 4 && be_u32(&chunk) > 0 => color.gamma = Some(be_u32(&chunk)),
+                b"cHRM" if chunk.len() == 32 => {
+                    let mut values = [0u32; 8];
+                    for (value, bytes) in values.iter_mut().zip(chunk.chunks_exact(4)) {
+                        *value = be_u32(bytes);
+                    }
+                    color.chromaticities = Some(values);
+                }
+                _ => {}
+            }
+        }
+        Ok((color.gamma.is_some() || color.chromaticities.is_some()).then_some(color))
+    }
+
+    /// Add gAMA and cHRM chunks to encoded PNG bytes
+    pub fn set_png_color(mut png: Vec<u8>, color: &PngColorChunks) -> Result<Vec<u8>> {
+        if let Some(chromaticities) = &color.chromaticities {
+            let payload: Vec<u8> = chromaticities.iter().flat_map(|value| value.to_be_bytes()).collect();
+            png = Self::insert_png_chunk(png, b"cHRM", &payload)?;
+        }
+        if let Some(gamma) = color.gamma {
+            png = Self::insert_png_chunk(png, b"gAMA", &gamma.to_be_bytes())?;
+        }
+        Ok(png)
+    }
+
+    /// Embed an ICC profile into encoded PNG bytes as an iCCP chunk
+    pub fn set_png_icc(png: Vec<u8>, icc: &[u8], name: &str) -> Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(icc).context("Failed to compress ICC profile")?;
+        let compressed = encoder.finish().context("Failed to compress ICC profile")?;
+
+        // Keywords are limited to 1-79 Latin-1 characters
+        let name: String = name.chars().filter(|c| c.is_ascii_graphic() || *c == ' ').take(79).collect();
+        let name = if name.is_empty() { "ICC profile".to_string() } else { name };
+
+        let mut payload = Vec::with_capacity(name.len() + 2 + compressed.len());
+        payload.extend_from_slice(name.as_bytes());
+        payload.push(0);
+        payload.push(0);
+        payload.extend_from_slice(&compressed);
+
+        Self::insert_png_chunk(png, b"iCCP", &payload)
+    }
+
+    /// Inspect a source file for privacy-relevant metadata
+    pub fn inspect(path: &str) -> Result<MetadataPresence> {
+        let data = std::fs::read(path).context("Failed to read file")?;
+        let mut presence = MetadataPresence::default();
+
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)) {
+            presence.gps = exif.fields().any(|field| field.tag.context() == exif::Context::Gps);
+            presence.capture_time = [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+                .into_iter()
+                .find_map(|tag| exif.get_field(tag, exif::In::PRIMARY))
+                .map(|field| field.display_value().to_string());
+            presence.camera_serial = exif.get_field(exif::Tag::BodySerialNumber, exif::In::PRIMARY).is_some();
+            presence.thumbnail = exif.get_field(exif::Tag::JPEGInterchangeFormat, exif::In::THUMBNAIL).is_some();
+        }
+
+        presence.xmp = Self::read_jpeg_segments(&data)
+            .iter()
+            .any(|segment| segment.marker == MARKER_APP1 && segment.data.starts_with(XMP_HEADER))
+            || Self::read_png_chunks(&data)
+                .iter()
+                .any(|(chunk_type, chunk)| chunk_type == b"iTXt" && chunk.starts_with(b"XML:com.adobe.xmp"))
+            || data.windows(10).any(|window| window == b"<x:xmpmeta");
+
+        Ok(presence)
+    }
+
+    /// The JPEG thumbnail stored in the EXIF IFD1 of `data`, if there is one
+    pub fn exif_thumbnail(data: &[u8]) -> Option<Vec<u8>> {
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(data)).ok()?;
+        let field = |tag| exif.get_field(tag, exif::In::THUMBNAIL).and_then(|field| field.value.get_uint(0));
+        // The offset is relative to the TIFF header, which is where buf() starts
+        let offset = field(exif::Tag::JPEGInterchangeFormat)? as usize;
+        let length = field(exif::Tag::JPEGInterchangeFormatLength)? as usize;
+        exif.buf().get(offset..offset.checked_add(length)?).map(|thumbnail| thumbnail.to_vec())
+    }
+
+    /// Summarize the EXIF of a JPEG, HEIC or TIFF-based file, if it has any
+    pub fn exif_summary(path: &str) -> Result<Option<ExifSummary>> {
+        let data = std::fs::read(path).context("Failed to read file")?;
+        let Ok(exif) = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)) else {
+            return Ok(None);
+        };
+
+        let field = |tag| exif.get_field(tag, exif::In::PRIMARY);
+        let text = |tag| match field(tag).map(|f| &f.value) {
+            Some(exif::Value::Ascii(values)) => values.first()
+                .map(|v| String::from_utf8_lossy(v).trim_end_matches('\0').trim().to_string())
+                .filter(|v| !v.is_empty()),
+            _ => None,
+        };
+        let with_unit = |tag| field(tag).map(|f| f.display_value().with_unit(&exif).to_string());
+        let has_gps = exif.fields().any(|f| f.tag.context() == exif::Context::Gps);
+
+        Ok(Some(ExifSummary {
+            make: text(exif::Tag::Make),
+            model: text(exif::Tag::Model),
+            lens_model: text(exif::Tag::LensModel),
+            capture_time: [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+                .into_iter()
+                .find_map(field)
+                .map(|f| f.display_value().to_string()),
+            exposure_time: with_unit(exif::Tag::ExposureTime),
+            f_number: with_unit(exif::Tag::FNumber),
+            iso: field(exif::Tag::PhotographicSensitivity).and_then(|f| f.value.get_uint(0)),
+            focal_length: with_unit(exif::Tag::FocalLength),
+            orientation: field(exif::Tag::Orientation).and_then(|f| f.value.get_uint(0)),
+            has_gps,
+        }))
+    }
+
+    /// Read the physical resolution (horizontal, vertical DPI) of a source file.
+    /// JFIF density wins, then EXIF resolution, then PNG pHYs.
+    pub fn read_dpi(path: &str) -> Result<Option<(f64, f64)>> {
+        let data = std::fs::read(path).context("Failed to read file")?;
+
+        let jfif = Self::read_jpeg_segments(&data)
+            .into_iter()
+            .find(|segment| segment.marker == 0xE0 && segment.data.starts_with(b"JFIF\0"))
+            .filter(|segment| segment.data.len() >= 12);
+        if let Some(jfif) = jfif {
+            let x = u16::from_be_bytes([jfif.data[8], jfif.data[9]]) as f64;
+            let y = u16::from_be_bytes([jfif.data[10], jfif.data[11]]) as f64;
+            match jfif.data[7] {
+                1 if x > 0.0 && y > 0.0 => return Ok(Some((x, y))),
+                2 if x > 0.0 && y > 0.0 => return Ok(Some((x * 2.54, y * 2.54))),
+                _ => {}
+            }
+        }
+
+        if let Ok(exif) = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)) {
+            let rational = |tag| match exif.get_field(tag, exif::In::PRIMARY).map(|f| &f.value) {
+                Some(exif::Value::Rational(values)) => values.first().map(|v| v.to_f64()),
+                _ => None,
+            };
+            let unit = exif.get_field(exif::Tag::ResolutionUnit, exif::In::PRIMARY)
+                .and_then(|f| f.value.get_uint(0))
+                .unwrap_or(2);
+
+            if let (Some(x), Some(y)) = (rational(exif::Tag::XResolution), rational(exif::Tag::YResolution)) {
+                if x > 0.0 && y > 0.0 {
+                    return Ok(Some(match unit {
+                        3 => (x * 2.54, y * 2.54),
+                        _ => (x, y),
+                    }));
+                }
+            }
+        }
+
+        let phys = Self::read_png_chunks(&data)
+            .into_iter()
+            .find(|(chunk_type, chunk)| chunk_type == b"pHYs" && chunk.len() >= 9);
+        if let Some((_, chunk)) = phys {
+            // Unit 1 is pixels per meter; unit 0 is aspect ratio only
+            if chunk[8] == 1 {
+                let x = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as f64;
+                let y = u32::from_be_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as f64;
+                if x > 0.0 && y > 0.0 {
+                    return Ok(Some((x * 0.0254, y * 0.0254)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Set the density of the JFIF APP0 segment written by the encoder
+    pub fn set_jpeg_dpi(mut jpeg: Vec<u8>, dpi: (f64, f64)) -> Vec<u8> {
+        if jpeg.len() >= 18 && jpeg[2..4] == [0xFF, 0xE0] && &jpeg[6..11] == b"JFIF\0" {
+            jpeg[13] = 1;
+            jpeg[14..16].copy_from_slice(&(dpi.0.round().clamp(1.0, 65535.0) as u16).to_be_bytes());
+            jpeg[16..18].copy_from_slice(&(dpi.1.round().clamp(1.0, 65535.0) as u16).to_be_bytes());
+        }
+        jpeg
+    }
+
+    /// Add a pHYs chunk with the given DPI to encoded PNG bytes
+    pub fn set_png_dpi(png: Vec<u8>, dpi: (f64, f64)) -> Result<Vec<u8>> {
+        let mut payload = Vec::with_capacity(9);
+        payload.extend_from_slice(&((dpi.0 / 0.0254).round() as u32).to_be_bytes());
+        payload.extend_from_slice(&((dpi.1 / 0.0254).round() as u32).to_be_bytes());
+        payload.push(1);
+
+        Self::insert_png_chunk(png, b"pHYs", &payload)
+    }
+
+    /// Set the pixels-per-meter fields of a BMP info header
+    pub fn set_bmp_dpi(mut bmp: Vec<u8>, dpi: (f64, f64)) -> Vec<u8> {
+        if bmp.len() >= 46 && bmp.starts_with(b"BM") {
+            bmp[38..42].copy_from_slice(&((dpi.0 / 0.0254).round() as i32).to_le_bytes());
+            bmp[42..46].copy_from_slice(&((dpi.1 / 0.0254).round() as i32).to_le_bytes());
+        }
+        bmp
+    }
+
+    /// Set the Orientation tag of a JPEG's EXIF segment in place, leaving every other
+    /// byte alone. Returns false if there is no EXIF orientation to rewrite.
+    pub fn set_jpeg_orientation(jpeg: &mut [u8], orientation: u16) -> bool {
+        if jpeg.len() < 4 || jpeg[0] != 0xFF || jpeg[1] != MARKER_SOI {
+            return false;
+        }
+
+        let mut pos = 2;
+        while pos + 4 <= jpeg.len() && jpeg[pos] == 0xFF {
+            let marker = jpeg[pos + 1];
+            if marker == 0xFF {
+                pos += 1;
+                continue;
+            }
+            if marker == MARKER_SOS || marker == MARKER_EOI {
+                break;
+            }
+
+            let length = u16::from_be_bytes([jpeg[pos + 2], jpeg[pos + 3]]) as usize;
+            let end = pos + 2 + length;
+            if length < 2 || end > jpeg.len() {
+                break;
+            }
+            let segment = &mut jpeg[pos + 4..end];
+            if marker == MARKER_APP1 && segment.starts_with(EXIF_HEADER) {
+                return Self::set_exif_orientation(segment, orientation);
+            }
+            pos = end;
+        }
+
+        false
+    }
+
+    /// Rewrite the IFD0 Orientation tag in place. Returns false if the tag is absent.
+    pub fn set_exif_orientation(exif: &mut [u8], orientation: u16) -> bool {
+        let Some(tiff) = exif.strip_prefix(EXIF_HEADER) else {
+            return false;
+        };
+        let Some(reader) = TiffReader::new(tiff) else {
+            return false;
+        };
+        let Some(entry) = reader.first_ifd().and_then(|ifd| reader.find_entry(ifd, TAG_ORIENTATION)) else {
+            return false;
+        };
+
+        let bytes = if reader.little_endian {
+            orientation.to_le_bytes()
+        } else {
+            orientation.to_be_bytes()
+        };
+        let pos = EXIF_HEADER.len() + entry + 8;
+        exif[pos..pos + 2].copy_from_slice(&bytes);
+
+        true
+    }
+}
+
+/// Minimal reader for TIFF-structured data (RAW containers, EXIF payloads)
+pub struct TiffReader<'a> {
+    pub data: &'a [u8],
+    pub little_endian: bool,
+}
+
+impl<'a> TiffReader<'a> {
+    /// Detect the byte order from the TIFF header
+    pub fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(TiffReader { data, little_endian })
+    }
+
+    pub fn u16(&self, pos: usize) -> Option<u16> {
+        let b = self.data.get(pos..pos + 2)?;
+        Some(if self.little_endian {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        })
+    }
+
+    pub fn u32(&self, pos: usize) -> Option<u32> {
+        let b = self.data.get(pos..pos + 4)?;
+        Some(if self.little_endian {
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        } else {
+            u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+        })
+    }
+
+    /// Offset of IFD0
+    pub fn first_ifd(&self) -> Option<usize> {
+        self.u32(4).map(|v| v as usize)
+    }
+
+    /// Find the entry position of a tag in the IFD at `ifd`
+    pub fn find_entry(&self, ifd: usize, tag: u16) -> Option<usize> {
+        let count = self.u16(ifd)? as usize;
+        (0..count)
+            .map(|i| ifd + 2 + i * 12)
+            .find(|&entry| self.u16(entry) == Some(tag))
+    }
+
+    /// Read an ASCII entry, without its NUL terminator; None when empty
+    pub fn ascii(&self, entry: usize) -> Option<String> {
+        if self.u16(entry + 2)? != 2 {
+            return None;
+        }
+        let count = self.u32(entry + 4)? as usize;
+        let start = if count <= 4 { entry + 8 } else { self.u32(entry + 8)? as usize };
+        let bytes = self.data.get(start..start.checked_add(count)?)?;
+
+        let text = String::from_utf8_lossy(bytes).trim_end_matches('\0').trim().to_string();
+        (!text.is_empty()).then_some(text)
+    }
+
+    /// Read the SHORT/LONG/IFD values of an IFD entry
+    pub fn values(&self, entry: usize) -> Vec<u32> {
+        let (Some(field_type), Some(count)) = (self.u16(entry + 2), self.u32(entry + 4)) else {
+            return Vec::new();
+        };
+        let size = match field_type {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return Vec::new(),
+        };
+
+        let count = (count as usize).min(4096);
+        let base = if size * count <= 4 {
+            entry + 8
+        } else {
+            match self.u32(entry + 8) {
+                Some(offset) => offset as usize,
+                None => return Vec::new(),
+            }
+        };
+
+        (0..count)
+            .filter_map(|i| {
+                let pos = base + i * size;
+                if size == 2 {
+                    self.u16(pos).map(u32::from)
+                } else {
+                    self.u32(pos)
+                }
+            })
+            .collect()
+    }
+}
+
+/// Read a big-endian unsigned integer of `len` bytes at `offset`
+pub fn read_be(data: &[u8], offset: usize, len: usize) -> Option<u32> {
+    data.get(offset..offset + len)
+        .map(|bytes| bytes.iter().fold(0u32, |value, &b| (value << 8) | b as u32))
+}
+
+/// Iterate the ISOBMFF boxes directly inside `data`, yielding (type, body)
+pub fn iter_boxes(data: &[u8]) -> impl Iterator<Item = (&[u8; 4], &[u8])> {
+    let mut pos = 0;
+    std::iter::from_fn(move || {
+        let size = read_be(data, pos, 4)? as usize;
+        let kind: &[u8; 4] = data.get(pos + 4..pos + 8)?.try_into().ok()?;
+        let (header, size) = match size {
+            0 => (8, data.len() - pos),
+            1 => (16, usize::try_from((u64::from(read_be(data, pos + 8, 4)?) << 32) | u64::from(read_be(data, pos + 12, 4)?)).ok()?),
+            size => (8, size),
+        };
+        let body = data.get(pos + header..pos.checked_add(size)?)?;
+        pos += size;
+        Some((kind, body))
+    })
+}
+
+pub fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    iter_boxes(data).find(|(k, _)| *k == kind).map(|(_, body)| body)
+}
+
+/// 1-based ipco property indices associated with `item_id` in an ipma box
+fn ipma_indices(ipma: &[u8], item_id: u32) -> Option<Vec<usize>> {
+    let version = *ipma.first()?;
+    let wide_index = ipma.get(3)? & 0x01 != 0;
+    let entry_count = read_be(ipma, 4, 4)?;
+
+    let mut pos = 8;
+    for _ in 0..entry_count {
+        let id_len = if version < 1 { 2 } else { 4 };
+        let id = read_be(ipma, pos, id_len)?;
+        pos += id_len;
+        let association_count = *ipma.get(pos)? as usize;
+        pos += 1;
+
+        let index_len = if wide_index { 2 } else { 1 };
+        let mask = if wide_index { 0x7fff } else { 0x7f };
+        let indices = (0..association_count)
+            .map(|i| read_be(ipma, pos + i * index_len, index_len).map(|v| (v & mask) as usize))
+            .collect::<Option<Vec<_>>>()?;
+        pos += association_count * index_len;
+
+        if id == item_id {
+            return Some(indices);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image_processor::JpegEncodeOptions;
+    use crate::test_util::{gradient, TempDir};
+    use image::GenericImageView;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        ImageProcessor::encode_jpeg(&gradient(width, height), 90, JpegEncodeOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn jpeg_comment_round_trips() {
+        let dir = TempDir::new();
+        let data = MetadataProcessor::set_jpeg_comment(jpeg(32, 24), "Scanned at 600 dpi").unwrap();
+        let path = dir.write("comment.jpg", &data);
+
+        assert_eq!(MetadataProcessor::read_jpeg_comment(&path).unwrap().as_deref(), Some("Scanned at 600 dpi"));
+        assert_eq!(image::open(&path).unwrap().dimensions(), (32, 24));
+    }
+
+    #[test]
+    fn jpeg_comment_is_none_when_absent() {
+        let dir = TempDir::new();
+        let path = dir.write("plain.jpg", &jpeg(8, 8));
+
+        assert_eq!(MetadataProcessor::read_jpeg_comment(&path).unwrap(), None);
+    }
+
+    #[test]
+    fn jpeg_segments_go_after_leading_app_segments() {
+        let data = MetadataProcessor::set_jpeg_exif(jpeg(8, 8), &[EXIF_HEADER, EMPTY_TIFF].concat()).unwrap();
+        let data = MetadataProcessor::set_jpeg_comment(data, "note").unwrap();
+        let markers: Vec<u8> = MetadataProcessor::read_jpeg_segments(&data)
+            .iter()
+            .map(|segment| segment.marker)
+            .collect();
+
+        let com = markers.iter().position(|&marker| marker == MARKER_COM).unwrap();
+        assert!(markers[..com].iter().all(|marker| (0xE0..=0xEF).contains(marker)));
+    }
+
+    /// An IFD0 ASCII tag as kamadak-exif reads it back from a JPEG
+    fn exif_text(jpeg: &[u8], tag: exif::Tag) -> Option<String> {
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(jpeg)).ok()?;
+        match &exif.get_field(tag, exif::In::PRIMARY)?.value {
+            exif::Value::Ascii(strings) => Some(String::from_utf8_lossy(strings.first()?).into_owned()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn jpeg_attribution_reads_back_from_exif() {
+        let exif = MetadataProcessor::set_exif_attribution(None, Some("Ada Lovelace"), Some("© 2024 Analytical Works"));
+        let data = MetadataProcessor::set_jpeg_exif(jpeg(8, 8), &exif).unwrap();
+
+        assert_eq!(exif_text(&data, exif::Tag::Artist).as_deref(), Some("Ada Lovelace"));
+        assert_eq!(exif_text(&data, exif::Tag::Copyright).as_deref(), Some("© 2024 Analytical Works"));
+    }
+
+    #[test]
+    fn exif_attribution_keeps_existing_tags_and_replaces_old_attribution() {
+        let source = crate::test_util::exif_with_orientation(6);
+        let first = MetadataProcessor::set_exif_attribution(Some(source), Some("Old"), Some("Old notice"));
+        // Short enough to be stored inline in the entry
+        let exif = MetadataProcessor::set_exif_attribution(Some(first), Some("Bo"), None);
+        let data = MetadataProcessor::set_jpeg_exif(jpeg(8, 8), &exif).unwrap();
+
+        assert_eq!(exif_text(&data, exif::Tag::Artist).as_deref(), Some("Bo"));
+        assert_eq!(exif_text(&data, exif::Tag::Copyright).as_deref(), Some("Old notice"));
+        let exif = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(&data)).unwrap();
+        assert_eq!(exif.get_field(exif::Tag::Orientation, exif::In::PRIMARY).unwrap().value.get_uint(0), Some(6));
+    }
+
+    #[test]
+    fn attribution_xmp_escapes_markup() {
+        let xmp = MetadataProcessor::attribution_xmp(Some("A & B"), Some("<c> \"Studio\""));
+
+        assert!(xmp.contains("<rdf:li>A &amp; B</rdf:li>"));
+        assert!(xmp.contains("<rdf:li xml:lang=\"x-default\">&lt;c&gt; &quot;Studio&quot;</rdf:li>"));
+        assert!(!MetadataProcessor::attribution_xmp(Some("A"), None).contains("dc:rights"));
+    }
+
+    #[test]
+    fn png_attribution_reads_back_from_text_chunks() {
+        let png = ImageProcessor::encode_png(&gradient(8, 8), crate::image_processor::PngCompression::Fast).unwrap();
+        let data = MetadataProcessor::set_png_attribution(png, Some("Zoë"), Some("© 2024 東京スタジオ")).unwrap();
+        let chunks = MetadataProcessor::read_png_chunks(&data);
+        let chunk = |chunk_type: &[u8; 4], keyword: &str| chunks.iter()
+            .find(|(found, payload)| found == chunk_type && payload.starts_with(format!("{}\0", keyword).as_bytes()))
+            .map(|(_, payload)| payload.clone());
+
+        // Latin-1 text is stored as Latin-1 bytes
+        assert_eq!(chunk(b"tEXt", "Author").unwrap(), b"Author\0Zo\xEB");
+        let copyright = chunk(b"iTXt", "Copyright").unwrap();
+        assert!(copyright.ends_with("© 2024 東京スタジオ".as_bytes()));
+        let xmp = chunk(b"iTXt", "XML:com.adobe.xmp").unwrap();
+        assert!(String::from_utf8_lossy(&xmp).contains("<dc:rights><rdf:Alt><rdf:li xml:lang=\"x-default\">© 2024 東京スタジオ"));
+        assert_eq!(image::load_from_memory(&data).unwrap().dimensions(), (8, 8));
+    }
+}
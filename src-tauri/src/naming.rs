@@ -0,0 +1,339 @@
+use crate::metadata::{ExifSummary, MetadataProcessor};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Format used by a bare {exif_date} token
+const DEFAULT_DATE_FORMAT: &str = "%Y-%m-%d_%H%M%S";
+/// Folder for files with neither an EXIF capture time nor a readable mtime
+const UNKNOWN_FOLDER: &str = "unknown";
+/// Characters the target file system rejects in a name, besides control characters
+const ILLEGAL_CHARACTERS: &str = if cfg!(windows) { "<>:\"/\\|?*" } else { "/" };
+/// Device names Windows refuses as file names, with or without an extension
+const RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL",
+    "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9",
+    "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+/// Most file systems cap a name at 255 bytes; leave room for collision suffixes
+const MAX_NAME_BYTES: usize = 240;
+
+/// Letter case of batch output file extensions
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ExtensionCase {
+    /// Keep the extension of the requested output path as given
+    #[default]
+    AsRequested,
+    Lower,
+    /// e.g. `.JPG`, which some legacy systems expect
+    Upper,
+    /// Upper case when the source's extension is, lower case otherwise
+    Source,
+}
+
+/// Calendar fields of a capture or modification time
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct DateTime {
+    year: i64,
+    month: u32,
+    day: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+}
+
+impl DateTime {
+    /// Parse an EXIF date ("2024:06:01 14:32:05", or the dashed form kamadak-exif displays)
+    fn parse(text: &str) -> Option<DateTime> {
+        let fields: Vec<i64> = text
+            .split(|c: char| !c.is_ascii_digit())
+            .filter(|part| !part.is_empty())
+            .take(6)
+            .map(|part| part.parse().ok())
+            .collect::<Option<_>>()?;
+        let [year, month, day, hour, minute, second] = fields[..] else {
+            return None;
+        };
+        // Cameras without a clock set write all zeros
+        if year == 0 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            return None;
+        }
+
+        Some(DateTime {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: hour as u32,
+            minute: minute as u32,
+            second: second as u32,
+        })
+    }
+
+    /// UTC calendar time of a Unix timestamp
+    fn from_unix(seconds: i64) -> DateTime {
+        let (days, time) = (seconds.div_euclid(86_400), seconds.rem_euclid(86_400));
+
+        // Civil-from-days (proleptic Gregorian, eras of 400 years)
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let day_of_era = z - era * 146_097;
+        let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let shifted_month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+        let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month: month as u32,
+            day: day as u32,
+            hour: (time / 3600) as u32,
+            minute: (time % 3600 / 60) as u32,
+            second: (time % 60) as u32,
+        }
+    }
+
+    /// strftime subset: %Y %m %d %H %M %S and %%
+    fn format(&self, pattern: &str) -> Result<String> {
+        let mut out = String::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{:04}", self.year)),
+                Some('m') => out.push_str(&format!("{:02}", self.month)),
+                Some('d') => out.push_str(&format!("{:02}", self.day)),
+                Some('H') => out.push_str(&format!("{:02}", self.hour)),
+                Some('M') => out.push_str(&format!("{:02}", self.minute)),
+                Some('S') => out.push_str(&format!("{:02}", self.second)),
+                Some('%') => out.push('%'),
+                Some(other) => anyhow::bail!("Unsupported date field %{}", other),
+                None => anyhow::bail!("Date format ends with a lone %"),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Per-file values the template tokens draw on
+struct NameSource<'a> {
+    path: &'a str,
+    index: usize,
+}
+
+impl NameSource<'_> {
+    /// EXIF capture time, else the file's modification time (UTC), else None
+    fn date(&self, summary: Option<&ExifSummary>) -> Option<DateTime> {
+        summary
+            .and_then(|summary| summary.capture_time.as_deref())
+            .and_then(DateTime::parse)
+            .or_else(|| {
+                let modified = std::fs::metadata(self.path).and_then(|m| m.modified()).ok()?;
+                let seconds = modified.duration_since(UNIX_EPOCH).ok()?.as_secs();
+                Some(DateTime::from_unix(seconds as i64))
+            })
+    }
+}
+
+/// A piece of a parsed template
+enum Part<'a> {
+    Literal(&'a str),
+    Token { name: &'a str, argument: Option<&'a str> },
+}
+
+/// Split a template into literal text and {token} or {token:ARGUMENT} parts
+fn parse(template: &str) -> Result<Vec<Part<'_>>> {
+    let mut parts = Vec::new();
+    let mut rest = template;
+
+    while let Some(start) = rest.find('{') {
+        parts.push(Part::Literal(&rest[..start]));
+        let Some(end) = rest[start..].find('}') else {
+            anyhow::bail!("Unclosed '{{' in template");
+        };
+        let token = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let (name, argument) = match token.split_once(':') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (token, None),
+        };
+        parts.push(Part::Token { name, argument });
+    }
+    parts.push(Part::Literal(rest));
+
+    Ok(parts)
+}
+
+/// Reject unknown tokens and bad date formats before any file is looked at
+pub fn check_template(template: &str) -> Result<()> {
+    let sample = DateTime::from_unix(0);
+    for part in parse(template)? {
+        match part {
+            Part::Token { name: "exif_date", argument } => {
+                sample.format(argument.unwrap_or(DEFAULT_DATE_FORMAT))?;
+            }
+            Part::Token { name: "camera_model" | "name" | "index", .. } | Part::Literal(_) => {}
+            Part::Token { name, .. } => anyhow::bail!("Unknown template token {{{}}}", name),
+        }
+    }
+    Ok(())
+}
+
+/// Render `template` for one file. Tokens: {exif_date} or {exif_date:FORMAT},
+/// {camera_model}, {name} (source file stem) and {index} (1-based batch position).
+/// An {exif_date} without any date renders as `undated`.
+fn render(template: &str, source: &NameSource, date: Option<DateTime>, summary: Option<&ExifSummary>, undated: &str) -> Result<String> {
+    let mut out = String::new();
+
+    for part in parse(template)? {
+        let value = match part {
+            Part::Literal(text) => text.to_string(),
+            Part::Token { name: "exif_date", argument } => match date {
+                Some(date) => date.format(argument.unwrap_or(DEFAULT_DATE_FORMAT))?,
+                None => undated.to_string(),
+            },
+            Part::Token { name: "camera_model", .. } => summary
+                .and_then(|summary| summary.model.clone())
+                .unwrap_or_else(|| "unknown".to_string()),
+            Part::Token { name: "name", .. } => Path::new(source.path)
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("image")
+                .to_string(),
+            Part::Token { name: "index", .. } => format!("{:04}", source.index),
+            Part::Token { name, .. } => anyhow::bail!("Unknown template token {{{}}}", name),
+        };
+        out.push_str(&value);
+    }
+
+    Ok(out)
+}
+
+/// Render a folder template into sanitized path components. Files without any date
+/// all go to a single "unknown" folder rather than a partially rendered path.
+fn render_folder(template: &str, source: &NameSource, date: Option<DateTime>, summary: Option<&ExifSummary>) -> Result<PathBuf> {
+    if date.is_none() && template.contains("{exif_date") {
+        return Ok(PathBuf::from(UNKNOWN_FOLDER));
+    }
+
+    let rendered = render(template, source, date, summary, UNKNOWN_FOLDER)?;
+    Ok(rendered
+        .split(['/', '\\'])
+        .filter(|component| !component.trim().is_empty())
+        // sanitize also turns "." and ".." into plain names, keeping output inside the directory
+        .map(sanitize)
+        .collect())
+}
+
+/// Make `name` (without extension) valid on this platform: illegal and control
+/// characters become `_`, trailing dots and surrounding spaces are dropped, Windows
+/// device names get a `_` suffix, and overlong names are cut at a character boundary.
+pub fn sanitize(name: &str) -> String {
+    let name: String = name.chars()
+        .map(|c| if c.is_control() || ILLEGAL_CHARACTERS.contains(c) { '_' } else { c })
+        .collect();
+    let mut name = name.trim().trim_end_matches('.').trim_end().to_string();
+    if name.is_empty() {
+        name = "image".to_string();
+    }
+
+    if cfg!(windows) {
+        // "CON.tar" is as reserved as "CON"
+        let device = name.split('.').next().unwrap_or_default();
+        if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(device)) {
+            name.insert(device.len(), '_');
+        }
+    }
+
+    if name.len() > MAX_NAME_BYTES {
+        let cut = (0..=MAX_NAME_BYTES).rev().find(|&i| name.is_char_boundary(i)).unwrap_or(0);
+        name.truncate(cut);
+    }
+    name
+}
+
+/// `path` with its file name sanitized, keeping the directory and extension as given
+pub fn sanitize_output_path(path: &str) -> String {
+    let path = Path::new(path);
+    let Some(stem) = path.file_stem().and_then(|s| s.to_str()) else {
+        return path.to_string_lossy().to_string();
+    };
+    let name = match path.extension().and_then(|s| s.to_str()) {
+        Some(extension) => format!("{}.{}", sanitize(stem), sanitize(extension)),
+        None => sanitize(stem),
+    };
+    path.with_file_name(name).to_string_lossy().to_string()
+}
+
+/// `output` with its extension recased per `case`; `source` is the input path the
+/// Source case follows
+pub fn with_extension_case(output: &str, source: &str, case: ExtensionCase) -> String {
+    let path = Path::new(output);
+    let Some(extension) = path.extension().and_then(|s| s.to_str()) else {
+        return output.to_string();
+    };
+    let upper = match case {
+        ExtensionCase::AsRequested => return output.to_string(),
+        ExtensionCase::Lower => false,
+        ExtensionCase::Upper => true,
+        ExtensionCase::Source => Path::new(source)
+            .extension()
+            .and_then(|s| s.to_str())
+            .is_some_and(|source| source.chars().any(char::is_alphabetic) && !source.chars().any(char::is_lowercase)),
+    };
+    let extension = if upper { extension.to_uppercase() } else { extension.to_lowercase() };
+    path.with_extension(extension).to_string_lossy().to_string()
+}
+
+/// Rename each `(source, requested output)` pair with `name_template` and place it in
+/// the subfolder `folder_template` renders, both relative to the requested directory;
+/// the requested extension is kept. Names that collide with an earlier item or an
+/// existing file get `_1`, `_2`, ... appended, in batch order, so results are deterministic.
+pub fn resolve_output_paths(name_template: Option<&str>, folder_template: Option<&str>, items: &[(&str, &str)]) -> Result<Vec<String>> {
+    let mut taken: HashSet<String> = HashSet::new();
+    let mut resolved = Vec::with_capacity(items.len());
+
+    for (index, (source, requested)) in items.iter().enumerate() {
+        let source = NameSource { path: source, index: index + 1 };
+        let summary = MetadataProcessor::exif_summary(source.path).ok().flatten();
+        let date = source.date(summary.as_ref());
+        let requested = Path::new(requested);
+
+        let base = match name_template {
+            // Neither EXIF nor a readable mtime; the position still keeps names apart
+            Some(template) => sanitize(&render(template, &source, date, summary.as_ref(), &format!("{:04}", source.index))?),
+            None => sanitize(requested.file_stem().and_then(|s| s.to_str()).unwrap_or("image")),
+        };
+        let mut directory = requested.parent().unwrap_or(Path::new("")).to_path_buf();
+        if let Some(template) = folder_template {
+            directory.push(render_folder(template, &source, date, summary.as_ref())?);
+        }
+        let extension = requested.extension().and_then(|s| s.to_str());
+
+        let candidate = |suffix: usize| -> PathBuf {
+            let stem = if suffix == 0 { base.clone() } else { format!("{}_{}", base, suffix) };
+            match extension {
+                Some(extension) => directory.join(format!("{}.{}", stem, extension)),
+                None => directory.join(stem),
+            }
+        };
+        // Compare case-insensitively since macOS and Windows file systems do
+        let path = (0..)
+            .map(candidate)
+            .find(|path| !taken.contains(&path.to_string_lossy().to_lowercase()) && !path.exists())
+            .expect("unbounded suffix search");
+
+        taken.insert(path.to_string_lossy().to_lowercase());
+        resolved.push(path.to_string_lossy().to_string());
+    }
+
+    Ok(resolved)
+}
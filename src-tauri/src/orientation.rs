@@ -0,0 +1,84 @@
+use crate::color::ColorProfiles;
+use crate::image_processor::{ImageProcessor, PngCompression};
+use crate::metadata::{HeifTransformation, MetadataProcessor};
+use anyhow::{Context, Result};
+
+/// Quality for HEIC files that have to be re-encoded to drop irot/imir
+const HEIC_QUALITY: u8 = 90;
+
+/// Upright replacement for a source file
+pub struct Normalized {
+    pub data: Vec<u8>,
+    /// Produced by a lossless JPEG transform rather than a re-encode
+    pub lossless: bool,
+}
+
+pub struct OrientationNormalizer;
+
+impl OrientationNormalizer {
+    /// Work out the upright bytes for a JPEG, PNG or HEIC file. Returns the EXIF
+    /// orientation the source declared (1 when none) and None when the file is
+    /// already upright and should be left alone.
+    pub fn normalize(path: &str) -> Result<(u32, Option<Normalized>)> {
+        let format = ImageProcessor::get_format(path)?;
+        let orientation = ImageProcessor::read_exif_orientation(path)?
+            .filter(|orientation| (1..=8).contains(orientation))
+            .unwrap_or(1);
+
+        let normalized = match format.as_str() {
+            "jpg" | "jpeg" if orientation != 1 => {
+                let source = std::fs::read(path).context("Failed to read JPEG file")?;
+                let mut data = ImageProcessor::transform_jpeg_lossless(&source, orientation)?;
+                MetadataProcessor::set_jpeg_orientation(&mut data, 1);
+                Some(Normalized { data, lossless: true })
+            }
+            "png" if orientation != 1 => Some(Normalized {
+                data: Self::reencode_png(path)?,
+                lossless: false,
+            }),
+            // libheif applies irot/imir on decode, so those, not the EXIF tag, decide
+            // how viewers show the file
+            format if ImageProcessor::is_heif_format(format) => {
+                let transformed = MetadataProcessor::heif_transformations(path)?
+                    .iter()
+                    .any(|t| !matches!(t, HeifTransformation::CleanAperture));
+                if transformed || orientation != 1 {
+                    Some(Normalized { data: Self::reencode_heic(path)?, lossless: false })
+                } else {
+                    None
+                }
+            }
+            "jpg" | "jpeg" | "png" => None,
+            other => anyhow::bail!("Orientation can't be normalized for {} files", other),
+        };
+
+        Ok((orientation, normalized))
+    }
+
+    /// Decode upright and write a fresh PNG without the eXIf chunk, keeping ICC and DPI
+    fn reencode_png(path: &str) -> Result<Vec<u8>> {
+        let img = ImageProcessor::load_image(path)?;
+        let mut data = ImageProcessor::encode_png(&img, PngCompression::Best)?;
+
+        if let Some(icc) = ColorProfiles::read_source_profile(path)? {
+            let name = ColorProfiles::description(&icc).unwrap_or_else(|| "ICC profile".to_string());
+            data = MetadataProcessor::set_png_icc(data, &icc, &name)?;
+        }
+        if let Some(dpi) = MetadataProcessor::read_dpi(path)? {
+            data = MetadataProcessor::set_png_dpi(data, dpi)?;
+        }
+
+        Ok(data)
+    }
+
+    /// Decode with irot/imir applied and encode again without them, carrying EXIF over
+    /// with its orientation reset
+    fn reencode_heic(path: &str) -> Result<Vec<u8>> {
+        let img = ImageProcessor::load_image(path)?;
+        let mut exif = MetadataProcessor::read_exif_segment(path)?;
+        if let Some(exif) = exif.as_mut() {
+            MetadataProcessor::set_exif_orientation(exif, 1);
+        }
+        ImageProcessor::encode_heic(&img, HEIC_QUALITY, exif.as_deref())
+    }
+}
@@ -0,0 +1,302 @@
+use anyhow::{Context, Result};
+use image::{DynamicImage, GrayImage, RgbImage, RgbaImage};
+
+/// Largest image the parser will allocate for
+const MAX_PIXELS: u64 = 1 << 28;
+/// Most scanline bytes any supported layout needs for MAX_PIXELS (four 8-bit planes)
+const MAX_SCANLINE_BYTES: u64 = MAX_PIXELS * 4;
+/// Bytes a scanline may run past its pixels. Writers pad to an even length, some
+/// to a multiple of four.
+const MAX_LINE_PADDING: usize = 4;
+
+const HEADER_SIZE: usize = 128;
+/// Marks the 256-color palette appended after the pixel data
+const VGA_PALETTE_MARKER: u8 = 0x0C;
+
+/// Fields of the 128-byte PCX header that matter for decoding
+struct Header {
+    width: u32,
+    height: u32,
+    rle: bool,
+    bits_per_pixel: u8,
+    planes: u8,
+    bytes_per_line: usize,
+    /// 16-color palette used by images of up to 4 bits per pixel
+    ega_palette: [u8; 48],
+}
+
+impl Header {
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < HEADER_SIZE || data[0] != 0x0A {
+            anyhow::bail!("Not a PCX file");
+        }
+        let u16_at = |offset: usize| u16::from_le_bytes([data[offset], data[offset + 1]]) as u32;
+        let (x_min, y_min, x_max, y_max) = (u16_at(4), u16_at(6), u16_at(8), u16_at(10));
+        if x_max < x_min || y_max < y_min {
+            anyhow::bail!("PCX has an invalid image window");
+        }
+
+        let header = Header {
+            width: x_max - x_min + 1,
+            height: y_max - y_min + 1,
+            rle: data[2] == 1,
+            bits_per_pixel: data[3],
+            planes: data[65],
+            bytes_per_line: u16_at(66) as usize,
+            ega_palette: data[16..64].try_into().expect("slice is 48 bytes"),
+        };
+        if header.width as u64 * header.height as u64 > MAX_PIXELS {
+            anyhow::bail!("PCX is too large ({}x{})", header.width, header.height);
+        }
+        if !Self::supported(header.bits_per_pixel, header.planes) {
+            anyhow::bail!("Unsupported PCX layout: {} bits per pixel in {} planes", header.bits_per_pixel, header.planes);
+        }
+        let pixel_bytes = (header.width as usize * header.bits_per_pixel as usize).div_ceil(8);
+        if header.bytes_per_line < pixel_bytes {
+            anyhow::bail!("PCX scanlines are shorter than the image width");
+        }
+        if header.bytes_per_line > pixel_bytes + MAX_LINE_PADDING {
+            anyhow::bail!("PCX scanlines are longer than the image width");
+        }
+        header.scanline_bytes()?;
+        Ok(header)
+    }
+
+    /// 8-bit paletted, RGB and RGBA, and up to 4-bit indices packed or split in planes
+    fn supported(bits: u8, planes: u8) -> bool {
+        matches!((bits, planes), (8, 1 | 3 | 4)) || (matches!(bits, 1 | 2 | 4) && (1..=4).contains(&planes) && bits * planes <= 4)
+    }
+
+    /// Size of one scanline across all planes, and of all of them
+    fn scanline_bytes(&self) -> Result<(usize, usize)> {
+        let line_size = self.bytes_per_line * self.planes as usize;
+        line_size.checked_mul(self.height as usize)
+            .filter(|&total| total as u64 <= MAX_SCANLINE_BYTES)
+            .map(|total| (line_size, total))
+            .context("PCX scanlines are too large")
+    }
+}
+
+/// ZSoft PCX, which the image crate doesn't read
+pub struct PcxProcessor;
+
+impl PcxProcessor {
+    pub fn dimensions(path: &str) -> Result<(u32, u32)> {
+        let data = std::fs::read(path).context("Failed to read PCX file")?;
+        let header = Header::parse(&data)?;
+        Ok((header.width, header.height))
+    }
+
+    pub fn load(path: &str) -> Result<DynamicImage> {
+        let data = std::fs::read(path).context("Failed to read PCX file")?;
+        Self::decode(&data)
+    }
+
+    /// Decode 1/2/4-bit paletted (packed or planar EGA), 8-bit paletted, 24-bit and
+    /// 32-bit PCX images
+    pub fn decode(data: &[u8]) -> Result<DynamicImage> {
+        let header = Header::parse(data)?;
+        let (width, height) = (header.width as usize, header.height as usize);
+        let (line_size, scanline_bytes) = header.scanline_bytes()?;
+        let scanlines = Self::unpack(&data[HEADER_SIZE..], header.rle, scanline_bytes)?;
+        let line = |y: usize, plane: usize| {
+            let start = y * line_size + plane * header.bytes_per_line;
+            &scanlines[start..start + header.bytes_per_line]
+        };
+
+        match (header.bits_per_pixel, header.planes) {
+            (8, 1) => {
+                // The VGA palette trails the pixel data; files without one are grayscale
+                let palette = data.len().checked_sub(769)
+                    .filter(|&start| start >= HEADER_SIZE && data[start] == VGA_PALETTE_MARKER)
+                    .map(|start| &data[start + 1..]);
+                let mut img = RgbImage::new(header.width, header.height);
+                for (y, row) in img.rows_mut().enumerate() {
+                    for (pixel, &index) in row.zip(line(y, 0)) {
+                        let index = index as usize;
+                        pixel.0 = match palette {
+                            Some(palette) => [palette[index * 3], palette[index * 3 + 1], palette[index * 3 + 2]],
+                            None => [index as u8; 3],
+                        };
+                    }
+                }
+                Ok(DynamicImage::ImageRgb8(img))
+            }
+            (8, 3) => {
+                let mut img = RgbImage::new(header.width, header.height);
+                for (y, row) in img.rows_mut().enumerate() {
+                    for (x, pixel) in row.enumerate() {
+                        pixel.0 = [line(y, 0)[x], line(y, 1)[x], line(y, 2)[x]];
+                    }
+                }
+                Ok(DynamicImage::ImageRgb8(img))
+            }
+            (8, 4) => {
+                let mut img = RgbaImage::new(header.width, header.height);
+                for (y, row) in img.rows_mut().enumerate() {
+                    for (x, pixel) in row.enumerate() {
+                        pixel.0 = [line(y, 0)[x], line(y, 1)[x], line(y, 2)[x], line(y, 3)[x]];
+                    }
+                }
+                Ok(DynamicImage::ImageRgba8(img))
+            }
+            (1, 1) => {
+                let mut img = GrayImage::new(header.width, header.height);
+                for (y, row) in img.rows_mut().enumerate() {
+                    for (x, pixel) in row.enumerate() {
+                        let bit = (line(y, 0)[x / 8] >> (7 - x % 8)) & 1;
+                        pixel.0 = [bit * 255];
+                    }
+                }
+                Ok(DynamicImage::ImageLuma8(img))
+            }
+            (bits @ (1 | 2 | 4), planes @ 1..=4) if bits as u32 * planes as u32 <= 4 => {
+                // Plane n holds bit n of each palette index; packed images have one plane
+                let bits = bits as usize;
+                let mask = (1u8 << bits) - 1;
+                let mut indices = vec![0u8; width * height];
+                for y in 0..height {
+                    for plane in 0..planes as usize {
+                        let plane_line = line(y, plane);
+                        for x in 0..width {
+                            let bit_offset = x * bits;
+                            let value = (plane_line[bit_offset / 8] >> (8 - bits - bit_offset % 8)) & mask;
+                            indices[y * width + x] |= value << (plane * bits);
+                        }
+                    }
+                }
+                let palette = &header.ega_palette;
+                let img = RgbImage::from_fn(header.width, header.height, |x, y| {
+                    let index = indices[y as usize * width + x as usize] as usize;
+                    image::Rgb([palette[index * 3], palette[index * 3 + 1], palette[index * 3 + 2]])
+                });
+                Ok(DynamicImage::ImageRgb8(img))
+            }
+            (bits, planes) => anyhow::bail!("Unsupported PCX layout: {} bits per pixel in {} planes", bits, planes),
+        }
+    }
+
+    /// Expand the RLE stream (or copy raw data) into `len` bytes of scanlines. Runs may
+    /// cross plane and line boundaries, so the stream is decoded as one.
+    fn unpack(data: &[u8], rle: bool, len: usize) -> Result<Vec<u8>> {
+        if !rle {
+            return data.get(..len)
+                .map(<[u8]>::to_vec)
+                .context("PCX pixel data is truncated");
+        }
+
+        // A two-byte run expands to at most 63 bytes, so a short stream can't fill a
+        // large image; checked before allocating for it
+        if len > data.len().saturating_mul(32) {
+            anyhow::bail!("PCX pixel data is truncated");
+        }
+        let mut out = Vec::with_capacity(len);
+        let mut bytes = data.iter();
+        while out.len() < len {
+            let &byte = bytes.next().context("PCX pixel data is truncated")?;
+            if byte & 0xC0 == 0xC0 {
+                let &value = bytes.next().context("PCX pixel data is truncated")?;
+                let count = (byte & 0x3F) as usize;
+                out.extend(std::iter::repeat_n(value, count.min(len - out.len())));
+            } else {
+                out.push(byte);
+            }
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::allocated_by;
+
+    fn header(width: u16, height: u16, bits: u8, planes: u8, bytes_per_line: u16, rle: bool) -> Vec<u8> {
+        let mut data = vec![0u8; HEADER_SIZE];
+        data[0] = 0x0A;
+        data[1] = 5;
+        data[2] = rle as u8;
+        data[3] = bits;
+        data[8..10].copy_from_slice(&(width - 1).to_le_bytes());
+        data[10..12].copy_from_slice(&(height - 1).to_le_bytes());
+        data[65] = planes;
+        data[66..68].copy_from_slice(&bytes_per_line.to_le_bytes());
+        data
+    }
+
+    /// RLE as PCX writers produce it: runs of up to 63, and literal bytes that would
+    /// read as a run marker escaped as runs of one
+    fn rle(scanlines: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        for run in scanlines.chunk_by(|a, b| a == b) {
+            for chunk in run.chunks(63) {
+                if chunk.len() == 1 && chunk[0] & 0xC0 != 0xC0 {
+                    out.push(chunk[0]);
+                } else {
+                    out.extend([0xC0 | chunk.len() as u8, chunk[0]]);
+                }
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn rgb_planes_decode_with_rle_and_padding() {
+        // 3x2 with one byte of padding per plane line
+        let rows = [[[10, 20, 30], [200, 201, 202], [255, 255, 0]], [[0, 0, 0], [7, 7, 7], [99, 98, 97]]];
+        let mut scanlines = Vec::new();
+        for row in rows {
+            for channel in 0..3 {
+                scanlines.extend(row.iter().map(|pixel| pixel[channel]));
+                scanlines.push(0);
+            }
+        }
+        let data = [header(3, 2, 8, 3, 4, true), rle(&scanlines)].concat();
+
+        let img = PcxProcessor::decode(&data).unwrap().to_rgb8();
+        for (y, row) in rows.iter().enumerate() {
+            for (x, pixel) in row.iter().enumerate() {
+                assert_eq!(&img.get_pixel(x as u32, y as u32).0, pixel);
+            }
+        }
+    }
+
+    #[test]
+    fn paletted_image_uses_the_trailing_vga_palette() {
+        let indices = [0u8, 1, 2, 1, 0, 255];
+        let mut palette = vec![0u8; 768];
+        palette[3..6].copy_from_slice(&[255, 0, 0]);
+        palette[6..9].copy_from_slice(&[0, 0, 255]);
+        palette[765..].copy_from_slice(&[1, 2, 3]);
+        let data = [header(3, 2, 8, 1, 4, false), [&indices[..3], &[0], &indices[3..], &[0]].concat(), vec![VGA_PALETTE_MARKER], palette].concat();
+
+        let img = PcxProcessor::decode(&data).unwrap().to_rgb8();
+        assert_eq!(img.get_pixel(1, 0).0, [255, 0, 0]);
+        assert_eq!(img.get_pixel(2, 0).0, [0, 0, 255]);
+        assert_eq!(img.get_pixel(2, 1).0, [1, 2, 3]);
+    }
+
+    #[test]
+    fn huge_scanlines_are_rejected_before_allocating() {
+        // The header alone claims 65535-byte lines in 255 planes over 65535 rows
+        let data = [header(1, 65535, 8, 255, 65535, true), vec![0xC1, 0x00]].concat();
+        let (result, allocated) = allocated_by(|| PcxProcessor::decode(&data));
+
+        assert!(result.is_err());
+        assert!(allocated < 1 << 20, "allocated {} bytes", allocated);
+    }
+
+    #[test]
+    fn header_rejects_unsupported_layouts_and_overlong_lines() {
+        assert!(PcxProcessor::decode(&header(8, 8, 8, 255, 8, true)).unwrap_err().to_string().contains("Unsupported PCX layout"));
+        assert!(PcxProcessor::decode(&header(8, 8, 4, 2, 4, true)).is_err());
+        assert!(PcxProcessor::decode(&header(1, 8, 8, 1, 65535, true)).unwrap_err().to_string().contains("longer"));
+        assert!(PcxProcessor::decode(&header(16, 8, 8, 1, 8, true)).unwrap_err().to_string().contains("shorter"));
+    }
+
+    #[test]
+    fn short_rle_streams_are_truncated() {
+        let data = [header(1000, 1000, 8, 1, 1000, true), vec![0xFF, 0x00]].concat();
+        assert!(PcxProcessor::decode(&data).unwrap_err().to_string().contains("truncated"));
+    }
+}
@@ -0,0 +1,129 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const EMIT_INTERVAL: Duration = Duration::from_millis(100);
+const RATE_WINDOW: Duration = Duration::from_secs(3);
+
+#[derive(Clone, Serialize)]
+pub struct BatchProgress {
+    pub completed: usize,
+    pub total: usize,
+    pub bytes_done: u64,
+    /// Input bytes of the whole batch
+    pub total_bytes: u64,
+    /// Overall progress from 0 to 1, weighting each item by its input size so a few
+    /// large files at the end don't leave the bar stuck near full
+    pub fraction: f64,
+    /// Smoothed over a rolling window of recent completions
+    pub images_per_sec: f64,
+    /// Remaining bytes over the smoothed byte throughput; None until there's a rate
+    pub eta_secs: Option<f64>,
+}
+
+/// Completion counts at one point in time, for rate estimates
+struct Sample {
+    at: Instant,
+    completed: usize,
+    bytes_done: u64,
+}
+
+struct EmitState {
+    last_emit: Option<Instant>,
+    samples: VecDeque<Sample>,
+}
+
+/// Aggregates per-item completions from parallel workers into a steady,
+/// time-throttled progress stream
+pub struct ProgressTracker {
+    total: usize,
+    total_bytes: u64,
+    completed: AtomicUsize,
+    bytes_done: AtomicU64,
+    state: Mutex<EmitState>,
+}
+
+impl ProgressTracker {
+    /// `total_bytes` is the summed input size of all `total` items
+    pub fn new(total: usize, total_bytes: u64) -> Self {
+        ProgressTracker {
+            total,
+            total_bytes,
+            completed: AtomicUsize::new(0),
+            bytes_done: AtomicU64::new(0),
+            state: Mutex::new(EmitState {
+                last_emit: None,
+                samples: VecDeque::from([Sample { at: Instant::now(), completed: 0, bytes_done: 0 }]),
+            }),
+        }
+    }
+
+    /// Record a finished item of `bytes` input size. Returns a snapshot when it's time to emit one.
+    pub fn item_done(&self, bytes: u64) -> Option<BatchProgress> {
+        self.completed.fetch_add(1, Ordering::SeqCst);
+        self.bytes_done.fetch_add(bytes, Ordering::SeqCst);
+
+        // Another worker is emitting right now; its snapshot covers this item soon enough
+        let mut state = self.state.try_lock().ok()?;
+        let now = Instant::now();
+        let finished = self.completed.load(Ordering::SeqCst) == self.total;
+        if !finished && state.last_emit.is_some_and(|last| now - last < EMIT_INTERVAL) {
+            return None;
+        }
+        state.last_emit = Some(now);
+
+        Some(self.snapshot(&mut state, now))
+    }
+
+    /// Snapshot regardless of throttling, for the final event of a batch
+    pub fn finish(&self) -> BatchProgress {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        self.snapshot(&mut state, Instant::now())
+    }
+
+    fn snapshot(&self, state: &mut EmitState, now: Instant) -> BatchProgress {
+        let completed = self.completed.load(Ordering::SeqCst);
+        let bytes_done = self.bytes_done.load(Ordering::SeqCst);
+
+        state.samples.push_back(Sample { at: now, completed, bytes_done });
+        while state.samples.len() > 2
+            && state.samples.front().is_some_and(|sample| now - sample.at > RATE_WINDOW)
+        {
+            state.samples.pop_front();
+        }
+
+        let (images_per_sec, bytes_per_sec) = match state.samples.front() {
+            Some(start) if now > start.at => {
+                let elapsed = (now - start.at).as_secs_f64();
+                (
+                    (completed - start.completed) as f64 / elapsed,
+                    (bytes_done - start.bytes_done) as f64 / elapsed,
+                )
+            }
+            _ => (0.0, 0.0),
+        };
+
+        // Fall back to item counts when sizes are unknown (all unreadable or empty)
+        let fraction = if self.total_bytes > 0 {
+            bytes_done as f64 / self.total_bytes as f64
+        } else if self.total > 0 {
+            completed as f64 / self.total as f64
+        } else {
+            1.0
+        };
+        let eta_secs = (bytes_per_sec > 0.0)
+            .then(|| self.total_bytes.saturating_sub(bytes_done) as f64 / bytes_per_sec);
+
+        BatchProgress {
+            completed,
+            total: self.total,
+            bytes_done,
+            total_bytes: self.total_bytes,
+            fraction: fraction.min(1.0),
+            images_per_sec,
+            eta_secs,
+        }
+    }
+}
@@ -0,0 +1,326 @@
+use crate::metadata::{MetadataProcessor, TiffReader};
+use anyhow::{Context, Result};
+
+pub const RAW_EXTENSIONS: &[&str] = &[
+    "dng", "cr2", "nef", "nrw", "arw", "srf", "sr2", "orf", "rw2", "pef", "raf", "srw", "erf",
+    "kdc", "3fr", "mef", "mos", "iiq", "rwl",
+];
+
+/// Name load_image_with_options reports for images decoded from a RAW preview
+pub const PREVIEW_DECODER: &str = "image-jpeg (embedded RAW preview)";
+
+/// Share of the sensor's long side a preview must reach to stand in for the RAW
+/// data. Full-size previews only lose the sensor's masked borders; the next size
+/// cameras embed is a quarter or less.
+const ADEQUATE_PREVIEW_SHARE: f64 = 0.75;
+/// Long side a preview needs when the sensor size can't be read (RAF, and RAWs
+/// that aren't TIFF-based)
+const MIN_PREVIEW_LONG_SIDE: u32 = 1600;
+
+/// Location and size of a JPEG preview embedded in a RAW file
+#[derive(Debug)]
+pub struct RawPreview {
+    pub offset: usize,
+    pub length: usize,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// No embedded preview is close enough to the sensor size to convert from, and
+/// demosaicing the sensor data isn't supported
+#[derive(Debug)]
+pub struct NoAdequatePreview {
+    /// Size of the largest embedded JPEG, if there is one
+    pub preview: Option<(u32, u32)>,
+    /// Size of the sensor image, when the RAW's IFDs declare it
+    pub sensor: Option<(u32, u32)>,
+}
+
+impl std::fmt::Display for NoAdequatePreview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (self.preview, self.sensor) {
+            (None, _) => write!(f, "RAW file has no embedded JPEG preview")?,
+            (Some((width, height)), Some((sensor_width, sensor_height))) => write!(
+                f,
+                "RAW file's largest embedded preview ({}x{}) is much smaller than its sensor image ({}x{})",
+                width, height, sensor_width, sensor_height
+            )?,
+            (Some((width, height)), None) => write!(f, "RAW file's largest embedded preview ({}x{}) is too small", width, height)?,
+        }
+        f.write_str(", and full RAW decoding isn't supported")
+    }
+}
+
+impl std::error::Error for NoAdequatePreview {}
+
+/// What a walk over a TIFF-based RAW's IFDs found
+struct TiffScan {
+    /// Offset and length of each JPEG range
+    previews: Vec<(usize, usize)>,
+    /// Largest width and height any IFD declares
+    largest_image: Option<(u32, u32)>,
+}
+
+pub struct RawProcessor;
+
+impl RawProcessor {
+    pub fn is_raw_extension(extension: &str) -> bool {
+        RAW_EXTENSIONS.contains(&extension)
+    }
+
+    /// Find the largest camera-rendered JPEG embedded in a RAW file
+    pub fn find_largest_preview(data: &[u8]) -> Option<RawPreview> {
+        let candidates = if data.starts_with(b"FUJIFILMCCD-RAW") {
+            Self::raf_candidates(data)
+        } else {
+            Self::scan_tiff(data).previews
+        };
+
+        candidates
+            .into_iter()
+            .filter_map(|(offset, length)| Self::inspect_jpeg(data, offset, length))
+            .max_by_key(|preview| preview.width as u64 * preview.height as u64)
+    }
+
+    /// The largest embedded preview, when it's close to the full sensor size
+    pub fn adequate_preview(data: &[u8]) -> std::result::Result<RawPreview, NoAdequatePreview> {
+        let preview = Self::find_largest_preview(data);
+        let sensor = Self::sensor_dimensions(data);
+        let required = sensor.map_or(MIN_PREVIEW_LONG_SIDE, |(width, height)| {
+            (width.max(height) as f64 * ADEQUATE_PREVIEW_SHARE).ceil() as u32
+        });
+
+        match preview {
+            Some(preview) if preview.width.max(preview.height) >= required => Ok(preview),
+            preview => Err(NoAdequatePreview {
+                preview: preview.map(|preview| (preview.width, preview.height)),
+                sensor,
+            }),
+        }
+    }
+
+    /// Extract the bytes of the embedded JPEG preview, failing with NoAdequatePreview
+    /// when there's none near full size
+    pub fn extract_preview(path: &str) -> Result<Vec<u8>> {
+        let data = std::fs::read(path).context("Failed to read RAW file")?;
+        Self::preview_from(&data)
+    }
+
+    /// extract_preview for a RAW file already in memory (or mapped)
+    pub fn preview_from(data: &[u8]) -> Result<Vec<u8>> {
+        let preview = Self::adequate_preview(data)?;
+        Ok(data[preview.offset..preview.offset + preview.length].to_vec())
+    }
+
+    /// How a RAW source was decoded, given the decoder load_image_with_options
+    /// reported. None when the file wasn't decoded as a RAW at all, e.g. a JPEG
+    /// that only carried a RAW extension.
+    pub fn decode_path(decoder: &str) -> Option<&'static str> {
+        (decoder == PREVIEW_DECODER).then_some("embedded_preview")
+    }
+
+    /// Largest image size any IFD declares, which for TIFF-based RAWs is the sensor
+    /// data (or, where that has no size tags, the full-size preview)
+    pub fn sensor_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+        Self::scan_tiff(data).largest_image
+    }
+
+    /// Check that the candidate range holds a displayable (non-lossless) JPEG and read its size
+    fn inspect_jpeg(data: &[u8], offset: usize, length: usize) -> Option<RawPreview> {
+        let end = offset.checked_add(length)?;
+        if length < 4 || end > data.len() {
+            return None;
+        }
+
+        let jpeg = &data[offset..end];
+        let sof = MetadataProcessor::read_jpeg_segments(jpeg)
+            .into_iter()
+            .find(|segment| (0xC0..=0xCF).contains(&segment.marker)
+                && ![0xC4, 0xC8, 0xCC].contains(&segment.marker))?;
+
+        // Only baseline, extended and progressive DCT previews; lossless JPEG is raw sensor data
+        if !(0xC0..=0xC2).contains(&sof.marker) || sof.data.len() < 5 {
+            return None;
+        }
+
+        let height = u16::from_be_bytes([sof.data[1], sof.data[2]]) as u32;
+        let width = u16::from_be_bytes([sof.data[3], sof.data[4]]) as u32;
+
+        Some(RawPreview { offset, length, width, height })
+    }
+
+    fn raf_candidates(data: &[u8]) -> Vec<(usize, usize)> {
+        let read = |pos: usize| -> Option<usize> {
+            data.get(pos..pos + 4)
+                .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+        };
+
+        match (read(84), read(88)) {
+            (Some(offset), Some(length)) => vec![(offset, length)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Walk IFD0, its chain and any SubIFDs collecting JPEG ranges and the largest
+    /// declared image size
+    fn scan_tiff(data: &[u8]) -> TiffScan {
+        let Some(reader) = TiffReader::new(data) else {
+            return TiffScan { previews: Vec::new(), largest_image: None };
+        };
+
+        let mut candidates = Vec::new();
+        let mut largest: Option<(u32, u32)> = None;
+        let mut pending: Vec<usize> = reader.first_ifd().into_iter().collect();
+        let mut visited = Vec::new();
+
+        while let Some(ifd) = pending.pop() {
+            if ifd == 0 || visited.contains(&ifd) || visited.len() > 64 {
+                continue;
+            }
+            visited.push(ifd);
+
+            let Some(count) = reader.u16(ifd) else { continue };
+            let mut jpeg_offset = None;
+            let mut jpeg_length = None;
+            let mut compression = None;
+            let mut photometric = None;
+            let mut strip_offsets = Vec::new();
+            let mut strip_counts = Vec::new();
+            let (mut width, mut height) = (None, None);
+
+            for i in 0..count as usize {
+                let entry = ifd + 2 + i * 12;
+                let Some(tag) = reader.u16(entry) else { break };
+                match tag {
+                    0x0100 => width = reader.values(entry).first().copied(),
+                    0x0101 => height = reader.values(entry).first().copied(),
+                    0x0103 => compression = reader.values(entry).first().copied(),
+                    0x0106 => photometric = reader.values(entry).first().copied(),
+                    0x0111 => strip_offsets = reader.values(entry),
+                    0x0117 => strip_counts = reader.values(entry),
+                    0x0201 => jpeg_offset = reader.values(entry).first().copied(),
+                    0x0202 => jpeg_length = reader.values(entry).first().copied(),
+                    0x014A => pending.extend(reader.values(entry).into_iter().map(|v| v as usize)),
+                    _ => {}
+                }
+            }
+
+            if let (Some(width), Some(height)) = (width, height) {
+                if largest.is_none_or(|(w, h)| width as u64 * height as u64 > w as u64 * h as u64) {
+                    largest = Some((width, height));
+                }
+            }
+            if let (Some(offset), Some(length)) = (jpeg_offset, jpeg_length) {
+                candidates.push((offset as usize, length as usize));
+            }
+
+            // CFA (32803) and LinearRaw (34892) strips are sensor data, not previews
+            let is_sensor_data = matches!(photometric, Some(32803) | Some(34892));
+            if matches!(compression, Some(6) | Some(7))
+                && !is_sensor_data
+                && strip_offsets.len() == 1
+                && strip_counts.len() == 1
+            {
+                candidates.push((strip_offsets[0] as usize, strip_counts[0] as usize));
+            }
+
+            let next = ifd + 2 + count as usize * 12;
+            if let Some(next_ifd) = reader.u32(next) {
+                pending.push(next_ifd as usize);
+            }
+        }
+
+        TiffScan { previews: candidates, largest_image: largest }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::{AppError, ErrorCode};
+    use crate::image_processor::{ImageProcessor, JpegEncodeOptions, LoadOptions};
+    use crate::test_util::{gradient, TempDir};
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        ImageProcessor::encode_jpeg(&gradient(width, height), 80, JpegEncodeOptions::default()).unwrap()
+    }
+
+    /// A little-endian TIFF-based RAW: IFD0 describes the CFA sensor data (which
+    /// isn't there), and each following IFD in the chain holds one JPEG preview
+    fn raw_file(sensor: Option<(u32, u32)>, previews: &[Vec<u8>]) -> Vec<u8> {
+        let mut ifds: Vec<Vec<(u16, u16, u32)>> = Vec::new();
+        ifds.push(match sensor {
+            Some((width, height)) => vec![(0x0100, 4, width), (0x0101, 4, height), (0x0106, 3, 32803)],
+            None => vec![(0x0106, 3, 32803)],
+        });
+        let ifd_bytes = |ifd: &Vec<(u16, u16, u32)>| 2 + ifd.len() * 12 + 4;
+        let ifds_end = 8 + ifd_bytes(&ifds[0]) + previews.len() * (2 + 2 * 12 + 4);
+        let mut jpeg_offset = ifds_end;
+        for preview in previews {
+            ifds.push(vec![(0x0201, 4, jpeg_offset as u32), (0x0202, 4, preview.len() as u32)]);
+            jpeg_offset += preview.len();
+        }
+
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        for (i, ifd) in ifds.iter().enumerate() {
+            let next = if i + 1 < ifds.len() { data.len() + ifd_bytes(ifd) } else { 0 };
+            data.extend_from_slice(&(ifd.len() as u16).to_le_bytes());
+            for &(tag, field_type, value) in ifd {
+                data.extend_from_slice(&tag.to_le_bytes());
+                data.extend_from_slice(&field_type.to_le_bytes());
+                data.extend_from_slice(&1u32.to_le_bytes());
+                data.extend_from_slice(&value.to_le_bytes());
+            }
+            data.extend_from_slice(&(next as u32).to_le_bytes());
+        }
+        assert_eq!(data.len(), ifds_end);
+        previews.iter().for_each(|preview| data.extend_from_slice(preview));
+        data
+    }
+
+    #[test]
+    fn full_size_preview_is_decoded_and_reported() {
+        let dir = TempDir::new();
+        let path = dir.write("photo.dng", &raw_file(Some((400, 300)), &[jpeg(40, 30), jpeg(392, 294)]));
+
+        let (img, decoder) = ImageProcessor::load_image_with_options(&path, &LoadOptions::default()).unwrap();
+        assert_eq!((img.width(), img.height()), (392, 294));
+        assert_eq!(RawProcessor::decode_path(decoder), Some("embedded_preview"));
+        assert_eq!(ImageProcessor::peek_dimensions(&path).unwrap(), (392, 294));
+    }
+
+    #[test]
+    fn thumbnail_only_raws_fail_with_a_typed_error() {
+        let dir = TempDir::new();
+        let path = dir.write("photo.nef", &raw_file(Some((400, 300)), &[jpeg(160, 120)]));
+
+        let error = AppError::from(ImageProcessor::load_image(&path).unwrap_err());
+        assert_eq!(error.code, ErrorCode::DecodeRawPreviewInadequate);
+        assert_eq!(error.params["preview"], "160x120");
+        assert_eq!(error.params["sensor"], "400x300");
+        assert_eq!(AppError::from(ImageProcessor::peek_dimensions(&path).unwrap_err()).code, ErrorCode::DecodeRawPreviewInadequate);
+    }
+
+    #[test]
+    fn raws_without_previews_fail_with_a_typed_error() {
+        let inadequate = RawProcessor::adequate_preview(&raw_file(Some((400, 300)), &[])).unwrap_err();
+        assert_eq!((inadequate.preview, inadequate.sensor), (None, Some((400, 300))));
+    }
+
+    #[test]
+    fn without_a_sensor_size_previews_need_a_minimum_long_side() {
+        let small = RawProcessor::adequate_preview(&raw_file(None, &[jpeg(800, 600)])).unwrap_err();
+        assert_eq!((small.preview, small.sensor), (Some((800, 600)), None));
+
+        let large = RawProcessor::adequate_preview(&raw_file(None, &[jpeg(MIN_PREVIEW_LONG_SIDE, 8)])).unwrap();
+        assert_eq!((large.width, large.height), (MIN_PREVIEW_LONG_SIDE, 8));
+    }
+
+    #[test]
+    fn only_the_preview_decoder_counts_as_a_raw_decode() {
+        assert_eq!(RawProcessor::decode_path(PREVIEW_DECODER), Some("embedded_preview"));
+        // A JPEG named .cr2 decoded through the sniffing fallback
+        assert_eq!(RawProcessor::decode_path("turbojpeg (fallback)"), None);
+        assert_eq!(RawProcessor::decode_path("image-png"), None);
+    }
+}
@@ -0,0 +1,172 @@
+use crate::image_processor::ImageProcessor;
+use anyhow::Result;
+use image::{DynamicImage, GenericImageView};
+use serde::Serialize;
+use std::collections::HashSet;
+
+/// Quality the size estimate of the top suggestion assumes for lossy formats
+pub const ESTIMATE_QUALITY: u8 = 85;
+/// Longest side of the proxy the analysis runs on
+const ANALYSIS_SIZE: u32 = 256;
+/// Distinct colors counted before giving up; anything past this is "many"
+const COLOR_CAP: usize = 4096;
+/// At or below this many colors the image suits palette-style lossless coding
+const FEW_COLORS: usize = 256;
+/// Luma step between neighbours above which an edge counts as hard
+const HARD_EDGE_STEP: u8 = 64;
+/// Share of identical neighbours above which the image is flat (UI, diagrams)
+const FLAT_SHARE: f32 = 0.6;
+/// Share of hard edges among all non-flat neighbour steps that marks line art or text
+const HARD_EDGE_SHARE: f32 = 0.25;
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ContentStats {
+    /// Distinct RGBA colors in the proxy, capped at 4096
+    pub unique_colors: usize,
+    /// Whether any pixel is not fully opaque
+    pub has_alpha: bool,
+    /// Share of neighbouring pixel pairs with identical luma
+    pub flat_share: f32,
+    /// Share of the changing neighbour pairs that change abruptly
+    pub hard_edge_share: f32,
+}
+
+impl ContentStats {
+    pub fn few_colors(&self) -> bool {
+        self.unique_colors <= FEW_COLORS
+    }
+
+    /// Continuous tone with mostly gentle transitions, as in photographs
+    pub fn photographic(&self) -> bool {
+        !self.few_colors() && self.flat_share < FLAT_SHARE && self.hard_edge_share < HARD_EDGE_SHARE
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct FormatSuggestion {
+    /// Target format name as accepted by target_format
+    pub format: &'static str,
+    /// Machine-readable reasons for and against, e.g. "has_alpha", "photographic",
+    /// "few_colors", "drops_alpha"
+    pub reasons: Vec<&'static str>,
+    pub score: i32,
+}
+
+impl FormatSuggestion {
+    fn new(format: &'static str) -> Self {
+        FormatSuggestion { format, reasons: Vec::new(), score: 0 }
+    }
+
+    fn add(&mut self, score: i32, reason: &'static str) {
+        self.score += score;
+        self.reasons.push(reason);
+    }
+}
+
+/// Suggests output formats from what an image contains
+pub struct FormatAdvisor;
+
+impl FormatAdvisor {
+    /// Small stand-in for the image at `path`. Lossy sources go through the cheap
+    /// thumbnail path; everything else is sampled with nearest neighbour so that
+    /// resampling doesn't invent colors a palette image never had.
+    pub fn proxy(path: &str) -> Result<DynamicImage> {
+        let format = ImageProcessor::get_format(path)?;
+        if matches!(format.as_str(), "jpg" | "jpeg") || ImageProcessor::is_heif_format(&format) {
+            return Ok(ImageProcessor::load_thumbnail(path, ANALYSIS_SIZE)?.0);
+        }
+
+        let img = ImageProcessor::load_image(path)?;
+        let (width, height) = img.dimensions();
+        if width.max(height) <= ANALYSIS_SIZE {
+            return Ok(img);
+        }
+        Ok(img.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, image::imageops::FilterType::Nearest))
+    }
+
+    pub fn analyze(img: &DynamicImage) -> ContentStats {
+        let rgba = img.to_rgba8();
+
+        let mut colors = HashSet::new();
+        for pixel in rgba.pixels() {
+            colors.insert(u32::from_le_bytes(pixel.0));
+            if colors.len() > COLOR_CAP {
+                break;
+            }
+        }
+        let has_alpha = img.color().has_alpha() && rgba.pixels().any(|pixel| pixel.0[3] < 255);
+
+        let luma = img.to_luma8();
+        let (width, height) = luma.dimensions();
+        let (mut flat, mut smooth, mut hard) = (0u32, 0u32, 0u32);
+        let mut count = |a: u8, b: u8| match a.abs_diff(b) {
+            0 => flat += 1,
+            step if step > HARD_EDGE_STEP => hard += 1,
+            _ => smooth += 1,
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let value = luma.get_pixel(x, y).0[0];
+                if x + 1 < width {
+                    count(value, luma.get_pixel(x + 1, y).0[0]);
+                }
+                if y + 1 < height {
+                    count(value, luma.get_pixel(x, y + 1).0[0]);
+                }
+            }
+        }
+        let pairs = (flat + smooth + hard).max(1) as f32;
+
+        ContentStats {
+            unique_colors: colors.len().min(COLOR_CAP),
+            has_alpha,
+            flat_share: flat as f32 / pairs,
+            hard_edge_share: hard as f32 / (smooth + hard).max(1) as f32,
+        }
+    }
+
+    /// JPEG, PNG and WebP, best first. `source_format` is the input's format name
+    /// and marks the matching suggestion with "current_format".
+    pub fn rank(stats: &ContentStats, animated: bool, source_format: &str) -> Vec<FormatSuggestion> {
+        let (mut jpeg, mut png, mut webp) = (
+            FormatSuggestion::new("jpeg"),
+            FormatSuggestion::new("png"),
+            FormatSuggestion::new("webp"),
+        );
+
+        if animated {
+            webp.add(3, "animated");
+        }
+        if stats.has_alpha {
+            png.add(2, "has_alpha");
+            webp.add(2, "has_alpha");
+            jpeg.add(-3, "drops_alpha");
+        }
+        if stats.photographic() {
+            jpeg.add(2, "photographic");
+            webp.add(2, "photographic");
+            png.add(-1, "large_for_photos");
+            // Ties go to the format every viewer opens
+            jpeg.add(1, "widely_supported");
+        } else {
+            if stats.few_colors() {
+                png.add(2, "few_colors");
+                webp.add(1, "few_colors");
+            }
+            if stats.hard_edge_share >= HARD_EDGE_SHARE || stats.flat_share >= FLAT_SHARE {
+                png.add(1, "sharp_edges");
+                webp.add(1, "sharp_edges");
+                jpeg.add(-2, "compression_artifacts");
+            }
+        }
+
+        let current = if source_format == "jpg" { "jpeg" } else { source_format };
+        let mut suggestions = vec![jpeg, png, webp];
+        for suggestion in suggestions.iter_mut().filter(|suggestion| suggestion.format == current) {
+            suggestion.reasons.push("current_format");
+        }
+        // Stable, so equal scores keep the JPEG, PNG, WebP order
+        suggestions.sort_by_key(|suggestion| std::cmp::Reverse(suggestion.score));
+        suggestions
+    }
+}
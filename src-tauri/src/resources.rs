@@ -0,0 +1,47 @@
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Items being converted right now, across all running batches
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+/// Header pixel counts of the in-flight items
+static IN_FLIGHT_PIXELS: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Serialize, Clone, Debug)]
+pub struct ResourceUsage {
+    /// Resident set size of the app process; None where it can't be read
+    pub rss_bytes: Option<u64>,
+    pub in_flight: usize,
+    /// Megapixels of the images currently being decoded or converted
+    pub decoded_megapixels: f64,
+}
+
+/// Marks one batch item as in flight until dropped
+pub struct InFlight {
+    pixels: u64,
+}
+
+pub struct ResourceMonitor;
+
+impl ResourceMonitor {
+    /// Count an item of `pixels` (from its header; 0 when unknown) as in flight
+    pub fn begin(pixels: u64) -> InFlight {
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        IN_FLIGHT_PIXELS.fetch_add(pixels, Ordering::SeqCst);
+        InFlight { pixels }
+    }
+
+    pub fn usage() -> ResourceUsage {
+        ResourceUsage {
+            rss_bytes: memory_stats::memory_stats().map(|stats| stats.physical_mem as u64),
+            in_flight: IN_FLIGHT.load(Ordering::SeqCst),
+            decoded_megapixels: IN_FLIGHT_PIXELS.load(Ordering::SeqCst) as f64 / 1_000_000.0,
+        }
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        IN_FLIGHT_PIXELS.fetch_sub(self.pixels, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,55 @@
+use crate::file_ops::FileOps;
+use crate::metadata::ExifSummary;
+use crate::ConversionSettings;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+/// Bumped when a field is removed or changes meaning; new fields keep the version
+const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize)]
+pub struct SidecarImage<'a> {
+    /// None for in-memory sources such as the clipboard
+    pub path: Option<&'a str>,
+    pub format: String,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+    pub sha256: Option<&'a str>,
+    pub blake3: Option<&'a str>,
+}
+
+/// Machine-readable provenance record written next to a single output
+#[derive(Serialize)]
+pub struct SidecarRecord<'a> {
+    pub source: SidecarImage<'a>,
+    pub output: SidecarImage<'a>,
+    pub exif: Option<ExifSummary>,
+    pub encoder: &'a str,
+    pub settings: &'a ConversionSettings,
+}
+
+#[derive(Serialize)]
+struct VersionedRecord<'a> {
+    schema_version: u32,
+    converted_at: u64,
+    #[serde(flatten)]
+    record: &'a SidecarRecord<'a>,
+}
+
+impl SidecarRecord<'_> {
+    /// Write the record as `<output>.json`, returning the sidecar path
+    pub fn write(&self, output_path: &str, converted_at: u64) -> Result<String> {
+        let path = format!("{}.json", output_path);
+        let contents = serde_json::to_vec_pretty(&VersionedRecord {
+            schema_version: SCHEMA_VERSION,
+            converted_at,
+            record: self,
+        })
+        .context("Failed to serialize JSON sidecar")?;
+
+        FileOps::write_atomic(Path::new(&path), &contents)?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+#[cfg(feature = "straighten")]
+use image::{DynamicImage, GrayImage};
+
+/// Longest side the edge analysis runs at; tilt is scale invariant
+#[cfg(feature = "straighten")]
+const ANALYSIS_SIZE: u32 = 512;
+/// Angular resolution of the Hough accumulator, in degrees
+#[cfg(feature = "straighten")]
+const ANGLE_STEP: f32 = 0.1;
+/// Fraction of the strongest gradients treated as edge pixels
+#[cfg(feature = "straighten")]
+const EDGE_FRACTION: f32 = 0.05;
+/// A line must collect votes from at least this fraction of the analysis width
+#[cfg(feature = "straighten")]
+const MIN_LINE_SUPPORT: f32 = 0.25;
+/// Tilts smaller than this aren't worth resampling the image for
+#[cfg(feature = "straighten")]
+const MIN_CORRECTION: f32 = 0.1;
+
+pub struct Straightener;
+
+impl Straightener {
+    /// Find the dominant near-horizontal line and return the clockwise rotation in
+    /// degrees that levels it, or None when no line within `max_angle` is strong
+    /// enough to trust or the tilt is negligible.
+    ///
+    /// Sobel gradients on a downscaled luma copy pick edge pixels whose gradient is
+    /// mostly vertical; a Hough transform restricted to ±`max_angle` then votes for
+    /// the line they lie on.
+    #[cfg(feature = "straighten")]
+    pub fn detect(img: &DynamicImage, max_angle: f32) -> Result<Option<f32>> {
+        let gray = Self::analysis_image(img);
+        let (width, height) = gray.dimensions();
+        if width < 3 || height < 3 {
+            return Ok(None);
+        }
+
+        let edges = Self::horizontal_edges(&gray);
+        if edges.is_empty() {
+            return Ok(None);
+        }
+
+        let steps = (max_angle / ANGLE_STEP).round() as i32;
+        let angles: Vec<(f32, f32)> = (-steps..=steps)
+            .map(|step| (step as f32 * ANGLE_STEP).to_radians().sin_cos())
+            .collect();
+
+        // rho = y·cosθ - x·sinθ for a line y = x·tanθ + c, offset so indices stay positive
+        let diagonal = ((width * width + height * height) as f32).sqrt().ceil() as usize;
+        let rho_bins = diagonal * 2 + 1;
+        let mut accumulator = vec![0u32; angles.len() * rho_bins];
+        for &(x, y) in &edges {
+            for (angle_index, &(sin, cos)) in angles.iter().enumerate() {
+                let rho = (y as f32 * cos - x as f32 * sin).round() as isize + diagonal as isize;
+                accumulator[angle_index * rho_bins + rho as usize] += 1;
+            }
+        }
+
+        let (best, votes) = accumulator.iter()
+            .enumerate()
+            .max_by_key(|&(_, &votes)| votes)
+            .map(|(index, &votes)| (index / rho_bins, votes))
+            .unwrap_or((0, 0));
+        if (votes as f32) < width as f32 * MIN_LINE_SUPPORT {
+            return Ok(None);
+        }
+
+        // A line sloping down to the right (positive θ, y pointing down) is tilted
+        // clockwise; rotating counter-clockwise by the same angle levels it
+        let tilt = (best as i32 - steps) as f32 * ANGLE_STEP;
+        Ok((tilt.abs() >= MIN_CORRECTION).then_some(-tilt))
+    }
+
+    #[cfg(not(feature = "straighten"))]
+    pub fn detect(_img: &image::DynamicImage, _max_angle: f32) -> Result<Option<f32>> {
+        anyhow::bail!("Automatic straightening is not enabled in this build")
+    }
+
+    #[cfg(feature = "straighten")]
+    fn analysis_image(img: &DynamicImage) -> GrayImage {
+        if img.width().max(img.height()) > ANALYSIS_SIZE {
+            img.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, image::imageops::FilterType::Triangle).to_luma8()
+        } else {
+            img.to_luma8()
+        }
+    }
+
+    /// Coordinates of the strongest Sobel edges whose gradient points mostly up or
+    /// down, i.e. pixels on roughly horizontal lines
+    #[cfg(feature = "straighten")]
+    fn horizontal_edges(gray: &GrayImage) -> Vec<(u32, u32)> {
+        let (width, height) = gray.dimensions();
+        let at = |x: u32, y: u32| gray.get_pixel(x, y).0[0] as i32;
+
+        let mut candidates = Vec::new();
+        for y in 1..height - 1 {
+            for x in 1..width - 1 {
+                let gx = (at(x + 1, y - 1) + 2 * at(x + 1, y) + at(x + 1, y + 1))
+                    - (at(x - 1, y - 1) + 2 * at(x - 1, y) + at(x - 1, y + 1));
+                let gy = (at(x - 1, y + 1) + 2 * at(x, y + 1) + at(x + 1, y + 1))
+                    - (at(x - 1, y - 1) + 2 * at(x, y - 1) + at(x + 1, y - 1));
+                if gy.abs() > 2 * gx.abs() {
+                    candidates.push((gx * gx + gy * gy, x, y));
+                }
+            }
+        }
+
+        let keep = ((width * height) as f32 * EDGE_FRACTION) as usize;
+        if candidates.len() > keep {
+            candidates.select_nth_unstable_by(keep, |a, b| b.0.cmp(&a.0));
+            candidates.truncate(keep);
+        }
+        candidates.into_iter()
+            .filter(|&(magnitude, _, _)| magnitude > 0)
+            .map(|(_, x, y)| (x, y))
+            .collect()
+    }
+}
@@ -0,0 +1,163 @@
+use image::{DynamicImage, GrayImage, Luma, Rgb, RgbImage};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static NEXT_DIR: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// System allocator that adds up the bytes each thread allocates, so tests running in
+/// parallel don't see each other's allocations
+struct CountingAllocator;
+
+impl CountingAllocator {
+    fn count(size: usize) {
+        // Unavailable while the thread is being torn down
+        ALLOCATED.try_with(|total| total.set(total.get() + size)).ok();
+    }
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn alloc_zeroed(&self, layout: Layout) -> *mut u8 {
+        Self::count(layout.size());
+        System.alloc_zeroed(layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        Self::count(new_size.saturating_sub(layout.size()));
+        System.realloc(ptr, layout, new_size)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Run `f`, returning its result and the bytes it allocated on this thread
+pub fn allocated_by<R>(f: impl FnOnce() -> R) -> (R, usize) {
+    let before = ALLOCATED.with(Cell::get);
+    let result = f();
+    (result, ALLOCATED.with(Cell::get) - before)
+}
+
+/// A fresh directory under the system temp dir, removed with its contents on drop
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let dir = std::env::temp_dir().join(format!(
+            "image-converter-test-{}-{}",
+            std::process::id(),
+            NEXT_DIR.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir).expect("create test directory");
+        TempDir(dir)
+    }
+
+    /// Path of `name` inside the directory, in the &str form the processors take
+    pub fn file(&self, name: &str) -> String {
+        self.0.join(name).to_string_lossy().into_owned()
+    }
+
+    /// Write `data` to `name` and return its path
+    pub fn write(&self, name: &str, data: &[u8]) -> String {
+        let path = self.file(name);
+        std::fs::write(&path, data).expect("write test file");
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        std::fs::remove_dir_all(&self.0).ok();
+    }
+}
+
+/// Red ramps up left to right and green top to bottom, so any rotation or flip of
+/// the image is detectable from its corners
+pub fn gradient(width: u32, height: u32) -> DynamicImage {
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        Rgb([(x * 255 / (width - 1).max(1)) as u8, (y * 255 / (height - 1).max(1)) as u8, 128])
+    }))
+}
+
+/// Smooth color gradients with a few levels of per-pixel noise, which compresses
+/// roughly like a camera photo rather than like flat synthetic art
+pub fn photo(width: u32, height: u32) -> DynamicImage {
+    let mut seed = 0x9e37_79b9u32;
+    let mut noise = move || {
+        seed ^= seed << 13;
+        seed ^= seed >> 17;
+        seed ^= seed << 5;
+        (seed % 9) as i32 - 4
+    };
+    DynamicImage::ImageRgb8(RgbImage::from_fn(width, height, |x, y| {
+        let (fx, fy) = (x as f32 / width as f32, y as f32 / height as f32);
+        let base = [
+            200.0 * fx + 30.0 * (fy * 9.0).sin(),
+            180.0 * fy + 40.0 * (fx * 7.0).cos(),
+            120.0 + 60.0 * ((fx + fy) * 5.0).sin(),
+        ];
+        Rgb(base.map(|value| (value as i32 + 20 + noise()).clamp(0, 255) as u8))
+    }))
+}
+
+/// Black "text" on white paper: lines of words made of letter-sized stems and bars,
+/// laid out by a fixed-seed generator so every run draws the same page
+pub fn text_page(width: u32, height: u32) -> GrayImage {
+    let mut page = GrayImage::from_pixel(width, height, Luma([255]));
+    let mut seed = 0x2545_f491u32;
+    let mut next = |range: u32| {
+        seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        (seed >> 16) % range
+    };
+    let margin = width / 12;
+    let fill = |page: &mut GrayImage, x: u32, y: u32, w: u32, h: u32| {
+        for py in y..(y + h).min(height) {
+            for px in x..(x + w).min(width - margin) {
+                page.put_pixel(px, py, Luma([0]));
+            }
+        }
+    };
+
+    let mut y = margin;
+    while y + 30 < height - margin {
+        let mut x = margin;
+        while x < width - margin {
+            for _ in 0..2 + next(8) {
+                fill(&mut page, x, y, 3, 24);
+                fill(&mut page, x, y + [0, 10, 21][next(3) as usize], 14, 3);
+                if next(2) == 0 {
+                    fill(&mut page, x + 11, y + 8, 3, 16);
+                }
+                x += 20;
+            }
+            x += 24;
+        }
+        y += 48;
+    }
+    page
+}
+
+/// An APP1 EXIF payload whose IFD0 holds only an Orientation tag
+pub fn exif_with_orientation(orientation: u16) -> Vec<u8> {
+    let mut exif = b"Exif\0\0II*\0\x08\0\0\0\x01\0".to_vec();
+    exif.extend_from_slice(&0x0112u16.to_le_bytes());
+    exif.extend_from_slice(&3u16.to_le_bytes());
+    exif.extend_from_slice(&1u32.to_le_bytes());
+    exif.extend_from_slice(&(orientation as u32).to_le_bytes());
+    exif.extend_from_slice(&0u32.to_le_bytes());
+    exif
+}
@@ -0,0 +1,121 @@
+use crate::file_ops::FileOps;
+use crate::image_processor::ImageProcessor;
+use anyhow::{Context, Result};
+use image::{DynamicImage, GenericImageView, ImageFormat};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+
+/// Tile sizes accepted for a pyramid; DZI viewers commonly use 254 or 256
+pub const TILE_SIZE_RANGE: (u32, u32) = (64, 4096);
+
+#[derive(Serialize, Clone, Debug)]
+pub struct TilePyramid {
+    /// The .dzi descriptor
+    pub descriptor: String,
+    /// Directory holding one subdirectory of tiles per level
+    pub tiles_dir: String,
+    pub width: u32,
+    pub height: u32,
+    pub tile_size: u32,
+    /// Number of levels, level 0 being 1x1 and the last one full resolution
+    pub levels: u32,
+    pub tile_count: u64,
+}
+
+/// Deep Zoom (DZI) tile pyramids for web viewers such as OpenSeadragon
+pub struct TileGenerator;
+
+impl TileGenerator {
+    /// Write `<name>.dzi` and `<name>_files/<level>/<column>_<row>.<ext>` under
+    /// `output_dir`. Each level halves the one above it (rounding up) down to a single
+    /// pixel, and only the current level is held in memory. Tiles on the right and
+    /// bottom edges are cut to what's left of the image; levels no larger than a tile
+    /// are a single tile.
+    pub fn generate(
+        img: DynamicImage,
+        name: &str,
+        tile_size: u32,
+        output_dir: &Path,
+        format: ImageFormat,
+        quality: u8,
+    ) -> Result<TilePyramid> {
+        let extension = Self::extension(format)?;
+        let (width, height) = img.dimensions();
+        let tiles_dir = output_dir.join(format!("{}_files", name));
+        std::fs::create_dir_all(&tiles_dir).context("Failed to create tile directory")?;
+
+        let max_level = Self::max_level(width.max(height));
+        let mut level_img = img;
+        let mut tile_count = 0u64;
+        for level in (0..=max_level).rev() {
+            if level < max_level {
+                let (w, h) = level_img.dimensions();
+                level_img = level_img.resize_exact(w.div_ceil(2), h.div_ceil(2), image::imageops::FilterType::Triangle);
+            }
+            tile_count += Self::write_level(&level_img, &tiles_dir.join(level.to_string()), tile_size, extension, format, quality)?;
+        }
+
+        let descriptor = output_dir.join(format!("{}.dzi", name));
+        let xml = format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" Format=\"{}\" Overlap=\"0\" TileSize=\"{}\">\n  \
+             <Size Width=\"{}\" Height=\"{}\"/>\n\
+             </Image>\n",
+            extension, tile_size, width, height,
+        );
+        FileOps::write_atomic(&descriptor, xml.as_bytes()).context("Failed to write DZI descriptor")?;
+
+        Ok(TilePyramid {
+            descriptor: descriptor.to_string_lossy().to_string(),
+            tiles_dir: tiles_dir.to_string_lossy().to_string(),
+            width,
+            height,
+            tile_size,
+            levels: max_level + 1,
+            tile_count,
+        })
+    }
+
+    /// Tile file extension, which DZI also uses as the format name
+    pub fn extension(format: ImageFormat) -> Result<&'static str> {
+        match format {
+            ImageFormat::Jpeg => Ok("jpg"),
+            ImageFormat::Png => Ok("png"),
+            ImageFormat::WebP => Ok("webp"),
+            _ => anyhow::bail!("Tiles can only be written as JPEG, PNG or WebP"),
+        }
+    }
+
+    /// Level at which the longest side reaches `size`, starting from 1 pixel at level 0
+    fn max_level(size: u32) -> u32 {
+        size.max(1).next_power_of_two().trailing_zeros()
+    }
+
+    /// Encode the tiles of one level in parallel; returns how many were written
+    fn write_level(
+        img: &DynamicImage,
+        dir: &Path,
+        tile_size: u32,
+        extension: &str,
+        format: ImageFormat,
+        quality: u8,
+    ) -> Result<u64> {
+        std::fs::create_dir_all(dir).context("Failed to create tile directory")?;
+        let (width, height) = img.dimensions();
+        let (columns, rows) = (width.div_ceil(tile_size), height.div_ceil(tile_size));
+
+        (0..columns)
+            .flat_map(|column| (0..rows).map(move |row| (column, row)))
+            .collect::<Vec<_>>()
+            .par_iter()
+            .try_for_each(|&(column, row)| -> Result<()> {
+                let (x, y) = (column * tile_size, row * tile_size);
+                let tile = img.crop_imm(x, y, tile_size.min(width - x), tile_size.min(height - y));
+                let data = ImageProcessor::encode_image(&tile, format, quality)?;
+                FileOps::write_atomic(&dir.join(format!("{}_{}.{}", column, row, extension)), &data)
+                    .context("Failed to write tile")
+            })?;
+        Ok(columns as u64 * rows as u64)
+    }
+}
@@ -0,0 +1,140 @@
+#[cfg(feature = "upright")]
+use crate::adjustments::Adjustments;
+use anyhow::Result;
+#[cfg(feature = "upright")]
+use image::{DynamicImage, GrayImage};
+
+/// Longest side the text analysis runs at; small print needs more than a thumbnail
+#[cfg(feature = "upright")]
+const ANALYSIS_SIZE: u32 = 1024;
+/// Share of ink pixels outside which the image is not treated as a text page
+#[cfg(feature = "upright")]
+const INK_RANGE: (f32, f32) = (0.005, 0.4);
+/// How much more line structure one axis must show than the other
+#[cfg(feature = "upright")]
+const MIN_AXIS_RATIO: f32 = 1.5;
+/// Text lines needed before the ascender/descender vote is trusted
+#[cfg(feature = "upright")]
+const MIN_LINES: usize = 3;
+/// Lines shorter than this many rows are noise, rules or underlines
+#[cfg(feature = "upright")]
+const MIN_LINE_HEIGHT: usize = 4;
+/// How much more ink must sit above the x-height band than below it (or the reverse)
+#[cfg(feature = "upright")]
+const MIN_ASCENDER_RATIO: f32 = 1.3;
+
+pub struct UprightDetector;
+
+impl UprightDetector {
+    /// Guess the clockwise rotation (90, 180 or 270 degrees) that makes the text in
+    /// `img` upright, or None when it already is or the evidence is weak.
+    ///
+    /// The ink projection of a text page swings between lines and gaps along the axis
+    /// the lines stack on, which tells horizontal from vertical text. Latin script
+    /// has more ascenders than descenders, so upright lines carry more ink above
+    /// their x-height band than below it, which tells up from down. Pages without
+    /// enough lines of text are left alone.
+    #[cfg(feature = "upright")]
+    pub fn detect(img: &DynamicImage) -> Result<Option<u16>> {
+        let Some(ink) = Self::ink_mask(img) else {
+            return Ok(None);
+        };
+
+        let rows = Self::profile_spread(&Self::row_profile(&ink));
+        let columns = Self::profile_spread(&Self::row_profile(&image::imageops::rotate90(&ink)));
+        let (lines, quarter_turn) = if rows >= columns * MIN_AXIS_RATIO {
+            (ink, 0)
+        } else if columns >= rows * MIN_AXIS_RATIO {
+            // Lines run top to bottom; turning clockwise lays them flat
+            (image::imageops::rotate90(&ink), 90)
+        } else {
+            return Ok(None);
+        };
+
+        let rotation = match Self::upside_down(&lines) {
+            Some(false) => quarter_turn,
+            Some(true) => quarter_turn + 180,
+            None => return Ok(None),
+        };
+        Ok((rotation != 0).then_some(rotation))
+    }
+
+    #[cfg(not(feature = "upright"))]
+    pub fn detect(_img: &image::DynamicImage) -> Result<Option<u16>> {
+        anyhow::bail!("Orientation detection is not enabled in this build")
+    }
+
+    /// Downscaled ink mask, or None when the ink share doesn't look like a text page
+    #[cfg(feature = "upright")]
+    fn ink_mask(img: &DynamicImage) -> Option<GrayImage> {
+        let gray = if img.width().max(img.height()) > ANALYSIS_SIZE {
+            img.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, image::imageops::FilterType::Triangle).to_luma8()
+        } else {
+            img.to_luma8()
+        };
+
+        let (mask, ink_share) = Adjustments::ink_mask(&gray);
+        (INK_RANGE.0..=INK_RANGE.1).contains(&ink_share).then_some(mask)
+    }
+
+    /// Ink pixels in each row
+    #[cfg(feature = "upright")]
+    fn row_profile(mask: &GrayImage) -> Vec<u32> {
+        mask.rows()
+            .map(|row| row.map(|pixel| pixel.0[0] as u32).sum())
+            .collect()
+    }
+
+    /// Coefficient of variation of a profile between its first and last inked entry
+    #[cfg(feature = "upright")]
+    fn profile_spread(profile: &[u32]) -> f32 {
+        let (Some(first), Some(last)) = (profile.iter().position(|&v| v > 0), profile.iter().rposition(|&v| v > 0)) else {
+            return 0.0;
+        };
+        let span = &profile[first..=last];
+        let mean = span.iter().sum::<u32>() as f32 / span.len() as f32;
+        let variance = span.iter().map(|&v| (v as f32 - mean).powi(2)).sum::<f32>() / span.len() as f32;
+        variance.sqrt() / mean
+    }
+
+    /// Whether horizontal text lines in `mask` read upside down, by comparing the ink
+    /// above each line's x-height band with the ink below it. None when undecided.
+    #[cfg(feature = "upright")]
+    fn upside_down(mask: &GrayImage) -> Option<bool> {
+        let profile = Self::row_profile(mask);
+        let (mut lines, mut above, mut below) = (0, 0u64, 0u64);
+
+        let mut start = 0;
+        while start < profile.len() {
+            if profile[start] == 0 {
+                start += 1;
+                continue;
+            }
+            let end = profile[start..].iter().position(|&v| v == 0).map_or(profile.len(), |len| start + len);
+            let line = &profile[start..end];
+            start = end;
+            if line.len() < MIN_LINE_HEIGHT {
+                continue;
+            }
+
+            // The x-height band holds the densest rows of the line
+            let peak = *line.iter().max().unwrap_or(&0);
+            let band_top = line.iter().position(|&v| v * 2 >= peak).unwrap_or(0);
+            let band_bottom = line.iter().rposition(|&v| v * 2 >= peak).unwrap_or(line.len() - 1);
+            above += line[..band_top].iter().map(|&v| v as u64).sum::<u64>();
+            below += line[band_bottom + 1..].iter().map(|&v| v as u64).sum::<u64>();
+            lines += 1;
+        }
+
+        if lines < MIN_LINES {
+            return None;
+        }
+        if above as f32 >= below as f32 * MIN_ASCENDER_RATIO {
+            Some(false)
+        } else if below as f32 >= above as f32 * MIN_ASCENDER_RATIO {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
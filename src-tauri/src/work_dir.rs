@@ -0,0 +1,83 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+/// Free space a directory needs to be accepted as the working directory
+pub const MIN_FREE_BYTES: u64 = 512 * 1024 * 1024;
+
+/// File in the app config directory remembering the chosen directory
+const SETTINGS_FILE: &str = "working_dir.json";
+
+static WORK_DIR: RwLock<Option<PathBuf>> = RwLock::new(None);
+
+#[derive(Serialize, Deserialize, Default)]
+struct Saved {
+    path: Option<PathBuf>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct WorkDirInfo {
+    pub path: String,
+    /// False while the OS temp directory is in use
+    pub custom: bool,
+    pub available_bytes: Option<u64>,
+}
+
+/// Where previews, thumbnails and temporary uploads are written
+pub struct WorkDir;
+
+impl WorkDir {
+    /// The configured directory, or the OS temp directory when none is set
+    pub fn path() -> PathBuf {
+        WORK_DIR.read()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone()
+            .unwrap_or_else(std::env::temp_dir)
+    }
+
+    pub fn info() -> WorkDirInfo {
+        let custom = WORK_DIR.read().unwrap_or_else(|e| e.into_inner()).is_some();
+        let path = Self::path();
+        WorkDirInfo {
+            available_bytes: fs2::available_space(&path).ok(),
+            path: path.to_string_lossy().to_string(),
+            custom,
+        }
+    }
+
+    /// Restore the directory saved under `config_dir`. A saved directory that is gone
+    /// (e.g. an unplugged drive) is ignored for this session and the temp dir is used.
+    pub fn load(config_dir: &Path) {
+        let saved = std::fs::read(config_dir.join(SETTINGS_FILE))
+            .ok()
+            .and_then(|data| serde_json::from_slice::<Saved>(&data).ok())
+            .and_then(|saved| saved.path)
+            .filter(|path| path.is_dir());
+        *WORK_DIR.write().unwrap_or_else(|e| e.into_inner()) = saved;
+    }
+
+    /// Create `dir` if needed and make sure files can be written there. Returns the
+    /// free space on its volume.
+    pub fn check(dir: &Path) -> Result<u64> {
+        std::fs::create_dir_all(dir).context("Failed to create working directory")?;
+        let probe = dir.join(format!(".write_test_{}", std::process::id()));
+        std::fs::write(&probe, b"")
+            .and_then(|_| std::fs::remove_file(&probe))
+            .context("Working directory is not writable")?;
+        fs2::available_space(dir).context("Failed to read free space of working directory")
+    }
+
+    /// Use `dir` from now on, or the OS temp directory for None, and save the choice
+    /// under `config_dir`. Returns the directory that was in use before.
+    pub fn set(config_dir: &Path, dir: Option<PathBuf>) -> Result<PathBuf> {
+        std::fs::create_dir_all(config_dir).context("Failed to create config directory")?;
+        let data = serde_json::to_vec_pretty(&Saved { path: dir.clone() })
+            .context("Failed to serialize working directory")?;
+        std::fs::write(config_dir.join(SETTINGS_FILE), data).context("Failed to save working directory")?;
+
+        let previous = Self::path();
+        *WORK_DIR.write().unwrap_or_else(|e| e.into_inner()) = dir;
+        Ok(previous)
+    }
+}
@@ -0,0 +1,196 @@
+#!/usr/bin/env python3
+"""Writes the BMP fixtures in this directory and the reference PNG for each.
+
+Both are produced from the same pixel values here, independently of the Rust
+decoder, so the tests in src/bmp.rs compare it against a separate encoder.
+Run from this directory with any Python 3; no packages are needed.
+"""
+import struct
+import zlib
+
+WIDTH, HEIGHT = 7, 5
+
+PALETTE = [(i * 16, 255 - i * 16, (i * 112) % 256) for i in range(16)]
+
+
+def indices():
+    """Palette indices in image order: a run of three, then varied pixels. Two
+    pixels of the top row are index 0 so RLE8 can skip them with a delta."""
+    rows = [[y + 1] * 3 + [(x * 3 + y) % 16 for x in range(3, WIDTH)] for y in range(HEIGHT)]
+    rows[0][3:5] = [0, 0]
+    return rows
+
+
+def rgb(x, y):
+    return (x * 36, y * 60, (x * y * 20) % 256)
+
+
+def expand(value, bits):
+    """A `bits`-wide channel scaled to 8 bits, rounded to nearest"""
+    top = (1 << bits) - 1
+    return (value * 255 + top // 2) // top
+
+
+def file_rows(rows, top_down):
+    return rows if top_down else rows[::-1]
+
+
+def pad(row):
+    return row + b"\0" * (-len(row) % 4)
+
+
+def bitmap(dib, palette, pixels):
+    offset = 14 + len(dib) + len(palette)
+    size = offset + len(pixels)
+    return b"BM" + struct.pack("<IHHI", size, 0, 0, offset) + dib + palette + pixels
+
+
+def info_header(bits, compression, top_down=False, colors=0, size=40, masks=None):
+    height = -HEIGHT if top_down else HEIGHT
+    dib = struct.pack("<IiiHHIIiiII", size, WIDTH, height, 1, bits, compression, 0, 2835, 2835, colors, 0)
+    if size >= 108:
+        # Masks, sRGB color space, unused endpoints and gamma
+        dib += struct.pack("<4I", *masks) + b"BGRs" + b"\0" * 48
+    return dib
+
+
+def bgr0(palette):
+    return b"".join(struct.pack("<BBBx", b, g, r) for r, g, b in palette)
+
+
+def runs(row):
+    out, start = [], 0
+    for x in range(1, len(row) + 1):
+        if x == len(row) or row[x] != row[start]:
+            out.append((row[start], x - start))
+            start = x
+    return out
+
+
+def rle8(rows, use_delta):
+    out = b""
+    for y, row in enumerate(rows):
+        literal = []
+
+        def flush():
+            nonlocal out, literal
+            if len(literal) >= 3:
+                out += bytes([0, len(literal)]) + bytes(literal) + b"\0" * (len(literal) % 2)
+            else:
+                out += b"".join(bytes([1, value]) for value in literal)
+            literal = []
+
+        for value, count in runs(row):
+            if count == 1:
+                literal.append(value)
+                continue
+            flush()
+            if use_delta and value == 0:
+                out += bytes([0, 2, count, 0])
+            else:
+                out += bytes([count, value])
+        flush()
+        out += b"\0\1" if y == len(rows) - 1 else b"\0\0"
+    return out
+
+
+def rle4(rows):
+    out = b""
+    for y, row in enumerate(rows):
+        # Encoded runs for the repeats, one absolute run for the rest
+        (value, count), rest = runs(row)[0], row[runs(row)[0][1]:]
+        out += bytes([count, value << 4 | value])
+        packed = bytes((rest[i] << 4) | (rest[i + 1] if i + 1 < len(rest) else 0) for i in range(0, len(rest), 2))
+        out += bytes([0, len(rest)]) + packed + b"\0" * (len(packed) % 2)
+        out += b"\0\1" if y == len(rows) - 1 else b"\0\0"
+    return out
+
+
+def packed_16(shifts_bits, top_down=False):
+    rows = []
+    for y in range(HEIGHT):
+        row = b""
+        for x in range(WIDTH):
+            value = 0
+            for channel, (shift, bits) in zip(rgb(x, y), shifts_bits):
+                value |= (channel >> (8 - bits)) << shift
+            row += struct.pack("<H", value)
+        rows.append(pad(row))
+    return b"".join(file_rows(rows, top_down))
+
+
+def reference_16(shifts_bits):
+    return [[tuple(expand(c >> (8 - bits), bits) for c, (_, bits) in zip(rgb(x, y), shifts_bits))
+             for x in range(WIDTH)] for y in range(HEIGHT)]
+
+
+def paletted(rows):
+    return [[PALETTE[i] for i in row] for row in rows]
+
+
+def direct():
+    return [[rgb(x, y) for x in range(WIDTH)] for y in range(HEIGHT)]
+
+
+def png(pixels):
+    def chunk(kind, data):
+        return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+    raw = b"".join(b"\0" + bytes(c for pixel in row for c in pixel) for row in pixels)
+    header = struct.pack(">IIBBBBB", WIDTH, HEIGHT, 8, 2, 0, 0, 0)
+    return b"\x89PNG\r\n\x1a\n" + chunk(b"IHDR", header) + chunk(b"IDAT", zlib.compress(raw, 9)) + chunk(b"IEND", b"")
+
+
+def main():
+    rows = indices()
+    palette16 = bgr0(PALETTE)
+    rgb565 = [(11, 5), (5, 6), (0, 5)]
+    rgb555 = [(10, 5), (5, 5), (0, 5)]
+    rgb444 = [(8, 4), (4, 4), (0, 4)]
+    fixtures = {
+        "rle8": (
+            bitmap(info_header(8, 1, colors=16), palette16, rle8(file_rows(rows, False), True)),
+            paletted(rows),
+        ),
+        "rle8_top_down": (
+            bitmap(info_header(8, 1, top_down=True, colors=16), palette16, rle8(rows, False)),
+            paletted(rows),
+        ),
+        "rle4": (
+            bitmap(info_header(4, 2, colors=16), palette16, rle4(file_rows(rows, False))),
+            paletted(rows),
+        ),
+        "rgb565": (
+            bitmap(info_header(16, 3), struct.pack("<3I", 0xF800, 0x07E0, 0x001F), packed_16(rgb565)),
+            reference_16(rgb565),
+        ),
+        "rgb555": (
+            bitmap(info_header(16, 0), b"", packed_16(rgb555)),
+            reference_16(rgb555),
+        ),
+        "rgb444_v4_top_down": (
+            bitmap(info_header(16, 3, top_down=True, size=108, masks=(0x0F00, 0x00F0, 0x000F, 0)), b"",
+                   packed_16(rgb444, top_down=True)),
+            reference_16(rgb444),
+        ),
+        "rgb24_top_down": (
+            bitmap(info_header(24, 0, top_down=True), b"",
+                   b"".join(pad(b"".join(bytes(pixel[::-1]) for pixel in row)) for row in direct())),
+            direct(),
+        ),
+        "os2_core_8": (
+            bitmap(struct.pack("<IHHHH", 12, WIDTH, HEIGHT, 1, 8),
+                   b"".join(bytes(color[::-1]) for color in PALETTE + [(0, 0, 0)] * 240),
+                   b"".join(pad(bytes(row)) for row in file_rows(rows, False))),
+            paletted(rows),
+        ),
+    }
+    for name, (bmp, reference) in fixtures.items():
+        with open(name + ".bmp", "wb") as f:
+            f.write(bmp)
+        with open(name + ".png", "wb") as f:
+            f.write(png(reference))
+
+
+if __name__ == "__main__":
+    main()
@@ -1,16 +1,40 @@
 import { useState } from 'react';
 import { invoke } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
 import { useImageStore } from '@/store/imageStore';
 import { Button } from '@/components/ui/button';
 import { Loader2, FolderOpen, ArrowRight, Trash2, CheckCircle2 } from 'lucide-react';
 import { join } from '@tauri-apps/api/path';
 
+interface AppError {
+  code: string;
+  message: string;
+  params: Record<string, string>;
+}
+
+// Also emitted per item as a `conversion_result` event while the batch runs. Events
+// arrive in completion order, not input order, so match them up by file_id.
 interface BatchConversionResult {
   file_id: string;
   success: boolean;
   output_path: string | null;
-  error: string | null;
+  error: AppError | null;
+}
+
+interface BatchSummary {
+  total: number;
+  succeeded: number;
+  failed: number;
+  skipped: number;
+  total_input_bytes: number;
+  total_output_bytes: number;
+  elapsed_ms: number;
+}
+
+interface BatchConversionResponse {
+  results: BatchConversionResult[];
+  summary: BatchSummary;
 }
 
 export function ConversionControls() {
@@ -49,6 +73,26 @@ export function ConversionControls() {
       updateFile(file.id, { status: 'processing', progress: 0 });
     });
 
+    const applyResult = (result: BatchConversionResult) => {
+      if (result.success) {
+        updateFile(result.file_id, {
+          status: 'completed',
+          progress: 100,
+          outputPath: result.output_path || undefined,
+        });
+      } else {
+        updateFile(result.file_id, {
+          status: 'error',
+          error: result.error?.message || 'Unknown error',
+        });
+      }
+    };
+
+    // Fill in results as items finish rather than waiting for the whole batch
+    const unlistenResult = await listen<BatchConversionResult>('conversion_result', (event) => {
+      applyResult(event.payload);
+    });
+
     try {
       // Prepare batch items with output paths
       const items = await Promise.all(
@@ -64,7 +108,7 @@ export function ConversionControls() {
       );
 
       // Call batch conversion (parallel processing on backend)
-      const results = await invoke<BatchConversionResult[]>('convert_images_batch', {
+      const { results, summary } = await invoke<BatchConversionResponse>('convert_images_batch', {
         items,
         settings: {
           target_format: settings.targetFormat,
@@ -73,35 +117,23 @@ export function ConversionControls() {
         },
       });
 
-      // Update file statuses based on results
-      results.forEach((result) => {
-        if (result.success) {
-          updateFile(result.file_id, {
-            status: 'completed',
-            progress: 100,
-            outputPath: result.output_path || undefined,
-          });
-        } else {
-          updateFile(result.file_id, {
-            status: 'error',
-            error: result.error || 'Unknown error',
-          });
-        }
-      });
+      // The final results match the streamed ones; apply them again in case an event was missed
+      results.forEach(applyResult);
 
       // Auto-clear completed files after a short delay
-      const successCount = results.filter(r => r.success).length;
-      if (successCount > 0) {
+      if (summary.succeeded > 0) {
         setTimeout(() => {
           clearCompleted();
         }, 1500);
       }
     } catch (error) {
       // If batch fails completely, mark all as error
-      const errorMsg = error instanceof Error ? error.message : 'Batch conversion failed';
+      const errorMsg = (error as AppError | undefined)?.message || 'Batch conversion failed';
       pendingFiles.forEach((file) => {
         updateFile(file.id, { status: 'error', error: errorMsg });
       });
+    } finally {
+      unlistenResult();
     }
 
     setIsConverting(false);
@@ -36,7 +36,7 @@ export function DragDropOverlay() {
           filePaths.map(async (path) => {
             try {
               const ext = path.split('.').pop()?.toLowerCase();
-              if (!['jpg', 'jpeg', 'png', 'heic', 'heif'].includes(ext || '')) {
+              if (!['jpg', 'jpeg', 'png', 'heic', 'heics', 'hif', 'heif', 'heifs', 'avci', 'avcs', 'avif', 'avifs'].includes(ext || '')) {
                 console.log('DragDropOverlay: Skipping non-image:', path);
                 return null;
               }